@@ -0,0 +1,63 @@
+// cargo bench で CPU, PPU, APU のホットパスを criterion で測る (rscom --bench は同じ内容の簡易版)
+use criterion::{criterion_group, criterion_main, Criterion};
+use nes_core::apu::{AudioConfig, APU};
+use nes_core::bench::bench_rom;
+use nes_core::nes::Nes;
+use std::hint::black_box;
+
+// 1フレーム進めてから測る (PPUの描画が有効になっている)
+fn bench_nes() -> Nes {
+    let mut nes = Nes::new(bench_rom(), APU::headless(&AudioConfig::new())).unwrap();
+    nes.run_frame();
+    nes
+}
+
+// 命令のフェッチ, デコード, 実行 (1命令)
+fn cpu_dispatch(c: &mut Criterion) {
+    let mut nes = bench_nes();
+    c.bench_function("cpu_dispatch", |b| b.iter(|| nes.step().unwrap()));
+}
+
+// 1スキャンライン (341ドット) をCPUと同じく1サイクル3ドットずつ進める
+fn ppu_scanline(c: &mut Criterion) {
+    let mut nes = bench_nes();
+    c.bench_function("ppu_scanline", |b| {
+        b.iter(|| {
+            let ppu = nes.bus_mut().ppu_mut();
+            for _ in 0..113 {
+                ppu.tick(3);
+            }
+            black_box(ppu.tick(2))
+        })
+    });
+}
+
+// 1サンプル分のAPUのクロック (44.1kHzで約41 CPUサイクル) と取り出し
+fn apu_sample(c: &mut Criterion) {
+    let config = AudioConfig::new();
+    let mut apu = APU::headless(&config);
+    apu.write_status(0x0F);
+    apu.write1ch(0x4000, 0xBF);
+    apu.write1ch(0x4002, 0xFD);
+    apu.write1ch(0x4003, 0x08);
+    apu.write3ch(0x4008, 0xFF);
+    apu.write3ch(0x400A, 0x80);
+    apu.write3ch(0x400B, 0x08);
+    apu.write4ch(0x400C, 0x3F);
+    apu.write4ch(0x400E, 0x04);
+    let cycles = (1_789_773 / config.sample_rate) as usize;
+    let mut samples = Vec::with_capacity(16);
+    c.bench_function("apu_sample", |b| {
+        b.iter(|| {
+            for _ in 0..cycles {
+                apu.tick();
+            }
+            apu.drain_samples(&mut samples);
+            black_box(samples.len());
+            samples.clear();
+        })
+    });
+}
+
+criterion_group!(benches, cpu_dispatch, ppu_scanline, apu_sample);
+criterion_main!(benches);
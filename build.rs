@@ -0,0 +1,18 @@
+// --features capi のときは src/capi.rs から C のヘッダー (include/nes_core.h) を作る
+// 設定は cbindgen.toml。中身が変わらなければファイルは書き換えない
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "capi")]
+    {
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/capi.rs", dir))
+            .generate()
+            .expect("cbindgen: src/capi.rs")
+            .write_to_file(format!("{}/include/nes_core.h", dir));
+    }
+}
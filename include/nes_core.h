@@ -0,0 +1,103 @@
+/* nes_core の C API (src/capi.rs)
+ *   cargo build --release --lib --features capi
+ *   cc app.c -Iinclude -Ltarget/release -lnes_core
+ * 関数はスレッドセーフではない。1つのインスタンスは1つのスレッドから使う
+ */
+
+#ifndef NES_CORE_H
+#define NES_CORE_H
+
+/* cbindgen で作っている。手で書き換えない (cbindgen.toml を参照) */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ ボタンのビット (nes_set_input)
+ */
+#define NES_BUTTON_A 1
+
+#define NES_BUTTON_B 2
+
+#define NES_BUTTON_SELECT 4
+
+#define NES_BUTTON_START 8
+
+#define NES_BUTTON_UP 16
+
+#define NES_BUTTON_DOWN 32
+
+#define NES_BUTTON_LEFT 64
+
+#define NES_BUTTON_RIGHT 128
+
+/*
+ 中身は見せない
+ */
+typedef struct NesInstance NesInstance;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ sample_rate: 0 なら既定 (44100)
+ */
+struct NesInstance *nes_create(int32_t sample_rate);
+
+/*
+ nes_create で作ったインスタンスを解放する
+ */
+void nes_destroy(struct NesInstance *instance);
+
+/*
+ iNES/NES 2.0 のイメージ。data はコピーするので戻ったら解放してよい
+ */
+bool nes_load_rom(struct NesInstance *instance, const uint8_t *data, size_t len);
+
+/*
+ 1フレーム実行する。ROMが読み込まれていなければ false
+ */
+bool nes_run_frame(struct NesInstance *instance);
+
+/*
+ nes_video_width() x nes_video_height() の RGB (1画素3バイト)。次の nes_run_frame / nes_load_rom まで有効
+ */
+const uint8_t *nes_video(const struct NesInstance *instance);
+
+/*
+ 画面の幅 (256)
+ */
+size_t nes_video_width(void);
+
+/*
+ 画面の高さ (240)
+ */
+size_t nes_video_height(void);
+
+/*
+ 直前のフレームのサンプル (float, モノラル)。数を count に書く
+ */
+const float *nes_audio(const struct NesInstance *instance, size_t *count);
+
+/*
+ player: 0-3, buttons: NES_BUTTON_* の組み合わせ
+ */
+void nes_set_input(struct NesInstance *instance, size_t player, uint8_t buttons);
+
+/*
+ リセットボタンを押す
+ */
+void nes_reset(struct NesInstance *instance);
+
+/*
+ 電源を入れ直す
+ */
+void nes_power_cycle(struct NesInstance *instance);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NES_CORE_H */
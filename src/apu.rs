@@ -1,11 +1,55 @@
 // use log::{debug, info, trace};
 use bitflags::bitflags;
-use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::time::Duration;
-
+use crate::state::{State, StateError, StateReader, StateWriter};
+use crate::vgm::VgmLogger;
+use crate::region::Region;
+use crate::snapshot::ApuSnapshot;
+use crate::{impl_state, impl_state_bits};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use crate::sync::{Arc, Mutex};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// チャンネルの周波数はこのクロックで計算し、サンプル生成時にリージョンのクロックとの比を掛ける
 const CPU_CLOCK: f32 = 1_789_772.5;  // 1.789 MHz
-const _CLOCK_DIV: usize = 7457;      // 7457分周
+
+// フレームシーケンサのステップ (CPUサイクル, カッコ内はAPUサイクル)
+// https://www.nesdev.org/wiki/APU_Frame_Counter
+struct FrameTiming {
+    step_1: usize,
+    step_2: usize,
+    step_3: usize,
+    step4_irq: usize, // 4ステップ: 割り込みフラグセット
+    step4_4: usize,   // 4ステップ: 最終ステップ
+    step4_end: usize, // 4ステップ: 0に戻る
+    step5_4: usize,   // 5ステップ: なにもしない
+    step5_5: usize,   // 5ステップ: 最終ステップ
+    step5_end: usize, // 5ステップ: 0に戻る
+}
+
+const NTSC_FRAME_TIMING: FrameTiming = FrameTiming {
+    step_1: 7457,     // (3728.5)
+    step_2: 14913,    // (7456.5)
+    step_3: 22371,    // (11185.5)
+    step4_irq: 29828, // (14914)
+    step4_4: 29829,   // (14914.5)
+    step4_end: 29830, // (14915)
+    step5_4: 29829,   // (14914.5)
+    step5_5: 37281,   // (18640.5)
+    step5_end: 37282, // (18641)
+};
+
+const PAL_FRAME_TIMING: FrameTiming = FrameTiming {
+    step_1: 8313,     // (4156.5)
+    step_2: 16627,    // (8313.5)
+    step_3: 24939,    // (12469.5)
+    step4_irq: 33252, // (16626)
+    step4_4: 33253,   // (16626.5)
+    step4_end: 33254, // (16627)
+    step5_4: 33253,   // (16626.5)
+    step5_5: 41565,   // (20782.5)
+    step5_end: 41566, // (20783)
+};
 
 const _DUTY_12P5: f32 = 0.125;       // Duty 12.5％
 const _DUTY_25: f32 = 0.25;          // Duty 25％
@@ -14,6 +58,12 @@ const _DUTY_75: f32 = 0.75;          // Duty 75％
 
 const MASTER_VOLUME: f32 = 0.25;
 
+const DEFAULT_SAMPLE_RATE: i32 = 44100;
+const DEFAULT_BUFFER_SAMPLES: u16 = 1024;
+const DEFAULT_MAX_RATE_DELTA: f32 = 0.005; // ±0.5%
+// リングバッファに溜めてよい最大量(秒)
+const RING_SECONDS: f32 = 0.25;
+
 const _CH1 :u8 = 0b0000_0001;
 const _CH2 :u8 = 0b0000_0010;
 const _CH3 :u8 = 0b0000_0100;
@@ -48,6 +98,16 @@ lazy_static! {
         noise_tbl.iter().map(|&x| x as f32).collect()
     };
 
+    pub static ref NOISE_TBL_PAL: Vec<f32> = {
+        let noise_tbl: Vec<u16> = vec![
+            0x0002, 0x0004, 0x0007, 0x000F, 0x001E,
+            0x002C, 0x003B, 0x004A, 0x005E, 0x0076,
+            0x00B1, 0x00EC, 0x0162, 0x01D8, 0x03B1, 0x0761,
+        ];
+
+        noise_tbl.iter().map(|&x| x as f32).collect()
+    };
+
     pub static ref  LENGTH_COUNTER_TBL: Vec<u8> = vec![
         0x05, 0x7F, 0x0A, 0x01, 0x14, 0x02, 0x28, 0x03,
         0x50, 0x04, 0x1E, 0x05, 0x07, 0x06, 0x0D, 0x07,
@@ -55,6 +115,192 @@ lazy_static! {
         0x60, 0x0C, 0x24, 0x0D, 0x08, 0x0E, 0x10, 0x0F,];
 }
 
+// オーディオバックエンドの設定
+// buffer_samples を小さくすると遅延が減るが、アンダーランしやすくなる
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioConfig {
+    pub sample_rate: i32,
+    pub buffer_samples: u16,
+    // バッファの溜まり具合に応じて再生レートを微調整する(A/V同期用)
+    pub dynamic_rate: bool,
+    pub max_rate_delta: f32,
+}
+
+impl AudioConfig {
+    pub fn new() -> Self {
+        AudioConfig {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            buffer_samples: DEFAULT_BUFFER_SAMPLES,
+            dynamic_rate: true,
+            max_rate_delta: DEFAULT_MAX_RATE_DELTA,
+        }
+    }
+
+    pub fn latency_ms(&self) -> f32 {
+        self.buffer_samples as f32 * 1000.0 / self.sample_rate as f32
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioStats {
+    pub sample_rate: i32,     // 実際にオープンできたサンプリングレート
+    pub buffer_samples: u16,  // 実際にオープンできたバッファサイズ
+    pub latency_ms: f32,
+    pub underruns: usize,     // オーディオ側がエミュレーションに追いついてしまった回数
+    pub fill_ms: f32,         // リングバッファに溜まっているサンプル量
+    pub rate_ratio: f32,      // 現在の再生レート補正 (1.0 = 補正なし)
+}
+
+// サンプルのリングバッファ (エミュレーションスレッド -> オーディオコールバック)
+// 書き込み側・読み出し側とも1スレッドずつなので、ロックもアロケーションも不要
+struct SampleRing {
+    buf: Vec<AtomicU32>, // f32のビット列
+    head: AtomicUsize,   // 次に読む位置 (オーディオコールバックだけが進める)
+    tail: AtomicUsize,   // 次に書く位置 (エミュレーションだけが進める)
+}
+
+impl SampleRing {
+    fn new(capacity: usize) -> Self {
+        // 満杯と空を区別するため1つ余分に確保する
+        SampleRing {
+            buf: (0..capacity + 1).map(|_| AtomicU32::new(0)).collect(),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (tail + self.buf.len() - head) % self.buf.len()
+    }
+
+    // 満杯なら捨てて false を返す
+    fn push(&self, sample: f32) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % self.buf.len();
+        if next == self.head.load(Ordering::Acquire) {
+            return false;
+        }
+        self.buf[tail].store(sample.to_bits(), Ordering::Relaxed);
+        self.tail.store(next, Ordering::Release);
+        true
+    }
+
+    fn pop(&self) -> Option<f32> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let sample = f32::from_bits(self.buf[head].load(Ordering::Relaxed));
+        self.head.store((head + 1) % self.buf.len(), Ordering::Release);
+        Some(sample)
+    }
+}
+
+// 音声の出力先 (SDLのオーディオデバイスなど、フロントエンド側で実装する)
+// APU はリングバッファに書くだけで、出力先は渡された AudioStream を自分のスレッド (オーディオコールバック) で読む
+pub trait AudioSink {
+    // デバイスを開いて、実際に開けた (サンプルレート, バッファのサンプル数) を返す
+    fn open(&mut self, config: &AudioConfig, stream: AudioStream) -> Result<(i32, u16), String>;
+}
+
+// リングバッファの読み出し側 (オーディオコールバックから fill を呼ぶ)
+pub struct AudioStream {
+    ring: Arc<SampleRing>,
+    underruns: Arc<AtomicUsize>,
+    started: bool,
+    last: f32,
+}
+
+impl AudioStream {
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let mut starved = false;
+        for x in out.iter_mut() {
+            match self.ring.pop() {
+                Some(sample) => {
+                    self.started = true;
+                    self.last = sample;
+                }
+                // 足りない分は直前の値で埋める (プチノイズ防止)
+                None => starved = true,
+            }
+            *x = self.last;
+        }
+
+        // エミュレーション開始前はカウントしない
+        if starved && self.started {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApuChannel {
+    Square1,
+    Square2,
+    Triangle,
+    Noise,
+}
+
+// デバッガ表示用のチャンネルの状態 (書き込まれたレジスタの値)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStatus {
+    pub enabled: bool, // $4015
+    pub period: u16,
+    pub volume: u8, // 三角波は0
+    pub duty: u8,   // 矩形波のみ
+    pub length: u8, // 長さカウンタのインデックス
+}
+
+// 波形表示用のリングバッファ (APUが書き込む)
+pub struct ChannelScope {
+    ring: Mutex<(Vec<f32>, usize)>,
+}
+
+impl ChannelScope {
+    fn new(len: usize) -> Self {
+        ChannelScope {
+            ring: Mutex::new((vec![0.0; len.max(1)], 0)),
+        }
+    }
+
+    fn push(&self, sample: f32) {
+        // 表示側が読んでいる間はエミュレーションを止めないよう捨てる
+        if let Some(mut ring) = self.ring.try_lock() {
+            let (buf, pos) = &mut *ring;
+            buf[*pos] = sample;
+            *pos = (*pos + 1) % buf.len();
+        }
+    }
+
+    // 古い順に並べたサンプル
+    pub fn samples(&self) -> Vec<f32> {
+        let ring = self.ring.lock();
+        let (buf, pos) = &*ring;
+        let mut out = buf[*pos..].to_vec();
+        out.extend_from_slice(&buf[..*pos]);
+        out
+    }
+}
+
+// オシロスコープ表示 (NSFPlay/Mesen風) 向けに各チャンネルの直近の出力を見せる
+pub struct Oscilloscope {
+    channels: [Arc<ChannelScope>; 4],
+}
+
+impl Oscilloscope {
+    pub fn samples(&self, ch: ApuChannel) -> Vec<f32> {
+        self.channels[ch as usize].samples()
+    }
+}
+
 #[allow(dead_code)]
 pub struct APU {
     ch1_register: Ch1Register,
@@ -63,29 +309,76 @@ pub struct APU {
     ch4_register: Ch4Register,
     frame_counter: FrameCounter,
     status: StatusRegister,
-    cycles: usize,
-    counter: usize,
-
-    ch1_device: AudioDevice<SquareWave>,
-    ch1_sender: Sender<SquareEvent>,
-
-    ch2_device: AudioDevice<SquareWave>,
-    ch2_sender: Sender<SquareEvent>,
-
-    ch3_device: AudioDevice<TriangleWave>,
-    ch3_sender: Sender<TriangleEvent>,
-
-    ch4_device: AudioDevice<NoiseWave>,
-    ch4_sender: Sender<NoiseEvent>,
+    sequencer: FrameSequencer,
+    frame_reset_delay: u8,
+
+    ch1: SquareWave,
+    ch2: SquareWave,
+    ch3: TriangleWave,
+    ch4: NoiseWave,
+
+    has_output: bool, // デバイスがリングバッファを読んでいる (ヘッドレスならfalse)
+    buffer_samples: u16,
+    ring: Arc<SampleRing>,
+    underruns: Arc<AtomicUsize>,
+    sample_rate: f32,
+    sample_clock: f32,   // 次のサンプルまでのCPUサイクル
+    target_fill: usize,  // リングバッファに溜めておきたいサンプル数
+    dynamic_rate: bool,
+    max_rate_delta: f32,
+    rate_ratio: f32,
+    scope: Option<[Arc<ChannelScope>; 4]>,
+    expansion: f32, // カートリッジの拡張音源
+    region: Region,
+
+    total_cycles: u64,
+    vgm: Option<VgmLogger>,
+    recording: Option<Vec<f32>>, // 動画の書き出し用に出力を横取りする
 }
 
 impl APU {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
-        let (ch1_device, ch1_sender) = init_square(&sdl_context);
-        let (ch2_device, ch2_sender) = init_square(&sdl_context);
-        let (ch3_device, ch3_sender) = init_triangle(&sdl_context);
-        let (ch4_device, ch4_sender) = init_noise(&sdl_context);
+    // デバイスは sink 側で持つので、エミュレーションは別のスレッドで動かせる
+    pub fn with_sink(sink: &mut dyn AudioSink, config: &AudioConfig) -> Result<Self, String> {
+        let capacity = (config.sample_rate as f32 * RING_SECONDS) as usize;
+        let ring = Arc::new(SampleRing::new(capacity.max(4 * config.buffer_samples as usize)));
+        let underruns = Arc::new(AtomicUsize::new(0));
+        let stream = AudioStream {
+            ring: Arc::clone(&ring),
+            underruns: Arc::clone(&underruns),
+            started: false,
+            last: 0.0,
+        };
+        let (sample_rate, buffer_samples) = sink.open(config, stream)?;
+        Ok(APU::build(true, ring, underruns, sample_rate, buffer_samples, config))
+    }
 
+    // オーディオデバイスを開かない (テストやスクリプトからの実行用)
+    // 生成したサンプルは drain_samples で取り出す
+    pub fn headless(config: &AudioConfig) -> Self {
+        let capacity = (config.sample_rate as f32 * RING_SECONDS) as usize;
+        let ring = Arc::new(SampleRing::new(capacity));
+        let config = AudioConfig {
+            dynamic_rate: false,
+            ..config.clone()
+        };
+        APU::build(
+            false,
+            ring,
+            Arc::new(AtomicUsize::new(0)),
+            config.sample_rate,
+            config.buffer_samples,
+            &config,
+        )
+    }
+
+    fn build(
+        has_output: bool,
+        ring: Arc<SampleRing>,
+        underruns: Arc<AtomicUsize>,
+        sample_rate: i32,
+        buffer_samples: u16,
+        config: &AudioConfig,
+    ) -> Self {
         APU {
             ch1_register: Ch1Register::new(),
             ch2_register: Ch2Register::new(),
@@ -93,280 +386,489 @@ impl APU {
             ch4_register: Ch4Register::new(),
             frame_counter: FrameCounter::new(),
             status: StatusRegister::new(),
-            cycles: 0,
-            counter: 0,
+            sequencer: FrameSequencer::new(),
+            frame_reset_delay: 0,
+
+            ch1: SquareWave::new(),
+            ch2: SquareWave::new(),
+            ch3: TriangleWave::new(),
+            ch4: NoiseWave::new(),
+
+            has_output,
+            buffer_samples,
+            ring,
+            underruns,
+            sample_rate: sample_rate as f32,
+            sample_clock: 0.0,
+            // バッファ2つ分を目標にする
+            target_fill: 2 * buffer_samples as usize,
+            dynamic_rate: config.dynamic_rate,
+            max_rate_delta: config.max_rate_delta,
+            rate_ratio: 1.0,
+            scope: None,
+            expansion: 0.0,
+            region: Region::Ntsc,
+
+            total_cycles: 0,
+            vgm: None,
+            recording: None,
+        }
+    }
 
-            ch1_device: ch1_device,
-            ch1_sender: ch1_sender,
+    // リセットボタン: $4015に0を書いたのと同じ状態にし、$4017は直前の値を書き直す
+    pub fn reset(&mut self) {
+        self.write_status(0);
+        self.status.remove(StatusRegister::ENABLE_FRAME_IRQ);
+        let frame_counter = self.frame_counter.bits();
+        self.write_frame_counter(frame_counter);
+    }
 
-            ch2_device: ch2_device,
-            ch2_sender: ch2_sender,
+    // 電源の入れ直し: レジスタとチャンネルを初期状態に戻す (オーディオデバイスはそのまま)
+    pub fn power_on(&mut self) {
+        self.ch1_register = Ch1Register::new();
+        self.ch2_register = Ch2Register::new();
+        self.ch3_register = Ch3Register::new();
+        self.ch4_register = Ch4Register::new();
+        self.frame_counter = FrameCounter::new();
+        self.status = StatusRegister::new();
+        self.sequencer = FrameSequencer::new();
+        self.frame_reset_delay = 0;
+        self.ch1 = SquareWave::new();
+        self.ch2 = SquareWave::new();
+        self.ch3 = TriangleWave::new();
+        self.ch4 = NoiseWave::new();
+        self.expansion = 0.0;
+    }
 
-            ch3_device: ch3_device,
-            ch3_sender: ch3_sender,
+    // 電源投入時のフレームシーケンサの位相 (power_on の後に呼ぶ)
+    pub fn set_power_on_phase(&mut self, cycles: usize) {
+        self.sequencer.cycles = cycles;
+    }
+
+    // CPUクロック, ノイズの周期表, フレームシーケンサのタイミングを切り替える
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
 
-            ch4_device: ch4_device,
-            ch4_sender: ch4_sender,
+    fn frame_timing(&self) -> &'static FrameTiming {
+        if self.region.is_pal_apu() {
+            &PAL_FRAME_TIMING
+        } else {
+            &NTSC_FRAME_TIMING
+        }
+    }
+
+    // VGMログ記録開始 (以降のレジスタ書き込みを記録)
+    pub fn start_vgm_log(&mut self) {
+        self.vgm = Some(VgmLogger::new(self.total_cycles));
+    }
+
+    // VGMログ記録終了 (記録中でなければ None)
+    pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+        let cycle = self.total_cycles;
+        self.vgm.take().map(|mut vgm| vgm.finish(cycle))
+    }
+
+    pub fn is_vgm_logging(&self) -> bool {
+        self.vgm.is_some()
+    }
+
+    // 出力したサンプルを記録する (動画の書き出し用)
+    // 記録中は速度調整をせず、エミュレーター上の1秒がちょうど sample_rate サンプルになる
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+        self.rate_ratio = 1.0;
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    // 前回から記録したサンプル
+    pub fn take_recorded_samples(&mut self) -> Vec<f32> {
+        self.recording.as_mut().map_or(vec![], core::mem::take)
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    fn log_write(&mut self, addr: u16, value: u8) {
+        if let Some(vgm) = &mut self.vgm {
+            vgm.write_apu(self.total_cycles, addr, value);
+        }
+    }
+
+    // 波形表示を有効にする (detach_scope するまで各chが len サンプル分を保持)
+    pub fn attach_scope(&mut self, len: usize) -> Oscilloscope {
+        let scope = Oscilloscope {
+            channels: [
+                Arc::new(ChannelScope::new(len)),
+                Arc::new(ChannelScope::new(len)),
+                Arc::new(ChannelScope::new(len)),
+                Arc::new(ChannelScope::new(len)),
+            ],
+        };
+        self.scope = Some(scope.channels.clone());
+        scope
+    }
+
+    pub fn detach_scope(&mut self) {
+        self.scope = None;
+    }
+
+    pub fn stats(&self) -> AudioStats {
+        AudioStats {
+            sample_rate: self.sample_rate as i32,
+            buffer_samples: self.buffer_samples,
+            latency_ms: self.buffer_samples as f32 * 1000.0 / self.sample_rate,
+            underruns: self.underruns.load(Ordering::Relaxed),
+            fill_ms: self.ring.len() as f32 * 1000.0 / self.sample_rate,
+            rate_ratio: self.rate_ratio,
         }
     }
 
     pub fn write1ch(&mut self, addr: u16, value: u8) {
+        self.log_write(addr, value);
         self.ch1_register.write(addr, value);
 
-            self.ch1_sender
-            .send(SquareEvent::Note(SquareNote {
-                duty: self.ch1_register.duty,
-            }))
-                .unwrap();
-
-        self.ch1_sender
-                .send(SquareEvent::Envelope(Envelope::new(
-                self.ch1_register.volume,
-                    self.ch1_register.envelope_flag,
-                    !self.ch1_register.key_off_counter_flag,
-                )))
-            .unwrap();
-
-        self.ch1_sender
-            .send(SquareEvent::LengthCounter(LengthCounter::new(
-                self.ch1_register.key_off_counter_flag,
-                LENGTH_COUNTER_TBL[self.ch1_register.key_off_count as usize],
-            )))
-            .unwrap();
-
-        self.ch1_sender
-            .send(SquareEvent::Sweep(Sweep::new(
-                self.ch1_register.frequency,
-                self.ch1_register.sweep_change_amount,
-                self.ch1_register.sweep_direction,
-                self.ch1_register.sweep_timer_count,
-                self.ch1_register.sweep_enabled,
-            )))
-            .unwrap();
+        self.ch1.note = SquareNote {
+            duty: self.ch1_register.duty,
+        };
+        self.ch1.envelope = Envelope::new(
+            self.ch1_register.volume,
+            self.ch1_register.envelope_flag,
+            !self.ch1_register.key_off_counter_flag,
+        );
+        self.ch1.length_counter = LengthCounter::new(
+            self.ch1_register.key_off_counter_flag,
+            LENGTH_COUNTER_TBL[self.ch1_register.key_off_count as usize],
+        );
+        self.ch1.sweep = Sweep::new(
+            self.ch1_register.frequency,
+            self.ch1_register.sweep_change_amount,
+            self.ch1_register.sweep_direction,
+            self.ch1_register.sweep_timer_count,
+            self.ch1_register.sweep_enabled,
+        );
 
         if addr == 0x4003 {
-            self.ch1_sender.send(SquareEvent::Reset()).unwrap();
+            self.ch1.reset();
         }
     }
 
     pub fn write2ch(&mut self, addr: u16, value: u8) {
+        self.log_write(addr, value);
         self.ch2_register.write(addr, value);
 
-            self.ch2_sender
-            .send(SquareEvent::Note(SquareNote {
-                duty: self.ch2_register.duty,
-            }))
-                .unwrap();
-
-        self.ch2_sender
-                .send(SquareEvent::Envelope(Envelope::new(
-                    self.ch2_register.volume,
-                    self.ch2_register.envelope_flag,
-                    !self.ch2_register.key_off_counter_flag,
-                )))
-            .unwrap();
-
-        self.ch2_sender
-            .send(SquareEvent::LengthCounter(LengthCounter::new(
-                self.ch2_register.key_off_counter_flag,
-                LENGTH_COUNTER_TBL[self.ch2_register.key_off_count as usize],
-            )))
-            .unwrap();
-
-        self.ch2_sender
-            .send(SquareEvent::Sweep(Sweep::new(
-                self.ch2_register.frequency,
-                self.ch2_register.sweep_change_amount,
-                self.ch2_register.sweep_direction,
-                self.ch2_register.sweep_timer_count,
-                self.ch2_register.sweep_enabled,
-            )))
-            .unwrap();
+        self.ch2.note = SquareNote {
+            duty: self.ch2_register.duty,
+        };
+        self.ch2.envelope = Envelope::new(
+            self.ch2_register.volume,
+            self.ch2_register.envelope_flag,
+            !self.ch2_register.key_off_counter_flag,
+        );
+        self.ch2.length_counter = LengthCounter::new(
+            self.ch2_register.key_off_counter_flag,
+            LENGTH_COUNTER_TBL[self.ch2_register.key_off_count as usize],
+        );
+        self.ch2.sweep = Sweep::new(
+            self.ch2_register.frequency,
+            self.ch2_register.sweep_change_amount,
+            self.ch2_register.sweep_direction,
+            self.ch2_register.sweep_timer_count,
+            self.ch2_register.sweep_enabled,
+        );
 
         if addr == 0x4007 {
-            self.ch2_sender.send(SquareEvent::Reset()).unwrap();
+            self.ch2.reset();
         }
     }
 
     pub fn write3ch(&mut self, addr: u16, value: u8) {
+        self.log_write(addr, value);
         self.ch3_register.write(addr, value);
 
-        self.ch3_sender
-            .send(TriangleEvent::Note(TriangleNote {
-                frequency: self.ch2_register.frequency,
-            }))
-            .unwrap();
-
-        self.ch3_sender
-            .send(TriangleEvent::LengthCounter(LengthCounter::new(
-                self.ch3_register.key_off_counter_flag,
-                LENGTH_COUNTER_TBL[self.ch3_register.key_off_count as usize],
-            )))
-            .unwrap();
+        self.ch3.note = TriangleNote {
+            frequency: self.ch3_register.frequency,
+        };
+        self.ch3.length_counter = LengthCounter::new(
+            self.ch3_register.key_off_counter_flag,
+            LENGTH_COUNTER_TBL[self.ch3_register.key_off_count as usize],
+        );
 
         if addr == 0x400B {
-            self.ch3_sender.send(TriangleEvent::Reset()).unwrap();
+            self.ch3.length_counter.reset();
         }
     }
 
     pub fn write4ch(&mut self, addr: u16, value: u8) {
+        self.log_write(addr, value);
         self.ch4_register.write(addr, value);
 
-        let hz = CPU_CLOCK / NOISE_TBL[self.ch4_register.frequency as usize];
+        let noise_tbl: &[f32] = if self.region.is_pal_apu() { &NOISE_TBL_PAL } else { &NOISE_TBL };
+        let hz = CPU_CLOCK / noise_tbl[self.ch4_register.frequency as usize];
         let is_long = match self.ch4_register.kind {
             NoiseKind::Long => true,
             _ => false,
         };
         let volume = (self.ch4_register.volume as f32) / 15.0;
 
-        self.ch4_sender
-            .send(NoiseEvent::Note(NoiseNote {
-                hz: hz,
-                is_long: is_long,
-                volume: volume,
-            }))
-            .unwrap();
-
-        self.ch4_sender
-            .send(NoiseEvent::Envelope(Envelope::new(
-                self.ch4_register.volume,
-                self.ch4_register.envelope_flag,
-                !self.ch4_register.key_off_counter_flag,
-            )))
-            .unwrap();
-
-        self.ch4_sender
-            .send(NoiseEvent::LengthCounter(LengthCounter::new(
-                self.ch4_register.key_off_counter_flag,
-                LENGTH_COUNTER_TBL[self.ch4_register.key_off_count as usize],
-            )))
-            .unwrap();
+        self.ch4.note = NoiseNote {
+            hz: hz,
+            is_long: is_long,
+            volume: volume,
+        };
+        self.ch4.envelope = Envelope::new(
+            self.ch4_register.volume,
+            self.ch4_register.envelope_flag,
+            !self.ch4_register.key_off_counter_flag,
+        );
+        self.ch4.length_counter = LengthCounter::new(
+            self.ch4_register.key_off_counter_flag,
+            LENGTH_COUNTER_TBL[self.ch4_register.key_off_count as usize],
+        );
 
         if addr == 0x400F {
-            self.ch4_sender.send(NoiseEvent::Reset()).unwrap();
+            self.ch4.envelope.reset();
+            self.ch4.length_counter.reset();
         }
     }
 
+    pub fn write5ch(&mut self, addr: u16, value: u8) {
+        self.log_write(addr, value);
+        // TODO DMCch
+    }
+
     pub fn read_status(&mut self) -> u8 {
         let res = self.status.bits();
         self.status.remove(StatusRegister::ENABLE_FRAME_IRQ);
         res
     }
 
+    // フレームIRQのフラグを消さずに読む (デバッガ用)
+    pub fn peek_status(&self) -> u8 {
+        self.status.bits()
+    }
+
     pub fn write_status(&mut self, data: u8) {
+        self.log_write(0x4015, data);
         self.status.update(data);
 
-        self.ch1_sender
-            .send(SquareEvent::Enable(
-                self.status.contains(StatusRegister::ENABLE_1CH),
-            ))
-            .unwrap();
-
-        self.ch2_sender
-            .send(SquareEvent::Enable(
-                self.status.contains(StatusRegister::ENABLE_2CH),
-            ))
-            .unwrap();
-
-        self.ch3_sender
-            .send(TriangleEvent::Enable(
-                self.status.contains(StatusRegister::ENABLE_3CH),
-            ))
-            .unwrap();
+        self.ch1.enabled = self.status.contains(StatusRegister::ENABLE_1CH);
+        self.ch2.enabled = self.status.contains(StatusRegister::ENABLE_2CH);
+        self.ch3.enabled = self.status.contains(StatusRegister::ENABLE_3CH);
+        self.ch4.enabled = self.status.contains(StatusRegister::ENABLE_4CH);
+        // 止めたチャンネルは長さカウンタが0になり、次のキーオンまで鳴らない
+        // https://www.nesdev.org/wiki/APU#Status_($4015)
+        if !self.ch1.enabled {
+            self.ch1.length_counter.clear();
+        }
+        if !self.ch2.enabled {
+            self.ch2.length_counter.clear();
+        }
+        if !self.ch3.enabled {
+            self.ch3.length_counter.clear();
+        }
+        if !self.ch4.enabled {
+            self.ch4.length_counter.clear();
+        }
+    }
 
-        self.ch4_sender
-            .send(NoiseEvent::Enable(
-                self.status.contains(StatusRegister::ENABLE_4CH),
-            ))
-            .unwrap();
+    pub fn channel_status(&self, ch: ApuChannel) -> ChannelStatus {
+        let enabled = self.status.bits() & (1 << ch as u8) != 0;
+        match ch {
+            ApuChannel::Square1 => ChannelStatus {
+                enabled,
+                period: self.ch1_register.frequency,
+                volume: self.ch1_register.volume,
+                duty: self.ch1_register.duty,
+                length: self.ch1_register.key_off_count,
+            },
+            ApuChannel::Square2 => ChannelStatus {
+                enabled,
+                period: self.ch2_register.frequency,
+                volume: self.ch2_register.volume,
+                duty: self.ch2_register.duty,
+                length: self.ch2_register.key_off_count,
+            },
+            ApuChannel::Triangle => ChannelStatus {
+                enabled,
+                period: self.ch3_register.frequency,
+                volume: 0,
+                duty: 0,
+                length: self.ch3_register.key_off_count,
+            },
+            ApuChannel::Noise => ChannelStatus {
+                enabled,
+                period: self.ch4_register.frequency as u16,
+                volume: self.ch4_register.volume,
+                duty: 0,
+                length: self.ch4_register.key_off_count,
+            },
+        }
     }
 
     pub fn irq(&self) -> bool {
         self.status.contains(StatusRegister::ENABLE_FRAME_IRQ)
     }
 
+    pub fn snapshot(&self) -> ApuSnapshot {
+        ApuSnapshot {
+            channels: [
+                self.channel_status(ApuChannel::Square1),
+                self.channel_status(ApuChannel::Square2),
+                self.channel_status(ApuChannel::Triangle),
+                self.channel_status(ApuChannel::Noise),
+            ],
+            status: self.status.bits(),
+            frame_counter: self.frame_counter.bits(),
+            sequencer_mode: self.frame_counter.mode(),
+            sequencer_cycles: self.sequencer.cycles,
+            frame_irq: self.irq(),
+            cycles: self.total_cycles,
+            expansion: self.expansion,
+        }
+    }
+
     pub fn write_frame_counter(&mut self, value: u8) {
+        self.log_write(0x4017, value);
         self.frame_counter.update(value);
-        self.cycles = 0;
-        self.counter = 0;
-    }
 
-    pub fn tick(&mut self, cycles: u8) {
-        self.cycles += cycles as usize;
+        if self.frame_counter.contains(FrameCounter::DISABLE_IRQ) {
+            self.status.remove(StatusRegister::ENABLE_FRAME_IRQ);
+        }
+        // シーケンサのリセットは、書き込みが偶数サイクルなら3サイクル後、
+        // 奇数サイクルなら4サイクル後に反映される
+        self.frame_reset_delay = if self.is_odd_cycle() { 4 } else { 3 };
+    }
 
-        let interval = 7457;
-        if self.cycles >= interval {
-            self.cycles -= interval;
-            self.counter += 1;
+    fn is_odd_cycle(&self) -> bool {
+        self.total_cycles & 1 == 1
+    }
 
-        match self.frame_counter.mode() {
-            4 => {
-                    // - - - f      60 Hz
-                    // - l - l     120 Hz
-                    // e e e e     240 Hz
-                if self.counter == 2 || self.counter == 4 {
-                        // 長さカウンタとスイープユニットのクロック生成
-                        self.send_length_counter_tick();
-                        self.send_sweep_tick();
-                }
-                if self.counter == 4 {
-                    // 割り込みフラグセット
-                    self.counter = 0;
-                    self.status.insert(StatusRegister::ENABLE_FRAME_IRQ);
+    // CPUの1サイクルごとに呼ぶ
+    pub fn tick(&mut self) {
+        self.total_cycles += 1;
+
+        if self.frame_reset_delay > 0 {
+            self.frame_reset_delay -= 1;
+            if self.frame_reset_delay == 0 {
+                self.sequencer.reset();
+                // 5ステップモードにすると、すぐに全ユニットをクロックする
+                if self.frame_counter.mode() == 5 {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
                 }
-                    // エンベロープと三角波の線形カウンタのクロック生成
-                    self.send_envelope_tick();
-                }
-                5 => {
-                    // - - - - -   (割り込みフラグはセットしない)
-                    // l - l - -    96 Hz
-                    // e e e e -   192 Hz
-
-                    if self.counter == 1 || self.counter == 3 {
-                        // 長さカウンタとスイープユニットのクロック生成
-                        self.send_length_counter_tick();
-                        self.send_sweep_tick();
-                    }
-                    if self.counter <= 4 {
-                        // エンベロープと三角波の線形カウンタのクロック生成
-                        self.send_envelope_tick();
-                    }
-                    if self.counter == 5 {
-                        self.counter = 0;
-                    }
-                }
-            _ => panic!("can't be"),
+            }
+        }
+
+        let clock = self.sequencer.step(self.frame_counter.mode(), self.frame_timing());
+        if clock.quarter {
+            self.clock_quarter_frame();
+        }
+        if clock.half {
+            self.clock_half_frame();
+        }
+        if clock.irq && !self.frame_counter.contains(FrameCounter::DISABLE_IRQ) {
+            // 割り込みフラグセット
+            self.status.insert(StatusRegister::ENABLE_FRAME_IRQ);
+        }
+
+        self.sample_clock -= 1.0;
+        if self.sample_clock <= 0.0 {
+            self.output_sample();
         }
     }
+
+    // エンベロープと三角波の線形カウンタのクロック生成
+    fn clock_quarter_frame(&mut self) {
+        self.ch1.envelope.tick();
+        self.ch2.envelope.tick();
+        self.ch4.envelope.tick();
+    }
+
+    // 長さカウンタとスイープユニットのクロック生成
+    fn clock_half_frame(&mut self) {
+        self.ch1.length_counter.tick();
+        self.ch2.length_counter.tick();
+        self.ch3.length_counter.tick();
+        self.ch4.length_counter.tick();
+        self.ch1.sweep.tick();
+        self.ch2.sweep.tick();
     }
 
-    fn send_envelope_tick(&self) {
-        self.ch1_sender.send(SquareEvent::EnvelopeTick()).unwrap();
-        self.ch2_sender.send(SquareEvent::EnvelopeTick()).unwrap();
-        self.ch4_sender.send(NoiseEvent::EnvelopeTick()).unwrap();
+    // 1サンプル合成してリングバッファに積む
+    fn output_sample(&mut self) {
+        // リングバッファが目標より溜まっていれば少しゆっくり、減っていれば少し速く生成する
+        if self.dynamic_rate && self.recording.is_none() {
+            let target = self.target_fill as f32;
+            let error = ((self.ring.len() as f32 - target) / target).clamp(-1.0, 1.0);
+            self.rate_ratio = 1.0 - error * self.max_rate_delta;
+        }
+        let rate = self.sample_rate * self.rate_ratio;
+        let cpu_clock = self.region.cpu_clock();
+        self.sample_clock += cpu_clock / rate;
+
+        let step = cpu_clock / CPU_CLOCK / rate;
+        let ch1 = self.ch1.sample(step);
+        let ch2 = self.ch2.sample(step);
+        let ch3 = self.ch3.sample(step);
+        let ch4 = self.ch4.sample(step);
+
+        if let Some(scope) = &self.scope {
+            scope[ApuChannel::Square1 as usize].push(ch1);
+            scope[ApuChannel::Square2 as usize].push(ch2);
+            scope[ApuChannel::Triangle as usize].push(ch3);
+            scope[ApuChannel::Noise as usize].push(ch4);
+        }
+
+        let sample = ch1 + ch2 + ch3 + ch4 + self.expansion;
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(sample);
+        }
+        // 溢れた分は捨てる (オーディオ側が止まっている)
+        self.ring.push(sample);
     }
 
-    fn send_length_counter_tick(&self) {
-        self.ch1_sender
-            .send(SquareEvent::LengthCounterTick())
-            .unwrap();
-        self.ch2_sender
-            .send(SquareEvent::LengthCounterTick())
-            .unwrap();
-        self.ch3_sender
-            .send(TriangleEvent::LengthCounterTick())
-            .unwrap();
-        self.ch4_sender
-            .send(NoiseEvent::LengthCounterTick())
-            .unwrap();
+    // 溜まっているサンプルを取り出す (ヘッドレス用。デバイスがあるとオーディオ側と取り合いになる)
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        if self.has_output {
+            return;
+        }
+        while let Some(sample) = self.ring.pop() {
+            out.push(sample);
+        }
     }
 
-    fn send_sweep_tick(&self) {
-        self.ch1_sender.send(SquareEvent::SweepTick()).unwrap();
-        self.ch2_sender.send(SquareEvent::SweepTick()).unwrap();
+    // 拡張音源の出力を設定する (tick の前に毎サイクル呼ぶ)
+    pub fn set_expansion_audio(&mut self, value: f32) {
+        self.expansion = value;
     }
 }
 
+// ステートセーブにはチャンネルとシーケンサの状態だけを含める
+// (オーディオデバイスやリングバッファ、レート補正はそのまま)
+impl_state!(APU {
+    ch1_register,
+    ch2_register,
+    ch3_register,
+    ch4_register,
+    frame_counter,
+    status,
+    sequencer,
+    frame_reset_delay,
+    ch1,
+    ch2,
+    ch3,
+    ch4,
+    sample_clock,
+    expansion,
+    total_cycles,
+});
+impl_state_bits!(StatusRegister, FrameCounter);
+
 struct Ch1Register {
     volume: u8,
     envelope_flag: bool,
@@ -383,6 +885,8 @@ struct Ch1Register {
     key_off_count: u8,
 }
 
+impl_state!(Ch1Register { volume, envelope_flag, key_off_counter_flag, duty, sweep_change_amount, sweep_direction, sweep_timer_count, sweep_enabled, frequency, key_off_count });
+
 impl Ch1Register {
     pub fn new() -> Self {
         Ch1Register {
@@ -444,6 +948,8 @@ struct Ch2Register {
     key_off_count: u8,
 }
 
+impl_state!(Ch2Register { volume, envelope_flag, key_off_counter_flag, duty, sweep_change_amount, sweep_direction, sweep_timer_count, sweep_enabled, frequency, key_off_count });
+
 impl Ch2Register {
     pub fn new() -> Self {
         Ch2Register {
@@ -499,6 +1005,8 @@ struct Ch3Register {
     key_off_count: u8,
 }
 
+impl_state!(Ch3Register { length, key_off_counter_flag, frequency, key_off_count });
+
 impl Ch3Register {
     pub fn new() -> Self {
         Ch3Register {
@@ -547,6 +1055,20 @@ struct Ch4Register {
     key_off_count: u8,
 }
 
+impl State for NoiseKind {
+    fn save(&self, w: &mut StateWriter) {
+        matches!(self, NoiseKind::Short).save(w);
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut short = false;
+        short.load(r)?;
+        *self = if short { NoiseKind::Short } else { NoiseKind::Long };
+        Ok(())
+    }
+}
+
+impl_state!(Ch4Register { volume, envelope_flag, key_off_counter_flag, frequency, kind, key_off_count });
+
 impl Ch4Register {
     pub fn new() -> Self {
         Ch4Register {
@@ -591,6 +1113,8 @@ struct Envelope {
     division_period: u8,
 }
 
+impl_state!(Envelope { rate, enabled, loop_flag, counter, division_period });
+
 impl Envelope {
     fn new(rate: u8, enabled: bool, loop_flag: bool) -> Self {
         Envelope {
@@ -641,6 +1165,8 @@ struct LengthCounter {
     counter: u8,
 }
 
+impl_state!(LengthCounter { enabled, count, counter });
+
 impl LengthCounter {
     fn new(enabled: bool, counter: u8) -> Self {
         LengthCounter {
@@ -666,6 +1192,12 @@ impl LengthCounter {
     fn reset(&mut self) {
         self.counter = self.count;
     }
+
+    // $4015 でチャンネルを止めたとき (停止フラグに関係なく鳴らなくなる)
+    fn clear(&mut self) {
+        self.enabled = true;
+        self.counter = 0;
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -679,6 +1211,8 @@ struct Sweep {
     counter: u8,
 }
 
+impl_state!(Sweep { org_freq, frequency, change_amount, direction, timer_count, enabled, counter });
+
 impl Sweep {
     fn new(
         frequency: u16,
@@ -741,24 +1275,13 @@ impl Sweep {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum SquareEvent {
-    Note(SquareNote),
-    Enable(bool),
-    Envelope(Envelope),
-    EnvelopeTick(),
-    LengthCounter(LengthCounter),
-    LengthCounterTick(),
-    Sweep(Sweep),
-    SweepTick(),
-    Reset(),
-}
-
 #[derive(Debug, Clone, PartialEq)]
 struct SquareNote {
     duty: u8,
 }
 
+impl_state!(SquareNote { duty });
+
 impl SquareNote {
     fn new() -> Self {
         SquareNote { duty: 0 }
@@ -776,9 +1299,7 @@ impl SquareNote {
 }
 
 struct SquareWave {
-    freq: f32,
     phase: f32,
-    receiver: Receiver<SquareEvent>,
     enabled: bool,
     note: SquareNote,
     envelope: Envelope,
@@ -786,93 +1307,56 @@ struct SquareWave {
     sweep: Sweep,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+impl_state!(SquareWave { phase, enabled, note, envelope, length_counter, sweep });
 
-    fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            loop {
-            let res = self.receiver.recv_timeout(Duration::from_millis(0));
-            match res {
-                Ok(SquareEvent::Note(note)) => self.note = note,
-                Ok(SquareEvent::Envelope(e)) => self.envelope = e,
-                Ok(SquareEvent::EnvelopeTick()) => self.envelope.tick(),
-                Ok(SquareEvent::Enable(b)) => self.enabled = b,
-                    Ok(SquareEvent::LengthCounter(l)) => self.length_counter = l,
-                    Ok(SquareEvent::LengthCounterTick()) => self.length_counter.tick(),
-                    Ok(SquareEvent::Sweep(s)) => self.sweep = s,
-                    Ok(SquareEvent::SweepTick()) => self.sweep.tick(),
-                    Ok(SquareEvent::Reset()) => {
-                        self.envelope.reset();
-                        self.length_counter.reset();
-                        self.sweep.reset();
-                    }
-                    Err(_) => break,
-                }
-            }
-            *x = if self.phase <= self.note.duty() {
-                self.envelope.volume()
-            } else {
-                -self.envelope.volume()
-            } * MASTER_VOLUME;
-
-            if self.length_counter.mute() {
-                *x = 0.0;
-            }
-
-            if !self.enabled {
-                *x = 0.0;
-            }
-            let hz = self.sweep.hz();
-            if hz != 0.0 {
-                self.phase = (self.phase + hz / self.freq) % 1.0;
-            }
-        }
-    }
-}
-
-fn init_square(sdl_context: &sdl2::Sdl) -> (AudioDevice<SquareWave>, Sender<SquareEvent>) {
-    let audio_subsystem = sdl_context.audio().unwrap();
-
-    let (sender, receiver) = channel::<SquareEvent>();
-
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),
-        samples: None,
-    };
-
-    let device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| SquareWave {
-            freq: spec.freq as f32,
+impl SquareWave {
+    fn new() -> Self {
+        SquareWave {
             phase: 0.0,
-            receiver: receiver,
             enabled: true,
             note: SquareNote::new(),
             envelope: Envelope::new(0, false, false),
             length_counter: LengthCounter::new(false, 0),
             sweep: Sweep::new(0, 0, 0, 0, false),
-        })
-        .unwrap();
+        }
+    }
 
-    device.resume();
+    fn reset(&mut self) {
+        self.envelope.reset();
+        self.length_counter.reset();
+        self.sweep.reset();
+    }
 
-    (device, sender)
-}
+    // step: 1サンプルの長さ(秒)
+    fn sample(&mut self, step: f32) -> f32 {
+        let mut x = if self.phase <= self.note.duty() {
+            self.envelope.volume()
+        } else {
+            -self.envelope.volume()
+        } * MASTER_VOLUME;
 
-#[derive(Debug, Clone, PartialEq)]
-enum TriangleEvent {
-    Note(TriangleNote),
-    Enable(bool),
-    LengthCounter(LengthCounter),
-    LengthCounterTick(),
-    Reset(),
+        if self.length_counter.mute() {
+            x = 0.0;
+        }
+
+        if !self.enabled {
+            x = 0.0;
+        }
+        let hz = self.sweep.hz();
+        if hz != 0.0 {
+            self.phase = (self.phase + hz * step) % 1.0;
+        }
+        x
+    }
 }
+
 #[derive(Debug, Clone, PartialEq)]
 struct TriangleNote {
     frequency: u16,
 }
 
+impl_state!(TriangleNote { frequency });
+
 impl TriangleNote {
     fn new() -> Self {
         TriangleNote { frequency: 0 }
@@ -884,88 +1368,45 @@ impl TriangleNote {
 }
 
 struct TriangleWave {
-    freq: f32,
     phase: f32,
-    receiver: Receiver<TriangleEvent>,
-
     enabled: bool,
     note: TriangleNote,
     length_counter: LengthCounter,
 }
 
-impl AudioCallback for TriangleWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            loop {
-            let res = self.receiver.recv_timeout(Duration::from_millis(0));
-            match res {
-                Ok(TriangleEvent::Note(note)) => self.note = note,
-                    Ok(TriangleEvent::Enable(b)) => self.enabled = b,
-                    Ok(TriangleEvent::LengthCounter(l)) => self.length_counter = l,
-                    Ok(TriangleEvent::LengthCounterTick()) => self.length_counter.tick(),
-                    Ok(TriangleEvent::Reset()) => self.length_counter.reset(),
-                    Err(_) => break,
-                }
-            }
-            *x = (if self.phase <= 0.5 {
-                self.phase
-            } else {
-                1.0 - self.phase
-            } - 0.25)
-                * 4.0
-                * MASTER_VOLUME;
-
-            if self.length_counter.mute() {
-                *x = 0.0;
-            }
-
-            if !self.enabled {
-                *x = 0.0;
-            }
-            self.phase = (self.phase + self.note.hz() / self.freq) % 1.0;
-        }
-    }
-}
-
-fn init_triangle(sdl_context: &sdl2::Sdl) -> (AudioDevice<TriangleWave>, Sender<TriangleEvent>) {
-    let audio_subsystem = sdl_context.audio().unwrap();
-
-    let (sender, receiver) = channel::<TriangleEvent>();
+impl_state!(TriangleWave { phase, enabled, note, length_counter });
 
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),
-        samples: None,
-    };
-
-    let device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| TriangleWave {
-            freq: spec.freq as f32,
+impl TriangleWave {
+    fn new() -> Self {
+        TriangleWave {
             phase: 0.0,
-            receiver: receiver,
             enabled: true,
             note: TriangleNote::new(),
             length_counter: LengthCounter::new(false, 0),
-        })
-        .unwrap();
+        }
+    }
 
-    device.resume();
+    fn sample(&mut self, step: f32) -> f32 {
+        let mut x = (if self.phase <= 0.5 {
+            self.phase
+        } else {
+            1.0 - self.phase
+        } - 0.25)
+            * 4.0
+            * MASTER_VOLUME;
 
-    (device, sender)
-}
+        if self.length_counter.mute() {
+            x = 0.0;
+        }
 
-#[derive(Debug, Clone, PartialEq)]
-enum NoiseEvent {
-    Note(NoiseNote),
-    Enable(bool),
-    Envelope(Envelope),
-    EnvelopeTick(),
-    LengthCounter(LengthCounter),
-    LengthCounterTick(),
-    Reset(),
+        if !self.enabled {
+            x = 0.0;
+        }
+        self.phase = (self.phase + self.note.hz() * step) % 1.0;
+        x
+    }
 }
+
 #[derive(Debug, Clone, PartialEq)]
 struct NoiseNote {
     hz: f32,
@@ -974,9 +1415,7 @@ struct NoiseNote {
 }
 
 struct NoiseWave {
-    freq: f32,
     phase: f32,
-    receiver: Receiver<NoiseEvent>,
     value: bool,
     long_random: NoiseRandom,
     short_random: NoiseRandom,
@@ -987,48 +1426,48 @@ struct NoiseWave {
     length_counter: LengthCounter,
 }
 
-impl AudioCallback for NoiseWave {
-    type Channel = f32;
+impl_state!(NoiseNote { hz, is_long, volume });
+impl_state!(NoiseWave { phase, value, long_random, short_random, enabled, envelope, note, length_counter });
 
-    fn callback(&mut self, out: &mut [Self::Channel]) {
-        for x in out.iter_mut() {
-            loop {
-            let res = self.receiver.recv_timeout(Duration::from_millis(0));
-            match res {
-                Ok(NoiseEvent::Note(note)) => self.note = note,
-                    Ok(NoiseEvent::Enable(b)) => self.enabled = b,
-                    Ok(NoiseEvent::Envelope(e)) => self.envelope = e,
-                    Ok(NoiseEvent::EnvelopeTick()) => self.envelope.tick(),
-                    Ok(NoiseEvent::LengthCounter(l)) => self.length_counter = l,
-                    Ok(NoiseEvent::LengthCounterTick()) => self.length_counter.tick(),
-                    Ok(NoiseEvent::Reset()) => {
-                        self.envelope.reset();
-                        self.length_counter.reset();
-                    }
-                    Err(_) => break,
-            }
-            }
+impl NoiseWave {
+    fn new() -> Self {
+        NoiseWave {
+            phase: 0.0,
+            value: false,
+            long_random: NoiseRandom::long(),
+            short_random: NoiseRandom::short(),
+            enabled: true,
+            envelope: Envelope::new(0, false, false),
+            note: NoiseNote {
+                hz: 0.0,
+                is_long: true,
+                volume: 0.0,
+            },
+            length_counter: LengthCounter::new(false, 0),
+        }
+    }
 
-            *x = if self.value { 0.0 } else { 1.0 } * self.envelope.volume() * MASTER_VOLUME;
+    fn sample(&mut self, step: f32) -> f32 {
+        let mut x = if self.value { 0.0 } else { 1.0 } * self.envelope.volume() * MASTER_VOLUME;
 
-            if self.length_counter.mute() {
-                *x = 0.0;
-            }
+        if self.length_counter.mute() {
+            x = 0.0;
+        }
 
-            if !self.enabled {
-                *x = 0.0;
-            }
+        if !self.enabled {
+            x = 0.0;
+        }
 
-            let last_phase = self.phase;
-            self.phase = (self.phase + self.note.hz / self.freq) % 1.0;
-            if last_phase > self.phase {
-                self.value = if self.note.is_long {
-                    self.long_random.next()
-                } else {
-                    self.short_random.next()
-                };
-            }
+        let last_phase = self.phase;
+        self.phase = (self.phase + self.note.hz * step) % 1.0;
+        if last_phase > self.phase {
+            self.value = if self.note.is_long {
+                self.long_random.next()
+            } else {
+                self.short_random.next()
+            };
         }
+        x
     }
 }
 
@@ -1037,6 +1476,8 @@ struct NoiseRandom {
     value: u16,
 }
 
+impl_state!(NoiseRandom { bit, value });
+
 impl NoiseRandom {
     pub fn long() -> Self {
         NoiseRandom { bit: 1, value: 1 }
@@ -1060,39 +1501,76 @@ impl NoiseRandom {
     }
 }
 
-fn init_noise(sdl_context: &sdl2::Sdl) -> (AudioDevice<NoiseWave>, Sender<NoiseEvent>) {
-    let audio_subsystem = sdl_context.audio().unwrap();
 
-    let (sender, receiver) = channel::<NoiseEvent>();
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameClock {
+    quarter: bool, // エンベロープ, 線形カウンタ
+    half: bool,    // 長さカウンタ, スイープ
+    irq: bool,
+}
 
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),
-        samples: None,
-    };
+// フレームシーケンサ ($4017 書き込みからのCPUサイクルを数える)
+//   4ステップ          5ステップ
+//   - - - f  60 Hz     - - - - -   (割り込みフラグはセットしない)
+//   - l - l 120 Hz     l - l - -    96 Hz
+//   e e e e 240 Hz     e e e e -   192 Hz
+struct FrameSequencer {
+    cycles: usize,
+}
 
-    let device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| NoiseWave {
-            freq: spec.freq as f32,
-            phase: 0.0,
-            receiver: receiver,
-            value: false,
-            long_random: NoiseRandom::long(),
-            short_random: NoiseRandom::short(),
-            enabled: true,
-            envelope: Envelope::new(0, false, false),
-            note: NoiseNote {
-                hz: 0.0,
-                is_long: true,
-                volume: 0.0,
-            },
-            length_counter: LengthCounter::new(false, 0),
-        })
-        .unwrap();
+impl_state!(FrameSequencer { cycles });
 
-    device.resume();
+impl FrameSequencer {
+    fn new() -> Self {
+        FrameSequencer { cycles: 0 }
+    }
+
+    fn reset(&mut self) {
+        self.cycles = 0;
+    }
 
-    (device, sender)
+    // 1CPUサイクル進める
+    fn step(&mut self, mode: u8, timing: &FrameTiming) -> FrameClock {
+        self.cycles += 1;
+
+        let mut clock = FrameClock {
+            quarter: false,
+            half: false,
+            irq: false,
+        };
+        let t = timing;
+        match (mode, self.cycles) {
+            (_, c) if c == t.step_1 || c == t.step_3 => {
+                clock.quarter = true;
+            }
+            (_, c) if c == t.step_2 => {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            (4, c) if c == t.step4_irq => {
+                clock.irq = true;
+            }
+            (4, c) if c == t.step4_4 => {
+                clock.quarter = true;
+                clock.half = true;
+                clock.irq = true;
+            }
+            (4, c) if c == t.step4_end => {
+                clock.irq = true;
+                self.cycles = 0;
+            }
+            (5, c) if c == t.step5_4 => {}
+            (5, c) if c == t.step5_5 => {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            (5, c) if c == t.step5_end => {
+                self.cycles = 0;
+            }
+            _ => {}
+        }
+        clock
+    }
 }
 
 impl FrameCounter {
@@ -1113,6 +1591,12 @@ impl FrameCounter {
     }
 }
 
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StatusRegister {
     pub fn new() -> Self {
         StatusRegister::from_bits_truncate(0b0000_0000)
@@ -1122,3 +1606,106 @@ impl StatusRegister {
         *self.0.bits_mut() = data;
     }
 }
+
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(sequencer: &mut FrameSequencer, mode: u8, cycles: usize) -> Vec<(usize, FrameClock)> {
+        run_with(sequencer, mode, cycles, &NTSC_FRAME_TIMING)
+    }
+
+    fn run_with(sequencer: &mut FrameSequencer, mode: u8, cycles: usize, timing: &FrameTiming) -> Vec<(usize, FrameClock)> {
+        let mut events = vec![];
+        for i in 1..=cycles {
+            let clock = sequencer.step(mode, timing);
+            if clock.quarter || clock.half || clock.irq {
+                events.push((i, clock));
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn test_frame_sequencer_4step() {
+        let mut sequencer = FrameSequencer::new();
+        let events = run(&mut sequencer, 4, NTSC_FRAME_TIMING.step4_end);
+        let cycles: Vec<usize> = events.iter().map(|e| e.0).collect();
+        assert_eq!(cycles, vec![7457, 14913, 22371, 29828, 29829, 29830]);
+        assert_eq!(events.iter().filter(|e| e.1.half).count(), 2);
+        assert_eq!(events.iter().filter(|e| e.1.irq).count(), 3);
+
+        // 2周目も同じタイミング
+        let events = run(&mut sequencer, 4, NTSC_FRAME_TIMING.step4_end);
+        assert_eq!(events[0].0, 7457);
+    }
+
+    #[test]
+    fn test_frame_sequencer_5step() {
+        let mut sequencer = FrameSequencer::new();
+        let events = run(&mut sequencer, 5, NTSC_FRAME_TIMING.step5_end);
+        let cycles: Vec<usize> = events.iter().map(|e| e.0).collect();
+        assert_eq!(cycles, vec![7457, 14913, 22371, 37281]);
+        assert!(events.iter().all(|e| !e.1.irq));
+    }
+
+    #[test]
+    fn test_frame_sequencer_pal() {
+        let mut sequencer = FrameSequencer::new();
+        let events = run_with(&mut sequencer, 4, PAL_FRAME_TIMING.step4_end, &PAL_FRAME_TIMING);
+        let cycles: Vec<usize> = events.iter().map(|e| e.0).collect();
+        assert_eq!(cycles, vec![8313, 16627, 24939, 33252, 33253, 33254]);
+
+        let events = run_with(&mut sequencer, 5, PAL_FRAME_TIMING.step5_end, &PAL_FRAME_TIMING);
+        let cycles: Vec<usize> = events.iter().map(|e| e.0).collect();
+        assert_eq!(cycles, vec![8313, 16627, 24939, 41565]);
+    }
+
+    #[test]
+    fn test_reset_silences_channels() {
+        let mut apu = APU::headless(&AudioConfig::new());
+        let step = 1.0 / 44100.0;
+        apu.write_status(0x0F);
+        apu.write3ch(0x4008, 0x01);
+        apu.write3ch(0x400A, 0x40);
+        apu.write3ch(0x400B, 0x08);
+        apu.write4ch(0x400C, 0x1F);
+        apu.write4ch(0x400E, 0x04);
+        apu.write4ch(0x400F, 0x08);
+        assert!((0..100).any(|_| apu.ch3.sample(step) != 0.0));
+        assert!((0..100).any(|_| apu.ch4.sample(step) != 0.0));
+
+        // リセットの後でチャンネルを有効に戻しても、キーオンするまでは鳴らない
+        apu.reset();
+        apu.write_status(0x0F);
+        assert!((0..100).all(|_| apu.ch3.sample(step) == 0.0));
+        assert!((0..100).all(|_| apu.ch4.sample(step) == 0.0));
+        assert!((0..100).all(|_| apu.ch1.sample(step) == 0.0));
+    }
+
+    #[test]
+    fn test_sample_ring() {
+        let ring = SampleRing::new(3);
+        assert_eq!(ring.pop(), None);
+        assert!(ring.push(0.5));
+        assert!(ring.push(-0.5));
+        assert!(ring.push(0.25));
+        // 満杯
+        assert!(!ring.push(1.0));
+        assert_eq!(ring.len(), 3);
+
+        assert_eq!(ring.pop(), Some(0.5));
+        assert!(ring.push(1.0));
+        assert_eq!(ring.pop(), Some(-0.5));
+        assert_eq!(ring.pop(), Some(0.25));
+        assert_eq!(ring.pop(), Some(1.0));
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.len(), 0);
+    }
+}
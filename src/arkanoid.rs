@@ -0,0 +1,143 @@
+// アルカノイドのパドル (Vaus)
+// https://www.nesdev.org/wiki/Arkanoid_controller
+// ファミコン版 (拡張ポート): $4016 D1: ボタン、$4017 D1: つまみの位置
+// NES版 (コントローラーポート): D3: ボタン、D4: つまみの位置
+// 位置は8ビットを上位ビットから反転して出力する
+// ストローブ ($4016 bit0 = 1) の間に位置をラッチする
+use core::any::Any;
+
+use crate::controller::{ExpansionDevice, Peripheral};
+use crate::cpu::in_trace;
+use crate::impl_state;
+
+// 実機のつまみで出る範囲
+pub const PADDLE_MIN: u8 = 0x62;
+pub const PADDLE_MAX: u8 = 0xF2;
+
+pub struct ArkanoidPaddle {
+    position: u8,
+    fire: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl ArkanoidPaddle {
+    pub fn new() -> Self {
+        ArkanoidPaddle {
+            position: ((PADDLE_MIN as u16 + PADDLE_MAX as u16) / 2) as u8, // 真ん中
+            fire: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    // マウスのX移動量をそのままつまみの移動量にする
+    pub fn move_by(&mut self, dx: i32) {
+        let position = (self.position as i32 + dx).clamp(PADDLE_MIN as i32, PADDLE_MAX as i32);
+        self.position = position as u8;
+    }
+
+    pub fn position(&self) -> u8 {
+        self.position
+    }
+
+    pub fn set_fire(&mut self, fire: bool) {
+        self.fire = fire;
+    }
+
+    fn latch(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.shift = self.position;
+        }
+    }
+
+    fn shift_out(&mut self) -> u8 {
+        let bit = !self.shift >> 7 & 1;
+        if !self.strobe && !in_trace() {
+            self.shift <<= 1;
+        }
+        bit
+    }
+}
+
+impl Default for ArkanoidPaddle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_state!(ArkanoidPaddle { position, fire, strobe, shift });
+
+impl ExpansionDevice for ArkanoidPaddle {
+    fn name(&self) -> &'static str {
+        "arkanoid"
+    }
+
+    fn write(&mut self, data: u8) {
+        self.latch(data);
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        match port {
+            0 => (self.fire as u8) << 1,
+            _ => self.shift_out() << 1,
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Peripheral for ArkanoidPaddle {
+    fn name(&self) -> &'static str {
+        "arkanoid"
+    }
+
+    fn write(&mut self, data: u8) {
+        self.latch(data);
+    }
+
+    fn read(&mut self) -> u8 {
+        (self.fire as u8) << 3 | self.shift_out() << 4
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::controller::{ControllerPorts, PortDevice};
+
+    #[test]
+    fn test_arkanoid_paddle() {
+        let mut ports = ControllerPorts::new();
+        ports.connect_expansion(Box::new(ArkanoidPaddle::new()));
+        let paddle = ports.expansion_mut::<ArkanoidPaddle>().unwrap();
+        paddle.move_by(-1000);
+        assert_eq!(paddle.position(), PADDLE_MIN);
+        paddle.move_by(0xA5 - PADDLE_MIN as i32);
+        paddle.set_fire(true);
+
+        ports.write(1);
+        ports.write(0);
+        assert_eq!(ports.read(0), 0x42);
+        // 0xA5 を反転して上位ビットから
+        let bits: Vec<u8> = (0..8).map(|_| ports.read(1) >> 1 & 1).collect();
+        assert_eq!(bits, vec![0, 1, 0, 1, 1, 0, 1, 0]);
+
+        // NES版はポート2の D3/D4
+        let mut ports = ControllerPorts::new();
+        ports.set_device(1, PortDevice::ArkanoidPaddle);
+        let paddle = ports.device_mut::<ArkanoidPaddle>(1).unwrap();
+        paddle.move_by(0xA5 - paddle.position() as i32);
+        ports.write(1);
+        ports.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| ports.read(1) >> 4 & 1).collect();
+        assert_eq!(bits, vec![0, 1, 0, 1, 1, 0, 1, 0]);
+    }
+}
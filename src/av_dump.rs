@@ -0,0 +1,142 @@
+// 音声付きの動画の書き出し (ffmpeg)
+// https://ffmpeg.org/ffmpeg-formats.html#rawvideo
+// 映像はフレームごとに ffmpeg の標準入力へ rgb24 で流し、音声は f32le で一時ファイルに書いておき、
+// 最後に1つのファイル (拡張子で MP4/MKV など) にまとめる
+// フレームレートとサンプル数はどちらもエミュレーター上の時間から決まるので、
+// 実際の時間がずれても (遅い/早送り) 映像と音声はずれない
+use crate::frame::Frame;
+use crate::region::Region;
+use log::info;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+pub struct AvDump {
+    ffmpeg: String,
+    output: PathBuf,
+    video_path: PathBuf, // 映像だけの一時ファイル
+    audio_path: PathBuf, // f32le モノラルの一時ファイル
+    sample_rate: u32,
+    child: Child,
+    video: ChildStdin,
+    audio: BufWriter<File>,
+    frames: usize,
+    samples: usize,
+}
+
+impl AvDump {
+    // 録画を始める前に APU::start_recording を呼んでおく
+    pub fn start(ffmpeg: &str, output: &Path, region: Region, sample_rate: u32) -> io::Result<Self> {
+        let video_path = output.with_extension("video.mkv");
+        let audio_path = output.with_extension("audio.raw");
+        if let Some(dir) = output.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut child = Command::new(ffmpeg)
+            .args(video_args(region, &video_path))
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let video = child.stdin.take().expect("[ERR] ffmpeg stdin");
+        Ok(AvDump {
+            ffmpeg: ffmpeg.to_string(),
+            output: output.to_path_buf(),
+            video_path,
+            audio: BufWriter::new(File::create(&audio_path)?),
+            audio_path,
+            sample_rate,
+            child,
+            video,
+            frames: 0,
+            samples: 0,
+        })
+    }
+
+    // 1フレーム分の画面と、そのフレームで生成したサンプル
+    pub fn push(&mut self, frame: &Frame, samples: &[f32]) -> io::Result<()> {
+        self.video.write_all(&frame.data)?;
+        for sample in samples {
+            self.audio.write_all(&sample.to_le_bytes())?;
+        }
+        self.frames += 1;
+        self.samples += samples.len();
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    // 映像のエンコードを終わらせて、音声と合わせる
+    pub fn finish(self) -> io::Result<PathBuf> {
+        let AvDump {
+            ffmpeg,
+            output,
+            video_path,
+            audio_path,
+            sample_rate,
+            mut child,
+            video,
+            mut audio,
+            frames,
+            samples,
+        } = self;
+        info!("AV DUMP: {} frames, {} samples ({}Hz)", frames, samples, sample_rate);
+        drop(video);
+        audio.flush()?;
+        drop(audio);
+        let encoded = child.wait()?;
+        let result = if !encoded.success() {
+            Err(io::Error::other(format!("ffmpeg: {}", encoded)))
+        } else {
+            Command::new(&ffmpeg)
+                .args(mux_args(&video_path, &audio_path, sample_rate, &output))
+                .status()
+                .and_then(|status| match status.success() {
+                    true => Ok(output.clone()),
+                    false => Err(io::Error::other(format!("ffmpeg: {}", status))),
+                })
+        };
+        let _ = std::fs::remove_file(&video_path);
+        let _ = std::fs::remove_file(&audio_path);
+        result
+    }
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+fn video_args(region: Region, video_path: &Path) -> Vec<String> {
+    let (num, den) = region.frame_rate_fraction();
+    let mut args = strings(&["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24", "-s", "256x240"]);
+    args.extend(strings(&["-framerate", &format!("{}/{}", num, den), "-i", "-"]));
+    args.extend(strings(&["-c:v", "libx264", "-preset", "veryfast", "-crf", "18", "-pix_fmt", "yuv420p"]));
+    args.push(video_path.to_string_lossy().into_owned());
+    args
+}
+
+fn mux_args(video_path: &Path, audio_path: &Path, sample_rate: u32, output: &Path) -> Vec<String> {
+    let mut args = strings(&["-y", "-loglevel", "error", "-i", &video_path.to_string_lossy()]);
+    args.extend(strings(&["-f", "f32le", "-ar", &sample_rate.to_string(), "-ac", "1"]));
+    args.extend(strings(&["-i", &audio_path.to_string_lossy(), "-map", "0:v", "-map", "1:a"]));
+    args.extend(strings(&["-c:v", "copy", "-c:a", "aac", "-b:a", "192k", &output.to_string_lossy()]));
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_av_dump_args() {
+        let args = video_args(Region::Ntsc, Path::new("out.video.mkv"));
+        let rate = args.iter().position(|a| a == "-framerate").unwrap();
+        assert_eq!(args[rate + 1], "236250000/3931048");
+        assert_eq!(args.last().unwrap(), "out.video.mkv");
+
+        let args = mux_args(Path::new("v.mkv"), Path::new("a.raw"), 44100, Path::new("out.mp4"));
+        assert!(args.windows(2).any(|w| w == ["-ar", "44100"]));
+        assert_eq!(args.last().unwrap(), "out.mp4");
+    }
+}
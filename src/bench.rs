@@ -0,0 +1,116 @@
+// ホットパスのマイクロベンチマーク (rscom --bench)
+// 命令のディスパッチ, PPUの描画, APUのサンプル生成を個別に測る
+// cargo bench は criterion で測る (benches/core.rs)。rscom からは criterion を使えないので、
+// 同じやり方 (ウォームアップ -> 複数回の計測の中央値) を小さく実装している
+use crate::apu::{AudioConfig, APU};
+use crate::frame::Frame;
+use crate::nes::Nes;
+use crate::render;
+use crate::rom::Rom;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const WARMUP: Duration = Duration::from_millis(200);
+const SAMPLES: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub ns_per_iter: f64, // 中央値
+    pub min_ns: f64,
+    pub max_ns: f64,
+}
+
+impl std::fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<16} {:>12.1} ns/iter (min {:.1}, max {:.1})",
+            self.name, self.ns_per_iter, self.min_ns, self.max_ns
+        )
+    }
+}
+
+// f を iters 回呼ぶのを samples 回測って、1回あたりの時間を返す
+pub fn measure<F: FnMut()>(name: &'static str, warmup: Duration, samples: usize, iters: u32, mut f: F) -> BenchResult {
+    let start = Instant::now();
+    while start.elapsed() < warmup {
+        f();
+    }
+    let mut times: Vec<f64> = (0..samples.max(1))
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..iters {
+                f();
+            }
+            start.elapsed().as_nanos() as f64 / iters as f64
+        })
+        .collect();
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    BenchResult {
+        name,
+        ns_per_iter: times[times.len() / 2],
+        min_ns: times[0],
+        max_ns: times[times.len() - 1],
+    }
+}
+
+// 演算とメモリアクセスを混ぜたループを回し続けるNROM (benches/core.rs でも使う)
+pub fn bench_rom() -> Rom {
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+    raw.resize(16, 0);
+    let mut prg = vec![0xEA; 0x4000];
+    let program = [
+        0xA9, 0x1E, // LDA #$1E (背景とスプライトを表示)
+        0x8D, 0x01, 0x20, // STA $2001
+        0xA2, 0x00, // loop: LDX #$00
+        0x8A, // inner: TXA
+        0x69, 0x03, // ADC #$03
+        0x95, 0x00, // STA $00,X
+        0x5D, 0x00, 0x03, // EOR $0300,X
+        0x9D, 0x00, 0x03, // STA $0300,X
+        0xE8, // INX
+        0xD0, 0xF3, // BNE inner
+        0x4C, 0x05, 0x80, // JMP loop
+    ];
+    prg[..program.len()].copy_from_slice(&program);
+    prg[0x3FFA..0x3FFC].copy_from_slice(&[0x00, 0x80]);
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    raw.extend(prg);
+    raw.extend((0..0x2000).map(|i| (i * 37 % 251) as u8));
+    Rom::from_bytes(&raw).unwrap()
+}
+
+pub fn run_all() -> Vec<BenchResult> {
+    let mut nes = Nes::new(bench_rom(), APU::headless(&AudioConfig::new())).unwrap();
+    nes.run_frame();
+    let mut results = vec![];
+
+    results.push(measure("cpu_step", WARMUP, SAMPLES, 100_000, || nes.step().unwrap()));
+
+    let mut frame = Frame::new();
+    results.push(measure("ppu_render", WARMUP, SAMPLES, 20, || {
+        render::render(nes.bus().ppu(), &mut frame);
+        black_box(&frame);
+    }));
+
+    results.push(measure("apu_tick", WARMUP, SAMPLES, 100_000, || nes.apu_mut().tick()));
+
+    results.push(measure("run_frame", WARMUP, SAMPLES, 5, || {
+        black_box(nes.run_frame());
+    }));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_measure() {
+        let mut count = 0u64;
+        let result = measure("count", Duration::ZERO, 5, 1000, || count = black_box(count + 1));
+        assert_eq!(count, 5000);
+        assert!(result.min_ns <= result.ns_per_iter && result.ns_per_iter <= result.max_ns);
+    }
+}
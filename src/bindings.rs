@@ -0,0 +1,214 @@
+// 入力の割り当てファイル (キーボード/ゲームコントローラーとNESのボタンの対応)
+// ポートごとに別の割り当て (プロファイル) を持てる
+//
+// 1行1プロファイル、空白区切り:
+//   key <ポート(1-2)> <SDLのキー名>=<ボタン> ...
+//   pad <ポート(1-2)> <SDLのボタン名>=<ボタン> ... [stick=0|1] [deadzone=<0-32767>]
+// キー名の空白は '_' で書く (例: Right_Shift)
+// ボタン: A B SELECT START UP DOWN LEFT RIGHT
+// 書かれたポートは既定の割り当てを置き換える。'#'以降はコメント
+use sdl2::controller::Button as PadButton;
+use sdl2::keyboard::Scancode;
+use std::path::Path;
+
+use crate::game_controller::ControllerMapping;
+use nes_core::gamepad::Button;
+use crate::keyboard::KeyboardMapping;
+
+pub const PORT_COUNT: usize = 2;
+
+pub struct InputBindings {
+    pub keyboard: Vec<KeyboardMapping>,
+    pub pads: [ControllerMapping; PORT_COUNT],
+}
+
+impl InputBindings {
+    // キーボードは1Pのみ、ゲームコントローラーは両ポート既定の割り当て
+    pub fn new() -> Self {
+        InputBindings {
+            keyboard: vec![KeyboardMapping::new()],
+            pads: [ControllerMapping::new(), ControllerMapping::new()],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        InputBindings::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        InputBindings::parse_with(text, Scancode::from_name, PadButton::from_string)
+    }
+
+    // 名前の解決はSDLに任せるが、テストでは差し替えられるようにしておく
+    fn parse_with<K, P>(text: &str, key_from_name: K, pad_from_name: P) -> Result<Self, String>
+    where
+        K: Fn(&str) -> Option<Scancode>,
+        P: Fn(&str) -> Option<PadButton>,
+    {
+        let mut bindings = InputBindings::new();
+        let mut replaced_keyboard = [false; PORT_COUNT];
+        let mut replaced_pad = [false; PORT_COUNT];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", i + 1, e);
+            let mut fields = line.split_whitespace();
+            let device = fields.next().unwrap_or("");
+            let port = parse_port(fields.next().unwrap_or("")).map_err(error)?;
+            match device {
+                "key" => {
+                    if !replaced_keyboard[port] {
+                        bindings.keyboard.retain(|k| k.port != port);
+                        bindings.keyboard.push(KeyboardMapping::empty(port));
+                        replaced_keyboard[port] = true;
+                    }
+                    for field in fields {
+                        let (name, button) = parse_binding(field).map_err(error)?;
+                        let key = key_from_name(&name).ok_or(error(format!("unknown key: {}", name)))?;
+                        bindings.bind_key(port, key, button);
+                    }
+                }
+                "pad" => {
+                    if !replaced_pad[port] {
+                        bindings.pads[port].buttons.clear();
+                        replaced_pad[port] = true;
+                    }
+                    for field in fields {
+                        let (name, value) = field.split_once('=').ok_or(error(format!("expected name=button: {}", field)))?;
+                        match name {
+                            "stick" => {
+                                bindings.pads[port].use_left_stick = match value {
+                                    "0" => false,
+                                    "1" => true,
+                                    _ => return Err(error(format!("stick: {}", value))),
+                                }
+                            }
+                            "deadzone" => {
+                                bindings.pads[port].deadzone =
+                                    value.parse().map_err(|e| error(format!("deadzone: {}", e)))?
+                            }
+                            _ => {
+                                let (name, button) = parse_binding(field).map_err(error)?;
+                                let pad = pad_from_name(&name).ok_or(error(format!("unknown pad button: {}", name)))?;
+                                bindings.bind_pad(port, pad, button);
+                            }
+                        }
+                    }
+                }
+                _ => return Err(error(format!("unknown device: {}", device))),
+            }
+        }
+        Ok(bindings)
+    }
+
+    // 実行中の割り当て変更 (そのポートのプロファイルがなければ作る)
+    pub fn bind_key(&mut self, port: usize, key: Scancode, button: Button) {
+        match self.keyboard.iter_mut().find(|k| k.port == port) {
+            Some(mapping) => mapping.bind(key, button),
+            None => {
+                let mut mapping = KeyboardMapping::empty(port);
+                mapping.bind(key, button);
+                self.keyboard.push(mapping);
+            }
+        }
+    }
+
+    pub fn bind_pad(&mut self, port: usize, pad: PadButton, button: Button) {
+        self.pads[port].bind(pad, button);
+    }
+
+    pub fn keyboard_buttons<F: Fn(Scancode) -> bool>(&self, port: usize, is_pressed: F) -> Button {
+        self.keyboard
+            .iter()
+            .filter(|k| k.port == port)
+            .fold(Button::empty(), |buttons, k| buttons | k.buttons(&is_pressed))
+    }
+}
+
+fn parse_port(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(port) if (1..=PORT_COUNT).contains(&port) => Ok(port - 1),
+        _ => Err(format!("port must be 1-{}: {}", PORT_COUNT, value)),
+    }
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    match name.to_ascii_uppercase().as_str() {
+        "A" => Some(Button::BUTTON_A),
+        "B" => Some(Button::BUTTON_B),
+        "SELECT" => Some(Button::SELECT),
+        "START" => Some(Button::START),
+        "UP" => Some(Button::UP),
+        "DOWN" => Some(Button::DOWN),
+        "LEFT" => Some(Button::LEFT),
+        "RIGHT" => Some(Button::RIGHT),
+        _ => None,
+    }
+}
+
+fn parse_binding(field: &str) -> Result<(String, Button), String> {
+    let (name, button) = field.split_once('=').ok_or(format!("expected name=button: {}", field))?;
+    let button = parse_button(button).ok_or(format!("unknown button: {}", button))?;
+    Ok((name.replace('_', " "), button))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テストではSDLを呼ばずに名前を解決する
+    fn key_from_name(name: &str) -> Option<Scancode> {
+        match name {
+            "W" => Some(Scancode::W),
+            "K" => Some(Scancode::K),
+            "Right Shift" => Some(Scancode::RShift),
+            _ => None,
+        }
+    }
+
+    fn pad_from_name(name: &str) -> Option<PadButton> {
+        match name {
+            "a" => Some(PadButton::A),
+            _ => None,
+        }
+    }
+
+    fn parse(text: &str) -> Result<InputBindings, String> {
+        InputBindings::parse_with(text, key_from_name, pad_from_name)
+    }
+
+    #[test]
+    fn test_bindings_parse() {
+        let mut bindings = parse(
+            "# comment\n\
+             key 2 W=up K=A\n\
+             key 2 Right_Shift=select\n\
+             pad 1 a=A stick=0 deadzone=100\n",
+        )
+        .unwrap();
+
+        // 1Pのキーボードは既定のまま、2Pは追加
+        assert_eq!(bindings.keyboard_buttons(0, |k| k == Scancode::Z), Button::BUTTON_B);
+        let pressed = [Scancode::W, Scancode::K, Scancode::RShift, Scancode::Z];
+        assert_eq!(
+            bindings.keyboard_buttons(1, |k| pressed.contains(&k)),
+            Button::UP | Button::BUTTON_A | Button::SELECT
+        );
+
+        assert_eq!(bindings.pads[0].buttons, vec![(PadButton::A, Button::BUTTON_A)]);
+        assert!(!bindings.pads[0].use_left_stick);
+        assert_eq!(bindings.pads[0].deadzone, 100);
+        assert_eq!(bindings.pads[1].buttons.len(), ControllerMapping::new().buttons.len());
+
+        bindings.bind_key(1, Scancode::W, Button::DOWN);
+        assert_eq!(bindings.keyboard_buttons(1, |k| k == Scancode::W), Button::DOWN);
+
+        assert!(parse("key 3 W=up").is_err());
+        assert!(parse("key 1 Q=up").is_err());
+        assert!(parse("key 1 W=turbo").is_err());
+        assert!(parse("mouse 1 W=up").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// blargg のテストROMを画面なしで実行して結果を読む
+// https://github.com/christopherpow/nes-test-roms/blob/master/instr_test-v5/readme.txt
+//   $6001-$6003 が DE B0 61 になったら $6000 が状態
+//     $80: 実行中, $81: リセットボタンを押してほしい (100ms以上待ってから), それ以外: 結果 (0なら合格)
+//   $6004 から NUL終わりの文字列 (画面に出すのと同じ内容)
+// 2005年ごろの古いROM (sprite_hit_tests, sprite_overflow_tests, vbl_nmi_timing) は $6000 を使わない
+//   結果は $F8 に残る (1: 合格, 2以上: 失敗したテストの番号)。途中でも書き換わるので決めたフレーム数だけ回してから読む
+// ROMはリポジトリに含めないので test_roms/<スイート名>/ に置く (NES_TEST_ROMS で場所を変えられる)
+use crate::apu::{AudioConfig, APU};
+use crate::cartridge::load_rom;
+use crate::error::EmuError;
+use crate::memory_view::{read_block, MemorySpace};
+use crate::nes::Nes;
+use std::path::PathBuf;
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT: u16 = 0x6004;
+const TEXT_MAX: usize = 0x1000;
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+const RESET_DELAY_FRAMES: usize = 8; // 100ms より長く
+const LEGACY_RESULT: u16 = 0x00F8;
+const LEGACY_PASSED: u8 = 1;
+const TEST_ROMS_ENV: &str = "NES_TEST_ROMS";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlarggResult {
+    pub code: Option<u8>, // 終わらなければNone
+    pub text: String,
+    pub frames: usize,
+}
+
+impl BlarggResult {
+    pub fn passed(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+// 終わるまで (最大 max_frames) 実行する
+pub fn run(nes: &mut Nes, max_frames: usize) -> Result<BlarggResult, EmuError> {
+    let mut reset_at = None;
+    for frame in 1..=max_frames {
+        nes.run_frame();
+        let status = read_block(nes, MemorySpace::Cpu, STATUS, 4)?;
+        if status[1..] != SIGNATURE {
+            continue;
+        }
+        match status[0] {
+            RUNNING => {}
+            NEEDS_RESET => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    nes.soft_reset();
+                    reset_at = None;
+                }
+                _ => {}
+            },
+            code => {
+                return Ok(BlarggResult {
+                    code: Some(code),
+                    text: read_text(nes)?,
+                    frames: frame,
+                })
+            }
+        }
+    }
+    Ok(BlarggResult {
+        code: None,
+        text: read_text(nes)?,
+        frames: max_frames,
+    })
+}
+
+pub fn run_file(path: &str, max_frames: usize) -> Result<BlarggResult, EmuError> {
+    let mut nes = Nes::new(load_rom(path)?, APU::headless(&AudioConfig::new()))?;
+    run(&mut nes, max_frames)
+}
+
+// 古いROMを frames だけ実行して $F8 を読む (まだ0なら終わっていない)
+pub fn run_legacy(nes: &mut Nes, frames: usize) -> Result<BlarggResult, EmuError> {
+    for _ in 0..frames {
+        nes.run_frame();
+    }
+    let code = match nes.ram()[LEGACY_RESULT as usize] {
+        0 => None,
+        LEGACY_PASSED => Some(0),
+        code => Some(code),
+    };
+    Ok(BlarggResult {
+        code,
+        text: String::new(),
+        frames,
+    })
+}
+
+pub fn run_legacy_file(path: &str, frames: usize) -> Result<BlarggResult, EmuError> {
+    let mut nes = Nes::new(load_rom(path)?, APU::headless(&AudioConfig::new()))?;
+    run_legacy(&mut nes, frames)
+}
+
+// テストROMの置き場所 (NES_TEST_ROMS, なければ manifest_dir/test_roms)
+pub fn test_roms_root(manifest_dir: &str) -> PathBuf {
+    std::env::var(TEST_ROMS_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(manifest_dir).join("test_roms"))
+}
+
+// test_roms_root の suite の下の .nes を名前順に
+// ディレクトリがなければNone (ROMを置いていない環境ではテストを飛ばす)
+pub fn test_roms(manifest_dir: &str, suite: &str) -> Option<Vec<PathBuf>> {
+    let mut roms: Vec<PathBuf> = std::fs::read_dir(test_roms_root(manifest_dir).join(suite))
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "nes"))
+        .collect();
+    roms.sort();
+    Some(roms)
+}
+
+fn read_text(nes: &mut Nes) -> Result<String, EmuError> {
+    let bytes = read_block(nes, MemorySpace::Cpu, TEXT, TEXT_MAX)?;
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_blargg_result() {
+        // 1回目はリセットを頼み、リセット後に "ok" を書いて 0 で終わる
+        let source = "
+                LDA #$DE
+                STA $6001
+                LDA #$B0
+                STA $6002
+                LDA #$61
+                STA $6003
+                LDA $6000
+                CMP #$81
+                BEQ second
+                STA $10
+                LDA #$81
+                STA $6000
+            wait: JMP wait
+            second: LDA #$6F
+                STA $6004
+                LDA #$6B
+                STA $6005
+                LDA #0
+                STA $6006
+                STA $6000
+            done: JMP done
+        ";
+        let mut nes = TestRom::new().asm(0x8000, source).nes().unwrap();
+        let result = run(&mut nes, 60).unwrap();
+        assert!(result.passed());
+        assert_eq!(result.text, "ok");
+        assert!(result.frames > RESET_DELAY_FRAMES);
+
+        let mut nes = TestRom::new().asm(0x8000, "loop: JMP loop").nes().unwrap();
+        assert_eq!(run(&mut nes, 5).unwrap().code, None);
+
+        let mut nes = TestRom::new().asm(0x8000, "LDA #3\nSTA $F8\nloop: JMP loop").nes().unwrap();
+        assert_eq!(run_legacy(&mut nes, 2).unwrap().code, Some(3));
+        nes.ram_mut()[0xF8] = 1;
+        assert!(run_legacy(&mut nes, 1).unwrap().passed());
+    }
+}
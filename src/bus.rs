@@ -1,8 +1,26 @@
-use crate::gamepad::GamePad;
+use crate::call_stack::{CallFrame, CallStack};
+use crate::cdl::{CodeDataLogger, CDL_CODE, CDL_INDIRECT_CODE};
+use crate::cheat::CheatManager;
+use crate::cpu::in_trace;
+use crate::event_viewer::{write_event, EventKind, EventLog, ScanlineKind};
+use crate::events::{EmuEvent, EventHooks};
+use crate::controller::ControllerPorts;
+use crate::frame::Frame;
+use crate::state::{State, StateError, StateReader, StateWriter};
+use crate::power_on::PowerOnState;
+use crate::symbols::{replace_operand, Label, SymbolTable};
+use crate::gamepad::{Button, GamePad};
+use crate::render;
+use crate::zapper::Zapper;
 use crate::ppu::PPU;
-use crate::rom::Rom;
-use crate::{apu::APU, MAPPER};
-use log::{debug, error, info, log_enabled, trace, warn, Level};
+use crate::region::Region;
+use crate::cartridge::{Cartridge, SharedCartridge};
+use crate::rom::{Rom, RomError};
+use crate::apu::APU;
+use crate::sync::{Arc, Mutex, MutexGuard};
+use log::{debug, error, trace, warn};
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
@@ -12,36 +30,211 @@ const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 const PRG_ROM: u16 = 0x8000;
 const PRG_ROM_END: u16 = 0xFFFF;
 
-pub struct Bus<'call> {
+// ホストの入力 (キーボードなど) をコントローラーに反映するタイミング
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputPoll {
+    // VBlankの始まり (フレームの描画が終わった直後)
+    StartOfFrame,
+    // ゲームが $4016 にストローブを立てた瞬間 (入力の遅延が最も少ない)
+    OnStrobe,
+}
+
+pub struct Bus {
     cpu_vram: [u8; 2048],
     // prg_rom: Vec<u8>,
     ppu: PPU,
-    gamepad_1: GamePad,
-    gamepad_2: GamePad,
+    cartridge: SharedCartridge,
+    ports: ControllerPorts,
     apu: APU,
+    region: Region,
+    cheats: CheatManager,
+    symbols: SymbolTable,
+    ppu_dot_fraction: u32, // PALはCPU 1サイクルが3.2ドットなので端数を持ち越す
 
     cycles: usize,
-    gameloop_callback: Box<dyn FnMut(&PPU, &mut GamePad) + 'call>,
+    frame_count: usize,
+    input_poll: InputPoll,
+    input_requested: bool, // ホストの入力を読むタイミングになった (Nes::run_frame_with_input で読む)
+    events: EventHooks,
+    irq_lines: (bool, bool), // (APU, マッパー) の前回の状態 (立ち上がりで通知する)
+    cdl: Option<CodeDataLogger>,
+    event_log: Option<EventLog>,
+    call_stack: Option<CallStack>,
+    power_on: PowerOnState,
+    strobe: bool,
+    // ザッパーの光の検出用 (そのフレームで最初に読まれたときに描く)
+    zapper_frame: Frame,
+    zapper_frame_count: Option<usize>,
 }
 
-impl<'a> Bus<'a> {
-    pub fn new<'call, F>(rom: Rom, apu: APU, gameloop_callback: F) -> Bus<'call>
-    where
-        F: FnMut(&PPU, &mut GamePad) + 'call,
-    {
-        let ppu = PPU::new(rom.chr_rom, rom.mirroring, rom.is_chr_ram);
-        Bus {
+impl Bus {
+    pub fn new(rom: Rom, apu: APU) -> Result<Bus, RomError> {
+        let cartridge = Arc::new(Mutex::new(Cartridge::new(&rom)?));
+        let region = Region::from_header(&rom.header).unwrap_or(Region::Ntsc);
+        let mut ppu = PPU::new(rom.mirroring, cartridge.clone());
+        ppu.set_region(region);
+        let mut apu = apu;
+        apu.set_region(region);
+        // 標準では両方のポートにコントローラーを挿しておく
+        let mut ports = ControllerPorts::new();
+        ports.connect(0, Box::new(GamePad::new()));
+        ports.connect(1, Box::new(GamePad::new()));
+        Ok(Bus {
             cpu_vram: [0; 2048],
             // prg_rom: rom.prg_rom,
             ppu: ppu,
-            gamepad_1: GamePad::new(),
-            gamepad_2: GamePad::new(),
+            cartridge,
+            ports,
             apu: apu,
+            region,
+            cheats: CheatManager::new(),
+            symbols: SymbolTable::new(),
+            ppu_dot_fraction: 0,
             cycles: 0,
-            gameloop_callback: Box::from(gameloop_callback),
+            frame_count: 0,
+            input_poll: InputPoll::StartOfFrame,
+            input_requested: false,
+            events: EventHooks::new(),
+            irq_lines: (false, false),
+            cdl: None,
+            event_log: None,
+            call_stack: None,
+            power_on: PowerOnState::new(),
+            strobe: false,
+            zapper_frame: Frame::new(),
+            zapper_frame_count: None,
+        })
+    }
+
+    pub fn cartridge(&self) -> MutexGuard<'_, Cartridge> {
+        self.cartridge.lock()
+    }
+
+    pub fn events_mut(&mut self) -> &mut EventHooks {
+        &mut self.events
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_ref()
+    }
+
+    // 記録を始める/止める (前のログを返す)
+    pub fn set_cdl(&mut self, cdl: Option<CodeDataLogger>) -> Option<CodeDataLogger> {
+        core::mem::replace(&mut self.cdl, cdl)
+    }
+
+    // CDL: オペコードを読む直前 (オペコードはデータとして数えない)
+    pub fn log_opcode_fetch(&mut self, addr: u16) {
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.begin_instruction(addr, 1, false);
         }
     }
 
+    // CDL: 命令のバイトをコードとして記録する
+    pub fn log_instruction(&mut self, addr: u16, len: u16, indirect: bool) {
+        if let Some(cdl) = self.cdl.as_mut() {
+            cdl.begin_instruction(addr, len, indirect);
+            let cartridge = self.cartridge.lock();
+            for addr in (0..len).map(|i| addr.wrapping_add(i)) {
+                if let Some(offset) = cartridge.prg_rom_offset(addr) {
+                    cdl.mark_prg(offset, addr, CDL_CODE);
+                }
+            }
+        }
+    }
+
+    // CDL: JMP ($nnnn) の飛び先
+    pub fn log_indirect_jump(&mut self, target: u16) {
+        if let Some(cdl) = self.cdl.as_mut() {
+            if let Some(offset) = self.cartridge.lock().prg_rom_offset(target) {
+                cdl.mark_prg(offset, target, CDL_INDIRECT_CODE);
+            }
+        }
+    }
+
+    // CDL: 命令のバイト以外の読み込みはデータ (デバッガからの読み込みは数えない)
+    fn log_data_read(&mut self, addr: u16) {
+        if let Some(cdl) = self.cdl.as_mut() {
+            if in_trace() || cdl.is_operand(addr) {
+                return;
+            }
+            if let Some(offset) = self.cartridge.lock().prg_rom_offset(addr) {
+                cdl.mark_prg(offset, addr, cdl.data_flags());
+            }
+        }
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.call_stack.as_ref()
+    }
+
+    // 記録を始める/止める (前の記録を返す)
+    pub fn set_call_stack(&mut self, stack: Option<CallStack>) -> Option<CallStack> {
+        core::mem::replace(&mut self.call_stack, stack)
+    }
+
+    // コールスタック: JSR/割り込み/PHP で積んだ
+    pub fn log_stack_push(&mut self, frame: CallFrame) {
+        if let Some(stack) = self.call_stack.as_mut() {
+            stack.push(frame);
+        }
+    }
+
+    // コールスタック: スタックから取り出した (sp は取り出した後)
+    pub fn log_stack_pop(&mut self, sp: u8) {
+        if let Some(stack) = self.call_stack.as_mut() {
+            stack.pop(sp);
+        }
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    // 記録を始める/止める (前の記録を返す)
+    pub fn set_event_log(&mut self, log: Option<EventLog>) -> Option<EventLog> {
+        core::mem::replace(&mut self.event_log, log)
+    }
+
+    // タイミング図: scanline で使ったCPUサイクル
+    fn log_cycles(&mut self, scanline: usize, cycles: usize, dma: bool) {
+        if let Some(log) = self.event_log.as_mut() {
+            let ppu = &self.ppu;
+            let kind = ScanlineKind::of(scanline, ppu.scanlines(), ppu.vblank_scanline(), ppu.rendering_enabled());
+            log.add_cycles(scanline, kind, cycles, dma);
+        }
+    }
+
+    // CPUが割り込みに入った/RTIで戻った (タイミング図で割り込みの中のサイクルを分ける)
+    pub fn enter_interrupt(&mut self) {
+        if let Some(log) = self.event_log.as_mut() {
+            log.enter_interrupt();
+        }
+    }
+
+    pub fn leave_interrupt(&mut self) {
+        if let Some(log) = self.event_log.as_mut() {
+            log.leave_interrupt();
+        }
+    }
+
+    // イベントビューア: 今のスキャンライン/ドットで記録する
+    fn log_event(&mut self, kind: EventKind) {
+        if let Some(log) = self.event_log.as_mut() {
+            log.record(self.ppu.scanline(), self.ppu.dot(), self.cycles, kind);
+        }
+    }
+
+    pub fn set_input_poll(&mut self, poll: InputPoll) {
+        self.input_poll = poll;
+    }
+
+    // コールバックは持たずに要求だけ立てておき、命令の切れ目でホスト側が読む
+    // (ストローブを書いた命令の次から読まれるので、OnStrobe でも遅れない)
+    pub fn take_input_request(&mut self) -> bool {
+        core::mem::take(&mut self.input_requested)
+    }
+
     // fn read_prg_rom(&self, mut addr: u16) -> u8 {
     //     addr -= 0x8000;
     //     if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
@@ -52,16 +245,213 @@ impl<'a> Bus<'a> {
     // }
 
     pub fn tick(&mut self, cycles: u8) {
+        self.tick_cycles(cycles, false);
+    }
+
+    // dma: OAM DMAでCPUが止まっているサイクル (イベントビューアで分けて数える)
+    fn tick_cycles(&mut self, cycles: u8, dma: bool) {
         self.cycles += cycles as usize;
 
         let nmi_before = self.ppu.nmi_interrupt.is_some();
-        self.ppu.tick(cycles * 3);
+        let scanline_before = self.ppu.scanline();
+        let a12_clocks = self.ppu.a12_clocks();
+        let sprite_zero_hits = self.ppu.sprite_zero_hits();
+        let (num, den) = self.region.ppu_dots_per_cpu_cycle();
+        self.ppu_dot_fraction += cycles as u32 * num;
+        self.ppu.tick((self.ppu_dot_fraction / den) as u8);
+        self.ppu_dot_fraction %= den;
         let nmi_after = self.ppu.nmi_interrupt.is_some();
+        if self.event_log.is_some() {
+            self.log_cycles(scanline_before, cycles as usize, dma);
+            for _ in 0..self.ppu.a12_clocks().wrapping_sub(a12_clocks) {
+                self.log_event(EventKind::MapperIrqClock);
+            }
+            if self.ppu.sprite_zero_hits() != sprite_zero_hits {
+                self.log_event(EventKind::Sprite0Hit);
+            }
+            if self.ppu.scanline() < scanline_before {
+                self.event_log.as_mut().unwrap().end_frame();
+            }
+        }
+        // NMIが無効でもフレームは数える
+        let vblank_scanline = self.ppu.vblank_scanline();
+        if scanline_before < vblank_scanline && self.ppu.scanline() >= vblank_scanline {
+            self.frame_count += 1;
+            self.cheats.apply(&mut self.cpu_vram);
+            if self.input_poll == InputPoll::StartOfFrame {
+                self.input_requested = true;
+            }
+            self.events.emit(EmuEvent::FrameComplete(self.frame_count));
+        }
 
-        self.apu.tick(cycles);
+        // APUとマッパーはCPUと同じクロックで1サイクルずつ進める
+        let mut cartridge = self.cartridge.lock();
+        for _ in 0..cycles {
+            cartridge.cpu_tick();
+            self.apu.set_expansion_audio(cartridge.audio_output());
+            self.apu.tick();
+        }
+        drop(cartridge);
 
         if !nmi_before && nmi_after {
-            (self.gameloop_callback)(&self.ppu, &mut self.gamepad_1);
+            self.log_event(EventKind::Nmi);
+            self.events.emit(EmuEvent::Nmi);
+            #[cfg(feature = "std")]
+            self.cartridge.lock().flush_sram_if_due();
+        }
+    }
+
+    // リセットボタン (CPU内部RAMはそのまま)
+    pub fn soft_reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+        self.cartridge.lock().reset();
+    }
+
+    // 電源の入れ直し
+    pub fn power_cycle(&mut self) {
+        self.cartridge.lock().power_cycle();
+        self.power_on_console();
+    }
+
+    // カートリッジを差し替えて電源を入れ直す
+    // ウィンドウやオーディオデバイスなどはそのまま使い続ける
+    // 差し替えに失敗した場合は元のカートリッジのまま
+    pub fn load_new_rom(&mut self, rom: Rom) -> Result<(), RomError> {
+        let cartridge = Cartridge::new(&rom)?;
+        // 古いカートリッジはここで手放され、SRAMが書き出される
+        *self.cartridge.lock() = cartridge;
+        self.ppu.mirroring = rom.mirroring;
+        self.symbols.clear(); // ラベルはROMごと
+        self.set_region(Region::from_header(&rom.header).unwrap_or(Region::Ntsc));
+        self.power_on_console();
+        Ok(())
+    }
+
+    pub fn power_on_state(&self) -> &PowerOnState {
+        &self.power_on
+    }
+
+    // 次の電源投入から使う
+    pub fn set_power_on_state(&mut self, state: PowerOnState) {
+        self.power_on = state;
+    }
+
+    // 本体側 (RAM, PPU, APU) の電源投入
+    fn power_on_console(&mut self) {
+        let values = self.power_on.roll();
+        self.cpu_vram = values.ram;
+        self.ppu.power_on();
+        self.ppu.set_power_on_dot(values.ppu_dot);
+        self.apu.power_on();
+        self.apu.set_power_on_phase(values.apu_cycle);
+        self.cycles = 0;
+        self.ppu_dot_fraction = 0;
+    }
+
+    // CPUクロック, PPUのタイミング, APUの周期表を切り替える
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.ppu_dot_fraction = 0;
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn cheats(&self) -> &CheatManager {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut CheatManager {
+        &mut self.cheats
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        &mut self.symbols
+    }
+
+    // addr のラベル (PRG ROMのラベルは今のバンクで探す)
+    pub fn label(&self, addr: u16) -> Option<&Label> {
+        if self.symbols.is_empty() {
+            return None;
+        }
+        let offset = self.cartridge.lock().prg_rom_offset(addr);
+        self.symbols.lookup(addr, offset)
+    }
+
+    // オペランドを名前に置き換える (ラベルが無ければそのまま)
+    pub fn label_operand(&self, text: &str) -> String {
+        if self.symbols.is_empty() {
+            return text.to_string();
+        }
+        replace_operand(text, |addr| self.label(addr).map(|label| label.name.clone()))
+    }
+
+    // VBlankに入った回数
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    // 電源投入からのCPUサイクル数
+    pub fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn apu(&self) -> &APU {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    // OSの入力イベントを通さずにボタンを押す (スクリプトやボット用)
+    // player: 0-3 (3P/4PはFour Scoreがつながっているときだけ)
+    pub fn set_controller_state(&mut self, player: usize, buttons: Button) {
+        self.ports.set_player_buttons(player, buttons);
+    }
+
+    pub fn controller_ports(&mut self) -> &mut ControllerPorts {
+        &mut self.ports
+    }
+
+    // 画面は render::render(bus.ppu(), &mut frame) で描ける
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        &mut self.ppu
+    }
+
+    // CPU内部RAM ($0000-$07FF)
+    pub fn ram(&self) -> &[u8; 2048] {
+        &self.cpu_vram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8; 2048] {
+        &mut self.cpu_vram
+    }
+
+    // ザッパーは今のVRAMの内容で描いた画面から光を検出する
+    // (描画はVBlankでまとめて行うので、前のフレームの画面だとフラッシュに間に合わない)
+    fn sense_zapper_light(&mut self, port: usize) {
+        if self.ports.device_mut::<Zapper>(port).is_none() {
+            return;
+        }
+        if self.zapper_frame_count != Some(self.frame_count) {
+            render::render(&self.ppu, &mut self.zapper_frame);
+            self.zapper_frame_count = Some(self.frame_count);
+        }
+        if let Some(zapper) = self.ports.device_mut::<Zapper>(port) {
+            zapper.sense_light(&self.zapper_frame);
         }
     }
 
@@ -76,8 +466,47 @@ impl<'a> Bus<'a> {
         res
     }
 
-    pub fn poll_apu_irq(&mut self) -> bool {
-        self.apu.irq()
+    // IRQライン (APUのフレームIRQとマッパーのIRQのワイヤードOR)
+    pub fn poll_irq(&mut self) -> bool {
+        let lines = (self.apu.irq(), self.cartridge.lock().irq_pending());
+        if lines.0 && !self.irq_lines.0 {
+            self.log_event(EventKind::ApuIrq);
+            self.events.emit(EmuEvent::ApuIrq);
+        }
+        if lines.1 && !self.irq_lines.1 {
+            self.log_event(EventKind::MapperIrq);
+            self.events.emit(EmuEvent::MapperIrq);
+        }
+        self.irq_lines = lines;
+        lines.0 || lines.1
+    }
+}
+
+// カートリッジは Nes 側でまとめて保存する
+impl State for Bus {
+    fn save(&self, w: &mut StateWriter) {
+        self.cpu_vram.save(w);
+        self.ppu.save(w);
+        self.ports.save(w);
+        self.apu.save(w);
+        self.cycles.save(w);
+        self.frame_count.save(w);
+        self.strobe.save(w);
+        self.ppu_dot_fraction.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.cpu_vram.load(r)?;
+        self.ppu.load(r)?;
+        self.ports.load(r)?;
+        self.apu.load(r)?;
+        self.cycles.load(r)?;
+        self.frame_count.load(r)?;
+        self.strobe.load(r)?;
+        self.ppu_dot_fraction.load(r)?;
+        // ザッパー用の画面は次に読まれたときに描き直す
+        self.zapper_frame_count = None;
+        Ok(())
     }
 }
 
@@ -86,13 +515,14 @@ pub trait Mem {
     fn mem_write(&mut self, addr: u16, data: u8);
 }
 
-impl Mem for Bus<'_> {
+impl Mem for Bus {
     fn mem_read(&mut self, addr: u16) -> u8 {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b_0000_0111_1111_1111;
                 let v = self.cpu_vram[mirror_down_addr as usize];
                 trace!(
+                    target: "cpu",
                     "RAM READ: {:04X} => {:04X} ({:02X})",
                     addr,
                     mirror_down_addr,
@@ -101,7 +531,7 @@ impl Mem for Bus<'_> {
                 v
             }
             0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                warn!("Attempt to read from write-only PPU address {:X}", addr);
+                debug!(target: "ppu", "Attempt to read from write-only PPU address {:X}", addr);
                 0
             }
             0x2002 => self.ppu.read_status(),
@@ -109,36 +539,55 @@ impl Mem for Bus<'_> {
             0x2007 => self.ppu.read_data(),
             0x2008..=PPU_REGISTERS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b00100000_00000111;
-                debug!("READ PPU MIRROR: {:04X} => {:04X}", addr, mirror_down_addr);
+                debug!(target: "ppu", "READ PPU MIRROR: {:04X} => {:04X}", addr, mirror_down_addr);
                 self.mem_read(mirror_down_addr)
             }
+            0x4015 if in_trace() => self.apu.peek_status(),
             0x4015 => self.apu.read_status(),
-            0x4016 => self.gamepad_1.read(),
+            0x4016 => {
+                self.sense_zapper_light(0);
+                self.ports.read(0)
+            }
+            // 読み込みはポート2、書き込みはAPUのフレームカウンタ
             0x4017 => {
-                // readはgamepad_2になるらしいが、writeはAPUらしい。。
-                // self.gamepad_2.read()
-                0
+                self.sense_zapper_light(1);
+                self.ports.read(1)
+            }
+            0x4020..=0x5FFF => {
+                // 拡張領域 (N163の内部RAMなど)
+                self.cartridge.lock().cpu_read(addr)
             }
             0x6000..=0x7FFF => {
-                trace!("Ext RAM Read: ${:04X}",addr);
-                MAPPER.lock().unwrap().read_prg_rom(addr)
+                trace!(target: "mapper", "Ext RAM Read: ${:04X}",addr);
+                self.log_data_read(addr);
+                self.cartridge.lock().cpu_read(addr)
             }
             PRG_ROM..=PRG_ROM_END => {
-                MAPPER.lock().unwrap().read_prg_rom(addr)
+                self.log_data_read(addr);
+                self.cartridge.lock().cpu_read(addr)
             }
             _ => {
-                warn!("Ignoreing mem access at {:X}", addr);
+                warn!(target: "cpu", "Ignoreing mem access at {:X}", addr);
                 0
             }
         }
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
+        // デバッガからの書き込みは記録しない (PPUレジスタのミラーは$2000-$2007に直してから記録される)
+        if self.event_log.is_some() && !in_trace() {
+            if let Some(kind) = write_event(addr, data) {
+                self.log_event(kind);
+            }
+        }
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0b_0000_0111_1111_1111;
+                // チートで固定中のアドレスは書き換えさせない
+                let data = self.cheats.frozen_value(mirror_down_addr).unwrap_or(data);
                 self.cpu_vram[mirror_down_addr as usize] = data;
                 trace!(
+                    target: "cpu",
                     "RAM WRITE: {:04X} => {:04X} ({:02X})",
                     addr,
                     mirror_down_addr,
@@ -177,21 +626,20 @@ impl Mem for Bus<'_> {
             0x4004..=0x4007 => self.apu.write2ch(addr, data),
             0x4008 | 0x400A | 0x400B => self.apu.write3ch(addr, data),
             0x400C | 0x400E | 0x400F => self.apu.write4ch(addr, data),
-            0x4010..=0x4013 => {
-                // TODO DMCch
-            }
+            0x4010..=0x4013 => self.apu.write5ch(addr, data),
             0x4015 => {
                 self.apu.write_status(data);
             }
             0x4016 => {
-                self.gamepad_1.write(data);
+                let strobe = data & 1 == 1;
+                if strobe && !self.strobe && self.input_poll == InputPoll::OnStrobe {
+                    self.input_requested = true;
+                }
+                self.strobe = strobe;
+                self.ports.write(data);
             }
             0x4017 => {
-                let pad_val = data;
-                let apu_val = data & 0b1100_0000; // Bit[7:6]だけもらう
-                self.gamepad_2.write(pad_val);
-                self.apu.write_frame_counter(apu_val);
-                info!("WRITE ACCESS 0x4017. {:02X}", data);
+                self.apu.write_frame_counter(data & 0b1100_0000); // Bit[7:6]だけもらう
             }
             0x4014 => {
                 // $XX を書き込むと、256 バイトのデータが
@@ -203,27 +651,41 @@ impl Mem for Bus<'_> {
                 }
                 self.ppu.write_to_oam_dma(values);
                 // Not counting the OAMDMA write tick, the above procedure takes 513 CPU cycles (+1 on odd CPU cycles)
-                for _ in 0..513 {
-                    self.ppu.tick(1);
+                let dma_cycles = if self.cycles % 2 == 1 { 514 } else { 513 };
+                // PPU/APU/マッパーも普通のサイクルと同じように進める
+                for _ in 0..dma_cycles {
+                    self.tick_cycles(1, true);
                 }
             }
+            0x4020..=0x5FFF => {
+                self.cartridge.lock().cpu_write(addr, data);
+            }
             0x6000..=0x7FFF => {
-                MAPPER.lock().unwrap().write(addr, data);
+                let mut cartridge = self.cartridge.lock();
+                // 聞いているフックがなければ読み直さない
+                let watch = cartridge.has_battery() && !self.events.is_empty();
+                let before = if watch { cartridge.cpu_read(addr) } else { 0 };
+                cartridge.cpu_write(addr, data);
+                if watch && cartridge.cpu_read(addr) != before {
+                    drop(cartridge);
+                    self.events.emit(EmuEvent::SramModified(addr));
+                }
                 trace!(
+                    target: "mapper",
                     "Ext RAM WRITE: ${:04X} => {:02X})",
                     addr,
                     data
                 );
             }
             PRG_ROM..=PRG_ROM_END => {
-                MAPPER.lock().unwrap().write(addr, data);
+                self.cartridge.lock().cpu_write(addr, data);
                 // warn!(
                 //     "Attempt to write to Cartrige ROM space {:04X} => {:02X}",
                 //     addr, data
                 // )
             }
             _ => {
-                error!("Ignoreing mem write-access at {:X}", addr)
+                error!(target: "cpu", "Ignoreing mem write-access at {:X}", addr)
             }
         }
     }
@@ -0,0 +1,181 @@
+// コールスタックとスタックページ ($0100-$01FF) の表示用データ
+// https://www.nesdev.org/wiki/Stack
+// JSR, 割り込み (NMI/IRQ/BRK), PHP で積んだものを記録しておき、スタックの各バイトが何なのかを付けて返す
+//   JSR: 戻り先-1 (上位, 下位)。割り込み: 戻り先 (上位, 下位), P
+// 記録はSPがその位置より上に戻ったら (RTS/RTI/PLA などで取り出したら) 消す
+// 記録を始める前に積まれていたものは Data になる
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Jsr,
+    Nmi,
+    Irq,
+    Brk,
+    Php, // コールスタックには出さない (スタックのバイトの説明だけ)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CallFrame {
+    pub kind: FrameKind,
+    pub caller: u16, // JSR/BRK のアドレス, 割り込まれた命令のアドレス
+    pub target: u16, // 呼び出し先 (割り込みならベクタの飛び先)
+    pub sp: u8,      // 積む前のSP (最初のバイトの位置)
+}
+
+impl CallFrame {
+    // sp は積んだ後のSP
+    pub fn new(kind: FrameKind, caller: u16, target: u16, sp: u8) -> Self {
+        let mut frame = CallFrame {
+            kind,
+            caller,
+            target,
+            sp: 0,
+        };
+        frame.sp = sp.wrapping_add(frame.len());
+        frame
+    }
+
+    fn len(&self) -> u8 {
+        match self.kind {
+            FrameKind::Jsr => 2,
+            FrameKind::Php => 1,
+            _ => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackByteKind {
+    ReturnHigh(u16), // 戻り先 (JSR なら RTS で戻るアドレス)
+    ReturnLow(u16),
+    Status(u8), // 積んだ P
+    Data,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackByte {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: StackByteKind,
+    pub frame: Option<usize>, // frames() の何番目か
+}
+
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack { frames: vec![] }
+    }
+
+    // 積んだ直後に呼ぶ (sp は積む前)
+    pub fn push(&mut self, frame: CallFrame) {
+        self.pop(frame.sp.wrapping_sub(frame.len()));
+        self.frames.push(frame);
+    }
+
+    // 取り出した直後に呼ぶ (sp は今のSP)
+    pub fn pop(&mut self, sp: u8) {
+        while self.frames.last().is_some_and(|frame| frame.sp <= sp) {
+            self.frames.pop();
+        }
+    }
+
+    // 外側から順 (PHP は含まない)
+    pub fn frames(&self) -> Vec<CallFrame> {
+        self.frames.iter().filter(|f| f.kind != FrameKind::Php).copied().collect()
+    }
+
+    // $0100+SP+1 から $01FF まで (stack はスタックページ 256バイト)
+    pub fn annotate(&self, stack: &[u8; 256], sp: u8) -> Vec<StackByte> {
+        let mut bytes: Vec<StackByte> = (sp as usize + 1..256)
+            .map(|n| StackByte {
+                addr: 0x0100 + n as u16,
+                value: stack[n],
+                kind: StackByteKind::Data,
+                frame: None,
+            })
+            .collect();
+        let calls = self.frames();
+        for frame in self.frames.iter().filter(|f| f.sp > sp) {
+            let index = calls.iter().position(|f| f == frame);
+            let at = |offset: u8| frame.sp.wrapping_sub(offset) as usize;
+            let ret = u16::from_le_bytes([stack[at(1)], stack[at(0)]]);
+            let kinds = match frame.kind {
+                FrameKind::Php => vec![StackByteKind::Status(stack[at(0)])],
+                FrameKind::Jsr => vec![StackByteKind::ReturnHigh(ret.wrapping_add(1)), StackByteKind::ReturnLow(ret.wrapping_add(1))],
+                _ => vec![StackByteKind::ReturnHigh(ret), StackByteKind::ReturnLow(ret), StackByteKind::Status(stack[at(2)])],
+            };
+            for (offset, kind) in kinds.into_iter().enumerate() {
+                let n = at(offset as u8);
+                if n > sp as usize {
+                    bytes[n - sp as usize - 1].kind = kind;
+                    bytes[n - sp as usize - 1].frame = index;
+                }
+            }
+        }
+        bytes
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::debugger::stack_view;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_call_stack() {
+        let source = "
+            reset: LDX #$FF
+                   TXS
+                   JSR outer
+            done:  JMP done
+            outer: PHP
+                   LDA #$42
+                   PHA
+                   JSR inner
+                   PLA
+                   PLP
+                   RTS
+            inner: NOP
+            stop:  RTS
+        ";
+        let mut nes = TestRom::new().asm(0x8000, source).nes().unwrap();
+        nes.start_call_stack();
+        nes.run_until_pc(0x8014).unwrap(); // stop
+        let frames = nes.call_stack().unwrap().frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].caller, frames[0].target), (0x8003, 0x8009));
+        assert_eq!((frames[1].caller, frames[1].target), (0x800D, 0x8013));
+
+        let stack = stack_view(&nes);
+        let kinds: Vec<StackByteKind> = stack.iter().map(|b| b.kind).collect();
+        assert_eq!(stack[0].addr, 0x01FA);
+        assert_eq!(
+            kinds,
+            vec![
+                StackByteKind::ReturnLow(0x8010),
+                StackByteKind::ReturnHigh(0x8010),
+                StackByteKind::Data,
+                StackByteKind::Status(stack[3].value),
+                StackByteKind::ReturnLow(0x8006),
+                StackByteKind::ReturnHigh(0x8006),
+            ]
+        );
+        assert_eq!((stack[2].value, stack[0].frame, stack[5].frame), (0x42, Some(1), Some(0)));
+
+        // RTS で戻ると消える
+        nes.run_until_pc(0x8006).unwrap();
+        assert!(nes.call_stack().unwrap().frames().is_empty());
+        assert!(stack_view(&nes).is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+// C/C++ のアプリやゲームエンジンに組み込むためのAPI
+//   cargo build --release --lib --features capi  (libnes_core.so / nes_core.dll と include/nes_core.h を使う)
+// 関数はすべて nes_create で作ったポインタを受け取る (インスタンスはいくつでも作れる)
+// ヘッダーは build.rs が cbindgen で作る。/// の1行目がヘッダーのコメントになる
+// パニックは C 側に伝えない (catch_unwind して false / NULL を返す)
+use crate::apu::{AudioConfig, APU};
+use crate::frame::Frame;
+use crate::gamepad::Button;
+use crate::nes::Nes;
+use crate::region::Region;
+use crate::rom::Rom;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// ボタンのビット (nes_set_input)
+pub const NES_BUTTON_A: u8 = 0x01;
+pub const NES_BUTTON_B: u8 = 0x02;
+pub const NES_BUTTON_SELECT: u8 = 0x04;
+pub const NES_BUTTON_START: u8 = 0x08;
+pub const NES_BUTTON_UP: u8 = 0x10;
+pub const NES_BUTTON_DOWN: u8 = 0x20;
+pub const NES_BUTTON_LEFT: u8 = 0x40;
+pub const NES_BUTTON_RIGHT: u8 = 0x80;
+
+/// 中身は見せない
+pub struct NesInstance {
+    nes: Option<Nes>, // ROMを読み込むまではNone
+    sample_rate: i32,
+    samples: Vec<f32>, // 直前のフレームで生成したサンプル (モノラル)
+}
+
+impl NesInstance {
+    fn load(&mut self, data: &[u8]) -> Result<(), String> {
+        let rom = Rom::from_bytes(data).map_err(|e| e.to_string())?;
+        let region = Region::from_header(&rom.header).unwrap_or(Region::Ntsc);
+        let config = AudioConfig {
+            sample_rate: self.sample_rate,
+            ..AudioConfig::new()
+        };
+        let mut nes = Nes::new(rom, APU::headless(&config)).map_err(|e| e.to_string())?;
+        nes.set_region(region);
+        self.nes = Some(nes);
+        self.samples.clear();
+        Ok(())
+    }
+}
+
+// パニックしたら failed を返す
+fn guard<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        log::warn!("[ERR] panic in the C API");
+        failed
+    })
+}
+
+/// sample_rate: 0 なら既定 (44100)
+#[no_mangle]
+pub extern "C" fn nes_create(sample_rate: i32) -> *mut NesInstance {
+    guard(std::ptr::null_mut(), || {
+        let instance = NesInstance {
+            nes: None,
+            sample_rate: if sample_rate > 0 { sample_rate } else { AudioConfig::new().sample_rate },
+            samples: vec![],
+        };
+        Box::into_raw(Box::new(instance))
+    })
+}
+
+/// nes_create で作ったインスタンスを解放する
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL。呼んだあとは使わない
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(instance: *mut NesInstance) {
+    guard((), || {
+        if !instance.is_null() {
+            drop(Box::from_raw(instance));
+        }
+    })
+}
+
+/// iNES/NES 2.0 のイメージ。data はコピーするので戻ったら解放してよい
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL。data は len バイト読めること
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(instance: *mut NesInstance, data: *const u8, len: usize) -> bool {
+    guard(false, || {
+        let instance = match instance.as_mut() {
+            Some(instance) if !data.is_null() => instance,
+            _ => return false,
+        };
+        match instance.load(std::slice::from_raw_parts(data, len)) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("[ERR] {}", e);
+                false
+            }
+        }
+    })
+}
+
+/// 1フレーム実行する。ROMが読み込まれていなければ false
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL
+#[no_mangle]
+pub unsafe extern "C" fn nes_run_frame(instance: *mut NesInstance) -> bool {
+    guard(false, || {
+        let instance = match instance.as_mut() {
+            Some(instance) => instance,
+            None => return false,
+        };
+        match instance.nes.as_mut() {
+            Some(nes) => {
+                nes.run_frame();
+                instance.samples.clear();
+                nes.apu_mut().drain_samples(&mut instance.samples);
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// nes_video_width() x nes_video_height() の RGB (1画素3バイト)。次の nes_run_frame / nes_load_rom まで有効
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL
+#[no_mangle]
+pub unsafe extern "C" fn nes_video(instance: *const NesInstance) -> *const u8 {
+    guard(std::ptr::null(), || match instance.as_ref().and_then(|instance| instance.nes.as_ref()) {
+        Some(nes) => nes.frame().data.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
+
+/// 画面の幅 (256)
+#[no_mangle]
+pub extern "C" fn nes_video_width() -> usize {
+    guard(0, || Frame::WIDTH)
+}
+
+/// 画面の高さ (240)
+#[no_mangle]
+pub extern "C" fn nes_video_height() -> usize {
+    guard(0, || Frame::HEIGHT)
+}
+
+/// 直前のフレームのサンプル (float, モノラル)。数を count に書く
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL。count は書き込めるポインタか NULL
+#[no_mangle]
+pub unsafe extern "C" fn nes_audio(instance: *const NesInstance, count: *mut usize) -> *const f32 {
+    guard(std::ptr::null(), || {
+        let samples = match instance.as_ref() {
+            Some(instance) => &instance.samples[..],
+            None => &[],
+        };
+        if let Some(count) = count.as_mut() {
+            *count = samples.len();
+        }
+        samples.as_ptr()
+    })
+}
+
+/// player: 0-3, buttons: NES_BUTTON_* の組み合わせ
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL
+#[no_mangle]
+pub unsafe extern "C" fn nes_set_input(instance: *mut NesInstance, player: usize, buttons: u8) {
+    guard((), || {
+        if let Some(nes) = instance.as_mut().and_then(|instance| instance.nes.as_mut()) {
+            nes.set_controller_state(player, Button::from_bits_truncate(buttons));
+        }
+    })
+}
+
+/// リセットボタンを押す
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL
+#[no_mangle]
+pub unsafe extern "C" fn nes_reset(instance: *mut NesInstance) {
+    guard((), || {
+        if let Some(nes) = instance.as_mut().and_then(|instance| instance.nes.as_mut()) {
+            nes.soft_reset();
+        }
+    })
+}
+
+/// 電源を入れ直す
+///
+/// # Safety
+/// instance は nes_create が返したポインタか NULL
+#[no_mangle]
+pub unsafe extern "C" fn nes_power_cycle(instance: *mut NesInstance) {
+    guard((), || {
+        if let Some(nes) = instance.as_mut().and_then(|instance| instance.nes.as_mut()) {
+            nes.power_cycle();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_capi() {
+        let rom = TestRom::new().asm(0x8000, "loop: JMP loop").to_bytes();
+        unsafe {
+            let instance = nes_create(0);
+            assert!(!nes_run_frame(instance));
+            assert!(nes_video(instance).is_null());
+            assert!(!nes_load_rom(instance, [0u8; 4].as_ptr(), 4));
+            assert!(nes_load_rom(instance, rom.as_ptr(), rom.len()));
+            assert!(nes_run_frame(instance));
+            assert!(!nes_video(instance).is_null());
+            let mut count = 0;
+            assert!(!nes_audio(instance, &mut count).is_null());
+            assert!(count > 0);
+            nes_set_input(instance, 0, 0x81);
+            nes_reset(instance);
+            nes_destroy(instance);
+            nes_destroy(std::ptr::null_mut());
+        }
+
+        // ヘッダーのボタンのビットは gamepad と同じ
+        let buttons = [NES_BUTTON_A, NES_BUTTON_B, NES_BUTTON_SELECT, NES_BUTTON_START];
+        let dpad = [NES_BUTTON_UP, NES_BUTTON_DOWN, NES_BUTTON_LEFT, NES_BUTTON_RIGHT];
+        let expected = [Button::BUTTON_A, Button::BUTTON_B, Button::SELECT, Button::START, Button::UP, Button::DOWN, Button::LEFT, Button::RIGHT];
+        for (bit, button) in buttons.iter().chain(&dpad).zip(expected) {
+            assert_eq!(*bit, button.bits());
+        }
+        assert!(!guard(false, || panic!("boom")));
+    }
+}
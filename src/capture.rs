@@ -0,0 +1,480 @@
+// 画面の録画 (アニメーションGIF / APNG)
+// https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+// https://wiki.mozilla.org/APNG_Specification
+// 前のフレームから変わった範囲だけを書き出す (GIFは変わっていない画素を透明にする)
+// decimation で何フレームに1枚残すかを決める (2なら約30fps)
+// APNGは外部クレートを使わないので、zlibは無圧縮ブロックで書く
+use crate::checksum;
+use crate::frame::Frame;
+use std::collections::HashMap;
+use std::path::Path;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+const MAX_COLORS: usize = 255; // GIFの透明色に1つ空けておく
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptureFormat {
+    Gif,
+    Apng,
+}
+
+impl CaptureFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CaptureFormat::Gif => "gif",
+            CaptureFormat::Apng => "png",
+        }
+    }
+}
+
+pub struct Capture {
+    format: CaptureFormat,
+    frame_rate: f64,
+    decimation: usize,
+    max_frames: usize,
+    count: usize,          // 受け取ったフレーム数 (間引く前)
+    frames: Vec<Vec<u8>>, // 色番号
+    colors: Vec<Rgb>,
+    lookup: HashMap<Rgb, u8>,
+}
+
+impl Capture {
+    // seconds 秒分 (エミュレーター上の時間) で打ち切る
+    pub fn new(format: CaptureFormat, frame_rate: f64, seconds: f64, decimation: usize) -> Self {
+        let decimation = decimation.max(1);
+        Capture {
+            format,
+            frame_rate,
+            decimation,
+            max_frames: ((seconds * frame_rate) as usize / decimation).max(1),
+            count: 0,
+            frames: Vec::new(),
+            colors: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.frames.len() >= self.max_frames
+    }
+
+    // 毎フレーム呼ぶ。いっぱいになったら false
+    pub fn push(&mut self, frame: &Frame) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        self.count += 1;
+        if (self.count - 1).is_multiple_of(self.decimation) {
+            let indices = frame.data.chunks_exact(3).map(|p| self.color_index((p[0], p[1], p[2]))).collect();
+            self.frames.push(indices);
+        }
+        !self.is_full()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        match self.format {
+            CaptureFormat::Gif => self.encode_gif(),
+            CaptureFormat::Apng => self.encode_apng(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(path, self.encode()).map_err(|e| e.to_string())
+    }
+
+    // パレットが埋まったら一番近い色にする (通常はNESの64色に収まる)
+    fn color_index(&mut self, rgb: Rgb) -> u8 {
+        if let Some(&index) = self.lookup.get(&rgb) {
+            return index;
+        }
+        if self.colors.len() < MAX_COLORS {
+            let index = self.colors.len() as u8;
+            self.colors.push(rgb);
+            self.lookup.insert(rgb, index);
+            return index;
+        }
+        let distance = |c: &Rgb| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(c.0, rgb.0) + d(c.1, rgb.1) + d(c.2, rgb.2)
+        };
+        let (index, _) = self.colors.iter().enumerate().min_by_key(|(_, c)| distance(c)).unwrap();
+        index as u8
+    }
+
+    // 前のフレームから変わった範囲 (x, y, w, h)。変化がなければ1画素だけ
+    fn changed_rect(prev: Option<&Vec<u8>>, frame: &[u8]) -> (usize, usize, usize, usize) {
+        let prev = match prev {
+            Some(prev) => prev,
+            None => return (0, 0, WIDTH, HEIGHT),
+        };
+        let (mut x1, mut y1, mut x2, mut y2) = (WIDTH, HEIGHT, 0, 0);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                if prev[y * WIDTH + x] != frame[y * WIDTH + x] {
+                    x1 = x1.min(x);
+                    y1 = y1.min(y);
+                    x2 = x2.max(x + 1);
+                    y2 = y2.max(y + 1);
+                }
+            }
+        }
+        if x1 >= x2 {
+            return (0, 0, 1, 1);
+        }
+        (x1, y1, x2 - x1, y2 - y1)
+    }
+
+    // 1枚の表示時間を 1/100 秒単位で、誤差をためないように配る
+    fn gif_delays(&self) -> Vec<u16> {
+        let frame_time = self.decimation as f64 * 100.0 / self.frame_rate;
+        let mut shown = 0;
+        (1..=self.frames.len())
+            .map(|i| {
+                let end = (i as f64 * frame_time).round() as u16;
+                let delay = end - shown;
+                shown = end;
+                delay
+            })
+            .collect()
+    }
+
+    fn encode_gif(&self) -> Vec<u8> {
+        // 透明色を含めた色数が収まるビット数
+        let mut bits = 2;
+        while (1 << bits) < self.colors.len() + 1 {
+            bits += 1;
+        }
+        let transparent = self.colors.len() as u8;
+
+        let mut out = b"GIF89a".to_vec();
+        out.extend_from_slice(&(WIDTH as u16).to_le_bytes());
+        out.extend_from_slice(&(HEIGHT as u16).to_le_bytes());
+        out.push(0x80 | ((bits - 1) << 4) as u8 | (bits - 1) as u8);
+        out.extend_from_slice(&[0, 0]);
+        for i in 0..(1 << bits) {
+            let (r, g, b) = self.colors.get(i).copied().unwrap_or((0, 0, 0));
+            out.extend_from_slice(&[r, g, b]);
+        }
+        // 無限ループ
+        out.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        for (i, (frame, delay)) in self.frames.iter().zip(self.gif_delays()).enumerate() {
+            let prev = if i == 0 { None } else { Some(&self.frames[i - 1]) };
+            let (x, y, w, h) = Capture::changed_rect(prev, frame);
+            let mut pixels = Vec::with_capacity(w * h);
+            for row in y..y + h {
+                for col in x..x + w {
+                    let index = frame[row * WIDTH + col];
+                    let same = prev.is_some_and(|prev| prev[row * WIDTH + col] == index);
+                    pixels.push(if same { transparent } else { index });
+                }
+            }
+
+            // Graphic Control Extension (前の画像を残す, 透明色あり)
+            out.extend_from_slice(&[0x21, 0xF9, 0x04, 0x05]);
+            out.extend_from_slice(&delay.to_le_bytes());
+            out.extend_from_slice(&[transparent, 0x00]);
+            // Image Descriptor
+            out.push(0x2C);
+            for v in [x, y, w, h] {
+                out.extend_from_slice(&(v as u16).to_le_bytes());
+            }
+            out.push(0x00);
+            out.push(bits as u8);
+            for block in lzw_encode(&pixels, bits as u8).chunks(255) {
+                out.push(block.len() as u8);
+                out.extend_from_slice(block);
+            }
+            out.push(0x00);
+        }
+        out.push(0x3B);
+        out
+    }
+
+    fn encode_apng(&self) -> Vec<u8> {
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8bit RGB
+        png_chunk(&mut out, b"IHDR", &ihdr);
+        let mut actl = Vec::new();
+        actl.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        actl.extend_from_slice(&0u32.to_be_bytes()); // 無限ループ
+        png_chunk(&mut out, b"acTL", &actl);
+
+        // 表示時間は decimation / frame_rate 秒 (分母は1/100fps単位)
+        let delay_num = (self.decimation * 100) as u16;
+        let delay_den = (self.frame_rate * 100.0).round() as u16;
+        let mut sequence = 0u32;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let prev = if i == 0 { None } else { Some(&self.frames[i - 1]) };
+            let (x, y, w, h) = Capture::changed_rect(prev, frame);
+            let mut fctl = Vec::new();
+            fctl.extend_from_slice(&sequence.to_be_bytes());
+            for v in [w, h, x, y] {
+                fctl.extend_from_slice(&(v as u32).to_be_bytes());
+            }
+            fctl.extend_from_slice(&delay_num.to_be_bytes());
+            fctl.extend_from_slice(&delay_den.to_be_bytes());
+            fctl.extend_from_slice(&[0, 0]); // dispose: none, blend: source
+            png_chunk(&mut out, b"fcTL", &fctl);
+            sequence += 1;
+
+            let mut raw = Vec::with_capacity((w * 3 + 1) * h);
+            for row in y..y + h {
+                raw.push(0); // フィルタなし
+                for col in x..x + w {
+                    let (r, g, b) = self.colors[frame[row * WIDTH + col] as usize];
+                    raw.extend_from_slice(&[r, g, b]);
+                }
+            }
+            let data = zlib_stored(&raw);
+            if i == 0 {
+                png_chunk(&mut out, b"IDAT", &data);
+            } else {
+                let mut fdat = sequence.to_be_bytes().to_vec();
+                fdat.extend_from_slice(&data);
+                png_chunk(&mut out, b"fdAT", &fdat);
+                sequence += 1;
+            }
+        }
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+}
+
+// 1枚だけのPNG (スクリーンショットやPPUのダンプ用)
+pub fn encode_png(width: usize, height: usize, pixels: &[Rgb]) -> Vec<u8> {
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8bit RGB
+    png_chunk(&mut out, b"IHDR", &ihdr);
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks(width).take(height) {
+        raw.push(0); // フィルタなし
+        for &(r, g, b) in row {
+            raw.extend_from_slice(&[r, g, b]);
+        }
+    }
+    png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+// encode_png で書いたPNG (8bit RGB, フィルタなし, 無圧縮ブロック) だけ読める。それ以外はNone
+pub fn decode_png(data: &[u8]) -> Option<(usize, usize, Vec<Rgb>)> {
+    let mut rest = data.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    let (mut width, mut height, mut idat) = (0, 0, Vec::new());
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[0..4].try_into().ok()?) as usize;
+        let body = rest.get(8..8 + len)?;
+        match &rest[4..8] {
+            b"IHDR" if body.get(8..10)? == [8, 2] && body.get(12)? == &0 => {
+                width = u32::from_be_bytes(body[0..4].try_into().ok()?) as usize;
+                height = u32::from_be_bytes(body[4..8].try_into().ok()?) as usize;
+            }
+            b"IHDR" => return None,
+            b"IDAT" => idat.extend_from_slice(body),
+            _ => {}
+        }
+        rest = rest.get(12 + len..)?;
+    }
+
+    // zlibのヘッダを飛ばして無圧縮ブロックをつなぐ
+    let mut raw = Vec::new();
+    let mut pos = 2;
+    loop {
+        let header = *idat.get(pos)?;
+        if header & 0x06 != 0 {
+            return None;
+        }
+        let len = u16::from_le_bytes([*idat.get(pos + 1)?, *idat.get(pos + 2)?]) as usize;
+        raw.extend_from_slice(idat.get(pos + 5..pos + 5 + len)?);
+        pos += 5 + len;
+        if header & 0x01 != 0 {
+            break;
+        }
+    }
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in raw.chunks(width * 3 + 1).take(height) {
+        if row[0] != 0 || row.len() != width * 3 + 1 {
+            return None;
+        }
+        pixels.extend(row[1..].chunks(3).map(|c| (c[0], c[1], c[2])));
+    }
+    (pixels.len() == width * height).then_some((width, height, pixels))
+}
+
+// GIFのLZW (可変長コード、4096で辞書をクリア)
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = Vec::new();
+    let (mut acc, mut acc_bits) = (0u32, 0u32);
+    let mut write = |code: u16, size: u32, out: &mut Vec<u8>| {
+        acc |= (code as u32) << acc_bits;
+        acc_bits += size;
+        while acc_bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            acc_bits -= 8;
+        }
+    };
+
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_code_size as u32 + 1;
+    write(clear, size, &mut out);
+    let mut prefix: Option<u16> = None;
+    for &k in indices {
+        let p = match prefix {
+            None => {
+                prefix = Some(k as u16);
+                continue;
+            }
+            Some(p) => p,
+        };
+        if let Some(&code) = dict.get(&(p, k)) {
+            prefix = Some(code);
+            continue;
+        }
+        write(p, size, &mut out);
+        if next < 4096 {
+            dict.insert((p, k), next);
+            next += 1;
+            // 次に出すコードが今のビット数に収まらなくなったら増やす
+            if next > (1 << size) && size < 12 {
+                size += 1;
+            }
+        } else {
+            write(clear, size, &mut out);
+            dict.clear();
+            next = end + 1;
+            size = min_code_size as u32 + 1;
+        }
+        prefix = Some(k as u16);
+    }
+    if let Some(p) = prefix {
+        write(p, size, &mut out);
+    }
+    write(end, size, &mut out);
+    write(0, 7, &mut out); // 端数を押し出す
+    out
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = checksum::crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// 無圧縮ブロックだけのzlibストリーム
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 0x01 } else { 0x00 });
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&checksum::adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テスト用のLZWデコーダ
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let mut dict: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_code_size as usize + 1;
+        let (mut pos, mut out, mut prev): (usize, Vec<u8>, Option<Vec<u8>>) = (0, vec![], None);
+        loop {
+            let mut code = 0;
+            for i in 0..size {
+                code |= ((data[(pos + i) / 8] >> ((pos + i) % 8)) as usize & 1) << i;
+            }
+            pos += size;
+            if code == clear {
+                dict = (0..clear).map(|i| vec![i as u8]).collect();
+                dict.push(vec![]);
+                dict.push(vec![]);
+                size = min_code_size as usize + 1;
+                prev = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (&prev, code < dict.len()) {
+                (_, true) => dict[code].clone(),
+                (Some(p), false) => [p.clone(), vec![p[0]]].concat(),
+                (None, false) => panic!("bad code"),
+            };
+            if let Some(p) = prev {
+                dict.push([p, vec![entry[0]]].concat());
+                if dict.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    #[test]
+    fn test_lzw_roundtrip() {
+        let mut pixels: Vec<u8> = (0..20000u32).map(|i| ((i * 7 / 13) % 5) as u8).collect();
+        pixels.extend((0..8000u32).map(|i| (i.wrapping_mul(2654435761) >> 28) as u8 % 17));
+        for &(bits, modulo) in [(3u8, 5u8), (5, 17)].iter() {
+            let data: Vec<u8> = pixels.iter().map(|p| p % modulo).collect();
+            assert_eq!(lzw_decode(&lzw_encode(&data, bits), bits), data);
+        }
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut frame = Frame::new();
+        let mut capture = Capture::new(CaptureFormat::Gif, 60.0, 0.1, 2);
+        for i in 0..6 {
+            frame.set_pixel(i, 10, (0xFF, 0, 0));
+            assert_eq!(capture.push(&frame), i < 4);
+        }
+        assert_eq!(capture.frames(), 3);
+        assert_eq!(capture.gif_delays(), vec![3, 4, 3]);
+        let gif = capture.encode();
+        assert!(gif.starts_with(b"GIF89a") && gif.ends_with(&[0x3B]));
+
+        let mut capture = Capture::new(CaptureFormat::Apng, 60.0, 0.05, 1);
+        capture.push(&frame);
+        let png = capture.encode();
+        assert!(png.starts_with(b"\x89PNG") && png.windows(4).any(|w| w == b"acTL"));
+    }
+}
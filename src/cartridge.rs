@@ -1,12 +1,475 @@
-use crate::rom::Rom;
-use std::fs::File;
+use crate::common::*;
+use crate::mapper::{Mapper, MapperMMC};
+use crate::cnrom::Cnrom;
+#[cfg(feature = "std")]
+use crate::error::EmuError;
+use crate::color_dreams::ColorDreams;
+use crate::fme7::Fme7;
+use crate::gxrom::Gxrom;
+use crate::mmc3::{Mmc3, Mmc3Features};
+use crate::n163::N163;
+use crate::nrom::Nrom;
+use crate::nsf::NsfMapper;
+use crate::uxrom::Uxrom;
+use crate::vrc4::Vrc4;
+use crate::rom::{Mirroring, Rom, RomError, RomHeader};
+use crate::snapshot::MapperSnapshot;
+use log::{error, warn};
+#[cfg(feature = "std")]
+use log::info;
+#[cfg(feature = "std")]
+use crate::sram::{SramConfig, SramFile};
+use crate::state::{State, StateError, StateReader, StateWriter};
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+#[cfg(feature = "std")]
 use std::io::Read;
+use crate::sync::{Arc, Mutex};
+use alloc::boxed::Box;
 
-pub fn load_rom(path: &str) -> Rom {
-    let mut f = File::open(path).expect("no file found");
-    let metadata = std::fs::metadata(path).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-    let rom = Rom::new(&buffer).expect("load error");
-    rom
-}
\ No newline at end of file
+#[cfg(feature = "std")]
+pub fn load_rom(path: &str) -> Result<Rom, EmuError> {
+    let buffer = std::fs::read(path)?;
+    Ok(Rom::from_bytes(&buffer)?)
+}
+
+// ネットワークやアーカイブなど、ファイル以外から読み込む (no_std では Rom::from_bytes)
+#[cfg(feature = "std")]
+pub fn load_rom_from_reader<R: Read>(mut reader: R) -> Result<Rom, EmuError> {
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    Ok(Rom::from_bytes(&buffer)?)
+}
+
+// CPUバスとPPUの両方から触るので共有する (本体ごとに1つ)
+pub type SharedCartridge = Arc<Mutex<Cartridge>>;
+
+// PRG RAM ($6000) 内でのトレーナーの位置
+const TRAINER_OFFSET: usize = 0x7000 - 0x6000;
+
+// マッパー番号からマッパーを組み立てる関数
+pub type MapperConstructor = fn(&Rom) -> Box<dyn Mapper>;
+
+lazy_static! {
+    // 実行時に追加・差し替えできるマッパーの一覧
+    static ref MAPPER_REGISTRY: Mutex<BTreeMap<u8, MapperConstructor>> = Mutex::new(builtin_mappers());
+}
+
+fn builtin_mappers() -> BTreeMap<u8, MapperConstructor> {
+    let mut mappers: BTreeMap<u8, MapperConstructor> = BTreeMap::new();
+    mappers.insert(_MAPPER_0, |rom| Box::new(Nrom::new(rom)));
+    mappers.insert(_MAPPER_1, mmc1);
+    mappers.insert(_MAPPER_2, |rom| Box::new(Uxrom::new(rom)));
+    mappers.insert(_MAPPER_3, |rom| Box::new(Cnrom::new(rom)));
+    mappers.insert(_MAPPER_4, |rom| Box::new(Mmc3::new(rom)));
+    mappers.insert(_MAPPER_11, |rom| Box::new(ColorDreams::new(rom)));
+    mappers.insert(_MAPPER_19, |rom| Box::new(N163::new(rom)));
+    for mapper in [_MAPPER_21, _MAPPER_22, _MAPPER_23, _MAPPER_25] {
+        mappers.insert(mapper, |rom| Box::new(Vrc4::new(rom)));
+    }
+    mappers.insert(_MAPPER_66, |rom| Box::new(Gxrom::new(rom)));
+    mappers.insert(_MAPPER_69, |rom| Box::new(Fme7::new(rom)));
+    mappers.insert(_MAPPER_206, |rom| {
+        Box::new(Mmc3::with_features(rom, Mmc3Features::NAMCO_108))
+    });
+    mappers
+}
+
+fn mmc1(rom: &Rom) -> Box<dyn Mapper> {
+    let mut mmc = MapperMMC::new();
+    mmc.prg_rom = rom.prg_rom.clone();
+    mmc.chr_rom = rom.chr_rom.clone();
+    mmc.is_chr_ram = rom.is_chr_ram;
+    mmc.is_prg_ram = rom.is_prg_ram;
+    mmc.mapper = rom.mapper;
+    mmc.rom_type = rom.rom_type.clone();
+    mmc.mmc_1.rom_type = rom.rom_type.clone();
+    mmc.mirroring = rom.mirroring.clone();
+    Box::new(mmc)
+}
+
+// マッパーを登録する (同じ番号があれば差し替えて、前のものを返す)
+// 実験用のマッパーなどをこのクレートを変更せずに追加できる
+pub fn register_mapper(mapper: u8, constructor: MapperConstructor) -> Option<MapperConstructor> {
+    MAPPER_REGISTRY.lock().insert(mapper, constructor)
+}
+
+pub fn is_mapper_supported(mapper: u8) -> bool {
+    MAPPER_REGISTRY.lock().contains_key(&mapper)
+}
+
+// ROMのヘッダから適切なマッパーを組み立てる
+fn create_mapper(rom: &Rom) -> Result<Box<dyn Mapper>, RomError> {
+    if let Some(nsf) = &rom.nsf {
+        return Ok(Box::new(NsfMapper::new(nsf)));
+    }
+    let constructor = MAPPER_REGISTRY.lock().get(&rom.mapper).copied();
+    match constructor {
+        Some(constructor) => Ok(constructor(rom)),
+        None => Err(RomError::UnsupportedMapper(rom.mapper)),
+    }
+}
+
+// トレーナーはPRG RAMの$7000-$71FFに置く
+fn load_trainer(mapper: &mut dyn Mapper, trainer: &[u8]) {
+    match mapper.prg_ram_mut() {
+        Some(ram) if ram.len() >= TRAINER_OFFSET + trainer.len() => {
+            ram[TRAINER_OFFSET..(TRAINER_OFFSET + trainer.len())].copy_from_slice(trainer);
+        }
+        _ => warn!(target: "mapper", "Trainer ignored (no PRG RAM at $7000)"),
+    }
+}
+
+pub struct Cartridge {
+    pub header: RomHeader,
+    rom: Rom, // 電源を入れ直すときに使う
+    mapper: Box<dyn Mapper>,
+    #[cfg(feature = "std")]
+    sram: Option<SramFile>,
+}
+
+impl Cartridge {
+    pub fn new(rom: &Rom) -> Result<Cartridge, RomError> {
+        let mut mapper = create_mapper(rom)?;
+        if let Some(trainer) = &rom.trainer {
+            load_trainer(mapper.as_mut(), trainer);
+        }
+        Ok(Cartridge {
+            header: rom.header.clone(),
+            rom: rom.clone(),
+            mapper,
+            #[cfg(feature = "std")]
+            sram: None,
+        })
+    }
+
+    // バッテリー付きのカートリッジなら.savを読み込み、以降の書き出し先にする
+    #[cfg(feature = "std")]
+    pub fn load_sram(&mut self, rom_path: &str, config: &SramConfig) -> io::Result<()> {
+        if !self.header.has_battery {
+            return Ok(());
+        }
+        let ram = match self.mapper.prg_ram_mut() {
+            Some(ram) => ram,
+            None => return Ok(()),
+        };
+        let path = config.save_path(Path::new(rom_path));
+        let sram = SramFile::open(path, config, ram)?;
+        info!("SRAM: {}", sram.path().display());
+        self.sram = Some(sram);
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save_sram(&mut self) -> io::Result<()> {
+        match (&mut self.sram, self.mapper.prg_ram_mut()) {
+            (Some(sram), Some(ram)) => sram.save(ram),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery
+    }
+
+    // 定期的に呼ばれ、間隔が経っていれば書き出す
+    #[cfg(feature = "std")]
+    pub fn flush_sram_if_due(&mut self) {
+        if self.sram.as_ref().is_some_and(|sram| sram.is_flush_due()) {
+            if let Err(e) = self.save_sram() {
+                error!("[ERR] SRAM save: {}", e);
+            }
+        }
+    }
+
+    // リセットボタン (マッパーごとの動作)
+    pub fn reset(&mut self) {
+        self.mapper.reset()
+    }
+
+    // 電源の入れ直し: マッパーを作り直す (バッテリーバックアップされたRAMは残る)
+    pub fn power_cycle(&mut self) {
+        if self.rom.prg_rom.is_empty() {
+            return;
+        }
+        #[cfg(feature = "std")]
+        if let Err(e) = self.save_sram() {
+            error!("[ERR] SRAM save: {}", e);
+        }
+        // 一度作れているので失敗しないはずだが、失敗したら今のマッパーのまま続ける
+        match create_mapper(&self.rom) {
+            Ok(mapper) => self.mapper = mapper,
+            Err(e) => {
+                error!("[ERR] {}", e);
+                return;
+            }
+        }
+        if let Some(trainer) = &self.rom.trainer {
+            load_trainer(self.mapper.as_mut(), trainer);
+        }
+        #[cfg(feature = "std")]
+        if let (Some(sram), Some(ram)) = (&self.sram, self.mapper.prg_ram_mut()) {
+            sram.restore(ram);
+        }
+    }
+
+    // ステートセーブ: 別のROMのステートを読み込まないように、CRC32とマッパー番号を先頭に置く
+    pub fn rom_crc32(&self) -> u32 {
+        self.rom.crc32
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        self.rom.crc32.save(w);
+        self.rom.mapper.save(w);
+        self.mapper.save(w);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let (mut crc32, mut mapper) = (0u32, 0u8);
+        crc32.load(r)?;
+        mapper.load(r)?;
+        if crc32 != self.rom.crc32 || mapper != self.rom.mapper {
+            return Err(StateError::Mismatch("cartridge"));
+        }
+        self.mapper.load(r)
+    }
+
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.cpu_read(addr)
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.cpu_write(addr, data)
+    }
+
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.prg_rom_offset(addr)
+    }
+
+    pub fn snapshot(&self) -> MapperSnapshot {
+        let (prg_banks, chr_banks) =
+            MapperSnapshot::banks(|addr| self.mapper.prg_rom_offset(addr), |addr| self.mapper.chr_offset(addr));
+        MapperSnapshot {
+            mapper: self.rom.mapper,
+            mirroring: self.mapper.mirroring(),
+            irq_pending: self.mapper.irq_pending(),
+            prg_banks,
+            chr_banks,
+            prg_rom_len: self.prg_rom_len(),
+            chr_rom_len: self.chr_rom_len(),
+        }
+    }
+
+    pub fn prg_rom_len(&self) -> usize {
+        self.rom.prg_rom.len()
+    }
+
+    // CHR RAMなら0
+    pub fn chr_rom_len(&self) -> usize {
+        if self.rom.is_chr_ram {
+            0
+        } else {
+            self.rom.chr_rom.len()
+        }
+    }
+
+    pub fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.mapper.ppu_read(addr)
+    }
+
+    pub fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.ppu_write(addr, data)
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.mapper.irq_pending()
+    }
+
+    pub fn ppu_a12_rising(&mut self) {
+        self.mapper.ppu_a12_rising()
+    }
+
+    pub fn cpu_tick(&mut self) {
+        self.mapper.cpu_tick()
+    }
+
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.audio_output()
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+}
+
+// 差し替えや終了で手放されるときに書き出す
+#[cfg(feature = "std")]
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(e) = self.save_sram() {
+            error!("[ERR] SRAM save: {}", e);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // 何を読んでも0x42を返すだけの実験用マッパー
+    struct TestMapper;
+
+    crate::impl_state!(TestMapper {});
+
+    impl Mapper for TestMapper {
+        fn cpu_read(&mut self, _addr: u16) -> u8 {
+            0x42
+        }
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+        fn ppu_read(&mut self, _addr: u16) -> u8 {
+            0x42
+        }
+        fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::VERTICAL
+        }
+    }
+
+    #[test]
+    fn test_register_mapper() {
+        // mapper 255 (flags6 = 0xF0, flags7 = 0xF0)
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0xF0, 0xF0];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        let rom = Rom::new(&raw).unwrap();
+        assert!(!is_mapper_supported(255));
+        assert!(Cartridge::new(&rom).is_err());
+
+        assert!(register_mapper(255, |_| Box::new(TestMapper)).is_none());
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 0x42);
+
+        // 差し替えると前のものが返る
+        assert!(register_mapper(255, |rom| Box::new(Nrom::new(rom))).is_some());
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 0);
+    }
+
+    #[test]
+    fn test_load_rom_from_reader() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x01, 0x00];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        let rom = load_rom_from_reader(std::io::Cursor::new(raw.clone())).unwrap();
+        assert_eq!(rom.prg_rom.len(), 0x4000);
+        assert_eq!(rom.mirroring, Mirroring::VERTICAL);
+
+        raw.truncate(100);
+        assert!(matches!(
+            load_rom_from_reader(&raw[..]),
+            Err(EmuError::Rom(RomError::TooShort { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x04, 0x00];
+        raw.resize(16, 0);
+        raw.extend((0..512).map(|i| i as u8));
+        let mut prg = vec![0; 0x4000];
+        prg[0] = 0xAA;
+        raw.extend(prg);
+        raw.resize(raw.len() + 0x2000, 0);
+
+        let rom = Rom::from_bytes(&raw).unwrap();
+        assert_eq!(rom.prg_rom[0], 0xAA);
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.cpu_read(0x6FFF), 0);
+        assert_eq!(cartridge.cpu_read(0x7000), 0);
+        assert_eq!(cartridge.cpu_read(0x7001), 1);
+        assert_eq!(cartridge.cpu_read(0x71FF), 0xFF);
+        assert_eq!(cartridge.cpu_read(0x8000), 0xAA);
+    }
+
+    #[test]
+    fn test_sram_lifecycle() {
+        let dir = std::env::temp_dir().join(format!("rscom_sram_{}", std::process::id()));
+        let config = SramConfig {
+            directory: Some(dir.clone()),
+            ..SramConfig::new()
+        };
+        // バッテリー付きNROM
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x02, 0x00];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        let rom = Rom::from_bytes(&raw).unwrap();
+
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.load_sram("test.nes", &config).unwrap();
+        cartridge.cpu_write(0x6000, 0x12);
+        cartridge.save_sram().unwrap();
+        cartridge.cpu_write(0x6001, 0x34);
+        drop(cartridge);
+
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.load_sram("test.nes", &config).unwrap();
+        assert_eq!(cartridge.cpu_read(0x6000), 0x12);
+        assert_eq!(cartridge.cpu_read(0x6001), 0x34);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_power_cycle() {
+        // UxROM (CHR RAM)
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 4, 0, 0x20, 0x00];
+        raw.resize(16, 0);
+        for bank in 0..4u8 {
+            raw.extend(vec![bank; 0x4000]);
+        }
+        let rom = Rom::from_bytes(&raw).unwrap();
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.cpu_write(0x8000, 2);
+        cartridge.ppu_write(0x0000, 0x55);
+        assert_eq!(cartridge.cpu_read(0x8000), 2);
+
+        // リセットボタンではバンクはそのまま
+        cartridge.reset();
+        assert_eq!(cartridge.cpu_read(0x8000), 2);
+
+        cartridge.power_cycle();
+        assert_eq!(cartridge.cpu_read(0x8000), 0);
+        assert_eq!(cartridge.ppu_read(0x0000), 0);
+    }
+
+    #[test]
+    fn test_cartridge_state() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 4, 0, 0x20, 0x00];
+        raw.resize(16, 0);
+        for bank in 0..4u8 {
+            raw.extend(vec![bank; 0x4000]);
+        }
+        let rom = Rom::from_bytes(&raw).unwrap();
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        cartridge.cpu_write(0x8000, 2);
+        cartridge.ppu_write(0x0010, 0x55);
+
+        let mut w = StateWriter::new();
+        cartridge.save_state(&mut w);
+        let state = w.into_bytes();
+
+        cartridge.power_cycle();
+        assert_eq!(cartridge.cpu_read(0x8000), 0);
+        cartridge.load_state(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 2);
+        assert_eq!(cartridge.ppu_read(0x0010), 0x55);
+
+        // 別のROM
+        raw[16] = 0xFF;
+        let mut other = Cartridge::new(&Rom::from_bytes(&raw).unwrap()).unwrap();
+        assert_eq!(
+            other.load_state(&mut StateReader::new(&state)),
+            Err(StateError::Mismatch("cartridge"))
+        );
+    }
+}
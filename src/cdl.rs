@@ -0,0 +1,146 @@
+// Code/Data Logger (FCEUX/Mesen と同じ .cdl 形式)
+// https://fceux.com/web/help/CodeDataLogger.html
+// PRG ROMの1バイトごとに1バイトのフラグを持ち、ファイルではその後ろにCHR ROMの分が続く
+//   PRG: xPdcAADC
+//     C: コードとして実行した
+//     D: データとして読んだ
+//     AA: 読んだときのCPUアドレスの8KB単位の位置 ($8000=0, $A000=1, $C000=2, $E000=3)
+//     c: 間接ジャンプ (JMP ($nnnn)) の飛び先
+//     d: 間接アドレッシング (($nn,X), ($nn),Y) で読んだ
+//     P: DMCのサンプル (このエミュレーターでは記録しない)
+//   CHR: 記録しない (読み込んだファイルの値をそのまま書き出す)
+#[cfg(feature = "std")]
+use std::path::Path;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const CDL_CODE: u8 = 0x01;
+pub const CDL_DATA: u8 = 0x02;
+pub const CDL_INDIRECT_CODE: u8 = 0x10;
+pub const CDL_INDIRECT_DATA: u8 = 0x20;
+pub const CDL_PCM: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CdlSummary {
+    pub code: usize,    // コードとして実行したバイト数
+    pub data: usize,    // データとして読んだバイト数 (コードと重なることもある)
+    pub unknown: usize, // まだ一度も触れていないバイト数
+}
+
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    // 実行中の命令 (オペランドの読み込みはデータとして数えない)
+    instruction: (u16, u16),
+    indirect: bool,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        CodeDataLogger {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+            instruction: (0, 0),
+            indirect: false,
+        }
+    }
+
+    // 以前のログの続きから記録する (ROMの大きさが合わなければエラー)
+    pub fn from_bytes(data: &[u8], prg_len: usize, chr_len: usize) -> Result<Self, String> {
+        if data.len() != prg_len + chr_len {
+            return Err(format!(
+                "CDL size {} does not match PRG {} + CHR {}",
+                data.len(),
+                prg_len,
+                chr_len
+            ));
+        }
+        let mut cdl = CodeDataLogger::new(prg_len, chr_len);
+        cdl.prg.copy_from_slice(&data[..prg_len]);
+        cdl.chr.copy_from_slice(&data[prg_len..]);
+        Ok(cdl)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path, prg_len: usize, chr_len: usize) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        CodeDataLogger::from_bytes(&data, prg_len, chr_len)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.prg[..], &self.chr[..]].concat()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    pub fn clear(&mut self) {
+        self.prg.iter_mut().for_each(|b| *b = 0);
+        self.chr.iter_mut().for_each(|b| *b = 0);
+    }
+
+    pub fn summary(&self) -> CdlSummary {
+        CdlSummary {
+            code: self.prg.iter().filter(|&&b| b & CDL_CODE != 0).count(),
+            data: self.prg.iter().filter(|&&b| b & CDL_DATA != 0).count(),
+            unknown: self.prg.iter().filter(|&&b| b & (CDL_CODE | CDL_DATA) == 0).count(),
+        }
+    }
+
+    // CPUが命令を実行する直前に呼ぶ
+    pub fn begin_instruction(&mut self, addr: u16, len: u16, indirect: bool) {
+        self.instruction = (addr, len);
+        self.indirect = indirect;
+    }
+
+    pub fn is_operand(&self, addr: u16) -> bool {
+        addr.wrapping_sub(self.instruction.0) < self.instruction.1
+    }
+
+    // データとして読んだときのフラグ
+    pub fn data_flags(&self) -> u8 {
+        if self.indirect {
+            CDL_DATA | CDL_INDIRECT_DATA
+        } else {
+            CDL_DATA
+        }
+    }
+
+    // offset はPRG ROMの位置, addr は読んだときのCPUアドレス
+    pub fn mark_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        if let Some(b) = self.prg.get_mut(offset) {
+            *b |= flags | (((addr >> 13) & 0x03) as u8) << 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cdl() {
+        let mut cdl = CodeDataLogger::new(0x4000, 0x2000);
+        cdl.begin_instruction(0xC000, 3, false);
+        assert!(cdl.is_operand(0xC002) && !cdl.is_operand(0xC003));
+        cdl.mark_prg(0x0000, 0xC000, CDL_CODE);
+        cdl.mark_prg(0x2000, 0xE000, cdl.data_flags());
+        cdl.begin_instruction(0xC003, 2, true);
+        cdl.mark_prg(0x2000, 0xE000, cdl.data_flags());
+        assert_eq!(cdl.prg()[0], CDL_CODE | 0x08);
+        assert_eq!(cdl.prg()[0x2000], CDL_DATA | CDL_INDIRECT_DATA | 0x0C);
+        assert_eq!(cdl.summary(), CdlSummary { code: 1, data: 1, unknown: 0x3FFE });
+
+        let bytes = cdl.to_bytes();
+        assert_eq!(bytes.len(), 0x6000);
+        let loaded = CodeDataLogger::from_bytes(&bytes, 0x4000, 0x2000).unwrap();
+        assert_eq!(loaded.prg(), cdl.prg());
+        assert!(CodeDataLogger::from_bytes(&bytes, 0x8000, 0x2000).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// RAMチート (Pro Action Replay 形式 AAAA:VV)
+// https://www.nesdev.org/wiki/Pro_Action_Replay
+// 有効なチートはフレームごと(VBlankの始まり)にCPU RAMへ書き込む
+// freezeを付けるとゲームからの書き込みも無視して値を固定する
+//
+// ROMごとのファイル (<directory>/<CRC32>.txt)、1行1チート:
+//   AAAA:VV [freeze] [off] [名前]
+// '#'で始まる行はコメント
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const RAM_MASK: u16 = 0x07FF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cheat {
+    pub address: u16, // $0000-$07FF (ミラーは読み込み時に落とす)
+    pub value: u8,
+    pub enabled: bool,
+    pub freeze: bool,
+    pub name: String,
+}
+
+impl Cheat {
+    // "AAAA:VV"
+    pub fn parse_code(code: &str) -> Result<(u16, u8), String> {
+        let (address, value) = code.split_once(':').ok_or(format!("expected AAAA:VV: {}", code))?;
+        let address = u16::from_str_radix(address, 16).map_err(|e| format!("address: {}", e))?;
+        let value = u8::from_str_radix(value, 16).map_err(|e| format!("value: {}", e))?;
+        Ok((address, value))
+    }
+
+    pub fn code(&self) -> String {
+        format!("{:04X}:{:02X}", self.address, self.value)
+    }
+}
+
+pub struct CheatManager {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatManager {
+    pub fn new() -> Self {
+        CheatManager { cheats: vec![] }
+    }
+
+    // 追加したチートの番号を返す (有効な状態で追加される)
+    pub fn add_cheat(&mut self, address: u16, value: u8, freeze: bool) -> Result<usize, String> {
+        if address > RAM_MIRRORS_END {
+            return Err(format!("address is not CPU RAM: ${:04X}", address));
+        }
+        self.cheats.push(Cheat {
+            address: address & RAM_MASK,
+            value,
+            enabled: true,
+            freeze,
+            name: String::new(),
+        });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn add_code(&mut self, code: &str, freeze: bool) -> Result<usize, String> {
+        let (address, value) = Cheat::parse_code(code)?;
+        self.add_cheat(address, value, freeze)
+    }
+
+    // 有効/無効を切り替えて、切り替え後の状態を返す
+    pub fn toggle(&mut self, id: usize) -> Option<bool> {
+        let cheat = self.cheats.get_mut(id)?;
+        cheat.enabled = !cheat.enabled;
+        Some(cheat.enabled)
+    }
+
+    // 以降のチートの番号は1つずつ詰まる
+    pub fn remove(&mut self, id: usize) -> Option<Cheat> {
+        if id < self.cheats.len() {
+            Some(self.cheats.remove(id))
+        } else {
+            None
+        }
+    }
+
+    pub fn set_name(&mut self, id: usize, name: &str) {
+        if let Some(cheat) = self.cheats.get_mut(id) {
+            cheat.name = name.to_string();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    // フレームごとに呼ぶ
+    pub fn apply(&self, ram: &mut [u8; 2048]) {
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            ram[cheat.address as usize] = cheat.value;
+        }
+    }
+
+    // 固定中のアドレスへの書き込みなら、書き込む代わりの値 (addrはミラーを落とした後)
+    pub fn frozen_value(&self, addr: u16) -> Option<u8> {
+        self.cheats
+            .iter()
+            .rev()
+            .find(|c| c.enabled && c.freeze && c.address == addr)
+            .map(|c| c.value)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn path(directory: &Path, rom_crc32: u32) -> PathBuf {
+        directory.join(format!("{:08X}.txt", rom_crc32))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        CheatManager::parse(&text)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut manager = CheatManager::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            manager
+                .parse_line(line)
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        }
+        Ok(manager)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = line;
+        let code = next_word(&mut rest);
+        let id = self.add_code(code, false)?;
+        loop {
+            let before = rest;
+            match next_word(&mut rest) {
+                "freeze" => self.cheats[id].freeze = true,
+                "off" => self.cheats[id].enabled = false,
+                _ => {
+                    rest = before;
+                    break;
+                }
+            }
+        }
+        self.cheats[id].name = rest.trim().to_string();
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for cheat in &self.cheats {
+            text += &cheat.code();
+            if cheat.freeze {
+                text += " freeze";
+            }
+            if !cheat.enabled {
+                text += " off";
+            }
+            if !cheat.name.is_empty() {
+                text += " ";
+                text += &cheat.name;
+            }
+            text += "\n";
+        }
+        text
+    }
+}
+
+impl Default for CheatManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 先頭の単語を取り出して、残りを rest に戻す
+fn next_word<'a>(rest: &mut &'a str) -> &'a str {
+    let trimmed = rest.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    *rest = &trimmed[end..];
+    &trimmed[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cheats() {
+        let mut cheats = CheatManager::new();
+        let lives = cheats.add_code("075A:09", false).unwrap();
+        let timer = cheats.add_cheat(0x0FF8, 0x99, true).unwrap();
+        assert!(cheats.add_code("8000:EA", false).is_err());
+        assert!(cheats.add_code("075A", false).is_err());
+        cheats.set_name(lives, "Infinite lives");
+
+        let mut ram = [0u8; 2048];
+        cheats.apply(&mut ram);
+        assert_eq!((ram[0x075A], ram[0x07F8]), (0x09, 0x99));
+        // ミラーは落として固定する
+        assert_eq!(cheats.frozen_value(0x07F8), Some(0x99));
+        assert_eq!(cheats.frozen_value(0x075A), None);
+
+        assert_eq!(cheats.toggle(timer), Some(false));
+        assert_eq!(cheats.frozen_value(0x07F8), None);
+        assert_eq!(cheats.toggle(5), None);
+
+        let text = cheats.to_text();
+        assert_eq!(text, "075A:09 Infinite lives\n07F8:99 freeze off\n");
+        let loaded = CheatManager::parse(&format!("# comment\n{}", text)).unwrap();
+        assert_eq!(loaded.cheats(), cheats.cheats());
+
+        assert_eq!(cheats.remove(lives).unwrap().name, "Infinite lives");
+        assert_eq!(cheats.cheats()[0].address, 0x07F8);
+        assert!(CheatManager::parse("xyz").is_err());
+    }
+}
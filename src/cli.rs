@@ -0,0 +1,235 @@
+// コマンドライン引数
+// 指定しなかった項目は common.rs の値を使う
+use nes_core::common::*;
+use nes_core::power_on::PowerOnState;
+use nes_core::region::Region;
+use nes_core::trace_log::TraceFilter;
+use nes_core::video::{AspectRatio, ScaleMode};
+
+pub const USAGE: &str = "\
+Usage: rscom [OPTIONS] [ROM]
+
+Options:
+  --region <ntsc|pal|dendy>  Console region (default: auto)
+  --scale <N>                Window scale (default: 2)
+  --fullscreen               Start in fullscreen
+  --scale-mode <MODE>        Scaling: integer or free (fill the window)
+  --aspect <square|ntsc>     Square pixels or 8:7 pixel aspect ratio
+  --no-vsync                 Pace frames with a timer instead of vsync
+  --palette <FILE>           Load a .pal palette (64 RGB entries)
+  --audio-driver <NAME>      SDL audio driver (e.g. pulseaudio, alsa, dummy)
+  --load-state <FILE>        Load a save state after power on
+  --seed <N|random>          Randomize RAM and clock phase at power on (same N, same run)
+  --frames <N>               Run N frames without a window and exit
+  --dump <FILE>              Record video and audio with ffmpeg (e.g. out.mp4)
+  --dump-ppu <DIR>           Write VRAM, nametables, OAM and palettes (.bin and .png) after the run
+  --trace                    Log every CPU instruction
+  --trace-file <FILE>        Write the instruction trace to a file (headless)
+  --trace-filter <SPEC>      Only trace matching instructions, e.g. 8000-80FF,branch,A=10
+  --compare-trace <FILE>     Compare against a Mesen/FCEUX/nestest trace, stop at the first difference
+  --instructions <N>         Compare at most N instructions
+  --gdb <PORT>               Wait for a GDB remote debugger on 127.0.0.1:PORT
+  --remote <PORT>            Accept remote control commands on 127.0.0.1:PORT
+  --debugger                 Open the egui debugger (needs the gui feature)
+  --profile                  Report cycles per subroutine (headless)
+  --bench                    Run the micro-benchmarks and exit
+  -h, --help                 Show this help";
+
+const DEFAULT_SCALE: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub rom_path: String,
+    pub region: Option<Region>, // Noneなら自動判定
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub scale_mode: Option<ScaleMode>, // Noneなら保存した設定
+    pub aspect: Option<AspectRatio>,
+    pub vsync: Option<bool>,
+    pub palette: Option<String>,
+    pub audio_driver: Option<String>,
+    pub load_state: Option<String>,
+    pub power_on: Option<PowerOnState>, // Noneなら固定の状態
+    pub frames: Option<usize>, // 指定されたらヘッドレスで実行する
+    pub dump: Option<String>,
+    pub dump_ppu: Option<String>, // ヘッドレスで実行し終わったらPPUのメモリを書き出す
+    pub trace: bool,
+    pub trace_file: Option<String>,
+    pub trace_filter: TraceFilter,
+    pub profile: bool,
+    pub compare_trace: Option<String>, // 指定されたらトレースを比べて終わる
+    pub instructions: Option<usize>,
+    pub gdb_port: Option<u16>, // 指定されたらウィンドウを出さずにGDBの接続を待つ
+    pub remote_port: Option<u16>, // 指定されたらウィンドウを出さずにリモート操作の接続を待つ (remote.rs)
+    pub debugger: bool, // SDLの代わりに egui のデバッガを開く (debugger_gui.rs)
+    pub bench: bool,
+    pub help: bool,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options {
+            rom_path: _NES_ROM_PATH.to_string(),
+            region: _REGION,
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            scale_mode: None,
+            aspect: None,
+            vsync: None,
+            palette: None,
+            audio_driver: None,
+            load_state: None,
+            power_on: None,
+            frames: None,
+            dump: None,
+            dump_ppu: None,
+            trace: false,
+            trace_file: None,
+            trace_filter: TraceFilter::new(),
+            profile: false,
+            compare_trace: None,
+            instructions: None,
+            gdb_port: None,
+            remote_port: None,
+            debugger: false,
+            bench: false,
+            help: false,
+        }
+    }
+
+    // プログラム名を除いた引数
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Options::new();
+        let mut rom_path = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+            match arg.as_str() {
+                "--region" => {
+                    options.region = Some(match value("--region")?.to_ascii_lowercase().as_str() {
+                        "ntsc" => Region::Ntsc,
+                        "pal" => Region::Pal,
+                        "dendy" => Region::Dendy,
+                        other => return Err(format!("unknown region: {}", other)),
+                    })
+                }
+                "--scale" => {
+                    options.scale = match value("--scale")?.parse() {
+                        Ok(scale) if scale >= 1 => scale,
+                        _ => return Err("--scale must be 1 or more".to_string()),
+                    }
+                }
+                "--fullscreen" => options.fullscreen = true,
+                "--scale-mode" => {
+                    options.scale_mode = Some(match value("--scale-mode")?.to_ascii_lowercase().as_str() {
+                        "integer" => ScaleMode::Integer,
+                        "free" => ScaleMode::Free,
+                        other => return Err(format!("unknown scale mode: {}", other)),
+                    })
+                }
+                "--aspect" => {
+                    options.aspect = Some(match value("--aspect")?.to_ascii_lowercase().as_str() {
+                        "square" => AspectRatio::Square,
+                        "ntsc" | "8:7" => AspectRatio::Ntsc,
+                        other => return Err(format!("unknown aspect ratio: {}", other)),
+                    })
+                }
+                "--no-vsync" => options.vsync = Some(false),
+                "--palette" => options.palette = Some(value("--palette")?),
+                "--audio-driver" => options.audio_driver = Some(value("--audio-driver")?),
+                "--load-state" => options.load_state = Some(value("--load-state")?),
+                "--seed" => {
+                    let seed = value("--seed")?;
+                    options.power_on = Some(match seed.as_str() {
+                        "random" => PowerOnState::random(),
+                        _ => PowerOnState::deterministic(seed.parse().map_err(|_| format!("--seed: {}", seed))?),
+                    })
+                }
+                "--frames" => {
+                    let frames = value("--frames")?;
+                    options.frames = Some(frames.parse().map_err(|_| format!("--frames: {}", frames))?)
+                }
+                "--dump" => options.dump = Some(value("--dump")?),
+                "--dump-ppu" => options.dump_ppu = Some(value("--dump-ppu")?),
+                "--trace" => options.trace = true,
+                "--trace-file" => options.trace_file = Some(value("--trace-file")?),
+                "--trace-filter" => options.trace_filter = TraceFilter::parse(&value("--trace-filter")?)?,
+                "--profile" => options.profile = true,
+                "--compare-trace" => options.compare_trace = Some(value("--compare-trace")?),
+                "--instructions" => {
+                    let count = value("--instructions")?;
+                    options.instructions = Some(count.parse().map_err(|_| format!("--instructions: {}", count))?)
+                }
+                "--gdb" => {
+                    let port = value("--gdb")?;
+                    options.gdb_port = Some(port.parse().map_err(|_| format!("--gdb: {}", port))?)
+                }
+                "--remote" => {
+                    let port = value("--remote")?;
+                    options.remote_port = Some(port.parse().map_err(|_| format!("--remote: {}", port))?)
+                }
+                "--debugger" => options.debugger = true,
+                "--bench" => options.bench = true,
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ if rom_path.is_none() => rom_path = Some(arg),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+        if let Some(path) = rom_path {
+            options.rom_path = path;
+        }
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_cli_parse() {
+        assert_eq!(parse(&[]).unwrap(), Options::new());
+
+        let options = parse(&["--region", "PAL", "game.nes", "--scale", "3", "--frames", "600", "--trace"]).unwrap();
+        assert_eq!(options.rom_path, "game.nes");
+        assert_eq!(options.region, Some(Region::Pal));
+        assert_eq!(options.scale, 3);
+        assert_eq!(options.frames, Some(600));
+        assert!(options.trace && !options.fullscreen && !options.profile);
+        assert!(parse(&["--profile"]).unwrap().profile);
+        assert!(parse(&["--debugger"]).unwrap().debugger);
+        assert_eq!(parse(&["--gdb", "2331"]).unwrap().gdb_port, Some(2331));
+        assert_eq!(parse(&["--seed", "42"]).unwrap().power_on, Some(PowerOnState::deterministic(42)));
+        assert_eq!(parse(&["--seed", "random"]).unwrap().power_on, Some(PowerOnState::random()));
+        assert!(parse(&["--seed", "-1"]).is_err());
+        assert!(parse(&["--gdb", "70000"]).is_err());
+        assert_eq!(parse(&["--remote", "4370"]).unwrap().remote_port, Some(4370));
+        assert_eq!(parse(&["--dump", "out.mkv"]).unwrap().dump.as_deref(), Some("out.mkv"));
+
+        let options = parse(&["--scale-mode", "free", "--aspect", "8:7", "--no-vsync"]).unwrap();
+        assert_eq!(options.scale_mode, Some(ScaleMode::Free));
+        assert_eq!(options.aspect, Some(AspectRatio::Ntsc));
+        assert_eq!(options.vsync, Some(false));
+        assert!(parse(&["--aspect", "4:3"]).is_err());
+
+        let options = parse(&["--trace-file", "trace.log", "--trace-filter", "C000-C0FF,jump"]).unwrap();
+        assert_eq!(options.trace_file.as_deref(), Some("trace.log"));
+        assert_eq!(options.trace_filter, TraceFilter::parse("C000-C0FF,jump").unwrap());
+        assert!(parse(&["--trace-filter", "A=XYZ"]).is_err());
+        assert_eq!(parse(&["--dump-ppu", "out"]).unwrap().dump_ppu.as_deref(), Some("out"));
+        let options = parse(&["--compare-trace", "nestest.log", "--instructions", "8991", "nestest.nes"]).unwrap();
+        assert_eq!((options.compare_trace.as_deref(), options.instructions), (Some("nestest.log"), Some(8991)));
+        assert!(parse(&["--instructions", "many"]).is_err());
+
+        assert!(parse(&["--scale", "0"]).is_err());
+        assert!(parse(&["--region"]).is_err());
+        assert!(parse(&["--region", "secam"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["a.nes", "b.nes"]).is_err());
+    }
+}
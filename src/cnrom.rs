@@ -0,0 +1,124 @@
+// Mapper 3 (CNROM)
+// https://www.nesdev.org/wiki/CNROM
+// PRG ROM 16KB/32KB固定, $8000-$FFFFへの書き込みで8KBのCHR ROMバンクを切り替える
+use crate::mapper::Mapper;
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    mirroring: Mirroring,
+    chr_bank: usize,
+    // 書き込んだ値とROMの値がANDされる (NES 2.0 サブマッパー1以外)
+    bus_conflicts: bool,
+}
+
+impl_state!(Cnrom { chr_bank });
+
+impl Cnrom {
+    pub fn new(rom: &Rom) -> Self {
+        Cnrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr_rom: rom.chr_rom.clone(),
+            mirroring: rom.mirroring.clone(),
+            chr_bank: 0,
+            bus_conflicts: rom.header.submapper != 1,
+        }
+    }
+
+    fn chr_bank_count(&self) -> usize {
+        (self.chr_rom.len() / CHR_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        let data = if self.bus_conflicts {
+            data & self.cpu_read(addr)
+        } else {
+            data
+        };
+        self.chr_bank = data as usize % self.chr_bank_count();
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let offset = self.chr_bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize;
+        self.chr_rom[offset % self.chr_rom.len()]
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {
+        // CHR ROMなので書き込めない
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some((self.chr_bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize) % self.chr_rom.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各CHRバンクの先頭にバンク番号を書いた CNROM (PRG 32KB, CHR 32KB)
+    fn cnrom(submapper: u8) -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 2, 4, 0x30, 0x08, submapper << 4];
+        raw.resize(16, 0);
+        let mut prg = vec![0xFF; 0x8000];
+        prg[0] = 0x01;
+        raw.extend(prg);
+        for bank in 0..4u8 {
+            let mut chr = vec![0; CHR_BANK_SIZE];
+            chr[0] = bank;
+            raw.extend(chr);
+        }
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_cnrom_chr_banking() {
+        let mut mapper = Cnrom::new(&cnrom(0));
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+
+        mapper.cpu_write(0x8001, 3);
+        assert_eq!(mapper.ppu_read(0x0000), 3);
+
+        // バスコンフリクト: $8000 の 0x01 とANDされる
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+
+        mapper.ppu_write(0x0000, 0x55);
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+    }
+
+    #[test]
+    fn test_cnrom_without_bus_conflicts() {
+        let mut mapper = Cnrom::new(&cnrom(1));
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 2);
+    }
+}
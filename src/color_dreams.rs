@@ -0,0 +1,70 @@
+// Mapper 11 (Color Dreams)
+// https://www.nesdev.org/wiki/Color_Dreams
+// $8000-$FFFF: CCCC LLPP  (C: 8KB CHRバンク, P: 32KB PRGバンク, L: ロックアウト回避用で未使用)
+use crate::mapper::Mapper;
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+pub struct ColorDreams {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    mirroring: Mirroring,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl_state!(ColorDreams { prg_bank, chr_bank });
+
+impl ColorDreams {
+    pub fn new(rom: &Rom) -> Self {
+        ColorDreams {
+            prg_rom: rom.prg_rom.clone(),
+            chr_rom: rom.chr_rom.clone(),
+            mirroring: rom.mirroring.clone(),
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for ColorDreams {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr).map_or(0, |offset| self.prg_rom[offset])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // バスコンフリクトあり
+        let data = data & self.cpu_read(addr);
+        self.prg_bank = (data & 0x03) as usize;
+        self.chr_bank = (data >> 4) as usize;
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let offset = self.chr_bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize;
+        self.chr_rom[offset % self.chr_rom.len()]
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some((self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some((self.chr_bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize) % self.chr_rom.len())
+    }
+}
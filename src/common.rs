@@ -1,3 +1,11 @@
+use crate::bus::InputPoll;
+#[cfg(feature = "std")]
+use crate::capture::CaptureFormat;
+use crate::controller::PortDevice;
+use crate::region::Region;
+#[cfg(feature = "std")]
+use crate::video::{AspectRatio, ScaleMode};
+
 // =========================================================================
 // [Common Define]
 // =========================================================================
@@ -89,10 +97,75 @@ pub const _MAPPER_1: u8 = 1;
 pub const _MAPPER_2: u8 = 2;
 pub const _MAPPER_3: u8 = 3;
 pub const _MAPPER_4: u8 = 4;
+pub const _MAPPER_11: u8 = 11;
+pub const _MAPPER_19: u8 = 19;
+pub const _MAPPER_21: u8 = 21;
+pub const _MAPPER_22: u8 = 22;
+pub const _MAPPER_23: u8 = 23;
+pub const _MAPPER_25: u8 = 25;
+pub const _MAPPER_66: u8 = 66;
+pub const _MAPPER_69: u8 = 69;
 pub const _MAPPER_105: u8 = 105;
 pub const _MAPPER_115: u8 = 115;
 pub const _MAPPER_118: u8 = 118;
 pub const _MAPPER_119: u8 = 119;
+pub const _MAPPER_206: u8 = 206;
+
+// [ROM]
+// ヘッダ上書きファイル (あれば読み込む)
+pub const _HEADER_OVERRIDES_PATH: &str = "rom/overrides.txt";
+
+// 本体の地域 (Noneならヘッダ -> ファイル名の順に自動判定)
+pub const _REGION: Option<Region> = None;
+
+// チートの保存先 (<CRC32>.txt)
+pub const _CHEAT_DIR: &str = "cheat";
+
+// ステートセーブのスロットの保存先 (ROMのCRC32ごとにディレクトリを分ける)
+pub const _SAVE_STATE_DIR: &str = "state";
+// 終了時 (ウィンドウを閉じた/Ctrl+C) にステートを autosave.state に残す
+pub const _AUTOSAVE_ON_EXIT: bool = true;
+
+// [Input]
+// 入力の割り当てファイル (なければ既定の割り当て)
+pub const _INPUT_BINDINGS_PATH: &str = "rom/input.txt";
+// ホストの入力を読むタイミング (ムービーの記録/再生中はフレームの始まりに固定)
+pub const _INPUT_POLL: InputPoll = InputPoll::OnStrobe;
+// ポート1/2に挿す機器 (実行中は F1/F2 で切り替え)
+// ザッパーとパドルはマウスで操作する
+pub const _PORT_DEVICES: [PortDevice; 2] = [PortDevice::Joypad, PortDevice::Joypad];
+// アルカノイドのパドル (ファミコン版) を拡張ポートにつなぐ
+pub const _ARKANOID_PADDLE: bool = false;
+// ムービーの記録/再生 (Noneで無効、両方指定したら再生を優先)
+pub const _MOVIE_RECORD_PATH: Option<&str> = None;
+pub const _MOVIE_PLAY_PATH: Option<&str> = None;
+
+// [Video]
+// 実行中に変えた設定 (拡大方法, 縦横比, 垂直同期, 処理時間の表示, スロット) の保存先
+pub const _SETTINGS_PATH: &str = "rom/settings.txt";
+// 拡大方法と縦横比 (実行中は F4/F8 で切り替え、F11で全画面)
+#[cfg(feature = "std")]
+pub const _SCALE_MODE: ScaleMode = ScaleMode::Integer;
+#[cfg(feature = "std")]
+pub const _ASPECT_RATIO: AspectRatio = AspectRatio::Square;
+// 垂直同期 (NTSCのときだけ使い、PAL/Dendyはタイマーで速度を合わせる。実行中は F12 で切り替え)
+pub const _VSYNC: bool = true;
+// 処理時間 (FPS, エミュレーション/描画の時間, 音声バッファの量) を画面に重ねる (実行中は F3 で切り替え)
+pub const _SHOW_PERF_HUD: bool = false;
+
+// [Capture]
+// F9で録画の開始/停止 (<CRC32>_<時刻>.gif)
+pub const _CAPTURE_DIR: &str = "capture";
+#[cfg(feature = "std")]
+pub const _CAPTURE_FORMAT: CaptureFormat = CaptureFormat::Gif;
+// 最長の秒数 (超えたら自動で止めて保存する)
+pub const _CAPTURE_SECONDS: f64 = 10.0;
+// 何フレームに1枚残すか (2なら約30fps)
+pub const _CAPTURE_DECIMATION: usize = 2;
+// F10で音声付きの動画の書き出しの開始/停止 (ffmpegが必要)
+pub const _FFMPEG_PATH: &str = "ffmpeg";
+pub const _AV_DUMP_EXTENSION: &str = "mp4";
+// Vで PPUのメモリ (VRAM, ネームテーブル, OAM, パレット) を .bin と .png で書き出す (<CRC32>_<時刻>_vram.png など)
 
 pub const _CHR_ROM: u8 = 0;
 pub const _CHR_RAM: u8 = 1;
@@ -0,0 +1,303 @@
+// コントローラーポート ($4016/$4017)
+// https://www.nesdev.org/wiki/Standard_controller
+// https://www.nesdev.org/wiki/Input_devices
+// $4016への書き込み (bit0: ストローブ) は両方のポートに届き、
+// 読み込みは $4016 がポート1、$4017 がポート2 のシリアル出力になる
+use core::any::Any;
+
+use crate::arkanoid::ArkanoidPaddle;
+use crate::four_score::FourScore;
+use crate::gamepad::{Button, GamePad};
+use crate::zapper::Zapper;
+use crate::state::{State, StateError, StateReader, StateWriter};
+use alloc::boxed::Box;
+
+// 読み込み時のD5-D7はオープンバス (直前にバスに乗った上位バイト $40 が見える)
+const OPEN_BUS: u8 = 0x40;
+
+// ポートに挿す機器 (標準コントローラー、ザッパー、マルチタップなど)
+// シフトレジスタやストローブの状態はステートセーブに含める (読み出しの途中でも再開できるように)
+pub trait Peripheral: Send + State {
+    // ステートの機器の種類の確認用
+    fn name(&self) -> &'static str;
+    // $4016 への書き込み (bit0: ストローブ、bit1-2: 拡張ポート用)
+    fn write(&mut self, data: u8);
+    // D0-D4 の値 (シリアル出力を1ビット進める)
+    fn read(&mut self) -> u8;
+    // フロントエンドから具体的な機器を取り出すため
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// ポートに挿す機器の種類 (設定ファイルやフロントエンドからの切り替え用)
+// Four Scoreは両方のポートを使うので connect_four_score でつなぐ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PortDevice {
+    None,
+    Joypad,
+    Zapper,
+    ArkanoidPaddle,
+}
+
+impl PortDevice {
+    pub fn create(self) -> Option<Box<dyn Peripheral>> {
+        match self {
+            PortDevice::None => None,
+            PortDevice::Joypad => Some(Box::new(GamePad::new())),
+            PortDevice::Zapper => Some(Box::new(Zapper::new())),
+            PortDevice::ArkanoidPaddle => Some(Box::new(ArkanoidPaddle::new())),
+        }
+    }
+
+    // 切り替え用 (None → Joypad → Zapper → ArkanoidPaddle → None)
+    pub fn next(self) -> Self {
+        match self {
+            PortDevice::None => PortDevice::Joypad,
+            PortDevice::Joypad => PortDevice::Zapper,
+            PortDevice::Zapper => PortDevice::ArkanoidPaddle,
+            PortDevice::ArkanoidPaddle => PortDevice::None,
+        }
+    }
+}
+
+// ファミコンの拡張ポートに挿す機器 (アルカノイドのパドルなど)
+// $4016と$4017の両方の D1-D4 に出力できる
+pub trait ExpansionDevice: Send + State {
+    fn name(&self) -> &'static str;
+    fn write(&mut self, data: u8);
+    // port: 0 = $4016, 1 = $4017
+    fn read(&mut self, port: usize) -> u8;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+pub struct ControllerPorts {
+    ports: [Option<Box<dyn Peripheral>>; 2],
+    expansion: Option<Box<dyn ExpansionDevice>>,
+}
+
+impl ControllerPorts {
+    pub fn new() -> Self {
+        ControllerPorts {
+            ports: [None, None],
+            expansion: None,
+        }
+    }
+
+    // port: 0 = $4016, 1 = $4017
+    pub fn connect(&mut self, port: usize, device: Box<dyn Peripheral>) -> Option<Box<dyn Peripheral>> {
+        self.ports[port].replace(device)
+    }
+
+    pub fn disconnect(&mut self, port: usize) -> Option<Box<dyn Peripheral>> {
+        self.ports[port].take()
+    }
+
+    pub fn is_connected(&self, port: usize) -> bool {
+        self.ports[port].is_some()
+    }
+
+    // 指定した種類の機器を新しく挿し直す (前の機器は外す)
+    pub fn set_device(&mut self, port: usize, device: PortDevice) {
+        self.ports[port] = device.create();
+    }
+
+    // PortDevice にない機器 (Four Scoreなど) はNone
+    pub fn device_type(&self, port: usize) -> Option<PortDevice> {
+        match &self.ports[port] {
+            None => Some(PortDevice::None),
+            Some(device) => match device.name() {
+                "joypad" => Some(PortDevice::Joypad),
+                "zapper" => Some(PortDevice::Zapper),
+                "arkanoid" => Some(PortDevice::ArkanoidPaddle),
+                _ => None,
+            },
+        }
+    }
+
+    // 挿さっている機器が T なら取り出す
+    pub fn device_mut<T: Peripheral + 'static>(&mut self, port: usize) -> Option<&mut T> {
+        self.ports[port]
+            .as_mut()
+            .and_then(|device| device.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn connect_expansion(&mut self, device: Box<dyn ExpansionDevice>) -> Option<Box<dyn ExpansionDevice>> {
+        self.expansion.replace(device)
+    }
+
+    pub fn disconnect_expansion(&mut self) -> Option<Box<dyn ExpansionDevice>> {
+        self.expansion.take()
+    }
+
+    pub fn expansion_mut<T: ExpansionDevice + 'static>(&mut self) -> Option<&mut T> {
+        self.expansion
+            .as_mut()
+            .and_then(|device| device.as_any_mut().downcast_mut::<T>())
+    }
+
+    // 両方のポートにFour Scoreをつなぐ
+    pub fn connect_four_score(&mut self) {
+        self.connect(0, Box::new(FourScore::port1()));
+        self.connect(1, Box::new(FourScore::port2()));
+    }
+
+    // player: 0-3 (3P/4PはFour Scoreがつながっているときだけ)
+    pub fn set_player_buttons(&mut self, player: usize, buttons: Button) {
+        let port = player % 2;
+        if let Some(four_score) = self.device_mut::<FourScore>(port) {
+            four_score.set_buttons(player / 2, buttons);
+        } else if player < 2 {
+            if let Some(pad) = self.device_mut::<GamePad>(port) {
+                pad.set_buttons(buttons);
+            }
+        }
+    }
+
+    pub fn player_buttons(&mut self, player: usize) -> Button {
+        let port = player % 2;
+        if let Some(four_score) = self.device_mut::<FourScore>(port) {
+            four_score.buttons(player / 2)
+        } else if player < 2 {
+            self.device_mut::<GamePad>(port).map_or(Button::empty(), |pad| pad.buttons())
+        } else {
+            Button::empty()
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        for device in self.ports.iter_mut().flatten() {
+            device.write(data);
+        }
+        if let Some(device) = self.expansion.as_mut() {
+            device.write(data);
+        }
+    }
+
+    // 何も挿さっていなければ0 (拡張ポートの機器は D1-D4 に重ねる)
+    pub fn read(&mut self, port: usize) -> u8 {
+        let mut data = match &mut self.ports[port] {
+            Some(device) => device.read() & 0x1F,
+            None => 0,
+        };
+        if let Some(device) = self.expansion.as_mut() {
+            data |= device.read(port) & 0x1E;
+        }
+        OPEN_BUS | data
+    }
+}
+
+impl Default for ControllerPorts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 挿さっている機器の種類も保存し、復元時に同じ構成か確認する
+fn save_device(name: Option<&'static str>, w: &mut StateWriter) {
+    let name = name.unwrap_or("");
+    (name.len() as u8).save(w);
+    w.write_bytes(name.as_bytes());
+}
+
+fn check_device(name: Option<&'static str>, r: &mut StateReader) -> Result<(), StateError> {
+    let mut len = 0u8;
+    len.load(r)?;
+    if r.read_bytes(len as usize)? != name.unwrap_or("").as_bytes() {
+        return Err(StateError::Mismatch("controller device"));
+    }
+    Ok(())
+}
+
+impl State for ControllerPorts {
+    fn save(&self, w: &mut StateWriter) {
+        for device in self.ports.iter() {
+            save_device(device.as_ref().map(|d| d.name()), w);
+            if let Some(device) = device {
+                device.save(w);
+            }
+        }
+        save_device(self.expansion.as_ref().map(|d| d.name()), w);
+        if let Some(device) = &self.expansion {
+            device.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for device in self.ports.iter_mut() {
+            check_device(device.as_ref().map(|d| d.name()), r)?;
+            if let Some(device) = device {
+                device.load(r)?;
+            }
+        }
+        check_device(self.expansion.as_ref().map(|d| d.name()), r)?;
+        if let Some(device) = &mut self.expansion {
+            device.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_controller_ports() {
+        let mut ports = ControllerPorts::new();
+        assert_eq!(ports.read(0), 0x40);
+        ports.connect(0, Box::new(GamePad::new()));
+        ports.connect(1, Box::new(GamePad::new()));
+
+        let pad = ports.device_mut::<GamePad>(0).unwrap();
+        pad.set_button_pressed_status(Button::BUTTON_A, true);
+        pad.set_button_pressed_status(Button::START, true);
+        ports.device_mut::<GamePad>(1).unwrap().set_button_pressed_status(Button::RIGHT, true);
+
+        // ストローブ中はAボタンを返し続ける
+        ports.write(1);
+        assert_eq!(ports.read(0), 0x41);
+        assert_eq!(ports.read(0), 0x41);
+        ports.write(0);
+
+        // A, B, Select, Start, Up, Down, Left, Right の順
+        let bits: Vec<u8> = (0..8).map(|_| ports.read(0) & 1).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 0, 0, 0, 0]);
+        // 8回読んだ後は1
+        assert_eq!(ports.read(0) & 1, 1);
+
+        let bits: Vec<u8> = (0..8).map(|_| ports.read(1) & 1).collect();
+        assert_eq!(bits, vec![0, 0, 0, 0, 0, 0, 0, 1]);
+
+        assert!(ports.disconnect(1).is_some());
+        assert_eq!(ports.read(1), 0x40);
+    }
+
+    #[test]
+    fn test_controller_ports_state() {
+        let mut ports = ControllerPorts::new();
+        ports.connect(0, Box::new(GamePad::new()));
+        ports.set_player_buttons(0, Button::SELECT | Button::START);
+        ports.write(1);
+        ports.write(0);
+        // A, B まで読んだところで保存
+        ports.read(0);
+        ports.read(0);
+        let mut w = StateWriter::new();
+        ports.save(&mut w);
+        let state = w.into_bytes();
+
+        let mut other = ControllerPorts::new();
+        other.connect(0, Box::new(GamePad::new()));
+        other.load(&mut StateReader::new(&state)).unwrap();
+        assert_eq!(other.read(0) & 1, 1); // Select
+        assert_eq!(other.read(0) & 1, 1); // Start
+        assert_eq!(other.read(0) & 1, 0);
+
+        // 挿さっている機器が違う
+        let mut other = ControllerPorts::new();
+        other.connect_four_score();
+        assert_eq!(
+            other.load(&mut StateReader::new(&state)),
+            Err(StateError::Mismatch("controller device"))
+        );
+    }
+}
@@ -1,6 +1,16 @@
-use log::{debug, info, trace};
+use log::{debug, trace};
 use crate::opcode::{call, CPU_OPS_CODES};
 use crate::bus::{Bus, Mem};
+use crate::call_stack::{CallFrame, FrameKind};
+use crate::impl_state;
+use crate::rom::{Rom, RomError};
+use crate::snapshot::CpuSnapshot;
+#[cfg(feature = "std")]
+use core::cell::Cell;
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::string::String;
+use alloc::vec::Vec;
 
 const FLAG_CARRY: u8 = 1 << 0;
 const FLAG_ZERO: u8 = 1 << 1;
@@ -70,7 +80,7 @@ impl OpCode {
     }
 }
 
-pub struct CPU<'a> {
+pub struct CPU {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
@@ -78,14 +88,61 @@ pub struct CPU<'a> {
     pub program_counter: u16,
     pub stack_pointer: u8,
     // pub memory: [u8; 0x10000], // 0xFFFF
-    pub bus: Bus<'a>,
+    pub bus: Bus,
 
     add_cycles: u8,
+    // JAM を実行した (PCはJAMのまま進まないので、ステートには含めなくても読み込んだあとにまた止まる)
+    jammed: bool,
+    // nmi() で入ったNMI (次の命令の前に処理する。命令の間でしか立たないのでステートには含めない)
+    nmi_line: bool,
+    // assert_irq() で入れたIRQ (レベル。clear_irq() まで入り続ける。入れた側が持つのでステートには含めない)
+    irq_line: bool,
+    // リード・モディファイ・ライト命令で、変更前の値を一度書いてから結果を書く (実機と同じ2回の書き込み)
+    rmw_double_write: bool,
 }
 
-pub static mut IN_TRACE: bool = false;
+impl_state!(CPU {
+    register_a,
+    register_x,
+    register_y,
+    status,
+    program_counter,
+    stack_pointer,
+    add_cycles,
+    bus,
+});
+
+// トレースやデバッガがメモリを覗いている間は、読み出しの副作用 (レジスタのクリアなど) を起こさない
+// 本体ごとに別のスレッドで動かせるようにスレッドローカルにしている (no_std ではスレッドがないのでただの static)
+#[cfg(feature = "std")]
+thread_local! {
+    static IN_TRACE: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(feature = "std")]
+pub fn in_trace() -> bool {
+    IN_TRACE.with(|flag| flag.get())
+}
+
+#[cfg(feature = "std")]
+pub fn set_in_trace(value: bool) {
+    IN_TRACE.with(|flag| flag.set(value));
+}
+
+#[cfg(not(feature = "std"))]
+static IN_TRACE: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(feature = "std"))]
+pub fn in_trace() -> bool {
+    IN_TRACE.load(Ordering::Relaxed)
+}
 
-impl Mem for CPU<'_> {
+#[cfg(not(feature = "std"))]
+pub fn set_in_trace(value: bool) {
+    IN_TRACE.store(value, Ordering::Relaxed);
+}
+
+impl Mem for CPU {
     fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
@@ -95,8 +152,8 @@ impl Mem for CPU<'_> {
     }
 }
 
-impl<'a> CPU<'a> {
-    pub fn new(bus: Bus<'a>) -> CPU<'a> {
+impl CPU {
+    pub fn new(bus: Bus) -> CPU {
         CPU {
             register_a: 0,
             register_x: 0,
@@ -107,11 +164,25 @@ impl<'a> CPU<'a> {
             // memory: [0x00; 0x10000],
             bus: bus,
             add_cycles: 0,
+            jammed: false,
+            nmi_line: false,
+            irq_line: false,
+            rmw_double_write: false,
         }
     }
 
+    // 命令から使うのはこちら (ページをまたいだ分のサイクルを足す)
     fn get_operand_address(&mut self, _mode: &AddressingMode) -> u16 {
-        match _mode {
+        let (addr, extra_cycles) = self.resolve_address(_mode);
+        self.add_cycles += extra_cycles;
+        addr
+    }
+
+    // 実効アドレスと、ページをまたいだときに増えるサイクル (+1) を返す
+    // PCはオペランドの先頭 (オペコードの次) を指していること。2バイトのオペランドは PC, PC+1 から読む
+    pub fn resolve_address(&mut self, mode: &AddressingMode) -> (u16, u8) {
+        let operand = self.program_counter;
+        match mode {
             AddressingMode::Implied => {
                 panic!("AddressingMode::Implied");
             }
@@ -119,113 +190,95 @@ impl<'a> CPU<'a> {
                 panic!("AddressingMode::Accumulator");
             }
             // LDA #$44 => a9 44
-            AddressingMode::Immediate => self.program_counter,
+            AddressingMode::Immediate => (operand, 0),
 
             // LDA $44 => a5 44
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            AddressingMode::ZeroPage => (self.mem_read(operand) as u16, 0),
 
             // LDA $4400 => ad 00 44
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::Absolute => (self.mem_read_u16(operand), 0),
 
             // LDA $44,X => b5 44
             AddressingMode::ZeroPage_X => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+                let pos = self.mem_read(operand);
+                (pos.wrapping_add(self.register_x) as u16, 0)
             }
 
             // LDX $44,Y => b6 44
             AddressingMode::ZeroPage_Y => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
+                let pos = self.mem_read(operand);
+                (pos.wrapping_add(self.register_y) as u16, 0)
             }
 
             // LDA $4400,X => bd 00 44
             AddressingMode::Absolute_X => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                // (+1 if page crossed)
-                if base & 0xFF00 != addr & 0xFF00 {
-                    self.add_cycles += 1;
-                }
-                addr
+                let base = self.mem_read_u16(operand);
+                indexed(base, self.register_x)
             }
 
             // LDA $4400,Y => b9 00 44
             AddressingMode::Absolute_Y => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                // (+1 if page crossed)
-                if base & 0xFF00 != addr & 0xFF00 {
-                    self.add_cycles += 1;
-                }
-                addr
+                let base = self.mem_read_u16(operand);
+                indexed(base, self.register_y)
             }
-            // JMP -> same Absolute
+
+            // JMP ($4400) => 6c 00 44
             AddressingMode::Indirect => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = self.mem_read_u16(base);
-                addr
+                let base = self.mem_read_u16(operand);
+                (self.read_indirect_u16(base), 0)
             }
 
             // LDA ($44,X) => a1 44
             AddressingMode::Indirect_X => {
-                let base = self.mem_read(self.program_counter);
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                let addr = self.mem_read_u16(ptr as u16);
-                addr
+                let base = self.mem_read(operand);
+                let ptr: u8 = base.wrapping_add(self.register_x);
+                (self.read_zero_page_u16(ptr), 0)
             }
 
             // LDA ($44),Y => b1 44
             AddressingMode::Indirect_Y => {
-                let base = self.mem_read(self.program_counter);
-                let deref_base = self.mem_read_u16(base as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                // (+1 if page crossed)
-                if deref_base & 0xFF00 != deref & 0xFF00 {
-                    self.add_cycles += 1;
-                }
-                deref
+                let base = self.mem_read(operand);
+                let deref_base = self.read_zero_page_u16(base);
+                indexed(deref_base, self.register_y)
             }
 
             // BCC *+4 => 90 04
             AddressingMode::Relative => {
-                let base = self.mem_read(self.program_counter);
-                let np = (base as i8) as i32 + self.program_counter as i32;
-                return np as u16;
+                let offset = self.mem_read(operand) as i8;
+                (operand.wrapping_add(offset as u16), 0)
             }
 
             AddressingMode::NoneAddressing => {
-                panic!("_mode {:?} is not supported", _mode);
+                panic!("_mode {:?} is not supported", mode);
             }
         }
     }
 
     pub fn mem_read_u16(&mut self, pos: u16) -> u16 {
-        // FIXME
-        if pos == 0x00FF || pos == 0x02FF {
-            debug!("mem_read_u16 page boundary. {:04X}", pos);
-            let lo = self.mem_read(pos) as u16;
-            let hi = self.mem_read(pos & 0xFF00) as u16;
-            return (hi << 8) | (lo as u16);
-        }
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
         (hi << 8) | (lo as u16)
     }
 
+    // JMP ($xxFF) は上位を $xx00 から読む (6502のバグ。65C02では直っている)
+    pub fn read_indirect_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos & 0xFF00 | (pos as u8).wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    // ゼロページのポインタ ($FF の次は $00。$0100 には行かない)
+    pub fn read_zero_page_u16(&mut self, ptr: u8) -> u16 {
+        let lo = self.mem_read(ptr as u16) as u16;
+        let hi = self.mem_read(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
     pub fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0x00FF) as u8;
         self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
-    }
-
-    fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load();
-        self.reset();
-        self.run();
+        self.mem_write(pos.wrapping_add(1), hi);
     }
 
     pub fn reset(&mut self) {
@@ -235,10 +288,81 @@ impl<'a> CPU<'a> {
         // FIXME あってる？
         self.status = FLAG_INTERRRUPT | FLAG_BREAK2;
         self.stack_pointer = 0xFD;
+        self.jammed = false;
+        self.nmi_line = false;
+        self.irq_line = false;
 
         self.program_counter = self.mem_read_u16(0xFFFC);
     }
 
+    // リセットボタン: A/X/Yはそのまま、SPは3減り、Iフラグが立つ
+    pub fn soft_reset(&mut self) {
+        self.bus.soft_reset();
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status |= FLAG_INTERRRUPT;
+        self.jammed = false;
+        self.program_counter = self.mem_read_u16(0xFFFC);
+    }
+
+    // NMIの入力 (エッジ検出なので、処理する前に何度呼んでも1回)
+    // 次の命令の前に PC と P を積んで $FFFA に飛ぶ。Iフラグでは止められない
+    pub fn nmi(&mut self) {
+        self.nmi_line = true;
+    }
+
+    // IRQの入力 (APUやマッパーのIRQとワイヤードOR)
+    // Iフラグが落ちていれば、次の命令の前に PC と P を積んで $FFFE に飛ぶ
+    pub fn assert_irq(&mut self) {
+        self.irq_line = true;
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.irq_line = false;
+    }
+
+    // 実機は ASL/LSR/ROL/ROR/INC/DEC (とその非公式命令) でメモリに2回書く (MMC1やPPUのレジスタで違いが出る)
+    // 既定では結果だけを書く
+    pub fn set_rmw_double_write(&mut self, enabled: bool) {
+        self.rmw_double_write = enabled;
+    }
+
+    fn write_modified(&mut self, addr: u16, old: u8, value: u8) {
+        if self.rmw_double_write {
+            self.mem_write(addr, old);
+        }
+        self.mem_write(addr, value);
+    }
+
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            p: self.status,
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+            cycles: self.bus.cycles(),
+            jammed: self.jammed,
+        }
+    }
+
+    // 電源の入れ直し: RAMやレジスタも含めてすべて初期化する
+    pub fn power_cycle(&mut self) {
+        self.bus.power_cycle();
+        self.reset();
+    }
+
+    // ROMを差し替えて電源を入れ直す
+    pub fn load_new_rom(&mut self, rom: Rom) -> Result<(), RomError> {
+        self.bus.load_new_rom(rom)?;
+        self.reset();
+        Ok(())
+    }
+
     pub fn load(&mut self) {
         // self.mem_write_u16(0xFFFC, 0x8000);
     }
@@ -252,53 +376,95 @@ impl<'a> CPU<'a> {
         F: FnMut(&mut CPU),
     {
         loop {
-            if let Some(_nmi) = self.bus.poll_nmi_status() {
-                self.interrupt_nmi();
-            }
+            self.step_with_callback(&mut callback);
+        }
+    }
 
-            if self.bus.poll_apu_irq() {
-                self.apu_irq();
-            }
+    // 1命令だけ実行する (割り込みがあれば先に処理する)
+    pub fn step(&mut self) {
+        self.step_with_callback(|_| {});
+    }
+
+    // 次のフレームの始まり (VBlank) まで実行する
+    pub fn run_frame(&mut self) {
+        self.run_frame_with_callback(|_| {});
+    }
+
+    pub fn run_frame_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        let frame = self.bus.frame_count();
+        while self.bus.frame_count() == frame {
+            self.step_with_callback(&mut callback);
+        }
+    }
 
-            let opscode = self.mem_read(self.program_counter);
-            self.program_counter += 1;
+    pub fn step_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        // PPUのVBlankと nmi() の両方を見る (どちらも1回で消える)
+        let ppu_nmi = self.bus.poll_nmi_status().is_some();
+        if ppu_nmi || core::mem::take(&mut self.nmi_line) {
+            self.interrupt_nmi();
+        }
 
-            let op = self.find_ops(opscode);
-            match op {
-                Some(op) => {
-                    self.add_cycles = 0;
+        // poll_irq はイベントを出すので、irq_line より先に呼ぶ
+        if self.bus.poll_irq() || self.irq_line {
+            self.interrupt_irq();
+        }
 
-                    callback(self);
-                    call(self, &op);
+        let opcode_addr = self.program_counter;
+        self.bus.log_opcode_fetch(opcode_addr);
+        let opscode = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        let op = self.find_ops(opscode);
+        match op {
+            Some(op) => {
+                self.add_cycles = 0;
+                let indirect = matches!(op.addressing_mode, AddressingMode::Indirect_X | AddressingMode::Indirect_Y);
+                self.bus.log_instruction(opcode_addr, op.bytes, indirect);
+
+                callback(self);
+                call(self, &op);
+                // JMP ($nnnn)
+                if op.code == 0x6C {
+                    self.bus.log_indirect_jump(self.program_counter);
+                }
 
-                    match op.cycle_calc_mode {
-                        CycleCalcMode::None => {
-                            self.add_cycles = 0;
-                        }
-                        CycleCalcMode::Page => {
-                            if self.add_cycles > 1 {
-                                panic!(
-                                    "Unexpected cycle_calc. {} {:?} => {}",
-                                    op.name, op.addressing_mode, self.add_cycles
-                                )
-                            }
+                match op.cycle_calc_mode {
+                    CycleCalcMode::None => {
+                        self.add_cycles = 0;
+                    }
+                    CycleCalcMode::Page => {
+                        if self.add_cycles > 1 {
+                            panic!(
+                                "Unexpected cycle_calc. {} {:?} => {}",
+                                op.name, op.addressing_mode, self.add_cycles
+                            )
                         }
-                        _ => {}
                     }
+                    _ => {}
+                }
 
-                    self.bus.tick(op.cycles + self.add_cycles);
-
-                    // if program_conter_state == self.program_counter {
-                    //   self.program_counter += (op.len - 1) as u16
-                    // }
+                self.bus.tick(op.cycles + self.add_cycles);
+                // RTI のサイクルまでが割り込みの中
+                if op.code == 0x40 {
+                    self.bus.leave_interrupt();
                 }
-                _ => {} // panic!("no implementation {:<02X}", opscode),
+
+                // if program_conter_state == self.program_counter {
+                //   self.program_counter += (op.len - 1) as u16
+                // }
             }
+            _ => {} // panic!("no implementation {:<02X}", opscode),
         }
     }
 
     fn interrupt_nmi(&mut self) {
-        debug!("** INTERRUPT_NMI **");
+        debug!(target: "cpu", "** INTERRUPT_NMI **");
         self._push_u16(self.program_counter);
         let mut status = self.status;
         status &= !FLAG_BREAK;
@@ -306,22 +472,29 @@ impl<'a> CPU<'a> {
         self._push(status);
 
         self.status |= FLAG_INTERRRUPT;
+        self.bus.enter_interrupt();
         self.bus.tick(2);
+        let caller = self.program_counter;
         self.program_counter = self.mem_read_u16(0xFFFA);
+        self.log_stack_push(FrameKind::Nmi, caller, self.program_counter);
     }
 
-    fn apu_irq(&mut self) {
-        info!("** APU_IRQ **");
+    fn interrupt_irq(&mut self) {
+        debug!(target: "cpu", "** INTERRUPT_IRQ **");
 
         if self.status & FLAG_INTERRRUPT != 0 {
             return;
         }
-        info!("  => CALL");
+        debug!(target: "cpu", "  => CALL");
 
+        // NMIと同じく B を落とし bit5 を立てて積む (BRKとの違い)
         self._push_u16(self.program_counter);
-        self._push(self.status);
+        self._push(self.status & !FLAG_BREAK | FLAG_BREAK2);
+        let caller = self.program_counter;
         self.program_counter = self.mem_read_u16(0xFFFE);
-        self.status |= FLAG_BREAK;
+        self.log_stack_push(FrameKind::Irq, caller, self.program_counter);
+        self.status |= FLAG_INTERRRUPT;
+        self.bus.enter_interrupt();
         self.bus.tick(2);
     }
 
@@ -371,7 +544,8 @@ impl<'a> CPU<'a> {
 
     pub fn jam(&mut self, _mode: &AddressingMode) {
         // Stop program counter (processor lock up).
-        self.program_counter -= 1;
+        self.program_counter = self.program_counter.wrapping_sub(1);
+        self.jammed = true;
         // panic!("CALL JAM operation.");
     }
 
@@ -513,16 +687,24 @@ impl<'a> CPU<'a> {
 
     pub fn rti(&mut self, _mode: &AddressingMode) {
         // スタックからプロセッサ フラグをプルし、続いてプログラム カウンタをプルします。
-        self.status = self._pop() & !FLAG_BREAK | FLAG_BREAK2;
+        self.pop_status();
         self.program_counter = self._pop_u16();
     }
 
     pub fn plp(&mut self, _mode: &AddressingMode) {
+        self.pop_status();
+    }
+
+    // PLP/RTI: レジスタには bit4 (B) と bit5 が無いので、取り出した値のそれらは無視する (bit5 は常に1)
+    fn pop_status(&mut self) {
         self.status = self._pop() & !FLAG_BREAK | FLAG_BREAK2;
     }
 
+    // PHP は BRK と同じく B と bit5 を立てて積む
     pub fn php(&mut self, _mode: &AddressingMode) {
         self._push(self.status | FLAG_BREAK | FLAG_BREAK2);
+        let pc = self.program_counter.wrapping_sub(1);
+        self.log_stack_push(FrameKind::Php, pc, pc);
     }
 
     pub fn pla(&mut self, _mode: &AddressingMode) {
@@ -559,14 +741,17 @@ impl<'a> CPU<'a> {
         self.update_zero_and_negative_flags(self.register_a);
     }
 
+    // 積まれているのは JSR の最後のバイトのアドレスなので +1 して戻る (下位, 上位の順に取り出す)
     pub fn rts(&mut self, _mode: &AddressingMode) {
-        let value = self._pop_u16() + 1;
+        let value = self._pop_u16().wrapping_add(1);
         self.program_counter = value;
     }
 
     pub fn jsr(&mut self, _mode: &AddressingMode) {
         let addr = self.get_operand_address(_mode);
-        self._push_u16(self.program_counter + 2 - 1);
+        // PCはオペランドの先頭なので、+1 で JSR の最後のバイト (戻り先-1)。上位, 下位の順に積む
+        self._push_u16(self.program_counter.wrapping_add(1));
+        self.log_stack_push(FrameKind::Jsr, self.program_counter.wrapping_sub(1), addr);
         self.program_counter = addr;
         // 後で+2するので整合性のため-2しておく
         self.program_counter = self.program_counter.wrapping_sub(2);
@@ -574,15 +759,21 @@ impl<'a> CPU<'a> {
 
     pub fn _push(&mut self, value: u8) {
         let addr = 0x0100 + self.stack_pointer as u16;
-        trace!("STACK PUSH: {:04X} => {:02X}", self.stack_pointer, value);
+        trace!(target: "cpu", "STACK PUSH: {:04X} => {:02X}", self.stack_pointer, value);
         self.mem_write(addr, value);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
+    // コールスタックの記録 (積んだ直後に呼ぶ)
+    fn log_stack_push(&mut self, kind: FrameKind, caller: u16, target: u16) {
+        self.bus.log_stack_push(CallFrame::new(kind, caller, target, self.stack_pointer));
+    }
+
     pub fn _pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.bus.log_stack_pop(self.stack_pointer);
         let addr = 0x0100 + self.stack_pointer as u16;
-        trace!("STACK POP: {:02X}", self.stack_pointer);
+        trace!(target: "cpu", "STACK POP: {:02X}", self.stack_pointer);
         self.mem_read(addr)
     }
 
@@ -601,12 +792,8 @@ impl<'a> CPU<'a> {
         let addr = self.get_operand_address(_mode);
         self.program_counter = addr;
         // 後で+2するので整合性のため-2しておく
-        self.program_counter -= 2;
-        // TODO
-        // オリジナルの 6502 は、間接ベクトルがページ境界にある場合、
-        // ターゲット アドレスを正しくフェッチしません (たとえば、$xxFF で、xx は $00 から $FF までの任意の値です)。
-        // この場合、予想どおり $xxFF から LSB を取得しますが、$xx00 から MSB を取得します。
-        // これは、65SC02 などの最近のチップで修正されているため、互換性のために、間接ベクトルがページの最後にないことを常に確認してください。
+        self.program_counter = self.program_counter.wrapping_sub(2);
+        // JMP ($xxFF) のページのバグは read_indirect_u16 で再現している
     }
 
     pub fn iny(&mut self, _mode: &AddressingMode) {
@@ -621,8 +808,9 @@ impl<'a> CPU<'a> {
 
     pub fn inc(&mut self, _mode: &AddressingMode) {
         let addr = self.get_operand_address(_mode);
-        let value = self.mem_read(addr).wrapping_add(1);
-        self.mem_write(addr, value);
+        let old = self.mem_read(addr);
+        let value = old.wrapping_add(1);
+        self.write_modified(addr, old, value);
         self.update_zero_and_negative_flags(value);
     }
 
@@ -638,8 +826,9 @@ impl<'a> CPU<'a> {
 
     pub fn dec(&mut self, _mode: &AddressingMode) {
         let addr = self.get_operand_address(_mode);
-        let value = self.mem_read(addr).wrapping_sub(1);
-        self.mem_write(addr, value);
+        let old = self.mem_read(addr);
+        let value = old.wrapping_sub(1);
+        self.write_modified(addr, old, value);
         self.update_zero_and_negative_flags(value);
     }
 
@@ -731,17 +920,16 @@ impl<'a> CPU<'a> {
     }
 
     pub fn brk(&mut self, _mode: &AddressingMode) {
-        if self.status & FLAG_BREAK != 0 {
-            return;
-        }
-
-        // プログラム カウンターとプロセッサ ステータスがスタックにプッシュされ、
-        self._push_u16(self.program_counter);
-        self._push(self.status);
+        // BRK の次の1バイトは読み飛ばされる (戻り先は BRK+2)
+        // PCH, PCL, P の順に積む。B は積んだほうにだけ立てる (レジスタには B は無い)
+        self._push_u16(self.program_counter.wrapping_add(1));
+        self._push(self.status | FLAG_BREAK | FLAG_BREAK2);
+        self.status |= FLAG_INTERRRUPT;
 
-        // $FFFE/F の IRQ 割り込みベクトルが PC にロードされ、ステータスのブレーク フラグが 1 に設定されます。
+        // $FFFE/F の IRQ 割り込みベクトルが PC にロードされる
+        let caller = self.program_counter.wrapping_sub(1);
         self.program_counter = self.mem_read_u16(0xFFFE);
-        self.status = self.status | FLAG_BREAK;
+        self.log_stack_push(FrameKind::Brk, caller, self.program_counter);
     }
 
     pub fn bpl(&mut self, _mode: &AddressingMode) {
@@ -790,11 +978,11 @@ impl<'a> CPU<'a> {
             (self.register_a, carry)
         } else {
             let addr = self.get_operand_address(_mode);
-            let value = self.mem_read(addr);
-            let carry = value & 0x01;
-            let value = value / 2;
+            let old = self.mem_read(addr);
+            let carry = old & 0x01;
+            let value = old / 2;
             let value = value | ((self.status & FLAG_CARRY) << 7);
-            self.mem_write(addr, value);
+            self.write_modified(addr, old, value);
             (value, carry)
         };
 
@@ -813,10 +1001,10 @@ impl<'a> CPU<'a> {
             (self.register_a, carry)
         } else {
             let addr = self.get_operand_address(_mode);
-            let value = self.mem_read(addr);
-            let (value, carry) = value.overflowing_mul(2);
+            let old = self.mem_read(addr);
+            let (value, carry) = old.overflowing_mul(2);
             let value = value | (self.status & FLAG_CARRY);
-            self.mem_write(addr, value);
+            self.write_modified(addr, old, value);
             (value, carry)
         };
 
@@ -835,10 +1023,10 @@ impl<'a> CPU<'a> {
             (self.register_a, carry)
         } else {
             let addr = self.get_operand_address(_mode);
-            let value = self.mem_read(addr);
-            let carry = value & 0x01;
-            let value = value / 2;
-            self.mem_write(addr, value);
+            let old = self.mem_read(addr);
+            let carry = old & 0x01;
+            let value = old / 2;
+            self.write_modified(addr, old, value);
             (value, carry)
         };
 
@@ -857,9 +1045,9 @@ impl<'a> CPU<'a> {
             (value, carry)
         } else {
             let addr = self.get_operand_address(_mode);
-            let value = self.mem_read(addr);
-            let (value, carry) = value.overflowing_mul(2);
-            self.mem_write(addr, value);
+            let old = self.mem_read(addr);
+            let (value, carry) = old.overflowing_mul(2);
+            self.write_modified(addr, old, value);
             (value, carry)
         };
 
@@ -965,6 +1153,20 @@ impl<'a> CPU<'a> {
     }
 }
 
+// base + index と、ページをまたいだら +1 サイクル
+fn indexed(base: u16, index: u8) -> (u16, u8) {
+    let addr = base.wrapping_add(index as u16);
+    (addr, (base & 0xFF00 != addr & 0xFF00) as u8)
+}
+
+// "cpu" ターゲットがTraceのときだけトレースする (run_frame_with_callback に渡す)
+// RUST_LOG=cpu=trace,ppu=debug のようにモジュールごとに絞れる
+pub fn trace_if_enabled(cpu: &mut CPU) {
+    if log::log_enabled!(target: "cpu", log::Level::Trace) {
+        trace(cpu);
+    }
+}
+
 pub fn trace(cpu: &mut CPU) -> String {
     // 0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD
     // OK 0064 => program_counter
@@ -972,15 +1174,15 @@ pub fn trace(cpu: &mut CPU) -> String {
     // OK LDX #$01 => asm code
     // "0400 @ 0400 = AA" => memory access
     // OK A:01 X:02 Y:03 P:24 SP:FD => register, status, stack_pointer
-    unsafe { IN_TRACE = true };
+    set_in_trace(true);
 
-    let program_counter = cpu.program_counter - 1;
+    let program_counter = cpu.program_counter.wrapping_sub(1);
     let pc = format!("{:<04X}", program_counter);
     let op = cpu.mem_read(program_counter);
     let ops = cpu.find_ops(op).unwrap();
     let mut args: Vec<u8> = vec![];
     for n in 1..ops.bytes {
-        let arg = cpu.mem_read(program_counter + n);
+        let arg = cpu.mem_read(program_counter.wrapping_add(n));
         args.push(arg);
     }
     let bin = binary(op, &args);
@@ -996,13 +1198,27 @@ pub fn trace(cpu: &mut CPU) -> String {
         status
     );
 
-    trace!("{}", log);
+    trace!(target: "cpu", "{}", log);
 
-    unsafe { IN_TRACE = false };
+    set_in_trace(false);
 
     log
 }
 
+// addr の1命令を逆アセンブルして (命令の文字列, バイト数) を返す (デバッガ用、副作用なし)
+pub fn disassemble(cpu: &mut CPU, addr: u16) -> (String, u16) {
+    set_in_trace(true);
+    let op = cpu.mem_read(addr);
+    let ops = cpu.find_ops(op).unwrap();
+    let mut args: Vec<u8> = vec![];
+    for n in 1..ops.bytes {
+        args.push(cpu.mem_read(addr.wrapping_add(n)));
+    }
+    set_in_trace(false);
+    let text = format!("{:<9}{}", binary(op, &args), disasm(addr, &ops, &args).trim_start());
+    (text, ops.bytes)
+}
+
 fn binary(op: u8, args: &Vec<u8>) -> String {
     let mut list: Vec<String> = vec![];
     list.push(format!("{:<02X}", op));
@@ -1082,7 +1298,7 @@ fn address(program_counter: u16, ops: &OpCode, args: &Vec<u8>) -> String {
         AddressingMode::Relative => {
             format!(
                 "${:<04X}",
-                (program_counter as i32 + (args[0] as i8) as i32) as u16 + 2
+                ((program_counter as i32 + (args[0] as i8) as i32) as u16).wrapping_add(2)
             )
         }
 
@@ -1098,7 +1314,7 @@ fn memory_access(cpu: &mut CPU, ops: &OpCode, args: &Vec<u8>) -> String {
             let hi = args[1] as u16;
             let lo = args[0] as u16;
             let addr = hi << 8 | lo;
-            let value = cpu.mem_read_u16(addr);
+            let value = cpu.read_indirect_u16(addr);
             return format!("= {:<04X}", value);
         }
         return format!("");
@@ -1145,13 +1361,13 @@ fn memory_access(cpu: &mut CPU, ops: &OpCode, args: &Vec<u8>) -> String {
         AddressingMode::Indirect_X => {
             let base = args[0];
             let ptr: u8 = (base as u8).wrapping_add(cpu.register_x);
-            let addr = cpu.mem_read_u16(ptr as u16);
+            let addr = cpu.read_zero_page_u16(ptr);
             let value = cpu.mem_read(addr);
             format!("@ {:<02X} = {:<04X} = {:<02X}", ptr, addr, value)
         }
         AddressingMode::Indirect_Y => {
             let base = args[0];
-            let deref_base = cpu.mem_read_u16(base as u16);
+            let deref_base = cpu.read_zero_page_u16(base);
             let deref = deref_base.wrapping_add(cpu.register_y as u16);
             let value = cpu.mem_read(deref);
             format!("= {:<04X} @ {:<04X} = {:<02X}", deref_base, deref, value)
@@ -1167,4 +1383,391 @@ fn cpu2str(cpu: &CPU) -> String {
         "A:{:<02X} X:{:<02X} Y:{:<02X} P:{:<02X} SP:{:<02X}",
         cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer,
     )
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::test_rom::TestRom;
+
+    // $8000 から source を実行するROM
+    fn nes_with(source: &str) -> Nes {
+        TestRom::new().asm(0x8000, source).nes().unwrap()
+    }
+
+    fn steps(nes: &mut Nes, count: usize) {
+        for _ in 0..count {
+            nes.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_wrapping_arithmetic() {
+        let source = "
+            LDX #0
+            DEX
+            LDY #0
+            DEY
+            LDA #0
+            SEC
+            SBC #1
+            TAX
+            INX
+        ";
+        // $FFFF (IRQベクタの上位) に NOP を置く
+        let mut nes = TestRom::new().asm(0x8000, source).irq(0xEA00).nes().unwrap();
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().register_x, 0xFF);
+        assert_ne!(nes.cpu().status & FLAG_NEGATIVE, 0);
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().register_y, 0xFF);
+        steps(&mut nes, 3);
+        assert_eq!(nes.cpu().register_a, 0xFF);
+        assert_eq!(nes.cpu().status & FLAG_CARRY, 0);
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().register_x, 0x00);
+        assert_ne!(nes.cpu().status & FLAG_ZERO, 0);
+
+        // $FFFF の次は $0000 (RAM)。RTS で $FFFF+1 に戻っても $0000
+        nes.ram_mut()[..3].copy_from_slice(&[0xA2, 0x05, 0x60]); // LDX #5, RTS
+        nes.ram_mut()[0x1FE..0x200].copy_from_slice(&[0xFF, 0xFF]);
+        nes.cpu_mut().stack_pointer = 0xFD;
+        nes.cpu_mut().program_counter = 0xFFFF;
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x0000);
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().register_x, nes.cpu().program_counter), (5, 0x0002));
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x0000);
+        assert!(trace(nes.cpu_mut()).starts_with("FFFF"));
+    }
+
+    #[test]
+    fn test_nmi() {
+        let mut nes = TestRom::new().asm(0x8000, "SEI\nloop: JMP loop").nmi(0x9000).nes().unwrap();
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().program_counter, 0x8001);
+        nes.cpu_mut().nmi();
+        nes.cpu_mut().nmi();
+        // 割り込みを処理してから $9000 の NOP を実行する (Iフラグが立っていても入る)
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x9001);
+        assert_eq!(nes.cpu().stack_pointer, 0xFA);
+        assert_eq!(nes.ram()[0x1FB..0x1FE], [FLAG_INTERRRUPT | FLAG_BREAK2, 0x01, 0x80]);
+        assert_ne!(nes.cpu().status & FLAG_INTERRRUPT, 0);
+        // 2回呼んでも1回だけ
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x9002);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut nes = TestRom::new().asm(0x8000, "SEI\nCLI\nloop: JMP loop").irq(0x9000).nes().unwrap();
+        steps(&mut nes, 1);
+        nes.cpu_mut().assert_irq();
+        // Iフラグが立っている間は入らない
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x8002);
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x9001);
+        assert_eq!(nes.cpu().stack_pointer, 0xFA);
+        assert_eq!(nes.ram()[0x1FB..0x1FE], [FLAG_BREAK2, 0x02, 0x80]);
+        assert_eq!(nes.cpu().status & (FLAG_INTERRRUPT | FLAG_BREAK), FLAG_INTERRRUPT);
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x9002);
+
+        // レベルなので、Iフラグを落とすとまた入る。clear_irq のあとは入らない
+        nes.cpu_mut().status &= !FLAG_INTERRRUPT;
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x9001, 0xF7));
+        nes.cpu_mut().clear_irq();
+        nes.cpu_mut().status &= !FLAG_INTERRRUPT;
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x9002, 0xF7));
+    }
+
+    #[test]
+    fn test_brk() {
+        let mut nes = TestRom::new()
+            .asm(0x8000, "CLI\nBRK\nNOP\nloop: JMP loop")
+            .asm(0x9123, "RTI")
+            .irq(0x9123)
+            .nes()
+            .unwrap();
+        steps(&mut nes, 2);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x9123, 0xFA));
+        assert_eq!(nes.ram()[0x1FB..0x1FE], [FLAG_BREAK | FLAG_BREAK2, 0x03, 0x80]);
+        assert_eq!(nes.cpu().status & (FLAG_INTERRRUPT | FLAG_BREAK), FLAG_INTERRRUPT);
+        // RTI で BRK+2 に戻り、B は残らない
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x8003, 0xFD));
+        assert_eq!(nes.cpu().status & (FLAG_INTERRRUPT | FLAG_BREAK), 0);
+        // 続けてもう一度 BRK できる
+        nes.cpu_mut().program_counter = 0x8001;
+        steps(&mut nes, 1);
+        assert_eq!(nes.cpu().program_counter, 0x9123);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let source = "
+            main:  JSR outer
+                   INX
+            loop:  JMP loop
+            outer: JSR inner
+                   INY
+                   RTS
+            inner: LDA #$42
+                   RTS
+        ";
+        let mut nes = nes_with(source);
+        // JSR outer: JSR の最後のバイト ($8002) を上位, 下位の順に積む
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x8007, 0xFB));
+        assert_eq!(nes.ram()[0x1FC..0x1FE], [0x02, 0x80]);
+        // JSR inner ($8007-$8009)
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x800C, 0xF9));
+        assert_eq!(nes.ram()[0x1FA..0x1FC], [0x09, 0x80]);
+        // LDA, RTS で inner の次 (INY) に戻り、INY, RTS で main の次 (INX) に戻る
+        steps(&mut nes, 2);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x800A, 0xFB));
+        steps(&mut nes, 2);
+        assert_eq!((nes.cpu().program_counter, nes.cpu().stack_pointer), (0x8003, 0xFD));
+        steps(&mut nes, 1);
+        assert_eq!((nes.cpu().register_a, nes.cpu().register_x, nes.cpu().register_y), (0x42, 1, 1));
+    }
+
+    #[test]
+    fn test_zero_page_wrap() {
+        let source = "
+            LDX #$20
+            LDA $F0,X
+            LDY #$30
+            LDX $F0,Y
+            LDX #$01
+            LDA ($FE,X)
+            LDY #$02
+            LDA ($FF),Y
+        ";
+        let mut nes = nes_with(source);
+        {
+            let ram = nes.ram_mut();
+            ram[0x10] = 0x11; // $F0+$20
+            ram[0x20] = 0x22; // $F0+$30
+            ram[0xFF] = 0x34; // ポインタの下位
+            ram[0x00] = 0x03; // 上位は $0000 から ($0100 ではない)
+            ram[0x100] = 0x07;
+            ram[0x334] = 0x44;
+            ram[0x336] = 0x55;
+        }
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().register_a, 0x11);
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().register_x, 0x22);
+        steps(&mut nes, 1);
+        nes.cpu_mut().step_with_callback(|cpu| assert!(trace(cpu).contains("($FE,X) @ FF = 0334 = 44")));
+        assert_eq!(nes.cpu().register_a, 0x44);
+        steps(&mut nes, 2);
+        assert_eq!(nes.cpu().register_a, 0x55);
+    }
+
+    #[test]
+    fn test_resolve_address() {
+        let source = "
+            LDA #$41
+            STA $0345
+            LDX #$05
+            INC $0340,X
+            LDY $0345
+            JMP ($03FF)
+        ";
+        let mut nes = nes_with(source);
+        {
+            let ram = nes.ram_mut();
+            ram[0x3FF] = 0x00;
+            ram[0x300] = 0x90; // 上位は $0300 から
+            ram[0x400] = 0xA0;
+        }
+        steps(&mut nes, 6);
+        assert_eq!(nes.ram()[0x345], 0x42);
+        assert_eq!(nes.cpu().register_y, 0x42);
+        assert_eq!(nes.cpu().program_counter, 0x9000);
+
+        // オペランドは PC, PC+1 から読む
+        let cpu = nes.cpu_mut();
+        cpu.program_counter = 0x0200;
+        cpu.register_x = 0x20;
+        cpu.register_y = 0x10;
+        cpu.bus.mem_write(0x0200, 0xF0);
+        cpu.bus.mem_write(0x0201, 0x12);
+        assert_eq!(cpu.resolve_address(&AddressingMode::Absolute), (0x12F0, 0));
+        assert_eq!(cpu.resolve_address(&AddressingMode::Absolute_X), (0x1310, 1));
+        assert_eq!(cpu.resolve_address(&AddressingMode::Absolute_Y), (0x1300, 1));
+        assert_eq!(cpu.resolve_address(&AddressingMode::ZeroPage_X), (0x0010, 0));
+        assert_eq!(cpu.resolve_address(&AddressingMode::Relative), (0x01F0, 0));
+        cpu.bus.mem_write(0x0200, 0x10);
+        cpu.bus.mem_write(0x0010, 0x80);
+        cpu.bus.mem_write(0x0011, 0x12);
+        assert_eq!(cpu.resolve_address(&AddressingMode::Indirect_Y), (0x1290, 0));
+        cpu.bus.mem_write(0x0201, 0x00);
+        assert_eq!(cpu.resolve_address(&AddressingMode::Indirect), (0x1280, 0));
+    }
+
+    #[test]
+    fn test_store() {
+        let source = "
+            LDA #$11
+            LDX #$22
+            LDY #$33
+            STA $6000
+            STX $0801
+            STY $1802
+            STA $10
+            STA $10,X
+            STX $20,Y
+            STY $30,X
+            STA $0700,X
+            STA $0700,Y
+            STA ($40,X)
+            STA ($60),Y
+            STA $6100,Y
+        ";
+        let mut nes = TestRom::new().battery().asm(0x8000, source).nes().unwrap();
+        {
+            let ram = nes.ram_mut();
+            ram[0x62..0x64].copy_from_slice(&[0x00, 0x05]); // ($40+X) = $0500
+            ram[0x60..0x62].copy_from_slice(&[0x10, 0x60]); // ($60)+Y = $6043
+        }
+        steps(&mut nes, 15);
+        let ram = nes.ram();
+        assert_eq!((ram[0x001], ram[0x002]), (0x22, 0x33)); // ミラー
+        assert_eq!((ram[0x10], ram[0x32], ram[0x53], ram[0x52]), (0x11, 0x11, 0x22, 0x33));
+        assert_eq!((ram[0x722], ram[0x733], ram[0x500]), (0x11, 0x11, 0x11));
+        let cpu = nes.cpu_mut();
+        assert_eq!(cpu.mem_read(0x0801), 0x22);
+        assert_eq!((cpu.mem_read(0x6000), cpu.mem_read(0x6043), cpu.mem_read(0x6133)), (0x11, 0x11, 0x11));
+        // プログラムは書き換わらない
+        assert_eq!(cpu.mem_read(0x8000), 0xA9);
+    }
+
+    #[test]
+    fn test_read_modify_write() {
+        let source = "
+            LDX #$01
+            ASL $10
+            LSR $11
+            ROL $12
+            ROR $13,X
+            INC $0214
+            DEC $0214,X
+            INC $2006
+            INC $2006
+        ";
+        let mut nes = nes_with(source);
+        nes.ram_mut()[0x10..0x15].copy_from_slice(&[0x81, 0x03, 0x40, 0x00, 0x00]);
+        nes.ram_mut()[0x214..0x216].copy_from_slice(&[0x7F, 0x00]);
+        steps(&mut nes, 2);
+        assert_eq!(nes.ram()[0x10], 0x02);
+        assert_ne!(nes.cpu().status & FLAG_CARRY, 0);
+        steps(&mut nes, 1);
+        assert_eq!(nes.ram()[0x11], 0x01);
+        steps(&mut nes, 1);
+        assert_eq!(nes.ram()[0x12], 0x81); // キャリーが入る
+        steps(&mut nes, 1);
+        assert_eq!(nes.ram()[0x14], 0x00);
+        steps(&mut nes, 1);
+        assert_eq!(nes.ram()[0x214], 0x80);
+        assert_ne!(nes.cpu().status & FLAG_NEGATIVE, 0);
+        steps(&mut nes, 1);
+        assert_eq!(nes.ram()[0x215], 0xFF);
+        assert_eq!(nes.cpu().register_a, 0x00); // A は変わらない
+
+        // $2006 は書くたびにラッチが切り替わるので、2回書くと元に戻る
+        steps(&mut nes, 1);
+        assert!(nes.snapshot().ppu.write_latch);
+        nes.cpu_mut().set_rmw_double_write(true);
+        steps(&mut nes, 1);
+        assert!(nes.snapshot().ppu.write_latch);
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut nes = nes_with("loop: JMP loop");
+        let flags = FLAG_CARRY | FLAG_ZERO | FLAG_NEGATIVE;
+        // (レジスタ, オペランド, C Z N)
+        let cases = [
+            (0x40, 0x40, FLAG_CARRY | FLAG_ZERO),
+            (0x41, 0x40, FLAG_CARRY),
+            (0x40, 0x41, FLAG_NEGATIVE),
+            (0x00, 0xFF, 0),
+            (0xFF, 0x00, FLAG_CARRY | FLAG_NEGATIVE),
+        ];
+        for (register, operand, expected) in cases {
+            for (opcode, name) in [(0xC9, "CMP"), (0xE0, "CPX"), (0xC0, "CPY")] {
+                nes.ram_mut()[0x300..0x302].copy_from_slice(&[opcode, operand]);
+                let cpu = nes.cpu_mut();
+                // 比べないレジスタには逆の結果になる値を入れておく
+                cpu.register_a = !register;
+                cpu.register_x = !register;
+                cpu.register_y = !register;
+                match name {
+                    "CMP" => cpu.register_a = register,
+                    "CPX" => cpu.register_x = register,
+                    _ => cpu.register_y = register,
+                }
+                cpu.status = FLAG_BREAK2 | (!expected & flags);
+                cpu.program_counter = 0x0300;
+                cpu.step();
+                assert_eq!(cpu.status & flags, expected, "{} {:02X} {:02X}", name, register, operand);
+            }
+        }
+
+        // 比べたあとの BCS/BCC
+        let source = "
+            LDY #$10
+            CPY #$08
+            BCS ge
+            LDA #1
+            JMP end
+        ge: LDA #2
+       end: JMP end
+        ";
+        let mut nes = nes_with(source);
+        steps(&mut nes, 4);
+        assert_eq!(nes.cpu().register_a, 2);
+    }
+
+    #[test]
+    fn test_php_plp() {
+        // nestest と同じ: PLP で $FF を取り出すと P:EF、$00 なら P:20。PHP は P:24 を $34 として積む
+        let source = "
+            LDA #$FF
+            PHA
+            PLP
+            LDA #$00
+            PHA
+            PLP
+            SEI
+            PHP
+            PLA
+            LDA #$80
+            PHA
+            LDA #$10
+            PHA
+            PHA
+            RTI
+        ";
+        let mut nes = nes_with(source);
+        steps(&mut nes, 3);
+        assert_eq!(nes.cpu().status, 0xEF);
+        steps(&mut nes, 3);
+        assert_eq!(nes.cpu().status, 0x20);
+        steps(&mut nes, 3);
+        assert_eq!(nes.cpu().register_a, 0x34);
+        // RTI でも B は取り込まれず bit5 は立つ
+        steps(&mut nes, 6);
+        assert_eq!((nes.cpu().status, nes.cpu().program_counter), (0x20, 0x8010));
+    }
+}
@@ -0,0 +1,378 @@
+// デバッガ (Mesen/FCEUXのデバッガのペインに相当する情報を取り出す)
+// https://www.nesdev.org/wiki/PPU_pattern_tables
+// https://www.nesdev.org/wiki/PPU_nametables
+//   CPUレジスタ, 逆アセンブル + ブレークポイント, メモリ(HEX表示), PPUビューア, APUチャンネル
+// CPU以外のイベント (スプライト0ヒット, NMI, IRQ, PPUレジスタへの書き込み, スキャンライン/ドット) でも止められる
+//   イベントはイベントビューアの記録 (EventLog) から拾うので、条件を付けて実行すると記録も始まる
+//   スプライト0ヒットとマッパーのIRQはライン単位なので、止まるのは立ったラインの終わり
+// GUI (egui/eframe) は rscom 側の debugger_gui.rs (gui フィーチャー)。画面側はこの関数を呼んで表示するだけにする
+use crate::apu::{ApuChannel, ChannelStatus};
+use crate::bus::Mem;
+use crate::call_stack::{CallStack, StackByte};
+use crate::cpu::{disassemble, set_in_trace};
+use crate::error::EmuError;
+use crate::event_viewer::{EventKind, TimedEvent};
+use crate::nes::Nes;
+use crate::palette;
+use crate::symbols::Label;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub type Rgb = (u8, u8, u8);
+
+pub const PATTERN_TABLE_WIDTH: usize = 128; // 16タイル x 8ドット
+pub const PATTERN_TABLE_HEIGHT: usize = 256; // $0000と$1000を縦に並べる
+pub const NAME_TABLE_WIDTH: usize = 512; // 2x2画面
+pub const NAME_TABLE_HEIGHT: usize = 480;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisasmLine {
+    pub address: u16,
+    pub text: String, // "A9 01    LDA #$01" (オペランドはラベルがあれば名前)
+    pub label: Option<Label>, // この行のアドレスのラベル
+    pub breakpoint: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    FrameEnd,
+    Jam(u16), // JAM 命令でCPUが止まった
+    Event(BreakCondition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakCondition {
+    Sprite0Hit,
+    Nmi,
+    ApuIrq,
+    MapperIrq,
+    PpuWrite(u16), // $2000-$2007 への書き込み (ミラーは落として比べる)
+    Position { scanline: usize, dot: usize }, // PPUがこの位置を通り過ぎた
+}
+
+impl BreakCondition {
+    // スクリプトやコマンドから: sprite0, nmi, apuirq, mapperirq, write=2001, scanline=100[,dot]
+    pub fn parse(text: &str) -> Result<BreakCondition, String> {
+        let text = text.trim().to_ascii_lowercase();
+        let (name, value) = text.split_once('=').unwrap_or((&text, ""));
+        let number = |s: &str, radix: u32| usize::from_str_radix(s.trim(), radix).map_err(|_| format!("{}: {}", name, s));
+        Ok(match name {
+            "sprite0" => BreakCondition::Sprite0Hit,
+            "nmi" => BreakCondition::Nmi,
+            "apuirq" => BreakCondition::ApuIrq,
+            "mapperirq" => BreakCondition::MapperIrq,
+            "write" => match number(value.trim_start_matches('$'), 16)? {
+                addr @ 0x2000..=0x3FFF => BreakCondition::PpuWrite(addr as u16),
+                _ => return Err(format!("not a PPU register: {}", value)),
+            },
+            "scanline" => {
+                let (scanline, dot) = value.split_once(',').unwrap_or((value, "0"));
+                BreakCondition::Position {
+                    scanline: number(scanline, 10)?,
+                    dot: number(dot, 10)?,
+                }
+            }
+            _ => return Err(format!("unknown break condition: {}", text)),
+        })
+    }
+
+    fn matches(&self, event: &TimedEvent) -> bool {
+        match (*self, event.kind) {
+            (BreakCondition::Sprite0Hit, EventKind::Sprite0Hit)
+            | (BreakCondition::Nmi, EventKind::Nmi)
+            | (BreakCondition::ApuIrq, EventKind::ApuIrq)
+            | (BreakCondition::MapperIrq, EventKind::MapperIrq) => true,
+            (BreakCondition::PpuWrite(reg), EventKind::PpuWrite { addr, .. }) => addr & 0x2007 == reg & 0x2007,
+            _ => false,
+        }
+    }
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>, // 実行アドレス
+    conditions: Vec<BreakCondition>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            conditions: vec![],
+        }
+    }
+
+    pub fn add_condition(&mut self, condition: BreakCondition) {
+        if !self.conditions.contains(&condition) {
+            self.conditions.push(condition);
+        }
+    }
+
+    pub fn remove_condition(&mut self, condition: BreakCondition) -> bool {
+        let len = self.conditions.len();
+        self.conditions.retain(|c| *c != condition);
+        self.conditions.len() != len
+    }
+
+    pub fn conditions(&self) -> &[BreakCondition] {
+        &self.conditions
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    // 逆アセンブル画面でクリックしたときに使う。戻り値は切り替えたあとの状態
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+            return true;
+        }
+        false
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // フレームの終わりかブレークポイントまで実行する
+    // 止まっているアドレスのブレークポイントでは止まらないので、続けて呼べば先に進む
+    pub fn run_frame(&self, nes: &mut Nes) -> StopReason {
+        let frame = nes.frame_count();
+        if !self.conditions.is_empty() && nes.event_log().is_none() {
+            nes.start_event_log();
+        }
+        loop {
+            let position = ppu_position(nes);
+            let events = nes.event_log().map_or(0, |log| log.pending_events().len());
+            if let Err(EmuError::CpuJam(pc)) = nes.step() {
+                return StopReason::Jam(pc);
+            }
+            if let Some(condition) = self.hit_condition(nes, position, events) {
+                return StopReason::Event(condition);
+            }
+            if nes.frame_count() != frame {
+                nes.render_frame();
+                return StopReason::FrameEnd;
+            }
+            let pc = nes.cpu().program_counter;
+            if self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+        }
+    }
+
+    // 1命令の間に起きたイベントと通り過ぎた位置を条件と比べる
+    // before: 命令の前の (スキャンライン, ドット), events: 命令の前の記録中のイベントの数
+    fn hit_condition(&self, nes: &Nes, before: (usize, usize), events: usize) -> Option<BreakCondition> {
+        if self.conditions.is_empty() {
+            return None;
+        }
+        let after = ppu_position(nes);
+        let new_events: Vec<&TimedEvent> = match nes.event_log() {
+            // フレームが切り替わったら前のフレームの残りも見る
+            Some(log) if after < before => {
+                let rest = &log.frame_events()[events.min(log.frame_events().len())..];
+                rest.iter().chain(log.pending_events()).collect()
+            }
+            Some(log) => log.pending_events()[events..].iter().collect(),
+            None => vec![],
+        };
+        self.conditions.iter().copied().find(|condition| match *condition {
+            BreakCondition::Position { scanline, dot } => {
+                let target = (scanline, dot);
+                if before <= after {
+                    before < target && target <= after
+                } else {
+                    before < target || target <= after
+                }
+            }
+            _ => new_events.iter().any(|event| condition.matches(event)),
+        })
+    }
+
+    // start から count 命令分
+    pub fn disassemble(&self, nes: &mut Nes, start: u16, count: usize) -> Vec<DisasmLine> {
+        let mut lines = Vec::with_capacity(count);
+        let mut addr = start;
+        for _ in 0..count {
+            let (text, bytes) = disassemble(nes.cpu_mut(), addr);
+            lines.push(DisasmLine {
+                address: addr,
+                text: nes.bus().label_operand(&text),
+                label: nes.bus().label(addr).cloned(),
+                breakpoint: self.breakpoints.contains(&addr),
+            });
+            addr = addr.wrapping_add(bytes);
+        }
+        lines
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn ppu_position(nes: &Nes) -> (usize, usize) {
+    let ppu = nes.bus().ppu();
+    (ppu.scanline(), ppu.dot())
+}
+
+pub fn registers(nes: &Nes) -> Registers {
+    let cpu = nes.cpu();
+    Registers {
+        a: cpu.register_a,
+        x: cpu.register_x,
+        y: cpu.register_y,
+        p: cpu.status,
+        sp: cpu.stack_pointer,
+        pc: cpu.program_counter,
+    }
+}
+
+// スタックページ (SP+1 から $01FF) に、記録しているコールスタックから戻り先や積んだPを付ける
+// 記録していなければすべて Data
+pub fn stack_view(nes: &Nes) -> Vec<StackByte> {
+    let mut stack = [0; 256];
+    stack.copy_from_slice(&nes.ram()[0x100..0x200]);
+    let sp = nes.cpu().stack_pointer;
+    match nes.call_stack() {
+        Some(call_stack) => call_stack.annotate(&stack, sp),
+        None => CallStack::new().annotate(&stack, sp),
+    }
+}
+
+// CPUから見えるメモリ (PPUレジスタなどを読んでも副作用はない)
+// $FFFF を越える範囲は折り返さずにエラーにする
+pub fn read_memory(nes: &mut Nes, start: u16, len: usize) -> Result<Vec<u8>, EmuError> {
+    // len は gdb の m パケットなど外から来るので、足し算であふれないようにする
+    if (start as usize).checked_add(len).is_none_or(|end| end > 0x10000) {
+        return Err(EmuError::InvalidAddress { start, len });
+    }
+    set_in_trace(true);
+    let data = (0..len)
+        .map(|i| nes.bus_mut().mem_read(start.wrapping_add(i as u16)))
+        .collect();
+    set_in_trace(false);
+    Ok(data)
+}
+
+// HEX表示の1行 ("0000: 00 01 ... 0F")
+pub fn hex_line(addr: u16, data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("{:04X}: {}", addr, bytes.join(" "))
+}
+
+// パターンテーブルを palette (0-7) の色で並べる
+pub fn pattern_tables(nes: &Nes, palette: usize) -> Vec<Rgb> {
+    let ppu = nes.bus().ppu();
+    let chr = ppu.pattern_tables();
+    let mut image = vec![(0, 0, 0); PATTERN_TABLE_WIDTH * PATTERN_TABLE_HEIGHT];
+    for tile in 0..512 {
+        let (tx, ty) = (tile % 16 * 8, tile / 16 * 8);
+        for y in 0..8 {
+            let lo = chr.get(tile * 16 + y).copied().unwrap_or(0);
+            let hi = chr.get(tile * 16 + y + 8).copied().unwrap_or(0);
+            for x in 0..8 {
+                let value = (((hi >> (7 - x)) & 1) << 1 | ((lo >> (7 - x)) & 1)) as usize;
+                let index = if value == 0 { ppu.palette_table[0] } else { ppu.palette_table[(palette * 4 + value) & 0x1F] };
+                image[(ty + y) * PATTERN_TABLE_WIDTH + tx + x] = palette::system_palette()[index as usize & 0x3F];
+            }
+        }
+    }
+    image
+}
+
+// 4画面分のネームテーブル (ミラーリング込み、スクロールは無視)
+pub fn name_tables(nes: &Nes) -> Vec<Rgb> {
+    let ppu = nes.bus().ppu();
+    let chr = ppu.pattern_tables();
+    let bank = ppu.ctrl.background_pattern_addr() as usize;
+    let mut image = vec![(0, 0, 0); NAME_TABLE_WIDTH * NAME_TABLE_HEIGHT];
+    for table in 0..4u16 {
+        let base = 0x2000 + table * 0x400;
+        let (ox, oy) = ((table as usize % 2) * 256, (table as usize / 2) * 240);
+        for i in 0..960u16 {
+            let tile = ppu.vram[ppu.mirror_vram_addr(base + i) as usize] as usize;
+            let (col, row) = (i as usize % 32, i as usize / 32);
+            let attr = ppu.vram[ppu.mirror_vram_addr(base + 0x3C0 + (row / 4 * 8 + col / 4) as u16) as usize];
+            let shift = (row % 4 / 2) * 4 + (col % 4 / 2) * 2;
+            let palette = ((attr >> shift) & 0x03) as usize;
+            for y in 0..8 {
+                let lo = chr.get(bank + tile * 16 + y).copied().unwrap_or(0);
+                let hi = chr.get(bank + tile * 16 + y + 8).copied().unwrap_or(0);
+                for x in 0..8 {
+                    let value = (((hi >> (7 - x)) & 1) << 1 | ((lo >> (7 - x)) & 1)) as usize;
+                    let index = if value == 0 { ppu.palette_table[0] } else { ppu.palette_table[palette * 4 + value] };
+                    image[(oy + row * 8 + y) * NAME_TABLE_WIDTH + ox + col * 8 + x] =
+                        palette::system_palette()[index as usize & 0x3F];
+                }
+            }
+        }
+    }
+    image
+}
+
+pub fn channel_status(nes: &mut Nes) -> [ChannelStatus; 4] {
+    let apu = nes.apu_mut();
+    [
+        apu.channel_status(ApuChannel::Square1),
+        apu.channel_status(ApuChannel::Square2),
+        apu.channel_status(ApuChannel::Triangle),
+        apu.channel_status(ApuChannel::Noise),
+    ]
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::apu::{AudioConfig, APU};
+    use crate::rom::banked_rom;
+
+    #[test]
+    fn test_read_memory_has_no_side_effects() {
+        // N163 (内部RAMの自動インクリメントあり)。リセットベクタは$0000なのでRAMでループさせる
+        let mut nes = Nes::new(banked_rom(19, 16, 16), APU::headless(&AudioConfig::new())).unwrap();
+        for (addr, data) in [(0x0000, 0x4C), (0x0001, 0x00), (0x0002, 0x00)] {
+            nes.bus_mut().mem_write(addr, data); // JMP $0000
+        }
+        nes.bus_mut().mem_write(0x4017, 0x00); // 4ステップモード、フレームIRQあり
+        nes.bus_mut().mem_write(0xF800, 0x80);
+        nes.bus_mut().mem_write(0x4800, 0x11);
+        nes.bus_mut().mem_write(0x4800, 0x22);
+        nes.bus_mut().mem_write(0xF800, 0x80);
+        nes.run_frame();
+        nes.run_frame();
+
+        // 何度読んでもフレームIRQのフラグも内部RAMのアドレスも変わらない
+        for _ in 0..2 {
+            assert_eq!(read_memory(&mut nes, 0x4015, 1).unwrap()[0] & 0x40, 0x40);
+            assert_eq!(read_memory(&mut nes, 0x4800, 1).unwrap(), vec![0x11]);
+        }
+        assert_eq!(nes.bus_mut().mem_read(0x4015) & 0x40, 0x40);
+        assert_eq!(nes.bus_mut().mem_read(0x4015) & 0x40, 0);
+        assert_eq!(nes.bus_mut().mem_read(0x4800), 0x11);
+        assert_eq!(nes.bus_mut().mem_read(0x4800), 0x22);
+
+        assert!(read_memory(&mut nes, 0xFFF0, 0x10).is_ok());
+        assert!(read_memory(&mut nes, 0xFFF0, 0x11).is_err());
+        assert!(read_memory(&mut nes, 0x0001, usize::MAX).is_err());
+    }
+}
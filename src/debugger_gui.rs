@@ -0,0 +1,236 @@
+// egui のデバッガ (rscom --debugger)
+// https://github.com/emilk/egui
+// 表示する内容はすべて nes_core::debugger から取り出す (このファイルはペインに並べるだけ)
+//   左: CPUレジスタとAPUチャンネル, 中央: 逆アセンブル (行をクリックでブレークポイント), 右: メモリ(HEX表示)
+//   下: 画面とPPUビューア (パターンテーブル, ネームテーブル)
+// 音は出さない (APUはヘッドレス)
+use crate::cli::Options;
+use eframe::egui;
+use log::{info, warn};
+use nes_core::apu::{AudioConfig, APU};
+use nes_core::debugger::{self, Debugger, Rgb, StopReason};
+use nes_core::error::EmuError;
+use nes_core::frame::Frame;
+use nes_core::nes::Nes;
+use nes_core::region::Region;
+use nes_core::rom::Rom;
+
+const DISASM_LINES: usize = 32;
+const MEMORY_ROWS: usize = 32; // 1行16バイト
+const CHANNEL_NAMES: [&str; 4] = ["Square1", "Square2", "Triangle", "Noise"];
+
+struct DebuggerApp {
+    nes: Nes,
+    debugger: Debugger,
+    running: bool,
+    stop: Option<StopReason>,
+    memory_start: String, // HEX表示の先頭アドレス (入力中の文字列)
+    palette: usize,       // パターンテーブルの色 (0-7)
+    screen: Option<egui::TextureHandle>,
+    pattern: Option<egui::TextureHandle>,
+    name: Option<egui::TextureHandle>,
+}
+
+impl DebuggerApp {
+    fn new(nes: Nes) -> Self {
+        DebuggerApp {
+            nes,
+            debugger: Debugger::new(),
+            running: false,
+            stop: None,
+            memory_start: "0000".to_string(),
+            palette: 0,
+            screen: None,
+            pattern: None,
+            name: None,
+        }
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button(if self.running { "Pause" } else { "Run" }).clicked() {
+                self.running = !self.running;
+            }
+            if ui.add_enabled(!self.running, egui::Button::new("Step")).clicked() {
+                self.stop = match self.nes.step() {
+                    Err(EmuError::CpuJam(pc)) => Some(StopReason::Jam(pc)),
+                    _ => None,
+                };
+            }
+            if ui.add_enabled(!self.running, egui::Button::new("Frame")).clicked() {
+                self.stop = Some(self.debugger.run_frame(&mut self.nes));
+            }
+            ui.separator();
+            ui.label(format!("frame {}", self.nes.frame_count()));
+            match self.stop {
+                Some(StopReason::Breakpoint(pc)) => ui.label(format!("breakpoint ${:04X}", pc)),
+                Some(StopReason::Jam(pc)) => ui.label(format!("JAM ${:04X}", pc)),
+                Some(StopReason::Event(condition)) => ui.label(format!("{:?}", condition)),
+                _ => ui.label(""),
+            };
+        });
+    }
+
+    fn cpu_pane(&mut self, ui: &mut egui::Ui) {
+        ui.heading("CPU");
+        let regs = debugger::registers(&self.nes);
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if regs.p & (0x80 >> i) != 0 { c } else { '.' })
+            .collect();
+        egui::Grid::new("registers").show(ui, |ui| {
+            for (name, value) in [
+                ("PC", format!("{:04X}", regs.pc)),
+                ("A", format!("{:02X}", regs.a)),
+                ("X", format!("{:02X}", regs.x)),
+                ("Y", format!("{:02X}", regs.y)),
+                ("SP", format!("{:02X}", regs.sp)),
+                ("P", format!("{:02X} {}", regs.p, flags)),
+            ] {
+                ui.monospace(name);
+                ui.monospace(value);
+                ui.end_row();
+            }
+        });
+        ui.separator();
+        ui.heading("APU");
+        let channels = debugger::channel_status(&mut self.nes);
+        egui::Grid::new("channels").show(ui, |ui| {
+            for label in ["", "on", "period", "vol", "duty", "len"] {
+                ui.label(label);
+            }
+            ui.end_row();
+            for (name, status) in CHANNEL_NAMES.iter().zip(channels.iter()) {
+                ui.label(*name);
+                ui.monospace(if status.enabled { "*" } else { "-" });
+                ui.monospace(format!("{:03X}", status.period));
+                ui.monospace(format!("{:X}", status.volume));
+                ui.monospace(format!("{}", status.duty));
+                ui.monospace(format!("{:02X}", status.length));
+                ui.end_row();
+            }
+        });
+    }
+
+    fn disasm_pane(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Disassembly");
+        let pc = self.nes.cpu().program_counter;
+        let lines = self.debugger.disassemble(&mut self.nes, pc, DISASM_LINES);
+        for line in lines {
+            if let Some(label) = &line.label {
+                ui.monospace(format!("{}:", label.name));
+            }
+            let marker = if line.breakpoint { "●" } else { " " };
+            let text = egui::RichText::new(format!("{} {:04X}  {}", marker, line.address, line.text)).monospace();
+            let text = if line.address == pc { text.strong() } else { text };
+            if ui.add(egui::Label::new(text).sense(egui::Sense::click())).clicked() {
+                self.debugger.toggle_breakpoint(line.address);
+            }
+        }
+    }
+
+    fn memory_pane(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Memory");
+        ui.horizontal(|ui| {
+            ui.label("$");
+            ui.add(egui::TextEdit::singleline(&mut self.memory_start).desired_width(48.0));
+        });
+        let start = u16::from_str_radix(self.memory_start.trim_start_matches('$'), 16).unwrap_or(0) & 0xFFF0;
+        let len = (MEMORY_ROWS * 16).min(0x10000 - start as usize);
+        match debugger::read_memory(&mut self.nes, start, len) {
+            Ok(data) => {
+                for (i, row) in data.chunks(16).enumerate() {
+                    ui.monospace(debugger::hex_line(start.wrapping_add(i as u16 * 16), row));
+                }
+            }
+            Err(e) => {
+                ui.label(format!("{}", e));
+            }
+        }
+    }
+
+    fn ppu_pane(&mut self, ui: &mut egui::Ui) {
+        let ctx = ui.ctx().clone();
+        let screen = texture(&ctx, &mut self.screen, "screen", [Frame::WIDTH, Frame::HEIGHT], &self.nes.frame().data);
+        let pattern = rgb_bytes(&debugger::pattern_tables(&self.nes, self.palette));
+        let pattern = texture(
+            &ctx,
+            &mut self.pattern,
+            "pattern_tables",
+            [debugger::PATTERN_TABLE_WIDTH, debugger::PATTERN_TABLE_HEIGHT],
+            &pattern,
+        );
+        let name = rgb_bytes(&debugger::name_tables(&self.nes));
+        let name = texture(
+            &ctx,
+            &mut self.name,
+            "name_tables",
+            [debugger::NAME_TABLE_WIDTH, debugger::NAME_TABLE_HEIGHT],
+            &name,
+        );
+        ui.horizontal(|ui| {
+            ui.image(&screen);
+            ui.vertical(|ui| {
+                ui.add(egui::Slider::new(&mut self.palette, 0..=7).text("palette"));
+                ui.image(&pattern);
+            });
+            ui.add(egui::Image::new(&name).fit_to_exact_size(egui::vec2(256.0, 240.0)));
+        });
+    }
+}
+
+impl eframe::App for DebuggerApp {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        if self.running {
+            let stop = self.debugger.run_frame(&mut self.nes);
+            if stop != StopReason::FrameEnd {
+                self.running = false;
+            }
+            self.stop = Some(stop);
+            ui.ctx().request_repaint();
+        }
+        egui::Panel::top("toolbar").show(ui, |ui| self.toolbar(ui));
+        egui::Panel::bottom("ppu").show(ui, |ui| self.ppu_pane(ui));
+        egui::Panel::left("cpu").show(ui, |ui| self.cpu_pane(ui));
+        egui::Panel::right("memory").show(ui, |ui| self.memory_pane(ui));
+        egui::CentralPanel::default_margins().show(ui, |ui| self.disasm_pane(ui));
+    }
+}
+
+fn rgb_bytes(image: &[Rgb]) -> Vec<u8> {
+    image.iter().flat_map(|&(r, g, b)| [r, g, b]).collect()
+}
+
+// 毎フレーム作り直さずに中身だけ入れ替える
+fn texture(
+    ctx: &egui::Context,
+    handle: &mut Option<egui::TextureHandle>,
+    name: &str,
+    size: [usize; 2],
+    rgb: &[u8],
+) -> egui::TextureHandle {
+    let image = egui::ColorImage::from_rgb(size, rgb);
+    match handle {
+        Some(handle) => handle.set(image, egui::TextureOptions::NEAREST),
+        None => *handle = Some(ctx.load_texture(name, image, egui::TextureOptions::NEAREST)),
+    }
+    handle.clone().unwrap()
+}
+
+pub fn run(options: &Options, rom: Rom) {
+    let region = options.region.unwrap_or_else(|| Region::detect(&rom.header, &options.rom_path));
+    let mut nes = Nes::new(rom, APU::headless(&AudioConfig::new())).unwrap_or_else(|e| panic!("[ERR] {}", e));
+    nes.set_region(region);
+    for (path, result) in nes.symbols_mut().load_beside(std::path::Path::new(&options.rom_path)) {
+        match result {
+            Ok(count) => info!("LABELS: {} ({} labels)", path.display(), count),
+            Err(e) => warn!("[ERR] LABELS: {}: {}", path.display(), e),
+        }
+    }
+    nes.render_frame();
+    let app = DebuggerApp::new(nes);
+    if let Err(e) = eframe::run_native("rscom debugger", eframe::NativeOptions::default(), Box::new(|_| Ok(Box::new(app)))) {
+        warn!("[ERR] DEBUGGER: {}", e);
+    }
+}
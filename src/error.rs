@@ -0,0 +1,56 @@
+// 公開APIのエラー (ROMの読み込み, ステート, メモリアクセス, 実行)
+// 壊れたROMやおかしなアドレスでもホストのプロセスを落とさずに呼び出し側へ返す
+use crate::rom::RomError;
+use crate::state::StateError;
+
+#[derive(Debug)]
+pub enum EmuError {
+    Rom(RomError),
+    State(StateError),
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    // アドレス空間 (CPUなら$0000-$FFFF) をはみ出した範囲
+    InvalidAddress { start: u16, len: usize },
+    // JAM (KIL) 命令でCPUが止まった (リセットするまで動かない)
+    CpuJam(u16),
+}
+
+impl core::fmt::Display for EmuError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            EmuError::Rom(e) => write!(f, "{}", e),
+            EmuError::State(e) => write!(f, "{}", e),
+            #[cfg(feature = "std")]
+            EmuError::Io(e) => write!(f, "{}", e),
+            EmuError::InvalidAddress { start, len } => {
+                write!(f, "Address range ${:04X}+{} is out of the address space", start, len)
+            }
+            EmuError::CpuJam(pc) => write!(f, "CPU jammed at ${:04X}", pc),
+        }
+    }
+}
+
+impl core::error::Error for EmuError {}
+
+impl From<RomError> for EmuError {
+    fn from(e: RomError) -> Self {
+        match e {
+            #[cfg(feature = "std")]
+            RomError::Io(e) => EmuError::Io(e),
+            e => EmuError::Rom(e),
+        }
+    }
+}
+
+impl From<StateError> for EmuError {
+    fn from(e: StateError) -> Self {
+        EmuError::State(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for EmuError {
+    fn from(e: std::io::Error) -> Self {
+        EmuError::Io(e)
+    }
+}
@@ -0,0 +1,162 @@
+// イベントビューア用の記録 (Mesenのイベントビューアに相当)
+// https://www.mesen.ca/docs/debugging/eventviewer.html
+// レジスタへの書き込み, NMI/IRQ, マッパーのIRQカウンタのクロックを、起きたスキャンライン/ドットと一緒に残す
+// 1フレームはスキャンライン0からプリレンダラインまで。終わったフレームの分を frame_events で読む
+// マッパーのIRQクロック (A12の立ち上がり) とスプライト0ヒットはライン単位でしか見ていないので、ドットは次のラインの先頭になる
+// タイミング図用に、スキャンラインごとのPPUの状態とCPUサイクルの内訳も集計する
+//   https://www.nesdev.org/wiki/PPU_rendering
+//   命令のサイクルは命令を始めたときのラインに数える (ラインをまたいでも分けない)
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    PpuWrite { addr: u16, value: u8 },    // $2000-$2007
+    ApuWrite { addr: u16, value: u8 },    // $4000-$4017 (OAM DMA, コントローラーを含む)
+    MapperWrite { addr: u16, value: u8 }, // $4020-$5FFF, $8000-$FFFF
+    Nmi,
+    ApuIrq,         // フレームカウンタ/DMCのIRQが立った
+    MapperIrq,      // マッパーのIRQが立った
+    MapperIrqClock, // A12の立ち上がり (MMC3のスキャンラインカウンタが進む)
+    Sprite0Hit,     // スプライト0ヒットが立った
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedEvent {
+    pub scanline: u16,
+    pub dot: u16,
+    pub cpu_cycle: usize, // 電源投入からのCPUサイクル
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanlineKind {
+    Rendering,  // 表示ライン (0-239) で描画が有効
+    Idle,       // 表示ラインで描画が無効、またはポストレンダライン (240)
+    VBlank,     // VBlankの開始からプリレンダの前まで
+    PreRender,  // 最後のライン
+}
+
+impl ScanlineKind {
+    pub fn of(scanline: usize, scanlines: usize, vblank_scanline: usize, rendering: bool) -> ScanlineKind {
+        if scanline + 1 == scanlines {
+            ScanlineKind::PreRender
+        } else if scanline >= vblank_scanline {
+            ScanlineKind::VBlank
+        } else if scanline < 240 && rendering {
+            ScanlineKind::Rendering
+        } else {
+            ScanlineKind::Idle
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanlineTiming {
+    pub kind: ScanlineKind,
+    pub cpu_cycles: usize,       // 命令を実行したサイクル (割り込み処理を含む)
+    pub interrupt_cycles: usize, // そのうちNMI/IRQのハンドラの中 (RTIまで)
+    pub dma_cycles: usize,       // OAM DMAでCPUが止まっていたサイクル
+}
+
+impl ScanlineTiming {
+    fn new(kind: ScanlineKind) -> Self {
+        ScanlineTiming {
+            kind,
+            cpu_cycles: 0,
+            interrupt_cycles: 0,
+            dma_cycles: 0,
+        }
+    }
+}
+
+pub struct EventLog {
+    current: Vec<TimedEvent>,
+    frame: Vec<TimedEvent>,
+    lines: Vec<ScanlineTiming>,
+    frame_lines: Vec<ScanlineTiming>,
+    interrupt_depth: usize,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            current: vec![],
+            frame: vec![],
+            lines: vec![],
+            frame_lines: vec![],
+            interrupt_depth: 0,
+        }
+    }
+
+    pub fn enter_interrupt(&mut self) {
+        self.interrupt_depth += 1;
+    }
+
+    // RTI (割り込みの途中から記録を始めたときは数えすぎないように0で止める)
+    pub fn leave_interrupt(&mut self) {
+        self.interrupt_depth = self.interrupt_depth.saturating_sub(1);
+    }
+
+    // scanline で使ったCPUサイクルを足す (dma なら OAM DMA)
+    pub fn add_cycles(&mut self, scanline: usize, kind: ScanlineKind, cycles: usize, dma: bool) {
+        if self.lines.len() <= scanline {
+            self.lines.resize(scanline + 1, ScanlineTiming::new(ScanlineKind::Idle));
+        }
+        let line = &mut self.lines[scanline];
+        line.kind = kind;
+        if dma {
+            line.dma_cycles += cycles;
+        } else {
+            line.cpu_cycles += cycles;
+            if self.interrupt_depth > 0 {
+                line.interrupt_cycles += cycles;
+            }
+        }
+    }
+
+    // 最後に終わったフレームのスキャンラインごとの内訳 (記録を始める前のラインは Idle, 0サイクル)
+    pub fn frame_scanlines(&self) -> &[ScanlineTiming] {
+        &self.frame_lines
+    }
+
+    pub fn record(&mut self, scanline: usize, dot: usize, cpu_cycle: usize, kind: EventKind) {
+        self.current.push(TimedEvent {
+            scanline: scanline as u16,
+            dot: dot as u16,
+            cpu_cycle,
+            kind,
+        });
+    }
+
+    // スキャンライン0に戻ったときに呼ぶ
+    pub fn end_frame(&mut self) {
+        self.frame = core::mem::take(&mut self.current);
+        self.frame_lines = core::mem::take(&mut self.lines);
+    }
+
+    // 最後に終わったフレームのイベント (起きた順)
+    pub fn frame_events(&self) -> &[TimedEvent] {
+        &self.frame
+    }
+
+    // 今のフレームでここまでに起きたイベント (デバッガで止めたとき用)
+    pub fn pending_events(&self) -> &[TimedEvent] {
+        &self.current
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 書き込み先のアドレスからイベントの種類を決める (記録しないアドレスはNone)
+pub fn write_event(addr: u16, value: u8) -> Option<EventKind> {
+    match addr {
+        0x2000..=0x2007 => Some(EventKind::PpuWrite { addr, value }),
+        0x4000..=0x4017 => Some(EventKind::ApuWrite { addr, value }),
+        0x4020..=0x5FFF | 0x8000..=0xFFFF => Some(EventKind::MapperWrite { addr, value }),
+        _ => None,
+    }
+}
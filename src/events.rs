@@ -0,0 +1,91 @@
+// 組み込み側 (フロントエンドやツール) への通知
+// https://www.nesdev.org/wiki/NMI
+// https://www.nesdev.org/wiki/IRQ
+// 内部の状態をフレームごとに覗かなくても、起きたときにフックが呼ばれる
+// フックは本体と一緒に別のスレッドへ渡せるように Send にする
+use crate::memory_view::MemorySpace;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmuEvent {
+    FrameComplete(usize), // VBlankの始まり (フレーム番号)
+    Nmi,
+    ApuIrq,                  // フレームカウンタ/DMCのIRQが立った
+    MapperIrq,               // マッパーのIRQが立った
+    SaveStateCreated(usize), // ステートのバイト数
+    SramModified(u16),       // バッテリーバックアップのRAMを書き換えたアドレス
+    MemoryEdited { space: MemorySpace, start: u16, len: usize }, // HEXエディタなどから書き換えた
+}
+
+pub type HookId = usize;
+
+type Hook = Box<dyn FnMut(&EmuEvent) + Send>;
+
+pub struct EventHooks {
+    hooks: Vec<(HookId, Hook)>,
+    next_id: HookId,
+}
+
+impl EventHooks {
+    pub fn new() -> Self {
+        EventHooks {
+            hooks: vec![],
+            next_id: 0,
+        }
+    }
+
+    // 戻り値の id で外せる
+    pub fn add<F>(&mut self, hook: F) -> HookId
+    where
+        F: FnMut(&EmuEvent) + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.hooks.push((id, Box::new(hook)));
+        id
+    }
+
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|(hook_id, _)| *hook_id != id);
+        self.hooks.len() != len
+    }
+
+    // 誰も聞いていなければイベントを作る手間も省ける
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn emit(&mut self, event: EmuEvent) {
+        for (_, hook) in self.hooks.iter_mut() {
+            hook(&event);
+        }
+    }
+}
+
+impl Default for EventHooks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_event_hooks() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut hooks = EventHooks::new();
+        let log = seen.clone();
+        let id = hooks.add(move |event| log.lock().unwrap().push(*event));
+        hooks.emit(EmuEvent::Nmi);
+        assert!(hooks.remove(id));
+        assert!(!hooks.remove(id));
+        hooks.emit(EmuEvent::ApuIrq);
+        assert!(hooks.is_empty());
+        assert_eq!(*seen.lock().unwrap(), vec![EmuEvent::Nmi]);
+    }
+}
@@ -0,0 +1,345 @@
+// Sunsoft FME-7 / 5B (Mapper 69)
+// https://www.nesdev.org/wiki/Sunsoft_FME-7
+// https://www.nesdev.org/wiki/Sunsoft_5B_audio
+// $8000: コマンド番号, $A000: パラメータ の2段階でレジスタに書き込む
+use crate::common::*;
+use crate::mapper::{prg_ram_read, prg_ram_write, Mapper};
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+// 5Bの音量は1段階 3dB
+const SUNSOFT_5B_VOLUME: f32 = 0.25;
+// 10^((v - 15) * 3 / 20) (no_stdでも使えるように表にしておく)
+const VOLUME_TBL: [f32; 16] = [
+    0.005623, 0.007943, 0.011220, 0.015849,
+    0.022387, 0.031623, 0.044668, 0.063096,
+    0.089125, 0.125893, 0.177828, 0.251189,
+    0.354813, 0.501187, 0.707946, 1.000000,
+];
+
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    prg_ram: Vec<u8>,
+    is_chr_ram: bool,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 4],  // $6000, $8000, $A000, $C000
+    prg_ram_select: bool, // $6000-$7FFF: RAM(true) / ROM(false)
+    prg_ram_enable: bool,
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5b,
+}
+
+impl_state!(Fme7 {
+    chr, prg_ram, command, chr_banks, prg_banks, prg_ram_select, prg_ram_enable,
+    mirroring, irq_enabled, irq_counter_enabled, irq_counter, irq_pending, audio
+});
+
+impl Fme7 {
+    pub fn new(rom: &Rom) -> Self {
+        Fme7 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: rom.chr_rom.clone(),
+            prg_ram: vec![0; rom.header.prg_ram_size],
+            is_chr_ram: rom.is_chr_ram,
+
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            prg_ram_select: false,
+            prg_ram_enable: false,
+            mirroring: rom.mirroring.clone(),
+
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+
+            audio: Sunsoft5b::new(),
+        }
+    }
+
+    fn prg_addr(&self, bank: usize, addr: u16) -> usize {
+        let count = self.prg_rom.len() / PRG_BANK_SIZE;
+        (bank % count) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr as usize >> 10) & 0x07] as usize;
+        let count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % count) * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+            0x8 => {
+                // ERbB BBBB  E: RAM有効, R: RAM/ROM選択, B: ROMバンク
+                self.prg_ram_enable = data & _BIT_7 != 0;
+                self.prg_ram_select = data & _BIT_6 != 0;
+                self.prg_banks[0] = data & 0x3F;
+            }
+            0x9..=0xB => self.prg_banks[(self.command - 0x8) as usize] = data & 0x3F,
+            0xC => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONE_SCREEN_LOWER,
+                    _ => Mirroring::ONE_SCREEN_UPPER,
+                };
+            }
+            0xD => {
+                // C------T  C: カウンタ有効, T: IRQ有効 (書き込みでIRQ応答)
+                self.irq_enabled = data & _BIT_0 != 0;
+                self.irq_counter_enabled = data & _BIT_7 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_select && self.prg_ram_enable => prg_ram_read(&self.prg_ram, addr),
+            0x6000..=0x7FFF if self.prg_ram_select => 0,
+            0x6000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr).unwrap()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_select && self.prg_ram_enable => {
+                prg_ram_write(&mut self.prg_ram, addr, data)
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.select(data),
+            0xE000..=0xFFFF => self.audio.write(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.is_chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = data;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    // $6000-$7FFF もROMを割り当てられる
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x6000..=0x7FFF if !self.prg_ram_select => Some(self.prg_addr(self.prg_banks[0] as usize, addr)),
+            0x8000..=0xDFFF => {
+                let slot = ((addr - 0x8000) as usize / PRG_BANK_SIZE) + 1;
+                Some(self.prg_addr(self.prg_banks[slot] as usize, addr))
+            }
+            0xE000..=0xFFFF => {
+                let last = self.prg_rom.len() / PRG_BANK_SIZE - 1;
+                Some(self.prg_addr(last, addr))
+            }
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+}
+
+// Sunsoft 5B 拡張音源 (YM2149F互換, 矩形波3ch)
+// ノイズとエンベロープは使っているソフトがほぼないので未対応
+struct Sunsoft5b {
+    register: u8,
+    periods: [u16; 3],
+    volumes: [u8; 3],
+    tone_disable: u8, // R7 の下位3ビット
+    counters: [u16; 3],
+    outputs: [bool; 3],
+    divider: u8,
+}
+
+impl_state!(Sunsoft5b { register, periods, volumes, tone_disable, counters, outputs, divider });
+
+impl Sunsoft5b {
+    fn new() -> Self {
+        Sunsoft5b {
+            register: 0,
+            periods: [0; 3],
+            volumes: [0; 3],
+            tone_disable: 0x07,
+            counters: [0; 3],
+            outputs: [false; 3],
+            divider: 0,
+        }
+    }
+
+    fn select(&mut self, data: u8) {
+        self.register = data & 0x0F;
+    }
+
+    fn write(&mut self, data: u8) {
+        match self.register {
+            0x0 | 0x2 | 0x4 => {
+                let ch = (self.register / 2) as usize;
+                self.periods[ch] = (self.periods[ch] & 0x0F00) | data as u16;
+            }
+            0x1 | 0x3 | 0x5 => {
+                let ch = (self.register / 2) as usize;
+                self.periods[ch] = (self.periods[ch] & 0x00FF) | ((data & 0x0F) as u16) << 8;
+            }
+            0x7 => self.tone_disable = data & 0x07,
+            0x8..=0xA => self.volumes[(self.register - 0x8) as usize] = data & 0x0F,
+            _ => {}
+        }
+    }
+
+    // CPUクロックで呼ぶ (トーンは16CPUサイクルごとに進む)
+    fn tick(&mut self) {
+        self.divider = (self.divider + 1) & 0x0F;
+        if self.divider != 0 {
+            return;
+        }
+        for ch in 0..3 {
+            self.counters[ch] += 1;
+            if self.counters[ch] >= self.periods[ch].max(1) {
+                self.counters[ch] = 0;
+                self.outputs[ch] = !self.outputs[ch];
+            }
+        }
+    }
+
+    fn output(&self) -> f32 {
+        (0..3)
+            .filter(|&ch| self.tone_disable & (1 << ch) == 0 && self.volumes[ch] != 0)
+            .map(|ch| {
+                let amp = VOLUME_TBL[self.volumes[ch] as usize & 0x0F];
+                let x = if self.outputs[ch] { amp } else { -amp };
+                x * SUNSOFT_5B_VOLUME
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::banked_rom;
+
+    // PRG 8KB x16, CHR 1KB x16
+    fn fme7() -> Rom {
+        banked_rom(69, 16, 16)
+    }
+
+    fn command(mapper: &mut Fme7, cmd: u8, param: u8) {
+        mapper.cpu_write(0x8000, cmd);
+        mapper.cpu_write(0xA000, param);
+    }
+
+    #[test]
+    fn test_fme7_banking() {
+        let mut mapper = Fme7::new(&fme7());
+        command(&mut mapper, 0x9, 3);
+        command(&mut mapper, 0xA, 4);
+        command(&mut mapper, 0xB, 5);
+        command(&mut mapper, 0x3, 9);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 4);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+        assert_eq!(mapper.ppu_read(0x0C00), 9);
+
+        // $6000: ROMバンク
+        command(&mut mapper, 0x8, 0x07);
+        assert_eq!(mapper.cpu_read(0x6000), 7);
+        // $6000: RAM
+        command(&mut mapper, 0x8, 0xC0);
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_read(0x6000), 0x55);
+    }
+
+    #[test]
+    fn test_fme7_irq() {
+        let mut mapper = Fme7::new(&fme7());
+        command(&mut mapper, 0xE, 2);
+        command(&mut mapper, 0xF, 0);
+        command(&mut mapper, 0xD, 0x81);
+
+        // 2 -> 1 -> 0 -> FFFF でIRQ
+        for _ in 0..2 {
+            mapper.cpu_tick();
+        }
+        assert!(!mapper.irq_pending());
+        mapper.cpu_tick();
+        assert!(mapper.irq_pending());
+
+        command(&mut mapper, 0xD, 0x00);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_sunsoft_5b_tone() {
+        let mut mapper = Fme7::new(&fme7());
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        mapper.cpu_write(0xC000, 0x0);
+        mapper.cpu_write(0xE000, 0x01); // ch A 周期1
+        mapper.cpu_write(0xC000, 0x7);
+        mapper.cpu_write(0xE000, 0x3E); // ch A のみ有効
+        mapper.cpu_write(0xC000, 0x8);
+        mapper.cpu_write(0xE000, 0x0F);
+
+        let first = mapper.audio_output();
+        assert!(first != 0.0);
+        for _ in 0..16 {
+            mapper.cpu_tick();
+        }
+        assert_eq!(mapper.audio_output(), -first);
+    }
+}
@@ -0,0 +1,112 @@
+// Four Score (4人用マルチタップ)
+// https://www.nesdev.org/wiki/Four_player_adapters
+// 各ポートから24ビット読める: 1人目(8) + 3人目(8) + シグネチャ(8)
+// シグネチャは $4016 が 0b0001_0000、$4017 が 0b0010_0000 (下位ビットから読み出される)
+use core::any::Any;
+
+use crate::controller::Peripheral;
+use crate::cpu::in_trace;
+use crate::gamepad::Button;
+use crate::impl_state;
+
+const SIGNATURE_PORT_1: u8 = 0b0001_0000;
+const SIGNATURE_PORT_2: u8 = 0b0010_0000;
+const REPORT_BITS: u8 = 24;
+
+pub struct FourScore {
+    pads: [Button; 2], // このポートにつながる2人分 (1P/3P または 2P/4P)
+    signature: u8,
+    strobe: bool,
+    bit_index: u8,
+}
+
+impl FourScore {
+    // $4016側 (1P, 3P)
+    pub fn port1() -> Self {
+        FourScore::new(SIGNATURE_PORT_1)
+    }
+
+    // $4017側 (2P, 4P)
+    pub fn port2() -> Self {
+        FourScore::new(SIGNATURE_PORT_2)
+    }
+
+    fn new(signature: u8) -> Self {
+        FourScore {
+            pads: [Button::empty(); 2],
+            signature,
+            strobe: false,
+            bit_index: 0,
+        }
+    }
+
+    // slot: 0 = 1P/2P, 1 = 3P/4P
+    pub fn set_buttons(&mut self, slot: usize, buttons: Button) {
+        self.pads[slot] = buttons;
+    }
+
+    pub fn buttons(&self, slot: usize) -> Button {
+        self.pads[slot]
+    }
+
+    fn report(&self) -> u32 {
+        self.pads[0].bits() as u32 | (self.pads[1].bits() as u32) << 8 | (self.signature as u32) << 16
+    }
+}
+
+impl_state!(FourScore { pads, signature, strobe, bit_index });
+
+impl Peripheral for FourScore {
+    fn name(&self) -> &'static str {
+        "four_score"
+    }
+
+    fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.bit_index = 0;
+        }
+    }
+
+    fn read(&mut self) -> u8 {
+        // 24ビット読み終わったら1
+        if self.bit_index >= REPORT_BITS {
+            return 1;
+        }
+        let bit = (self.report() >> self.bit_index) as u8 & 1;
+        if !self.strobe && !in_trace() {
+            self.bit_index += 1;
+        }
+        bit
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::ControllerPorts;
+
+    fn read_report(ports: &mut ControllerPorts, port: usize) -> u32 {
+        (0..REPORT_BITS).fold(0, |acc, i| acc | ((ports.read(port) & 1) as u32) << i)
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut ports = ControllerPorts::new();
+        ports.connect_four_score();
+        ports.set_player_buttons(0, Button::BUTTON_A);
+        ports.set_player_buttons(1, Button::BUTTON_B);
+        ports.set_player_buttons(2, Button::START);
+        ports.set_player_buttons(3, Button::RIGHT);
+
+        ports.write(1);
+        ports.write(0);
+        assert_eq!(read_report(&mut ports, 0), 0x10_08_01);
+        assert_eq!(read_report(&mut ports, 1), 0x20_80_02);
+        assert_eq!(ports.read(0) & 1, 1);
+    }
+}
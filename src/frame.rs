@@ -1,12 +1,13 @@
 use crate::palette;
+use alloc::vec::Vec;
 
 pub struct Frame {
     pub data: Vec<u8>,
 }
 
 impl Frame {
-    const WIDTH: usize = 256;
-    const HEIGHT: usize = 240;
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
 
     pub fn new() -> Self {
         Frame {
@@ -22,4 +23,19 @@ impl Frame {
             self.data[base + 2] = rgb.2;
         }
     }
+
+    // 画面の外ならNone
+    pub fn get_pixel(&self, x: i32, y: i32) -> Option<(u8, u8, u8)> {
+        if x < 0 || y < 0 || x as usize >= Frame::WIDTH || y as usize >= Frame::HEIGHT {
+            return None;
+        }
+        let base = (y as usize * Frame::WIDTH + x as usize) * 3;
+        Some((self.data[base], self.data[base + 1], self.data[base + 2]))
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,625 @@
+// SDLのフロントエンド (ウィンドウ, オーディオ, キーボード/ゲームコントローラー)
+// `sdl` フィーチャー (既定で有効) のときだけ含める
+use nes_core::common::*;
+use crate::cli::Options;
+use nes_core::bus::InputPoll;
+
+use nes_core::apu::{AudioConfig, AudioSink, AudioStream, APU};
+use nes_core::arkanoid::ArkanoidPaddle;
+use crate::bindings::InputBindings;
+use nes_core::av_dump::AvDump;
+use nes_core::capture::Capture;
+use nes_core::cheat::CheatManager;
+use nes_core::nes::Nes;
+use nes_core::frame::Frame;
+use nes_core::controller::{ControllerPorts, PortDevice};
+use crate::game_controller::GameControllers;
+use crate::settings::Settings;
+use nes_core::gamepad::Button;
+use nes_core::ppu_dump;
+use nes_core::perf::{draw_overlay, FrameTiming, PerfStats};
+use nes_core::movie::{Movie, MoviePlayer, MovieRecorder, MovieStart, PLAYER_COUNT};
+use log::{info, warn};
+use nes_core::rom::Rom;
+use nes_core::region::Region;
+use nes_core::video::Viewport;
+use nes_core::zapper::Zapper;
+use nes_core::save_slots::{SaveSlots, SLOT_COUNT};
+use nes_core::sram::SramConfig;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use sdl2::EventPump;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+
+// 入力の読み取り中は Nes を触れないので、フレームの区切りでまとめて処理する
+enum Hotkey {
+    Quit,              // Esc
+    SwitchPort(usize), // F1, F2
+    PerfHud,           // F3
+    ScaleMode,         // F4 (整数倍/ウィンドウいっぱい)
+    AspectRatio,       // F8 (正方形/8:7)
+    Fullscreen,        // F11
+    Vsync,             // F12
+    SaveState,         // F5
+    NextSlot,          // F6
+    LoadState,         // F7
+    Pause,             // P
+    FrameAdvance,      // \ (一時停止中は1フレーム進める)
+    Capture,           // F9 (録画の開始/停止)
+    AvDump,            // F10 (音声付きの動画の書き出しの開始/停止)
+    PpuDump,           // V (VRAM, ネームテーブル, OAM, パレットの書き出し)
+}
+
+// SDLのオーディオコールバックからリングバッファを読む
+struct SdlOutput(AudioStream);
+
+impl AudioCallback for SdlOutput {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+// デバイスはスレッドをまたげないので、開いたスレッドで持ち続ける (手放すと音が止まる)
+struct SdlAudioSink {
+    audio: sdl2::AudioSubsystem,
+    device: Option<AudioDevice<SdlOutput>>,
+}
+
+impl AudioSink for SdlAudioSink {
+    fn open(&mut self, config: &AudioConfig, stream: AudioStream) -> Result<(i32, u16), String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(config.sample_rate),
+            channels: Some(1),
+            samples: Some(config.buffer_samples),
+        };
+        let device = self.audio.open_playback(None, &desired_spec, |_| SdlOutput(stream))?;
+        device.resume();
+        let spec = device.spec();
+        let opened = (spec.freq, spec.samples);
+        self.device = Some(device);
+        Ok(opened)
+    }
+}
+
+// ウィンドウの大きさや設定が変わっても毎回置き直す
+// mouse_viewport はウィンドウの座標での位置 (高DPIでは描画先の画素数と違う)
+fn present(
+    canvas: &mut Canvas<Window>,
+    texture: &mut Texture,
+    frame: &Frame,
+    settings: &Settings,
+    mouse_viewport: &Cell<Viewport>,
+) {
+    texture.update(None, &frame.data, 256 * 3).unwrap();
+    let viewport = Viewport::fit(canvas.output_size().unwrap(), settings.scale_mode, settings.aspect);
+    mouse_viewport.set(Viewport::fit(canvas.window().size(), settings.scale_mode, settings.aspect));
+    canvas.set_draw_color(Color::BLACK);
+    canvas.clear();
+    canvas
+        .copy(texture, None, Rect::new(viewport.x, viewport.y, viewport.w, viewport.h))
+        .unwrap();
+    canvas.present();
+}
+
+// sdl2 0.35 には SDL_RenderSetVSync (SDL 2.0.18以降) のラッパーがない
+extern "C" {
+    fn SDL_RenderSetVSync(renderer: *mut sdl2::sys::SDL_Renderer, vsync: std::os::raw::c_int) -> std::os::raw::c_int;
+}
+
+fn set_vsync(canvas: &Canvas<Window>, vsync: bool) -> Result<(), String> {
+    if unsafe { SDL_RenderSetVSync(canvas.raw(), vsync as std::os::raw::c_int) } != 0 {
+        return Err(sdl2::get_error());
+    }
+    Ok(())
+}
+
+fn poll_events(event_pump: &mut EventPump, controllers: &mut Option<GameControllers>, hotkeys: &mut Vec<Hotkey>) {
+    for event in event_pump.poll_iter() {
+        if let Some(controllers) = controllers.as_mut() {
+            controllers.handle_event(&event);
+        }
+        match event {
+            // ウィンドウを閉じたときと Ctrl+C (SDLがSIGINTをQuitにする)
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => hotkeys.push(Hotkey::Quit),
+            Event::KeyDown {
+                keycode: Some(key),
+                repeat: false,
+                ..
+            } => match key {
+                Keycode::F1 => hotkeys.push(Hotkey::SwitchPort(0)),
+                Keycode::F2 => hotkeys.push(Hotkey::SwitchPort(1)),
+                Keycode::F3 => hotkeys.push(Hotkey::PerfHud),
+                Keycode::F4 => hotkeys.push(Hotkey::ScaleMode),
+                Keycode::F8 => hotkeys.push(Hotkey::AspectRatio),
+                Keycode::F11 => hotkeys.push(Hotkey::Fullscreen),
+                Keycode::F12 => hotkeys.push(Hotkey::Vsync),
+                Keycode::F5 => hotkeys.push(Hotkey::SaveState),
+                Keycode::F6 => hotkeys.push(Hotkey::NextSlot),
+                Keycode::F7 => hotkeys.push(Hotkey::LoadState),
+                Keycode::P => hotkeys.push(Hotkey::Pause),
+                Keycode::Backslash => hotkeys.push(Hotkey::FrameAdvance),
+                Keycode::F9 => hotkeys.push(Hotkey::Capture),
+                Keycode::F10 => hotkeys.push(Hotkey::AvDump),
+                Keycode::V => hotkeys.push(Hotkey::PpuDump),
+                _ => {}
+            },
+            _ => { /* do nothing */ }
+        }
+    }
+}
+
+// 録画の保存先 (<CRC32>_<UNIX時刻>.<拡張子>)
+fn capture_path(crc32: u32, extension: &str) -> std::path::PathBuf {
+    std::path::Path::new(_CAPTURE_DIR).join(format!("{}.{}", capture_name(crc32), extension))
+}
+
+fn capture_name(crc32: u32) -> String {
+    let time = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    format!("{:08X}_{}", crc32, time)
+}
+
+fn save_capture(capture: &Capture, crc32: u32) {
+    let path = capture_path(crc32, capture.format().extension());
+    match capture.save(&path) {
+        Ok(()) => info!("CAPTURE: {} frames to {}", capture.frames(), path.display()),
+        Err(e) => warn!("[ERR] CAPTURE: {}: {}", path.display(), e),
+    }
+}
+
+fn save_ppu_dump(nes: &mut Nes, crc32: u32) {
+    match ppu_dump::save_all(nes, std::path::Path::new(_CAPTURE_DIR), &capture_name(crc32)) {
+        Ok(paths) => info!("PPU DUMP: {} files to {}", paths.len(), _CAPTURE_DIR),
+        Err(e) => warn!("[ERR] PPU DUMP: {}", e),
+    }
+}
+
+fn start_av_dump(nes: &mut Nes, path: &std::path::Path, region: Region) -> Option<AvDump> {
+    nes.apu_mut().start_recording();
+    let sample_rate = nes.apu_mut().sample_rate() as u32;
+    match AvDump::start(_FFMPEG_PATH, path, region, sample_rate) {
+        Ok(dump) => {
+            info!("AV DUMP: recording to {}", path.display());
+            Some(dump)
+        }
+        Err(e) => {
+            warn!("[ERR] AV DUMP: {}: {}", _FFMPEG_PATH, e);
+            nes.apu_mut().stop_recording();
+            None
+        }
+    }
+}
+
+fn finish_av_dump(nes: &mut Nes, dump: AvDump) {
+    nes.apu_mut().stop_recording();
+    match dump.finish() {
+        Ok(path) => info!("AV DUMP: saved {}", path.display()),
+        Err(e) => warn!("[ERR] AV DUMP: {}", e),
+    }
+}
+
+pub fn run(options: &Options, rom: Rom) {
+    if let Some(driver) = options.audio_driver.as_deref() {
+        sdl2::hint::set("SDL_AUDIODRIVER", driver);
+    }
+    let sdl_context = sdl2::init().unwrap();
+    let event_pump = sdl_context.event_pump().unwrap();
+
+    let bindings_path = std::path::Path::new(_INPUT_BINDINGS_PATH);
+    let bindings = if bindings_path.exists() {
+        InputBindings::load(bindings_path).unwrap_or_else(|e| {
+            warn!("[ERR] {}: {}", _INPUT_BINDINGS_PATH, e);
+            InputBindings::new()
+        })
+    } else {
+        InputBindings::new()
+    };
+    let mut controllers = GameControllers::new(&sdl_context)
+        .map_err(|e| warn!("[ERR] CONTROLLER: {}", e))
+        .ok();
+    if let Some(controllers) = controllers.as_mut() {
+        controllers.mappings = bindings.pads.clone();
+    }
+    // イベント処理 (ゲームループ) と入力の読み取りで共有する
+    let event_pump = Rc::new(RefCell::new(event_pump));
+    let controllers = Rc::new(RefCell::new(controllers));
+
+    let region = options.region.unwrap_or_else(|| Region::detect(&rom.header, &options.rom_path));
+    info!("REGION: {:?} ({:.2} fps)", region, region.frame_rate());
+
+    // モニタの垂直同期はほぼ60Hzなので、NTSC以外はタイマーで速度を合わせる
+    let settings_path = std::path::Path::new(_SETTINGS_PATH);
+    let mut settings = if settings_path.exists() {
+        Settings::load(settings_path).unwrap_or_else(|e| {
+            warn!("[ERR] {}: {}", _SETTINGS_PATH, e);
+            Settings::new()
+        })
+    } else {
+        Settings::new()
+    };
+    // コマンドラインの指定を優先する
+    settings.scale_mode = options.scale_mode.unwrap_or(settings.scale_mode);
+    settings.aspect = options.aspect.unwrap_or(settings.aspect);
+    settings.vsync = options.vsync.unwrap_or(settings.vsync);
+    let mut vsync = settings.vsync && region == Region::Ntsc;
+    let video_subsystem = sdl_context.video().unwrap();
+    let window_w = (256.0 * settings.aspect.pixel_aspect()).round() as u32 * options.scale;
+    let mut window = video_subsystem.window("rscom -Rust NES Emulator-", window_w, 240 * options.scale);
+    window.position_centered().resizable();
+    if options.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().unwrap();
+    let mut canvas = if vsync {
+        window.into_canvas().present_vsync().build().unwrap()
+    } else {
+        window.into_canvas().build().unwrap()
+    };
+    // ザッパーの照準を合わせるため、入力の読み取りと画面の位置を共有する
+    let mouse_viewport = Rc::new(Cell::new(Viewport::fit(canvas.window().size(), settings.scale_mode, settings.aspect)));
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
+        .unwrap();
+
+    // ムービーは電源投入から記録/再生する
+    let mut movie_player = _MOVIE_PLAY_PATH.and_then(|path| match Movie::load(std::path::Path::new(path)) {
+        Ok(movie) => {
+            if !movie.matches(&rom) {
+                warn!("MOVIE: {} was recorded with a different ROM", path);
+            }
+            if movie.start != MovieStart::PowerOn {
+                warn!("MOVIE: {} starts from a save state, playing from power on", path);
+            }
+            info!("MOVIE: playing {} ({} frames)", path, movie.frames.len());
+            Some(MoviePlayer::new(movie))
+        }
+        Err(e) => {
+            warn!("[ERR] MOVIE: {}: {}", path, e);
+            None
+        }
+    });
+    let movie_recorder = Rc::new(RefCell::new(match (&movie_player, _MOVIE_RECORD_PATH) {
+        (None, Some(path)) => {
+            info!("MOVIE: recording to {}", path);
+            Some(MovieRecorder::new(&rom, MovieStart::PowerOn))
+        }
+        _ => None,
+    }));
+    // ムービーは1フレーム1入力なので、ストローブごとに読むと記録/再生がずれる
+    let input_poll = if movie_player.is_some() || movie_recorder.borrow().is_some() {
+        InputPoll::StartOfFrame
+    } else {
+        _INPUT_POLL
+    };
+
+    let audio_config = AudioConfig::new();
+    info!(
+        "AUDIO: {}Hz, buffer={} samples ({:.1}ms)",
+        audio_config.sample_rate,
+        audio_config.buffer_samples,
+        audio_config.latency_ms()
+    );
+    // デバイスはこのスレッドで持ち、APUはリングバッファに書くだけ
+    let mut audio_sink = SdlAudioSink {
+        audio: sdl_context.audio().unwrap(),
+        device: None,
+    };
+    let apu = APU::with_sink(&mut audio_sink, &audio_config).unwrap_or_else(|e| panic!("[ERR] AUDIO: {}", e));
+    let rom_crc32 = rom.crc32;
+    let save_slots = SaveSlots::new(std::path::Path::new(_SAVE_STATE_DIR), rom.crc32);
+    let cheat_path = CheatManager::path(std::path::Path::new(_CHEAT_DIR), rom.crc32);
+    let hotkeys = Rc::new(RefCell::new(Vec::new()));
+    let mut nes = Nes::new(rom, apu).unwrap_or_else(|e| panic!("[ERR] {}", e));
+    nes.set_region(region);
+    if let Some(state) = options.power_on {
+        nes.set_power_on_state(state);
+        nes.power_cycle();
+    }
+    if cheat_path.exists() {
+        match CheatManager::load(&cheat_path) {
+            Ok(cheats) => {
+                info!("CHEAT: {} cheats from {}", cheats.cheats().len(), cheat_path.display());
+                *nes.cheats_mut() = cheats;
+            }
+            Err(e) => warn!("[ERR] CHEAT: {}: {}", cheat_path.display(), e),
+        }
+    }
+    if let Err(e) = nes.cartridge().load_sram(&options.rom_path, &SramConfig::new()) {
+        warn!("[ERR] SRAM load: {}", e);
+    }
+    if let Some(path) = options.load_state.as_deref() {
+        match std::fs::read(path).map_err(|e| e.to_string()).and_then(|state| nes.load_state(&state).map_err(|e| e.to_string())) {
+            Ok(()) => info!("STATE: loaded {}", path),
+            Err(e) => warn!("[ERR] STATE: {}: {}", path, e),
+        }
+    }
+
+    let (input_event_pump, input_controllers, input_recorder, input_viewport) =
+        (event_pump.clone(), controllers.clone(), movie_recorder.clone(), mouse_viewport.clone());
+    nes.bus_mut().set_input_poll(input_poll);
+    let mut poll_input = move |ports: &mut ControllerPorts| {
+        // キーボードの状態を最新にする (イベントはキューに残り、次のフレームで処理される)
+        let mut event_pump = input_event_pump.borrow_mut();
+        event_pump.pump_events();
+
+        // ザッパーとパドルはマウスで操作する (ウィンドウの座標から画面の座標に直す)
+        let mouse = event_pump.mouse_state();
+        let paddle_dx = event_pump.relative_mouse_state().x();
+        if let Some(paddle) = ports.expansion_mut::<ArkanoidPaddle>() {
+            paddle.move_by(paddle_dx);
+            paddle.set_fire(mouse.left());
+        }
+        for port in 0..2 {
+            if let Some(paddle) = ports.device_mut::<ArkanoidPaddle>(port) {
+                paddle.move_by(paddle_dx);
+                paddle.set_fire(mouse.left());
+            }
+            if let Some(zapper) = ports.device_mut::<Zapper>(port) {
+                zapper.set_aim(input_viewport.get().to_frame(mouse.x(), mouse.y()));
+                zapper.set_trigger(mouse.left());
+            }
+        }
+
+        // キーボードとゲームコントローラーの状態をコントローラーに反映
+        let state = event_pump.keyboard_state();
+        let controllers = input_controllers.borrow();
+        for port in 0..2 {
+            let mut buttons = bindings.keyboard_buttons(port, |key| state.is_scancode_pressed(key));
+            if let Some(pad) = controllers.as_ref().and_then(|c| c.buttons(port)) {
+                buttons |= pad;
+            }
+            ports.set_player_buttons(port, buttons);
+        }
+
+        // 再生中はムービーの入力で上書きし、終わったら手元の入力に戻す
+        if let Some(player) = movie_player.as_mut() {
+            match player.next_frame() {
+                Some(input) => {
+                    for (player, buttons) in input.iter().enumerate() {
+                        ports.set_player_buttons(player, *buttons);
+                    }
+                }
+                None => {
+                    info!("MOVIE: finished at frame {}", player.frame());
+                    movie_player = None;
+                }
+            }
+        }
+        if let Some(recorder) = input_recorder.borrow_mut().as_mut() {
+            let mut input = [Button::empty(); PLAYER_COUNT];
+            for (player, buttons) in input.iter_mut().enumerate() {
+                *buttons = ports.player_buttons(player);
+            }
+            recorder.record(input);
+        }
+    };
+
+    for (port, device) in _PORT_DEVICES.iter().enumerate() {
+        nes.controller_ports().set_device(port, *device);
+    }
+    if _ARKANOID_PADDLE {
+        nes.controller_ports().connect_expansion(Box::new(ArkanoidPaddle::new()));
+    }
+
+    let mut capture: Option<Capture> = None;
+    let mut av_dump = options
+        .dump
+        .as_deref()
+        .and_then(|path| start_av_dump(&mut nes, std::path::Path::new(path), region));
+    let frame_time = Duration::from_secs_f64(1.0 / region.frame_rate());
+    let mut next_frame = Instant::now();
+    let mut perf = PerfStats::new();
+    let mut last_frame = Instant::now();
+    loop {
+        // 一時停止中はイベントだけ処理する
+        if nes.is_paused() {
+            poll_events(&mut event_pump.borrow_mut(), &mut controllers.borrow_mut(), &mut hotkeys.borrow_mut());
+            std::thread::sleep(Duration::from_millis(16));
+        } else {
+            let start = Instant::now();
+            nes.run_frame_with_input(&mut poll_input);
+            let emulated = Instant::now();
+            nes.render_frame();
+            if settings.perf_hud {
+                if let Some(summary) = perf.summary() {
+                    draw_overlay(nes.frame_mut(), &summary);
+                }
+            }
+            present(&mut canvas, &mut texture, nes.frame(), &settings, &mouse_viewport);
+            let now = Instant::now();
+            perf.record(FrameTiming {
+                emulation: emulated - start,
+                render: now - emulated,
+                interval: now - last_frame,
+                audio_fill_ms: nes.apu_mut().stats().fill_ms,
+            });
+            last_frame = now;
+            poll_events(&mut event_pump.borrow_mut(), &mut controllers.borrow_mut(), &mut hotkeys.borrow_mut());
+            if let Some(recording) = capture.as_mut() {
+                // 最長の秒数に達したら止めて保存する
+                if !recording.push(nes.frame()) {
+                    save_capture(recording, rom_crc32);
+                    capture = None;
+                }
+            }
+            if let Some(dump) = av_dump.as_mut() {
+                let samples = nes.apu_mut().take_recorded_samples();
+                if let Err(e) = dump.push(nes.frame(), &samples) {
+                    warn!("[ERR] AV DUMP: {}", e);
+                    finish_av_dump(&mut nes, av_dump.take().unwrap());
+                }
+            }
+            if !vsync {
+                next_frame += frame_time;
+                let now = Instant::now();
+                if next_frame > now {
+                    std::thread::sleep(next_frame - now);
+                } else {
+                    // 間に合っていなければ追いつこうとしない
+                    next_frame = now;
+                }
+            }
+        }
+
+        let pending: Vec<Hotkey> = hotkeys.borrow_mut().drain(..).collect();
+        for hotkey in pending {
+            match hotkey {
+                Hotkey::Quit => {
+                    if let Some(summary) = perf.summary() {
+                        info!("PERF: {}", summary);
+                    }
+                    // 途中で閉じても進み具合を失わないように、書き出せるものはすべて書き出す
+                    if let Some(dump) = av_dump.take() {
+                        finish_av_dump(&mut nes, dump);
+                    }
+                    if let Err(e) = nes.cartridge().save_sram() {
+                        warn!("[ERR] SRAM save: {}", e);
+                    }
+                    if !nes.cheats().is_empty() || cheat_path.exists() {
+                        if let Err(e) = nes.cheats().save(&cheat_path) {
+                            warn!("[ERR] CHEAT: {}: {}", cheat_path.display(), e);
+                        }
+                    }
+                    if let (Some(recorder), Some(path)) = (movie_recorder.borrow().as_ref(), _MOVIE_RECORD_PATH) {
+                        if let Err(e) = recorder.movie.save(std::path::Path::new(path)) {
+                            warn!("[ERR] MOVIE: {}: {}", path, e);
+                        }
+                    }
+                    if _AUTOSAVE_ON_EXIT {
+                        match save_slots.autosave(&nes.save_state()) {
+                            Ok(()) => info!("STATE: saved {}", save_slots.autosave_path().display()),
+                            Err(e) => warn!("[ERR] STATE: autosave: {}", e),
+                        }
+                    }
+                    if let Err(e) = settings.save(settings_path) {
+                        warn!("[ERR] {}: {}", _SETTINGS_PATH, e);
+                    }
+                    std::process::exit(0)
+                }
+                // ポートの機器を切り替える
+                Hotkey::SwitchPort(port) => {
+                    let ports = nes.controller_ports();
+                    let device = ports.device_type(port).unwrap_or(PortDevice::None).next();
+                    info!("PORT {}: {:?}", port + 1, device);
+                    ports.set_device(port, device);
+                }
+                Hotkey::PerfHud => {
+                    settings.perf_hud = !settings.perf_hud;
+                    info!("PERF HUD: {}", settings.perf_hud);
+                }
+                Hotkey::ScaleMode => {
+                    settings.scale_mode = settings.scale_mode.next();
+                    info!("SCALE: {:?}", settings.scale_mode);
+                    present(&mut canvas, &mut texture, nes.frame(), &settings, &mouse_viewport);
+                }
+                Hotkey::AspectRatio => {
+                    settings.aspect = settings.aspect.next();
+                    info!("ASPECT: {:?}", settings.aspect);
+                    present(&mut canvas, &mut texture, nes.frame(), &settings, &mouse_viewport);
+                }
+                // ボーダーレスの全画面 (画面の解像度は変えない)
+                Hotkey::Fullscreen => {
+                    let window = canvas.window_mut();
+                    let fullscreen = match window.fullscreen_state() {
+                        FullscreenType::Off => FullscreenType::Desktop,
+                        _ => FullscreenType::Off,
+                    };
+                    match window.set_fullscreen(fullscreen) {
+                        Ok(()) => info!("FULLSCREEN: {:?}", fullscreen),
+                        Err(e) => warn!("[ERR] FULLSCREEN: {}", e),
+                    }
+                }
+                // PAL/Dendyでは使わない (60Hzのモニタに合わせると速くなる)
+                Hotkey::Vsync => {
+                    if region != Region::Ntsc {
+                        info!("VSYNC: not used for {:?}", region);
+                    } else {
+                        match set_vsync(&canvas, !vsync) {
+                            Ok(()) => {
+                                vsync = !vsync;
+                                settings.vsync = vsync;
+                                next_frame = Instant::now();
+                                info!("VSYNC: {}", vsync);
+                            }
+                            Err(e) => warn!("[ERR] VSYNC: {}", e),
+                        }
+                    }
+                }
+                Hotkey::Pause => {
+                    if nes.is_paused() {
+                        nes.resume();
+                        // 止まっていた間を経過時間に含めない
+                        last_frame = Instant::now();
+                    } else {
+                        nes.pause();
+                    }
+                    info!("PAUSE: {}", nes.is_paused());
+                }
+                // 動いているときは一時停止する
+                Hotkey::FrameAdvance => {
+                    if nes.is_paused() {
+                        nes.run_frame_with_input(&mut poll_input);
+                        nes.render_frame();
+                        present(&mut canvas, &mut texture, nes.frame(), &settings, &mouse_viewport);
+                    } else {
+                        nes.pause();
+                    }
+                }
+                Hotkey::Capture => match capture.take() {
+                    Some(recording) => save_capture(&recording, rom_crc32),
+                    None => {
+                        info!("CAPTURE: started");
+                        capture = Some(Capture::new(
+                            _CAPTURE_FORMAT,
+                            region.frame_rate(),
+                            _CAPTURE_SECONDS,
+                            _CAPTURE_DECIMATION,
+                        ));
+                    }
+                },
+                Hotkey::AvDump => match av_dump.take() {
+                    Some(dump) => finish_av_dump(&mut nes, dump),
+                    None => av_dump = start_av_dump(&mut nes, &capture_path(rom_crc32, _AV_DUMP_EXTENSION), region),
+                },
+                Hotkey::PpuDump => save_ppu_dump(&mut nes, rom_crc32),
+                Hotkey::SaveState => match save_slots.save(settings.slot, &nes.save_state()) {
+                    Ok(()) => info!("STATE: saved to slot {}", settings.slot),
+                    Err(e) => warn!("[ERR] STATE: slot {}: {}", settings.slot, e),
+                },
+                Hotkey::NextSlot => {
+                    settings.slot = (settings.slot + 1) % SLOT_COUNT;
+                    let slot = settings.slot;
+                    let saved = save_slots.list().into_iter().find(|info| info.slot == slot);
+                    match saved.and_then(|info| info.modified.elapsed().ok()) {
+                        Some(age) => info!("STATE: slot {} (saved {}s ago)", slot, age.as_secs()),
+                        None => info!("STATE: slot {} (empty)", slot),
+                    }
+                }
+                Hotkey::LoadState => match save_slots.load(settings.slot) {
+                    Ok(state) => match nes.load_state(&state) {
+                        Ok(()) => info!("STATE: loaded slot {}", settings.slot),
+                        Err(e) => warn!("[ERR] STATE: slot {}: {}", settings.slot, e),
+                    },
+                    Err(e) => warn!("[ERR] STATE: slot {}: {}", settings.slot, e),
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// SDLのゲームコントローラー (物理ゲームパッド)
+// 接続/切断を検知してNESのポートに割り当て、毎フレームのボタン状態を作る
+// アナログスティックはデッドゾーンを超えたら十字キーとして扱う
+// パッドのガイドボタン (Xbox/PSボタン) を押すと、そのパッドを次のポートに移す
+use log::{info, warn};
+use sdl2::controller::{Axis, Button as PadButton, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+
+use crate::bindings::PORT_COUNT;
+use nes_core::gamepad::Button;
+
+const DEFAULT_DEADZONE: i16 = 8000;
+
+#[derive(Clone)]
+pub struct ControllerMapping {
+    pub buttons: Vec<(PadButton, Button)>,
+    pub use_left_stick: bool,
+    pub deadzone: i16,
+}
+
+impl ControllerMapping {
+    // 下のボタン(A): B, 右のボタン(B): A (NESのパッドと同じ並び)
+    pub fn new() -> Self {
+        ControllerMapping {
+            buttons: vec![
+                (PadButton::DPadUp, Button::UP),
+                (PadButton::DPadDown, Button::DOWN),
+                (PadButton::DPadLeft, Button::LEFT),
+                (PadButton::DPadRight, Button::RIGHT),
+                (PadButton::A, Button::BUTTON_B),
+                (PadButton::X, Button::BUTTON_B),
+                (PadButton::B, Button::BUTTON_A),
+                (PadButton::Y, Button::BUTTON_A),
+                (PadButton::Back, Button::SELECT),
+                (PadButton::Start, Button::START),
+            ],
+            use_left_stick: true,
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+
+    pub fn bind(&mut self, pad: PadButton, button: Button) {
+        self.buttons.retain(|(p, _)| *p != pad);
+        self.buttons.push((pad, button));
+    }
+
+    pub fn buttons<B, A>(&self, is_pressed: B, axis: A) -> Button
+    where
+        B: Fn(PadButton) -> bool,
+        A: Fn(Axis) -> i16,
+    {
+        let mut buttons = self
+            .buttons
+            .iter()
+            .filter(|(pad, _)| is_pressed(*pad))
+            .fold(Button::empty(), |buttons, (_, button)| buttons | *button);
+        if self.use_left_stick {
+            buttons |= stick_to_dpad(axis(Axis::LeftX), axis(Axis::LeftY), self.deadzone);
+        }
+        buttons
+    }
+}
+
+// スティックの傾きを十字キーに変換する (SDLのY軸は下が正)
+pub fn stick_to_dpad(x: i16, y: i16, deadzone: i16) -> Button {
+    let mut buttons = Button::empty();
+    let deadzone = deadzone.max(0) as i32;
+    let (x, y) = (x as i32, y as i32);
+    if x < -deadzone {
+        buttons |= Button::LEFT;
+    } else if x > deadzone {
+        buttons |= Button::RIGHT;
+    }
+    if y < -deadzone {
+        buttons |= Button::UP;
+    } else if y > deadzone {
+        buttons |= Button::DOWN;
+    }
+    buttons
+}
+
+// from のものを to に移す (to にあったものは from へ)
+fn move_port<T>(ports: &mut [Option<T>], from: usize, to: usize) -> bool {
+    if from >= ports.len() || to >= ports.len() {
+        return false;
+    }
+    ports.swap(from, to);
+    true
+}
+
+pub struct GameControllers {
+    subsystem: GameControllerSubsystem,
+    ports: [Option<GameController>; PORT_COUNT],
+    pub mappings: [ControllerMapping; PORT_COUNT],
+}
+
+impl GameControllers {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Self, String> {
+        Ok(GameControllers {
+            subsystem: sdl_context.game_controller()?,
+            ports: [None, None],
+            mappings: [ControllerMapping::new(), ControllerMapping::new()],
+        })
+    }
+
+    // 起動時に接続済みのコントローラーも ControllerDeviceAdded で通知される
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::ControllerDeviceAdded { which, .. } => self.open(*which),
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(port) = self.port_of(*which) {
+                    info!("CONTROLLER: disconnected from port {}", port + 1);
+                    self.ports[port] = None;
+                }
+            }
+            Event::ControllerButtonDown {
+                which,
+                button: PadButton::Guide,
+                ..
+            } => {
+                if let Some(port) = self.port_of(*which) {
+                    let next = (port + 1) % PORT_COUNT;
+                    if self.assign(*which, next) {
+                        info!("CONTROLLER: port {} => port {}", port + 1, next + 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 空いているポートに割り当てる
+    fn open(&mut self, joystick_index: u32) {
+        let port = match self.ports.iter().position(|p| p.is_none()) {
+            Some(port) => port,
+            None => return,
+        };
+        match self.subsystem.open(joystick_index) {
+            Ok(controller) => {
+                if self.port_of(controller.instance_id()).is_some() {
+                    return;
+                }
+                info!("CONTROLLER: {} => port {}", controller.name(), port + 1);
+                self.ports[port] = Some(controller);
+            }
+            Err(e) => warn!("[ERR] CONTROLLER: {}", e),
+        }
+    }
+
+    pub fn port_of(&self, instance_id: u32) -> Option<usize> {
+        self.ports
+            .iter()
+            .position(|p| p.as_ref().is_some_and(|c| c.instance_id() == instance_id))
+    }
+
+    // コントローラーを指定したポートに移す (移動先にあったものとは入れ替え)
+    pub fn assign(&mut self, instance_id: u32, port: usize) -> bool {
+        match self.port_of(instance_id) {
+            Some(current) => move_port(&mut self.ports, current, port),
+            None => false,
+        }
+    }
+
+    // 何も割り当てられていなければNone
+    pub fn buttons(&self, port: usize) -> Option<Button> {
+        self.ports[port]
+            .as_ref()
+            .map(|c| self.mappings[port].buttons(|b| c.button(b), |a| c.axis(a)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stick_to_dpad() {
+        assert_eq!(stick_to_dpad(0, 0, 8000), Button::empty());
+        assert_eq!(stick_to_dpad(7999, -7999, 8000), Button::empty());
+        assert_eq!(stick_to_dpad(-20000, 0, 8000), Button::LEFT);
+        assert_eq!(stick_to_dpad(32767, 32767, 8000), Button::RIGHT | Button::DOWN);
+        assert_eq!(stick_to_dpad(i16::MIN, i16::MIN, 8000), Button::LEFT | Button::UP);
+    }
+
+    #[test]
+    fn test_controller_mapping() {
+        let mut mapping = ControllerMapping::new();
+        let pressed = [PadButton::A, PadButton::Start];
+        let buttons = mapping.buttons(|b| pressed.contains(&b), |a| if a == Axis::LeftY { -30000 } else { 0 });
+        assert_eq!(buttons, Button::BUTTON_B | Button::START | Button::UP);
+
+        mapping.deadzone = i16::MAX;
+        let buttons = mapping.buttons(|_| false, |_| -30000);
+        assert_eq!(buttons, Button::empty());
+    }
+
+    #[test]
+    fn test_move_port() {
+        let mut ports = [Some(10), None];
+        assert!(move_port(&mut ports, 0, 1));
+        assert_eq!(ports, [None, Some(10)]);
+        // 移動先にいたものとは入れ替え
+        ports[0] = Some(20);
+        assert!(move_port(&mut ports, 1, 0));
+        assert_eq!(ports, [Some(10), Some(20)]);
+        assert!(!move_port(&mut ports, 0, PORT_COUNT));
+        assert_eq!(ports, [Some(10), Some(20)]);
+    }
+}
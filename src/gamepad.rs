@@ -1,6 +1,10 @@
 use bitflags::bitflags;
 
-use crate::cpu::IN_TRACE;
+use core::any::Any;
+
+use crate::controller::Peripheral;
+use crate::cpu::in_trace;
+use crate::{impl_state, impl_state_bits};
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq)]
@@ -16,6 +20,8 @@ bitflags! {
     }
 }
 
+impl_state_bits!(Button);
+
 pub struct GamePad {
     strobe: bool,
     button_index: u8,
@@ -31,28 +37,55 @@ impl GamePad {
         }
     }
 
-    pub fn write(&mut self, data: u8) {
+    pub fn set_button_pressed_status(&mut self, button: Button, value: bool) {
+        self.button_status.set(button, value)
+    }
+
+    // 全ボタンの状態をまとめて設定する (毎フレームの入力の反映用)
+    pub fn set_buttons(&mut self, buttons: Button) {
+        self.button_status = buttons;
+    }
+
+    pub fn buttons(&self) -> Button {
+        self.button_status
+    }
+}
+
+impl Default for GamePad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_state!(GamePad { strobe, button_index, button_status });
+
+impl Peripheral for GamePad {
+    fn name(&self) -> &'static str {
+        "joypad"
+    }
+
+    fn write(&mut self, data: u8) {
         self.strobe = data & 1 == 1;
         if self.strobe {
             self.button_index = 0
         }
     }
 
-    pub fn read(&mut self) -> u8 {
+    fn read(&mut self) -> u8 {
         if self.button_index > 7 {
             return 1;
         }
 
         let response = (self.button_status.bits() & (1 << self.button_index)) >> self.button_index;
         if !self.strobe && self.button_index <= 7 {
-            if !unsafe { IN_TRACE } {
+            if !in_trace() {
                 self.button_index += 1;
             }
         }
         response
     }
 
-    pub fn set_button_pressed_status(&mut self, button: Button, value: bool) {
-        self.button_status.set(button, value)
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
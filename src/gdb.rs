@@ -0,0 +1,476 @@
+// GDBのリモートプロトコル (RSP) のサーバー。パケットの処理は gdbstub に任せる
+// https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+// https://docs.rs/gdbstub/
+// 6502 はGDBに標準のアーキテクチャが無いので、レジスタは独自の並び
+//   g: A X Y P SP (各1バイト) PC (2バイト、リトルエンディアン)
+// ブレークポイントは Z0 (ソフトウェア) と Z1 (ハードウェア) のどちらも実行ブレークポイント
+// 実行中は Ctrl+C で止まる (フレームの切れ目で確認する)
+use crate::debugger::{read_memory, registers, Debugger, Registers, StopReason};
+use crate::error::EmuError;
+use crate::memory_view::{write_block, MemorySpace};
+use crate::nes::Nes;
+use core::marker::PhantomData;
+use core::num::NonZeroUsize;
+use gdbstub::arch::Arch;
+use gdbstub::common::Signal;
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::run_blocking::{BlockingEventLoop, Event, WaitForStopReasonError};
+use gdbstub::stub::{DisconnectReason, GdbStub, SingleThreadStopReason};
+use gdbstub::target::ext::base::single_register_access::{SingleRegisterAccess, SingleRegisterAccessOps};
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep,
+    SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::breakpoints::{
+    Breakpoints, BreakpointsOps, HwBreakpoint, HwBreakpointOps, SwBreakpoint, SwBreakpointOps,
+};
+use gdbstub::target::{Target, TargetError, TargetResult};
+use log::{info, warn};
+use std::net::{TcpListener, TcpStream};
+
+pub enum Mos6502 {}
+
+impl Arch for Mos6502 {
+    type Usize = u16;
+    type Registers = Registers;
+    type BreakpointKind = usize; // Z0,addr,kind の kind (命令の長さ) は使わない
+    type RegId = RegId;
+}
+
+impl gdbstub::arch::Registers for Registers {
+    type ProgramCounter = u16;
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        let pc = self.pc.to_le_bytes();
+        for b in [self.a, self.x, self.y, self.p, self.sp, pc[0], pc[1]] {
+            write_byte(Some(b));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let [a, x, y, p, sp, pc_lo, pc_hi] = bytes.try_into().map_err(|_| ())?;
+        *self = Registers {
+            a,
+            x,
+            y,
+            p,
+            sp,
+            pc: u16::from_le_bytes([pc_lo, pc_hi]),
+        };
+        Ok(())
+    }
+}
+
+// p/P パケットのレジスタ番号 (0-4: A X Y P SP, 5: PC)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegId {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+}
+
+impl gdbstub::arch::RegId for RegId {
+    fn from_raw_id(id: usize) -> Option<(Self, Option<NonZeroUsize>)> {
+        let reg = match id {
+            0 => RegId::A,
+            1 => RegId::X,
+            2 => RegId::Y,
+            3 => RegId::P,
+            4 => RegId::Sp,
+            5 => RegId::Pc,
+            _ => return None,
+        };
+        Some((reg, NonZeroUsize::new(reg.size())))
+    }
+}
+
+impl RegId {
+    fn size(self) -> usize {
+        if self == RegId::Pc {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+// 再開したときにどこまで進めるか
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ExecMode {
+    Step,
+    Continue,
+}
+
+// ブレークポイントは接続が切れても残す
+pub struct GdbTarget<'a> {
+    nes: &'a mut Nes,
+    debugger: Debugger,
+    exec: ExecMode,
+}
+
+impl<'a> GdbTarget<'a> {
+    pub fn new(nes: &'a mut Nes) -> Self {
+        GdbTarget {
+            nes,
+            debugger: Debugger::new(),
+            exec: ExecMode::Continue,
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    // 止まるまで実行する。interrupted が true を返したら (Ctrl+C) そこで止まる (None)
+    fn run<F>(&mut self, mut interrupted: F) -> Option<SingleThreadStopReason<u16>>
+    where
+        F: FnMut() -> bool,
+    {
+        if self.exec == ExecMode::Step {
+            return Some(match self.nes.step() {
+                Ok(()) => SingleThreadStopReason::DoneStep,
+                Err(_) => SingleThreadStopReason::Signal(Signal::SIGILL),
+            });
+        }
+        loop {
+            match self.debugger.run_frame(self.nes) {
+                StopReason::Breakpoint(_) => return Some(SingleThreadStopReason::SwBreak(())),
+                StopReason::Event(_) => return Some(SingleThreadStopReason::Signal(Signal::SIGTRAP)),
+                StopReason::Jam(_) => return Some(SingleThreadStopReason::Signal(Signal::SIGILL)),
+                StopReason::FrameEnd => {
+                    if interrupted() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Target for GdbTarget<'_> {
+    type Arch = Mos6502;
+    type Error = EmuError;
+
+    fn base_ops(&mut self) -> BaseOps<'_, Mos6502, EmuError> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbTarget<'_> {
+    fn read_registers(&mut self, regs: &mut Registers) -> TargetResult<(), Self> {
+        *regs = registers(self.nes);
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &Registers) -> TargetResult<(), Self> {
+        let cpu = self.nes.cpu_mut();
+        cpu.register_a = regs.a;
+        cpu.register_x = regs.x;
+        cpu.register_y = regs.y;
+        cpu.status = regs.p;
+        cpu.stack_pointer = regs.sp;
+        cpu.program_counter = regs.pc;
+        Ok(())
+    }
+
+    fn support_single_register_access(&mut self) -> Option<SingleRegisterAccessOps<'_, (), Self>> {
+        Some(self)
+    }
+
+    // $FFFF を越える分は読まない (読めたバイト数を返す)
+    fn read_addrs(&mut self, start_addr: u16, data: &mut [u8]) -> TargetResult<usize, Self> {
+        let len = data.len().min(0x10000 - start_addr as usize);
+        let bytes = read_memory(self.nes, start_addr, len).map_err(|_| TargetError::NonFatal)?;
+        data[..len].copy_from_slice(&bytes);
+        Ok(len)
+    }
+
+    fn write_addrs(&mut self, start_addr: u16, data: &[u8]) -> TargetResult<(), Self> {
+        write_block(self.nes, MemorySpace::Cpu, start_addr, data).map_err(|_| TargetError::NonFatal)
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleRegisterAccess<()> for GdbTarget<'_> {
+    fn read_register(&mut self, _tid: (), reg_id: RegId, buf: &mut [u8]) -> TargetResult<usize, Self> {
+        let r = registers(self.nes);
+        let pc = r.pc.to_le_bytes();
+        let bytes = match reg_id {
+            RegId::A => &[r.a][..],
+            RegId::X => &[r.x],
+            RegId::Y => &[r.y],
+            RegId::P => &[r.p],
+            RegId::Sp => &[r.sp],
+            RegId::Pc => &pc,
+        };
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn write_register(&mut self, _tid: (), reg_id: RegId, val: &[u8]) -> TargetResult<(), Self> {
+        if val.len() != reg_id.size() {
+            return Err(TargetError::NonFatal);
+        }
+        let cpu = self.nes.cpu_mut();
+        match reg_id {
+            RegId::A => cpu.register_a = val[0],
+            RegId::X => cpu.register_x = val[0],
+            RegId::Y => cpu.register_y = val[0],
+            RegId::P => cpu.status = val[0],
+            RegId::Sp => cpu.stack_pointer = val[0],
+            RegId::Pc => cpu.program_counter = u16::from_le_bytes([val[0], val[1]]),
+        }
+        Ok(())
+    }
+}
+
+// シグナルは送れないので無視する
+impl SingleThreadResume for GdbTarget<'_> {
+    fn resume(&mut self, _signal: Option<Signal>) -> Result<(), EmuError> {
+        self.exec = ExecMode::Continue;
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbTarget<'_> {
+    fn step(&mut self, _signal: Option<Signal>) -> Result<(), EmuError> {
+        self.exec = ExecMode::Step;
+        Ok(())
+    }
+}
+
+// ウォッチポイントには対応していない
+impl Breakpoints for GdbTarget<'_> {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn support_hw_breakpoint(&mut self) -> Option<HwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbTarget<'_> {
+    fn add_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        self.debugger.add_breakpoint(addr);
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        self.debugger.remove_breakpoint(addr);
+        Ok(true)
+    }
+}
+
+impl HwBreakpoint for GdbTarget<'_> {
+    fn add_hw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        self.debugger.add_breakpoint(addr);
+        Ok(true)
+    }
+
+    fn remove_hw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        self.debugger.remove_breakpoint(addr);
+        Ok(true)
+    }
+}
+
+// run_blocking から呼ばれる。c/s のあとに止まるまで進める
+pub struct GdbEventLoop<'a, C>(PhantomData<(&'a mut Nes, C)>);
+
+impl<'a, C: ConnectionExt> BlockingEventLoop for GdbEventLoop<'a, C> {
+    type Target = GdbTarget<'a>;
+    type Connection = C;
+    type StopReason = SingleThreadStopReason<u16>;
+
+    fn wait_for_stop_reason(
+        target: &mut GdbTarget<'a>,
+        conn: &mut C,
+    ) -> Result<Event<Self::StopReason>, WaitForStopReasonError<EmuError, C::Error>> {
+        let mut error = None;
+        let stopped = target.run(|| match conn.peek() {
+            Ok(byte) => byte.is_some(),
+            Err(e) => {
+                error = Some(e);
+                true
+            }
+        });
+        if let Some(e) = error {
+            return Err(WaitForStopReasonError::Connection(e));
+        }
+        match stopped {
+            Some(reason) => Ok(Event::TargetStopped(reason)),
+            None => {
+                let byte = conn.read().map_err(WaitForStopReasonError::Connection)?;
+                Ok(Event::IncomingData(byte))
+            }
+        }
+    }
+
+    fn on_interrupt(_target: &mut GdbTarget<'a>) -> Result<Option<Self::StopReason>, EmuError> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+// 1つの接続が切れるまで応答する
+pub fn serve<C>(target: &mut GdbTarget, conn: C) -> Result<DisconnectReason, String>
+where
+    C: ConnectionExt,
+    C::Error: core::fmt::Display,
+{
+    GdbStub::new(conn)
+        .run_blocking::<GdbEventLoop<C>>(target)
+        .map_err(|e| e.to_string())
+}
+
+// port で接続を待ち、切れたら次の接続を待つ
+pub fn listen(nes: &mut Nes, port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let mut target = GdbTarget::new(nes);
+    info!("GDB: listening on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let stream: TcpStream = stream?;
+        info!("GDB: connected from {}", stream.peer_addr()?);
+        match serve(&mut target, stream) {
+            Ok(reason) => info!("GDB: disconnected ({:?})", reason),
+            Err(e) => warn!("[ERR] GDB: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::{AudioConfig, APU};
+    use crate::rom::Rom;
+    use gdbstub::conn::Connection;
+    use std::collections::VecDeque;
+
+    // 受信するバイト列を先に全部積んでおく接続
+    struct TestConnection {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl Connection for TestConnection {
+        type Error = &'static str;
+
+        fn write(&mut self, byte: u8) -> Result<(), &'static str> {
+            self.output.push(byte);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), &'static str> {
+            Ok(())
+        }
+    }
+
+    impl ConnectionExt for TestConnection {
+        fn read(&mut self) -> Result<u8, &'static str> {
+            self.input.pop_front().ok_or("closed")
+        }
+
+        fn peek(&mut self) -> Result<Option<u8>, &'static str> {
+            Ok(self.input.front().copied())
+        }
+    }
+
+    // $payload#xx
+    fn packet(payload: &str) -> String {
+        let checksum = payload.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        format!("${}#{:02x}", payload, checksum)
+    }
+
+    // 返ってきたパケットの中身 (ACK の '+' は捨てる)
+    fn replies(output: &[u8]) -> Vec<String> {
+        let text = String::from_utf8_lossy(output);
+        text.split('$')
+            .skip(1)
+            .map(|p| p.split_once('#').unwrap().0.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_gdb_packets() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        let program = [
+            0xE6, 0x10, // loop: INC $10
+            0x4C, 0x00, 0x80, // JMP loop
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        let mut nes = Nes::new(Rom::from_bytes(&raw).unwrap(), APU::headless(&AudioConfig::new())).unwrap();
+        let mut target = GdbTarget::new(&mut nes);
+
+        let mut input = String::new();
+        for p in [
+            "?",
+            "M10,2:aa55",
+            "m10,2",
+            "mffff,2",
+            // PCを書き換えて1命令ずつ進める
+            "P5=0080",
+            "p5",
+            "s",
+            "m10,1",
+            "g",
+            "Z0,8000,1",
+            "c",
+            "z0,8000,1",
+            "c",
+        ] {
+            input += &packet(p);
+        }
+        input.push('\x03'); // 次のフレームの切れ目で止まる
+        input += &packet("Z2,10,1");
+        input += &packet("k");
+        let mut conn = TestConnection {
+            input: input.bytes().collect(),
+            output: vec![],
+        };
+
+        assert!(matches!(serve(&mut target, &mut conn as &mut dyn ConnectionExt<Error = _>), Ok(DisconnectReason::Kill)));
+        assert!(target.debugger().breakpoints().next().is_none());
+        // $FFFF より先は読めたところまで。g の応答は gdbstub がランレングス圧縮する ("0*\"" は 0 が6個)
+        let expected = [
+            "T05thread:01;",
+            "OK",
+            "aa55",
+            "ea",
+            "OK",
+            "0080",
+            "S05",
+            "ab",
+            "0*\"a4fd0280",
+            "OK",
+            "T05thread:01;swbreak:;",
+            "OK",
+            "S02",
+            "",
+        ];
+        assert_eq!(replies(&conn.output), expected);
+        assert_eq!(nes.cpu().program_counter & 0xFFF0, 0x8000);
+    }
+}
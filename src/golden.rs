@@ -0,0 +1,142 @@
+// 決まったフレームの画面を保存しておいた画像と比べる (PPUを速くしたときに絵が変わっていないかの回帰テスト)
+// 一覧 (golden.txt) は1行に1つ: <ROM> <フレーム> [CRC32]  (# から後はコメント)
+//   ROMは test_roms からの相対パス。画像は一覧と同じディレクトリの <ROMの名前>_<フレーム>.png
+//   画像がない (または encode_png 以外で書かれて読めない) ときは画面の CRC32 と比べる
+// NES_GOLDEN_UPDATE=1 を付けて実行すると、比べずに今の画面で画像を書き直す
+use crate::capture::{decode_png, encode_png, Rgb};
+use crate::checksum;
+use crate::error::EmuError;
+use crate::frame::Frame;
+use crate::nes::Nes;
+use std::path::Path;
+
+pub const UPDATE_ENV: &str = "NES_GOLDEN_UPDATE";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenCase {
+    pub rom: String,
+    pub frame: usize,
+    pub crc32: Option<u32>,
+}
+
+impl GoldenCase {
+    pub fn image_name(&self) -> String {
+        let stem = Path::new(&self.rom).file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        format!("{}_{}.png", stem, self.frame)
+    }
+}
+
+pub fn parse_manifest(text: &str) -> Result<Vec<GoldenCase>, String> {
+    let mut cases = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        let case = match fields.as_slice() {
+            [] => continue,
+            [rom, frame] | [rom, frame, _] => GoldenCase {
+                rom: rom.to_string(),
+                frame: frame.parse().map_err(|_| format!("line {}: bad frame: {}", i + 1, frame))?,
+                crc32: match fields.get(2) {
+                    Some(crc) => Some(u32::from_str_radix(crc, 16).map_err(|_| format!("line {}: bad crc32: {}", i + 1, crc))?),
+                    None => None,
+                },
+            },
+            _ => return Err(format!("line {}: expected <rom> <frame> [crc32]", i + 1)),
+        };
+        cases.push(case);
+    }
+    Ok(cases)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenOutcome {
+    Match,
+    Updated,
+    PixelsDiffer { count: usize, first: (usize, usize) }, // 違う画素の数と最初の位置 (x, y)
+    HashDiffers { expected: u32, actual: u32 },
+    Missing { actual: u32 }, // 画像もCRC32もない
+}
+
+impl GoldenOutcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, GoldenOutcome::Match | GoldenOutcome::Updated)
+    }
+}
+
+// frame 枚目が終わったところ (frame_count() == frame) まで進めて画面を返す
+pub fn run_to_frame(nes: &mut Nes, frame: usize) -> &Frame {
+    while nes.frame_count() < frame {
+        nes.advance_frame();
+    }
+    nes.render_frame()
+}
+
+pub fn compare(frame: &Frame, reference: &Path, crc32: Option<u32>, update: bool) -> Result<GoldenOutcome, EmuError> {
+    let pixels: Vec<Rgb> = frame.data.chunks(3).map(|c| (c[0], c[1], c[2])).collect();
+    if update {
+        if let Some(dir) = reference.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(reference, encode_png(Frame::WIDTH, Frame::HEIGHT, &pixels))?;
+        return Ok(GoldenOutcome::Updated);
+    }
+
+    let image = match std::fs::read(reference) {
+        Ok(data) => decode_png(&data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let actual = checksum::crc32(&frame.data);
+    Ok(match (image, crc32) {
+        (Some((width, height, expected)), _) if (width, height) == (Frame::WIDTH, Frame::HEIGHT) => {
+            let differ: Vec<usize> = (0..pixels.len()).filter(|&i| pixels[i] != expected[i]).collect();
+            match differ.first() {
+                None => GoldenOutcome::Match,
+                Some(&i) => GoldenOutcome::PixelsDiffer {
+                    count: differ.len(),
+                    first: (i % Frame::WIDTH, i / Frame::WIDTH),
+                },
+            }
+        }
+        (_, Some(expected)) if expected == actual => GoldenOutcome::Match,
+        (_, Some(expected)) => GoldenOutcome::HashDiffers {
+            expected,
+            actual,
+        },
+        (_, None) => GoldenOutcome::Missing { actual },
+    })
+}
+
+pub fn update_requested() -> bool {
+    std::env::var(UPDATE_ENV).is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_golden_compare() {
+        let cases = parse_manifest("# ROM frame crc\nsuite/a.nes 60\nb.nes 120 1234ABCD # title\n").unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!((cases[1].frame, cases[1].crc32), (120, Some(0x1234ABCD)));
+        assert_eq!(cases[0].image_name(), "a_60.png");
+        assert!(parse_manifest("a.nes x").is_err());
+
+        let dir = std::env::temp_dir().join(format!("rscom_golden_{}", std::process::id()));
+        let path = dir.join("a_60.png");
+        let mut frame = Frame::new();
+        frame.set_pixel(3, 2, (0xFF, 0, 0));
+        let crc = checksum::crc32(&frame.data);
+        assert_eq!(compare(&frame, &path, None, false).unwrap(), GoldenOutcome::Missing { actual: crc });
+        assert_eq!(compare(&frame, &path, Some(crc), false).unwrap(), GoldenOutcome::Match);
+        assert_eq!(compare(&frame, &path, None, true).unwrap(), GoldenOutcome::Updated);
+        assert_eq!(compare(&frame, &path, None, false).unwrap(), GoldenOutcome::Match);
+
+        frame.set_pixel(10, 20, (0, 0xFF, 0));
+        frame.set_pixel(11, 20, (0, 0xFF, 0));
+        let outcome = compare(&frame, &path, Some(crc), false).unwrap();
+        assert_eq!(outcome, GoldenOutcome::PixelsDiffer { count: 2, first: (10, 20) });
+        assert!(!outcome.passed());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+// Mapper 66 (GxROM)
+// https://www.nesdev.org/wiki/GxROM
+// $8000-$FFFF: xxPP xxCC  (P: 32KB PRGバンク, C: 8KB CHRバンク)
+use crate::mapper::Mapper;
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+pub struct Gxrom {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    mirroring: Mirroring,
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl_state!(Gxrom { prg_bank, chr_bank });
+
+impl Gxrom {
+    pub fn new(rom: &Rom) -> Self {
+        Gxrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr_rom: rom.chr_rom.clone(),
+            mirroring: rom.mirroring.clone(),
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Gxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr).map_or(0, |offset| self.prg_rom[offset])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // バスコンフリクトあり
+        let data = data & self.cpu_read(addr);
+        self.prg_bank = ((data >> 4) & 0x03) as usize;
+        self.chr_bank = (data & 0x03) as usize;
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let offset = self.chr_bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize;
+        self.chr_rom[offset % self.chr_rom.len()]
+    }
+
+    fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some((self.prg_bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some((self.chr_bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize) % self.chr_rom.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color_dreams::ColorDreams;
+
+    // PRG 32KB x4, CHR 8KB x4 (各バンクの先頭にバンク番号, 残りは0xFF)
+    fn multicart(mapper: u8) -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 8, 4, (mapper & 0x0F) << 4, mapper & 0xF0];
+        raw.resize(16, 0);
+        for bank in 0..4u8 {
+            let mut prg = vec![0xFF; PRG_BANK_SIZE];
+            prg[0] = bank;
+            raw.extend(prg);
+        }
+        for bank in 0..4u8 {
+            let mut chr = vec![0xFF; CHR_BANK_SIZE];
+            chr[0] = bank;
+            raw.extend(chr);
+        }
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_gxrom() {
+        let mut mapper = Gxrom::new(&multicart(66));
+        mapper.cpu_write(0x8001, 0x21);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.ppu_read(0x0000), 1);
+    }
+
+    #[test]
+    fn test_color_dreams() {
+        let mut mapper = ColorDreams::new(&multicart(11));
+        mapper.cpu_write(0x8001, 0x32);
+        assert_eq!(mapper.cpu_read(0x8000), 2);
+        assert_eq!(mapper.ppu_read(0x0000), 3);
+    }
+}
@@ -0,0 +1,68 @@
+// キーボードから標準コントローラーへの割り当て
+// 毎フレーム押されているキーを見て、ボタンの状態を丸ごと作り直す
+use sdl2::keyboard::Scancode;
+
+use nes_core::gamepad::Button;
+
+pub struct KeyboardMapping {
+    pub port: usize,
+    pub keys: Vec<(Scancode, Button)>,
+}
+
+impl KeyboardMapping {
+    // 1P: 十字キー, Z: B, X: A, Enter: Start, 右Shift: Select
+    pub fn new() -> Self {
+        KeyboardMapping {
+            port: 0,
+            keys: vec![
+                (Scancode::Up, Button::UP),
+                (Scancode::Down, Button::DOWN),
+                (Scancode::Left, Button::LEFT),
+                (Scancode::Right, Button::RIGHT),
+                (Scancode::Z, Button::BUTTON_B),
+                (Scancode::X, Button::BUTTON_A),
+                (Scancode::Return, Button::START),
+                (Scancode::RShift, Button::SELECT),
+            ],
+        }
+    }
+
+    // 何も割り当てられていないプロファイル
+    pub fn empty(port: usize) -> Self {
+        KeyboardMapping {
+            port,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn bind(&mut self, key: Scancode, button: Button) {
+        self.keys.retain(|(k, _)| *k != key);
+        self.keys.push((key, button));
+    }
+
+    // is_pressed: そのキーが押されているか (SDLの KeyboardState などから)
+    pub fn buttons<F: Fn(Scancode) -> bool>(&self, is_pressed: F) -> Button {
+        self.keys
+            .iter()
+            .filter(|(key, _)| is_pressed(*key))
+            .fold(Button::empty(), |buttons, (_, button)| buttons | *button)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard_mapping() {
+        let mut mapping = KeyboardMapping::new();
+        let pressed = [Scancode::Z, Scancode::Return, Scancode::Left];
+        let buttons = mapping.buttons(|key| pressed.contains(&key));
+        assert_eq!(buttons, Button::BUTTON_B | Button::START | Button::LEFT);
+
+        // 割り当て直すと前の割り当ては消える
+        mapping.bind(Scancode::Z, Button::BUTTON_A);
+        assert_eq!(mapping.buttons(|key| key == Scancode::Z), Button::BUTTON_A);
+        assert_eq!(mapping.buttons(|_| false), Button::empty());
+    }
+}
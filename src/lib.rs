@@ -0,0 +1,144 @@
+// エミュレーターの本体 (CPU, PPU, APU, カートリッジ, 周辺機器)
+// SDLには依存しないので、CPUだけ使いたい場合や libretro/wasm/C API からはこちらを使う
+// SDLのフロントエンドは rscom (src/main.rs, `sdl` フィーチャー) にある
+// マッパーごとの実装は cartridge (register_mapper) を通して使うので公開しない
+// std フィーチャーを外すと no_std + alloc でビルドする (CPU/PPU/APU/マッパーと本体だけ)
+//   ファイル入出力やデバッグ用のツール (トレース, GDB, 動画の書き出しなど) は std のときだけ
+//   ROMは Rom::from_bytes で読み込む
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[macro_use]
+extern crate alloc;
+// OSのあるターゲットでは no_std でも cdylib を作るので、パニックハンドラとアロケーターだけ std から借りる
+// (名前は付けないので、コードからは std:: を使えない)
+#[cfg(all(not(feature = "std"), not(target_os = "none")))]
+extern crate std as _;
+#[macro_use]
+extern crate lazy_static;
+
+pub mod apu;
+pub mod arkanoid;
+#[cfg(feature = "std")]
+pub mod av_dump;
+#[cfg(feature = "std")]
+pub mod bench;
+#[cfg(feature = "std")]
+pub mod blargg;
+pub mod bus;
+pub mod call_stack;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+pub mod capture;
+pub mod cartridge;
+pub mod cdl;
+pub mod cheat;
+pub mod checksum;
+mod cnrom;
+mod color_dreams;
+pub mod controller;
+pub mod cpu;
+pub mod debugger;
+pub mod error;
+pub mod event_viewer;
+pub mod events;
+mod fme7;
+pub mod frame;
+pub mod four_score;
+pub mod gamepad;
+#[cfg(feature = "std")]
+pub mod gdb;
+#[cfg(feature = "std")]
+pub mod golden;
+mod gxrom;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "lua")]
+pub mod lua;
+pub mod mapper;
+pub mod memory_view;
+mod mmc3;
+#[cfg(feature = "std")]
+pub mod movie;
+mod n163;
+pub mod nes;
+#[cfg(feature = "std")]
+pub mod netplay;
+mod nrom;
+pub mod nsf;
+pub mod opcode;
+#[cfg(feature = "std")]
+pub mod overrides;
+pub mod palette;
+#[cfg(feature = "std")]
+pub mod perf;
+pub mod power_on;
+pub mod ppu;
+#[cfg(feature = "std")]
+pub mod ppu_dump;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "std")]
+pub mod ram_map;
+#[cfg(feature = "std")]
+pub mod ram_search;
+pub mod region;
+#[cfg(feature = "std")]
+pub mod remote;
+pub mod render;
+pub mod rom;
+#[cfg(feature = "std")]
+pub mod romdb;
+#[cfg(feature = "std")]
+pub mod save_slots;
+#[cfg(feature = "std")]
+pub mod script;
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod sram;
+pub mod state;
+pub mod symbols;
+pub mod sync;
+#[cfg(feature = "std")]
+pub mod test_rom;
+#[cfg(feature = "std")]
+pub mod trace_compare;
+#[cfg(feature = "std")]
+pub mod trace_log;
+mod uxrom;
+pub mod vgm;
+#[cfg(feature = "std")]
+pub mod video;
+mod vrc4;
+#[cfg(feature = "std")]
+pub mod watch;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod zapper;
+pub mod common;
+
+// よく使う型はモジュール名を書かずに使えるようにする
+#[cfg(feature = "std")]
+pub use cartridge::{load_rom, load_rom_from_reader};
+pub use error::EmuError;
+pub use frame::Frame;
+pub use gamepad::Button as Buttons;
+pub use mapper::Mapper;
+pub use nes::Nes;
+pub use region::Region;
+pub use rom::{Rom, RomError};
+
+// use nes_core::prelude::*; で本体を動かすのに必要なものとデバッガーの型をまとめて使う
+pub mod prelude {
+    pub use crate::apu::{AudioConfig, APU};
+    pub use crate::call_stack::{CallFrame, CallStack, StackByte};
+    pub use crate::cheat::{Cheat, CheatManager};
+    pub use crate::debugger::{BreakCondition, Debugger, Registers, StopReason};
+    pub use crate::memory_view::MemorySpace;
+    pub use crate::power_on::PowerOnState;
+    pub use crate::snapshot::{ApuSnapshot, CpuSnapshot, DebugSnapshot, MapperSnapshot, PpuSnapshot};
+    pub use crate::state::StateError;
+    #[cfg(feature = "std")]
+    pub use crate::{load_rom, load_rom_from_reader};
+    pub use crate::{Buttons, EmuError, Frame, Mapper, Nes, Region, Rom, RomError};
+}
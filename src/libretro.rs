@@ -0,0 +1,403 @@
+// libretro コア (RetroArch などのフロントエンドから使う)
+// https://docs.libretro.com/development/cores/developing-cores/
+// https://github.com/libretro/libretro-common/blob/master/include/libretro.h
+//
+// `--features libretro` のときだけ含める
+//   cargo build --release --lib --features libretro  (libnes_core.so をコアとして読み込む)
+use crate::apu::{AudioConfig, APU};
+use crate::gamepad::Button;
+use crate::nes::Nes;
+use crate::region::Region;
+use crate::rom::Rom;
+use crate::sram::SramConfig;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void, CStr};
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD: [(c_uint, Button); 8] = [
+    (0, Button::BUTTON_B),
+    (2, Button::SELECT),
+    (3, Button::START),
+    (4, Button::UP),
+    (5, Button::DOWN),
+    (6, Button::LEFT),
+    (7, Button::RIGHT),
+    (8, Button::BUTTON_A),
+];
+
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_REGION_PAL: c_uint = 1;
+
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+const SAMPLE_RATE: i32 = 44100;
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+
+type EnvironmentFn = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = extern "C" fn();
+type InputStateFn = extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    nes: Nes,
+    video: Vec<u32>,   // XRGB8888
+    samples: Vec<f32>, // APUのモノラル出力
+    audio: Vec<i16>,   // ステレオに広げたもの
+}
+
+// libretro はすべて同じスレッドから呼ぶ決まりなので、スレッドローカルに持つ
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn with_core<R>(default: R, f: impl FnOnce(&mut Core) -> R) -> R {
+    CORE.with(|core| match core.borrow_mut().as_mut() {
+        Some(core) => f(core),
+        None => default,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    CALLBACKS.with(|c| c.borrow_mut().environment = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    CALLBACKS.with(|c| c.borrow_mut().video_refresh = Some(cb));
+}
+
+// 1サンプルずつの出力は使わない (バッチで送る)
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    CALLBACKS.with(|c| c.borrow_mut().audio_sample_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    CALLBACKS.with(|c| c.borrow_mut().input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    CALLBACKS.with(|c| c.borrow_mut().input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+/// # Safety
+/// info はフロントエンドが用意した書き込めるポインタ
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"rscom".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"nes|nsf".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// info はフロントエンドが用意した書き込めるポインタ
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    let region = with_core(Region::Ntsc, |core| core.nes.region());
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: 4.0 / 3.0,
+        },
+        timing: RetroSystemTiming {
+            fps: region.frame_rate(),
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+// ポート1/2ともジョイパッドのみ
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| core.nes.soft_reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.with(|c| {
+        let c = c.borrow();
+        (c.video_refresh, c.audio_sample_batch, c.input_poll, c.input_state)
+    });
+    let (video_refresh, audio_sample_batch, input_poll, input_state) = callbacks;
+
+    with_core((), |core| {
+        if let Some(poll) = input_poll {
+            poll();
+        }
+        if let Some(state) = input_state {
+            for port in 0..2 {
+                let mut buttons = Button::empty();
+                for &(id, button) in RETRO_DEVICE_ID_JOYPAD.iter() {
+                    if state(port, RETRO_DEVICE_JOYPAD, 0, id) != 0 {
+                        buttons.insert(button);
+                    }
+                }
+                core.nes.set_controller_state(port as usize, buttons);
+            }
+        }
+
+        let frame = core.nes.run_frame();
+        for (pixel, rgb) in core.video.iter_mut().zip(frame.data.chunks_exact(3)) {
+            *pixel = (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+        }
+        if let Some(refresh) = video_refresh {
+            refresh(core.video.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4);
+        }
+
+        core.samples.clear();
+        core.nes.apu_mut().drain_samples(&mut core.samples);
+        core.audio.clear();
+        for &sample in core.samples.iter() {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            core.audio.push(value);
+            core.audio.push(value);
+        }
+        if let Some(batch) = audio_sample_batch {
+            let mut sent = 0;
+            while sent < core.samples.len() {
+                let frames = batch(core.audio[sent * 2..].as_ptr(), core.samples.len() - sent);
+                if frames == 0 {
+                    break;
+                }
+                sent += frames;
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.nes.save_state().len())
+}
+
+/// # Safety
+/// data は size バイト書き込めること
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        let state = core.nes.save_state();
+        if state.len() > size {
+            return false;
+        }
+        std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+/// # Safety
+/// data は size バイト読めること
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core| core.nes.load_state(state).is_ok())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core((), |core| core.nes.cheats_mut().clear());
+}
+
+/// Pro Action Replay 形式 (AAAA:VV) のみ。'+'で複数つなげてもよい
+///
+/// # Safety
+/// code は NUL 終端の文字列か NULL
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy().into_owned();
+    with_core((), |core| {
+        for part in code.split('+') {
+            match core.nes.cheats_mut().add_code(part.trim(), false) {
+                Ok(id) => {
+                    if !enabled {
+                        core.nes.cheats_mut().toggle(id);
+                    }
+                }
+                Err(e) => log::warn!("[ERR] CHEAT: {}", e),
+            }
+        }
+    });
+}
+
+/// # Safety
+/// game はフロントエンドが渡した retro_game_info か NULL
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let game = &*game;
+    let data = std::slice::from_raw_parts(game.data as *const u8, game.size);
+    let path = if game.path.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(game.path).to_string_lossy().into_owned()
+    };
+
+    if let Some(environment) = CALLBACKS.with(|c| c.borrow().environment) {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+            return false;
+        }
+    }
+
+    let rom = match Rom::from_bytes(data) {
+        Ok(rom) => rom,
+        Err(e) => {
+            log::warn!("[ERR] {}: {}", path, e);
+            return false;
+        }
+    };
+    let region = Region::detect(&rom.header, &path);
+    let config = AudioConfig {
+        sample_rate: SAMPLE_RATE,
+        ..AudioConfig::new()
+    };
+    let mut nes = match Nes::new(rom, APU::headless(&config)) {
+        Ok(nes) => nes,
+        Err(e) => {
+            log::warn!("[ERR] {}: {}", path, e);
+            return false;
+        }
+    };
+    nes.set_region(region);
+    if !path.is_empty() {
+        if let Err(e) = nes.cartridge().load_sram(&path, &SramConfig::new()) {
+            log::warn!("[ERR] SRAM load: {}", e);
+        }
+    }
+
+    CORE.with(|core| {
+        *core.borrow_mut() = Some(Core {
+            nes,
+            video: vec![0; WIDTH * HEIGHT],
+            samples: vec![],
+            audio: vec![],
+        })
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_type: c_uint, _info: *const RetroGameInfo, _num: usize) -> bool {
+    false
+}
+
+// Nesを手放すとSRAMが書き出される
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    match with_core(Region::Ntsc, |core| core.nes.region()) {
+        Region::Ntsc => RETRO_REGION_NTSC,
+        _ => RETRO_REGION_PAL,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match id {
+        RETRO_MEMORY_SYSTEM_RAM => with_core(std::ptr::null_mut(), |core| {
+            core.nes.ram_mut().as_mut_ptr() as *mut c_void
+        }),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match id {
+        RETRO_MEMORY_SYSTEM_RAM => with_core(0, |core| core.nes.ram().len()),
+        _ => 0,
+    }
+}
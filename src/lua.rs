@@ -0,0 +1,204 @@
+// Luaのスクリプト (--features lua)
+// FCEUXと同じ名前の memory/emu/joypad/gui テーブルから ScriptContext を操作する
+// https://fceux.com/web/help/LuaFunctionsList.html
+// emu.frameadvance でループを回す書き方には対応しない。emu.registerafter に登録した関数をフレームごとに呼ぶ
+use crate::gamepad::Button;
+use crate::nes::Nes;
+use crate::script::{button_from_name, Overlay, Rgb, ScriptContext, ScriptHost};
+use log::warn;
+use mlua::{Function, Lua, Table};
+use std::cell::RefCell;
+
+// emu.registerafter の関数を入れておくレジストリの名前
+const AFTER_FRAME: &str = "after_frame";
+
+// スクリプトを実行して、emu.registerafter の関数を host に登録する
+// トップレベルでも memory などを使えるように、いまの nes に対して実行する (gui への描画は捨てる)
+pub fn load(host: &mut ScriptHost<'_>, nes: &mut Nes, source: &str) -> Result<(), String> {
+    let lua = Lua::new();
+    init(&lua).map_err(|e| e.to_string())?;
+    let mut gui = Overlay::new();
+    let mut context = ScriptContext {
+        nes,
+        gui: &mut gui,
+        ram_map: host.ram_map(),
+    };
+    with_context(&lua, &mut context, || lua.load(source).exec()).map_err(|e| e.to_string())?;
+
+    host.register_after(move |context| {
+        let result = with_context(&lua, context, || {
+            let callbacks: Table = lua.named_registry_value(AFTER_FRAME)?;
+            for callback in callbacks.sequence_values::<Function>() {
+                callback?.call::<()>(())?;
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("[ERR] LUA: {}", e);
+        }
+    });
+    Ok(())
+}
+
+// テーブルと、ScriptContext を使わない関数
+fn init(lua: &Lua) -> mlua::Result<()> {
+    lua.set_named_registry_value(AFTER_FRAME, lua.create_table()?)?;
+    for name in ["memory", "emu", "joypad", "gui"] {
+        lua.globals().set(name, lua.create_table()?)?;
+    }
+    let emu: Table = lua.globals().get("emu")?;
+    let register_after = lua.create_function(|lua, callback: Function| {
+        let callbacks: Table = lua.named_registry_value(AFTER_FRAME)?;
+        callbacks.push(callback)
+    })?;
+    emu.set("registerafter", register_after)?;
+    Ok(())
+}
+
+// context を使う関数をテーブルに入れてから f を呼ぶ (f から戻ると呼べなくなる)
+fn with_context<R>(lua: &Lua, context: &mut ScriptContext<'_>, f: impl FnOnce() -> mlua::Result<R>) -> mlua::Result<R> {
+    let context = RefCell::new(context);
+    let context = &context;
+    lua.scope(|scope| {
+        let globals = lua.globals();
+
+        let memory: Table = globals.get("memory")?;
+        memory.set(
+            "readbyte",
+            scope.create_function(|_, addr: u16| Ok(context.borrow_mut().read_byte(addr)))?,
+        )?;
+        memory.set(
+            "writebyte",
+            scope.create_function(|_, (addr, value): (u16, u8)| {
+                context.borrow_mut().write_byte(addr, value);
+                Ok(())
+            })?,
+        )?;
+        memory.set(
+            "getregister",
+            scope.create_function(|_, name: String| Ok(context.borrow().register(&name)))?,
+        )?;
+        memory.set(
+            "setregister",
+            scope.create_function(|_, (name, value): (String, u16)| Ok(context.borrow_mut().set_register(&name, value)))?,
+        )?;
+
+        let emu: Table = globals.get("emu")?;
+        emu.set("framecount", scope.create_function(|_, ()| Ok(context.borrow().frame_count()))?)?;
+
+        // joypad.set(1, {A = true, start = true})
+        let joypad: Table = globals.get("joypad")?;
+        joypad.set(
+            "set",
+            scope.create_function(|_, (player, buttons): (usize, Table)| {
+                let mut pressed = Button::empty();
+                for pair in buttons.pairs::<String, bool>() {
+                    let (name, down) = pair?;
+                    match button_from_name(&name) {
+                        Some(button) if down => pressed |= button,
+                        Some(_) => {}
+                        None => return Err(mlua::Error::runtime(format!("unknown button: {}", name))),
+                    }
+                }
+                context.borrow_mut().set_joypad(player, pressed);
+                Ok(())
+            })?,
+        )?;
+
+        let gui: Table = globals.get("gui")?;
+        gui.set(
+            "pixel",
+            scope.create_function(|_, (x, y, color): (i32, i32, String)| {
+                context.borrow_mut().gui.pixel(x, y, parse_color(&color)?);
+                Ok(())
+            })?,
+        )?;
+        gui.set(
+            "line",
+            scope.create_function(|_, (x1, y1, x2, y2, color): (i32, i32, i32, i32, String)| {
+                context.borrow_mut().gui.line(x1, y1, x2, y2, parse_color(&color)?);
+                Ok(())
+            })?,
+        )?;
+        // gui.box(x1, y1, x2, y2 [, 塗りつぶし [, 枠]]) 枠の既定は白
+        gui.set(
+            "box",
+            scope.create_function(
+                |_, (x1, y1, x2, y2, fill, outline): (i32, i32, i32, i32, Option<String>, Option<String>)| {
+                    let fill = fill.map(|fill| parse_color(&fill)).transpose()?;
+                    let outline = parse_color(outline.as_deref().unwrap_or("white"))?;
+                    context.borrow_mut().gui.draw_box(x1, y1, x2, y2, fill, outline);
+                    Ok(())
+                },
+            )?,
+        )?;
+
+        f()
+    })
+}
+
+// "#RRGGBB" か色の名前 (FCEUXと同じ)
+fn parse_color(text: &str) -> mlua::Result<Rgb> {
+    let rgb = match text {
+        "white" => (255, 255, 255),
+        "black" => (0, 0, 0),
+        "gray" | "grey" => (127, 127, 127),
+        "red" => (255, 0, 0),
+        "green" => (0, 255, 0),
+        "blue" => (0, 0, 255),
+        "yellow" => (255, 255, 0),
+        "orange" => (255, 127, 0),
+        "purple" => (127, 0, 255),
+        _ => {
+            let value = text
+                .strip_prefix('#')
+                .filter(|hex| hex.len() == 6)
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+            match value {
+                Some(value) => ((value >> 16) as u8, (value >> 8) as u8, value as u8),
+                None => return Err(mlua::Error::runtime(format!("bad color: {}", text))),
+            }
+        }
+    };
+    Ok(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_lua_script() {
+        let mut nes = TestRom::new().asm(0x8000, "loop: INC $10\nJMP loop").nes().unwrap();
+        let mut host = ScriptHost::new();
+        let source = r##"
+            memory.writebyte(0x0300, 0x12)
+            local frames = 0
+            emu.registerafter(function()
+                frames = frames + 1
+                memory.writebyte(0x0301, frames)
+                memory.writebyte(0x0302, emu.framecount())
+                memory.setregister("x", memory.readbyte(0x0300) + 1)
+                joypad.set(1, {A = true, start = true, B = false})
+                gui.box(0, 0, 2, 2, "#102030", "red")
+            end)
+        "##;
+        load(&mut host, &mut nes, source).unwrap();
+        assert_eq!(nes.ram()[0x300], 0x12);
+
+        let frame = host.run_frame(&mut nes);
+        assert_eq!(frame.get_pixel(1, 1), Some((0x10, 0x20, 0x30)));
+        assert_eq!(frame.get_pixel(0, 0), Some((255, 0, 0)));
+        host.run_frame(&mut nes);
+        assert_eq!(nes.ram()[0x301], 2);
+        assert_eq!(nes.ram()[0x302] as usize, nes.frame_count());
+        assert_eq!(nes.cpu().register_x, 0x13);
+
+        // 文法エラーや知らないボタン/色はエラーになる
+        assert!(load(&mut host, &mut nes, "memory.readbyte(").is_err());
+        let error = load(&mut host, &mut nes, "joypad.set(1, {turbo = true})").unwrap_err();
+        assert!(error.contains("unknown button: turbo"), "{}", error);
+        assert!(load(&mut host, &mut nes, "gui.pixel(0, 0, 'pink')").is_err());
+    }
+}
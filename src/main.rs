@@ -1,50 +1,230 @@
-#[macro_use]
-extern crate lazy_static;
-
-mod apu;
-mod bus;
-mod cartridge;
-mod cpu;
-mod frame;
-mod gamepad;
-mod mapper;
-mod opcode;
-mod palette;
-mod ppu;
-mod render;
-mod rom;
-mod common;
-use common::*;
-use crate::cpu::{trace, IN_TRACE};
-
-use self::bus::{Bus, Mem};
-use self::cpu::CPU;
-
-use apu::APU;
-use cartridge::load_rom;
-use frame::Frame;
-use gamepad::GamePad;
-use log::{debug, info, log_enabled, trace, Level};
-use mapper::MapperMMC;
-use ppu::PPU;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::EventPump;
-use std::collections::HashMap;
+// SDLのフロントエンド (エミュレーターの本体はライブラリの nes_core)
+#[cfg(feature = "sdl")]
+mod bindings;
+mod cli;
+#[cfg(feature = "gui")]
+mod debugger_gui;
+#[cfg(feature = "sdl")]
+mod frontend;
+#[cfg(feature = "sdl")]
+mod game_controller;
+#[cfg(feature = "sdl")]
+mod keyboard;
+#[cfg(feature = "sdl")]
+mod settings;
+use nes_core::apu::{AudioConfig, APU};
+use nes_core::av_dump::AvDump;
+use nes_core::cartridge::load_rom;
+use crate::cli::{Options, USAGE};
+use nes_core::cpu::{in_trace, trace_if_enabled};
+use nes_core::common::*;
+use nes_core::nes::Nes;
+use nes_core::overrides::HeaderOverrides;
+use nes_core::ppu_dump;
+use nes_core::profiler::Profiler;
+use nes_core::region::Region;
+use nes_core::rom::Rom;
+use nes_core::romdb::RomDatabase;
+use nes_core::save_slots::SaveSlots;
+use nes_core::sram::SramConfig;
+use nes_core::trace_compare::{self, CompareResult};
+use nes_core::trace_log::TraceLogger;
+use nes_core::{bench, checksum, gdb, palette, remote};
+use log::{info, warn};
 use std::io::Write;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-lazy_static! {
-    pub static ref MAPPER: Mutex<Box<MapperMMC>> = Mutex::new(Box::new(MapperMMC::new()));
+// --profile で表示するサブルーチンの数
+const PROFILE_TOP: usize = 20;
+
+// ヘッドレス実行中に Ctrl+C が押された (SDLのフロントエンドではSDLがQuitイベントにする)
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn catch_sigint() {
+    use std::os::raw::c_int;
+    const SIGINT: c_int = 2;
+    extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+    extern "C" fn on_sigint(_: c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    unsafe {
+        signal(SIGINT, on_sigint);
+    }
+}
+
+#[cfg(not(unix))]
+fn catch_sigint() {}
+
+// ROMを読み込んで、ROMデータベースとヘッダーの上書きを適用する
+fn open_rom(path: &str) -> Rom {
+    let mut rom = load_rom(path).unwrap_or_else(|e| panic!("[ERR] {}: {}", path, e));
+    if rom.apply_database(&RomDatabase::bundled()) {
+        info!(
+            "ROM DB: {} ({})",
+            rom.title.as_deref().unwrap_or(""),
+            rom.board.as_deref().unwrap_or("")
+        );
+    }
+    let overrides_path = std::path::Path::new(_HEADER_OVERRIDES_PATH);
+    if overrides_path.exists() {
+        match HeaderOverrides::load(overrides_path) {
+            Ok(overrides) => {
+                if rom.apply_overrides(&overrides) {
+                    info!("ROM: header overridden by {}", _HEADER_OVERRIDES_PATH);
+                }
+            }
+            Err(e) => warn!("[ERR] {}: {}", _HEADER_OVERRIDES_PATH, e),
+        }
+    }
+    info!(
+        "ROM: mapper={}, mirroring={:?} chr_ram={} battery={} trainer={} crc32={:08X} sha1={}",
+        rom.mapper,
+        rom.mirroring,
+        rom.is_chr_ram,
+        rom.header.has_battery,
+        rom.header.has_trainer,
+        rom.crc32,
+        checksum::to_hex(&rom.sha1)
+    );
+    rom
+}
+
+// ウィンドウを出さずに指定フレーム数だけ実行する (テストやトレースの採取用)
+fn run_headless(options: &Options, rom: Rom, frames: usize) {
+    let region = options.region.unwrap_or_else(|| Region::detect(&rom.header, &options.rom_path));
+    let mut nes = Nes::new(rom, APU::headless(&AudioConfig::new())).unwrap_or_else(|e| panic!("[ERR] {}", e));
+    nes.set_region(region);
+    if let Some(state) = options.power_on {
+        nes.set_power_on_state(state);
+        nes.power_cycle();
+    }
+    if let Err(e) = nes.cartridge().load_sram(&options.rom_path, &SramConfig::new()) {
+        warn!("[ERR] SRAM load: {}", e);
+    }
+    if let Some(path) = options.load_state.as_deref() {
+        let state = std::fs::read(path).unwrap_or_else(|e| panic!("[ERR] {}: {}", path, e));
+        nes.load_state(&state).unwrap_or_else(|e| panic!("[ERR] {}: {}", path, e));
+    }
+    let mut dump = options.dump.as_deref().map(|path| {
+        nes.apu_mut().start_recording();
+        let sample_rate = nes.apu_mut().sample_rate() as u32;
+        AvDump::start(_FFMPEG_PATH, std::path::Path::new(path), region, sample_rate)
+            .unwrap_or_else(|e| panic!("[ERR] {}: {}", _FFMPEG_PATH, e))
+    });
+    for (path, result) in nes.symbols_mut().load_beside(std::path::Path::new(&options.rom_path)) {
+        match result {
+            Ok(count) => info!("LABELS: {} ({} labels)", path.display(), count),
+            Err(e) => warn!("[ERR] LABELS: {}: {}", path.display(), e),
+        }
+    }
+    let mut trace_log = options.trace_file.as_deref().map(|path| {
+        TraceLogger::create(std::path::Path::new(path), options.trace_filter.clone())
+            .unwrap_or_else(|e| panic!("[ERR] {}: {}", path, e))
+    });
+    let mut profiler = options.profile.then(Profiler::new);
+    catch_sigint();
+    for _ in 0..frames {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            info!("HEADLESS: interrupted at frame {}", nes.frame_count());
+            break;
+        }
+        nes.cpu_mut().run_frame_with_callback(|cpu| {
+            trace_if_enabled(cpu);
+            if let Some(trace_log) = trace_log.as_mut() {
+                trace_log.log(cpu);
+            }
+            if let Some(profiler) = profiler.as_mut() {
+                profiler.record(cpu);
+            }
+        });
+        if let Some(dump) = dump.as_mut() {
+            let samples = nes.apu_mut().take_recorded_samples();
+            dump.push(nes.render_frame(), &samples).unwrap_or_else(|e| panic!("[ERR] {}: {}", _FFMPEG_PATH, e));
+        }
+    }
+    if let Some(dump) = dump.take() {
+        match dump.finish() {
+            Ok(path) => info!("AV DUMP: {}", path.display()),
+            Err(e) => warn!("[ERR] AV DUMP: {}", e),
+        }
+    }
+    if let Some(trace_log) = trace_log.take() {
+        let path = options.trace_file.as_deref().unwrap_or("");
+        match trace_log.finish() {
+            Ok((lines, _)) => info!("TRACE: {} lines to {}", lines, path),
+            Err(e) => warn!("[ERR] TRACE: {}: {}", path, e),
+        }
+    }
+    if let Some(profiler) = profiler.as_ref() {
+        for function in profiler.report().iter().take(PROFILE_TOP) {
+            info!("PROFILE: {}", function);
+        }
+    }
+    if let Some(dir) = options.dump_ppu.as_deref() {
+        let prefix = format!("{:08X}_{}", nes.cartridge().rom_crc32(), nes.frame_count());
+        match ppu_dump::save_all(&mut nes, std::path::Path::new(dir), &prefix) {
+            Ok(paths) => info!("PPU DUMP: {} files to {}", paths.len(), dir),
+            Err(e) => warn!("[ERR] PPU DUMP: {}: {}", dir, e),
+        }
+    }
+    if let Err(e) = nes.cartridge().save_sram() {
+        warn!("[ERR] SRAM save: {}", e);
+    }
+    // 途中で止めたときは続きから再開できるようにステートも残す
+    if INTERRUPTED.load(Ordering::SeqCst) && _AUTOSAVE_ON_EXIT {
+        let slots = SaveSlots::new(std::path::Path::new(_SAVE_STATE_DIR), nes.cartridge().rom_crc32());
+        match slots.autosave(&nes.save_state()) {
+            Ok(()) => info!("STATE: saved {}", slots.autosave_path().display()),
+            Err(e) => warn!("[ERR] STATE: autosave: {}", e),
+        }
+    }
+    let crc = checksum::crc32(&nes.render_frame().data);
+    info!("HEADLESS: {} frames ({:?}), frame crc32={:08X}", nes.frame_count(), region, crc);
+}
+
+// 他のエミュレーターのトレースと比べる。食い違ったら両方の状態を出して 1 で終わる
+fn compare_trace(options: &Options, rom: Rom, path: &str) {
+    let mut nes = Nes::new(rom, APU::headless(&AudioConfig::new())).unwrap_or_else(|e| panic!("[ERR] {}", e));
+    if let Some(state) = options.power_on {
+        nes.set_power_on_state(state);
+        nes.power_cycle();
+    }
+    let file = std::fs::File::open(path).unwrap_or_else(|e| panic!("[ERR] {}: {}", path, e));
+    match trace_compare::compare(&mut nes, std::io::BufReader::new(file), options.instructions) {
+        Ok(CompareResult::Match(count)) => info!("COMPARE: {} instructions match {}", count, path),
+        Ok(CompareResult::Diverged(divergence)) => {
+            println!("{}", divergence);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            warn!("[ERR] COMPARE: {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn main() {
-    env_logger::builder()
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        std::process::exit(2)
+    });
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let mut logger = env_logger::builder();
+    if options.trace {
+        // 命令ごとのトレースだけ (他のモジュールは RUST_LOG で指定する)
+        logger.filter_module("cpu", log::LevelFilter::Trace);
+    }
+    logger
         .format(|buf, record| {
             let style = buf.style();
-            if unsafe { IN_TRACE } {
+            if in_trace() {
                 writeln!(buf, "[TRACE] {}", style.value(record.args()))
             } else {
                 writeln!(buf, "        {}", style.value(record.args()))
@@ -53,85 +233,59 @@ fn main() {
         .format_timestamp(None)
         .init();
 
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("rscom -Rust NES Emulator-", (256.0 * 2.0) as u32, (240.0 * 2.0) as u32)
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(2.0, 2.0).unwrap();
-
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 256, 240)
-        .unwrap();
-
-    let mut key_map = HashMap::new();
-    key_map.insert(Keycode::Down, gamepad::Button::DOWN);
-    key_map.insert(Keycode::Up, gamepad::Button::UP);
-    key_map.insert(Keycode::Right, gamepad::Button::RIGHT);
-    key_map.insert(Keycode::Left, gamepad::Button::LEFT);
-    key_map.insert(Keycode::Space, gamepad::Button::SELECT);
-    key_map.insert(Keycode::Return, gamepad::Button::START);
-    key_map.insert(Keycode::A, gamepad::Button::BUTTON_A);
-    key_map.insert(Keycode::S, gamepad::Button::BUTTON_B);
-
-    let rom = load_rom(_NES_ROM_PATH);
-    MAPPER.lock().unwrap().prg_rom = rom.prg_rom.clone();
-    MAPPER.lock().unwrap().chr_rom = rom.chr_rom.clone();
-    MAPPER.lock().unwrap().is_chr_ram = rom.is_chr_ram.clone();
-    MAPPER.lock().unwrap().is_prg_ram = rom.is_prg_ram.clone();
-    MAPPER.lock().unwrap().mapper = rom.mapper.clone();
-    MAPPER.lock().unwrap().rom_type = rom.rom_type.clone();
-    MAPPER.lock().unwrap().mmc_1.rom_type = rom.rom_type.clone();
-
-    info!(
-        "ROM: mapper={}, mirroring={:?} chr_ram={}",
-        rom.mapper, rom.mirroring, rom.is_chr_ram
-    );
-
-    let mut frame = Frame::new();
-    let apu = APU::new(&sdl_context);
-    let bus = Bus::new(rom, apu, move |ppu: &PPU, gamepad_1: &mut GamePad| {
-        render::render(ppu, &mut frame);
-        texture.update(None, &frame.data, 256 * 3).unwrap();
-
-        canvas.copy(&texture, None, None).unwrap();
-
-        canvas.present();
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => std::process::exit(0),
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        gamepad_1.set_button_pressed_status(*key, true);
-                    }
-                }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = key_map.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        gamepad_1.set_button_pressed_status(*key, false);
-                    }
-                }
-                _ => { /* do nothing */ }
+    if let Some(path) = options.palette.as_deref() {
+        match palette::load_pal(std::path::Path::new(path)) {
+            Ok(pal) => {
+                palette::set_palette(pal);
+                info!("PALETTE: {}", path);
             }
+            Err(e) => warn!("[ERR] PALETTE: {}: {}", path, e),
         }
-    });
+    }
 
-    let mut cpu = CPU::new(bus);
+    // ベンチマークは組み込みのROMで動かす
+    if options.bench {
+        for result in bench::run_all() {
+            println!("{}", result);
+        }
+        return;
+    }
 
-    cpu.reset();
-    cpu.run_with_callback(move |cpu| {
-        if log_enabled!(Level::Trace) {
-            trace(cpu);
+    let rom = open_rom(&options.rom_path);
+    if let Some(port) = options.gdb_port {
+        let mut nes = Nes::new(rom, APU::headless(&AudioConfig::new())).unwrap_or_else(|e| panic!("[ERR] {}", e));
+        nes.symbols_mut().load_beside(std::path::Path::new(&options.rom_path));
+        if let Err(e) = gdb::listen(&mut nes, port) {
+            warn!("[ERR] GDB: {}", e);
         }
-    });
+        return;
+    }
+    if let Some(port) = options.remote_port {
+        let mut nes = Nes::new(rom, APU::headless(&AudioConfig::new())).unwrap_or_else(|e| panic!("[ERR] {}", e));
+        if let Err(e) = remote::listen(&mut nes, port) {
+            warn!("[ERR] REMOTE: {}", e);
+        }
+        return;
+    }
+    if let Some(path) = options.compare_trace.as_deref() {
+        compare_trace(&options, rom, path);
+        return;
+    }
+    if options.debugger {
+        #[cfg(feature = "gui")]
+        debugger_gui::run(&options, rom);
+        #[cfg(not(feature = "gui"))]
+        warn!("[ERR] built without the gui feature, rebuild with --features gui to use --debugger");
+        return;
+    }
+    if let Some(frames) = options.frames {
+        run_headless(&options, rom, frames);
+        return;
+    }
+    #[cfg(feature = "sdl")]
+    frontend::run(&options, rom);
+    #[cfg(not(feature = "sdl"))]
+    warn!("[ERR] built without SDL, use --frames to run headless");
 }
 
 #[cfg(test)]
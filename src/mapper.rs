@@ -1,8 +1,67 @@
-use log::{info, warn};
+use log::{debug, warn};
 // use log::{debug, error, info, log_enabled, trace, warn, Level};
 use crate::{common, rom::RomType};
 use common::*;
 use crate::rom::Mirroring;
+use crate::impl_state;
+use crate::state::State;
+use alloc::vec::Vec;
+
+// $6000-$7FFF のPRG RAM (8KBより小さければミラー、無ければオープンバス扱いで0)
+pub fn prg_ram_read(ram: &[u8], addr: u16) -> u8 {
+    if ram.is_empty() {
+        return 0;
+    }
+    ram[(addr as usize - 0x6000) % ram.len()]
+}
+
+pub fn prg_ram_write(ram: &mut [u8], addr: u16, data: u8) {
+    if !ram.is_empty() {
+        let len = ram.len();
+        ram[(addr as usize - 0x6000) % len] = data;
+    }
+}
+
+// カートリッジ側の回路 (CPU/PPUから見たアドレスをROM/RAMに割り当てる)
+// https://www.nesdev.org/wiki/Mapper
+// ステートセーブのため、バンクレジスタやIRQカウンタ、RAMを State で保存できること
+// (通常は impl_state! にフィールドを列挙する)
+pub trait Mapper: Send + State {
+    // $4020-$FFFF
+    fn cpu_read(&mut self, addr: u16) -> u8;
+    fn cpu_write(&mut self, addr: u16, data: u8);
+    // $0000-$1FFF (パターンテーブル)
+    fn ppu_read(&mut self, addr: u16) -> u8;
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    // マッパーからのIRQ要求
+    fn irq_pending(&self) -> bool {
+        false
+    }
+    // PPUアドレスバスのA12が立ち上がった (MMC3のスキャンラインカウンタ用)
+    fn ppu_a12_rising(&mut self) {}
+    // CPUの1サイクルごとに呼ばれる (VRC4などのCPUクロックで動くIRQカウンタ用)
+    fn cpu_tick(&mut self) {}
+    // 拡張音源の現在の出力 (APUの出力にミックスされる)
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+    // 現在のネームテーブルのミラーリング
+    fn mirroring(&self) -> Mirroring;
+    // リセットボタン (電源投入時は作り直すので呼ばれない)
+    fn reset(&mut self) {}
+    // $6000-$7FFF のPRG RAM (持っていなければNone)
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+    // CPUアドレスが今割り当てられているPRG ROMの位置 (ROMでなければNone。CDLの記録に使う)
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+    // PPUアドレス ($0000-$1FFF) が今割り当てられているCHR (ROM/RAM) の位置 (デバッガの表示用)
+    fn chr_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+}
 
 const PRG_RAM_ENABLE: u8 = 0;
 const PRG_RAM_DISABLE: u8 = 1;
@@ -59,6 +118,13 @@ pub struct Mapper1 {
     prg_bank: u8,
 }
 
+impl_state!(Mapper1 {
+    sp_reg, shift_reg, shift, ctrl_reg_r0, ctrl_reg_r1, ctrl_reg_r2, ctrl_reg_r3,
+    chr_bank_mode, prg_bank_mode, mirror, r1_chr_ram_bank_4k, r1_prg_ram_bank_8k,
+    r1_prg_rom_bank_256k, r2_chr_ram_bank_4k, r2_prg_ram_bank_8k,
+    r2_prg_rom_bank_256k, prg_ram_enable, prg_mem_type, prg_bank
+});
+
 impl Mapper1 {
     pub fn new() -> Self {
         Mapper1 {
@@ -130,7 +196,7 @@ impl Mapper1 {
                     0 | 1 | _ => (_MEM_SIZE_32K, 0x8000, IGNORING_LOW_BIT_BANK),
                 };
 
-                self.mirror = match (reg_r0 & (_BIT_1 | _BIT_0)) >> 1 {
+                self.mirror = match reg_r0 & (_BIT_1 | _BIT_0) {
                     3 => Mirroring::HORIZONTAL,
                     2 => Mirroring::VERTICAL,
                     1 => Mirroring::ONE_SCREEN_UPPER,
@@ -202,6 +268,12 @@ impl Mapper1 {
     }
 }
 
+impl Default for Mapper1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Mapper4 {
     bank_sel_reg: u8,           // バンクセレクトレジスタ($8000～$9FFEの偶数アドレス)
     bank_data_reg: [u8; 8],     // バンクデータレジスタ  ($8000～$9FFEの奇数アドレス)
@@ -226,6 +298,13 @@ pub struct Mapper4 {
 
 }
 
+impl_state!(Mapper4 {
+    bank_sel_reg, bank_data_reg, bank_reg_sel, chr_bank_mode, prg_bank_mode,
+    chr_a12_inv_mode, mirroring_reg, prg_ram_protect_reg, mirror, prg_ram_cs,
+    prg_ram_wp, irq_latch_reg, irq_reload_reg, irq_reload_flg, irq_di_reg,
+    irq_ei_reg, irq_flg
+});
+
 impl Mapper4 {
     pub fn new() -> Self {
         Mapper4 {
@@ -351,11 +430,19 @@ impl Mapper4 {
     }
 }
 
+impl Default for Mapper4 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Mmc3 {
     pub rom_type: RomType,
     mapper_4: Mapper4,
 }
 
+impl_state!(Mmc3 { mapper_4 });
+
 impl Mmc3 {
     pub fn new() -> Self {
         Mmc3 {
@@ -365,11 +452,19 @@ impl Mmc3 {
     }
 }
 
+impl Default for Mmc3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Mmc1 {
     pub rom_type: RomType,
     mapper_1: Mapper1,
 }
 
+impl_state!(Mmc1 { mapper_1 });
+
 impl Mmc1 {
     pub fn new() -> Self {
         Mmc1 {
@@ -379,6 +474,12 @@ impl Mmc1 {
     }
 }
 
+impl Default for Mmc1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct MapperMMC {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
@@ -388,12 +489,15 @@ pub struct MapperMMC {
     pub is_chr_ram: bool,
     pub is_prg_ram: bool,
     pub rom_type: RomType,
+    pub mirroring: Mirroring,
     bank_select: u8,
 
     pub mmc_1: Mmc1,
     pub mmc_3: Mmc3,
 }
 
+impl_state!(MapperMMC { chr_rom, chr_ram, ext_ram, mirroring, bank_select, mmc_1, mmc_3 });
+
 impl MapperMMC {
     pub fn new() -> Self {
         MapperMMC {
@@ -405,6 +509,7 @@ impl MapperMMC {
             is_prg_ram: false,
             mapper: 0,
             rom_type: RomType::NROM,
+            mirroring: Mirroring::VERTICAL,
             bank_select: 0,
 
             mmc_1: Mmc1::new(),
@@ -422,7 +527,8 @@ impl MapperMMC {
             0x8000..=0xFFFF => {
                 self.mmc_1.mapper_1.shift_reg_proc(addr, data, self.rom_type.clone());
             },
-            _ => panic!("[ERR] MMC1 Write Addr ${:04X} !!!", addr),
+            // $4020-$5FFF には何もない
+            _ => debug!(target: "mapper", "MMC1 Write Addr ${:04X} ignored", addr),
         }
     }
 
@@ -443,7 +549,7 @@ impl MapperMMC {
                 if (self.mmc_3.mapper_4.prg_ram_cs != true) && (self.mmc_3.mapper_4.prg_ram_wp != true) {
                     self.ext_ram[(addr - 0x6000) as usize] = data;
                 }else{
-                    warn!("Mapper 4, WRAM Write Protect")
+                    warn!(target: "mapper", "Mapper 4, WRAM Write Protect")
                 }
             },
             // レジスタ
@@ -475,7 +581,7 @@ impl MapperMMC {
                     self.mmc_3.mapper_4.irq_ei_reg_write(data);
                 }
             },
-            _ => panic!("[Warrning] MMC4 Write Addr ${:04X} !!!", addr),
+            _ => debug!(target: "mapper", "MMC3 Write Addr ${:04X} ignored", addr),
         }
     }
 
@@ -503,6 +609,22 @@ impl MapperMMC {
 
     fn mapper_1_read(&self, addr: u16) -> u8 {
         // TODO :Mapper 1 Read (このマッパーだけ激難すぎｗ)
+        match addr {
+            // 拡張RAM(WRAM)
+            0x6000..=0x7FFF => {
+                self.ext_ram[(addr - 0x6000) as usize]
+            },
+            // PRG-ROM Bank
+            0x8000..=0xFFFF => {
+                self.prg_rom[self.mapper_1_prg_offset(addr)]
+            },
+            // $4020-$5FFF はオープンバス
+            _ => 0,
+        }
+    }
+
+    // $8000-$FFFF のPRG ROMの位置
+    fn mapper_1_prg_offset(&self, addr: u16) -> usize {
         let(bank_len, _bank_addr, bank_ops) = self.mmc_1.mapper_1.prg_bank_mode;
         let mut first_bank_len: u16 = _MEM_SIZE_16K;
         let mut last_bank_len: u16  = _MEM_SIZE_16K;
@@ -514,21 +636,15 @@ impl MapperMMC {
         let bank_max = self.prg_rom.len() / (bank_len as usize);
 
         match addr {
-            // 拡張RAM(WRAM)
-            0x6000..=0x7FFF => {
-                self.ext_ram[(addr - 0x6000) as usize]
-            },
             // PRG-ROM Bank
             0x8000..=0xBFFF => {
-
                 let bank = self.mmc_1.mapper_1.prg_bank;
-                self.prg_rom[(addr as usize - 0x8000 + (first_bank_len as usize) * bank as usize) as usize]
+                addr as usize - 0x8000 + (first_bank_len as usize) * bank as usize
             },
             // PRG-ROM Bank(最後のバンク固定)
-            0xC000..=0xFFFF => {
-                self.prg_rom[(addr as usize - 0xC000 + (last_bank_len as usize) * (bank_max - 1)) as usize]
+            _ => {
+                addr as usize - 0xC000 + (last_bank_len as usize) * (bank_max - 1)
             },
-            _ => panic!("[ERR] MMC1 Read Addr ${:04X} !!!", addr),
         }
     }
 
@@ -548,7 +664,7 @@ impl MapperMMC {
             0xC000..=0xFFFF => {
                 self.prg_rom[(addr as usize - 0xC000 + bank_len * (bank_max - 1)) as usize]
             },
-            _ => panic!("[ERR] MMC2 Read Addr ${:04X} !!!", addr),
+            _ => 0,
         }
     }
 
@@ -576,7 +692,7 @@ impl MapperMMC {
                 0x1800..=0x1BFF => addr - (bank_len * 6) + r4 * bank_len,
                 // R5
                 0x1C00..=0x1FFF => addr - (bank_len * 7) + r5 * bank_len,
-                _ => { warn!("[ERR] Mapper 4 PPU Read Addr ${:04X} !!!", addr); 0 },
+                _ => { warn!(target: "mapper", "[ERR] Mapper 4 PPU Read Addr ${:04X} !!!", addr); 0 },
             }
         } else {
             match addr {
@@ -592,7 +708,7 @@ impl MapperMMC {
                 0x1000..=0x17FF => addr - (bank_len * 4) + r0 * bank_len,
                 // R1 (2KB)
                 0x1800..=0x1FFF => addr - (bank_len * 6) + r1 * bank_len,
-                _ => { warn!("[ERR] Mapper 4 PPU Read Addr ${:04X} !!!", addr); 0 },
+                _ => { warn!(target: "mapper", "[ERR] Mapper 4 PPU Read Addr ${:04X} !!!", addr); 0 },
             }
         }
     }
@@ -644,7 +760,7 @@ impl MapperMMC {
             0xE000..=0xFFFF => { // 最後のバンクに固定
                 self.prg_rom[(addr as usize - (bank_len * 3) + (bank_max - 1) * bank_len) - 0x8000]
             },
-            _ => panic!("[ERR] Mapper 4 Read Addr ${:04X} !!!", addr),
+            _ => 0,
         }
     }
 
@@ -661,7 +777,7 @@ impl MapperMMC {
             0x8000..=0xFFFF => {
                 self.prg_rom[(addr - 0x8000)as usize]
             },
-            _ => panic!("[ERR] Mapper 3 Read Addr ${:04X} !!!", addr),
+            _ => 0,
         }
     }
 
@@ -719,8 +835,86 @@ impl MapperMMC {
     }
 }
 
-#[cfg(test)]
+impl Default for MapperMMC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mapper for MapperMMC {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.read_prg_rom(addr)
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.write(addr, data)
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        match self.mapper {
+            _MAPPER_3 | _MAPPER_4 => self.read_chr_rom(addr),
+            _ => self.chr_rom[addr as usize],
+        }
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        match self.mapper {
+            _MAPPER_3 | _MAPPER_4 => self.write(addr, data),
+            _ => {
+                if self.is_chr_ram {
+                    self.chr_rom[addr as usize] = data;
+                }
+            }
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mapper {
+            _MAPPER_1 => self.mmc_1.mapper_1.mirror.clone(),
+            _MAPPER_4 => self.mmc_3.mapper_4.mirror.clone(),
+            _ => self.mirroring.clone(),
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.ext_ram)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match (self.mapper, addr) {
+            (_MAPPER_1, 0x8000..=0xFFFF) => Some(self.mapper_1_prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match self.mapper {
+            _MAPPER_3 | _MAPPER_4 => None,
+            _ => Some(addr as usize & 0x1FFF),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmc1_mirroring() {
+        // コントロールレジスタのbit1-0 (0: 1画面下位, 1: 1画面上位, 2: 垂直, 3: 水平)
+        let cases = [
+            (0, Mirroring::ONE_SCREEN_LOWER),
+            (1, Mirroring::ONE_SCREEN_UPPER),
+            (2, Mirroring::VERTICAL),
+            (3, Mirroring::HORIZONTAL),
+        ];
+        for (value, mirroring) in cases {
+            let mut mapper = Mapper1::new();
+            mapper.control_reg_write(0x8000, 0x0C | value, RomType::SNROM);
+            assert_eq!(mapper.mirror, mirroring, "{}", value);
+        }
+    }
+
     #[test]
     fn test_ptr_from_vec() {
         let my_vec: Vec<u8> = vec![1, 2, 3, 4, 5];
@@ -744,4 +938,4 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,205 @@
+// HEXエディタのペイン用のメモリの読み書き (CPU空間, PPU空間, OAM, パレットRAM)
+// https://www.nesdev.org/wiki/CPU_memory_map
+// https://www.nesdev.org/wiki/PPU_memory_map
+// 読むときは副作用なし (PPUレジスタを読んでもフラグは変わらない)
+// 書き換えたら EmuEvent::MemoryEdited を出すので、開いている他のペインも読み直せる
+use crate::bus::Mem;
+use crate::cpu::set_in_trace;
+use crate::debugger::read_memory;
+use crate::error::EmuError;
+use crate::events::EmuEvent;
+use crate::nes::Nes;
+use alloc::vec::Vec;
+
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const RAM_MASK: u16 = 0x07FF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemorySpace {
+    Cpu,     // $0000-$FFFF ($8000以降への書き込みはマッパーのレジスタへの書き込みになる)
+    Ppu,     // $0000-$3FFF (CHR, ネームテーブル, パレット)
+    Oam,     // 256バイト (スプライト64個)
+    Palette, // 32バイト ($3F00-$3F1F)
+}
+
+impl MemorySpace {
+    pub fn size(&self) -> usize {
+        match self {
+            MemorySpace::Cpu => 0x10000,
+            MemorySpace::Ppu => 0x4000,
+            MemorySpace::Oam => 256,
+            MemorySpace::Palette => 32,
+        }
+    }
+
+    fn check(&self, start: u16, len: usize) -> Result<(), EmuError> {
+        if (start as usize).checked_add(len).is_none_or(|end| end > self.size()) {
+            return Err(EmuError::InvalidAddress { start, len });
+        }
+        Ok(())
+    }
+}
+
+pub fn read_block(nes: &mut Nes, space: MemorySpace, start: u16, len: usize) -> Result<Vec<u8>, EmuError> {
+    space.check(start, len)?;
+    let addrs = (start..).take(len);
+    Ok(match space {
+        MemorySpace::Cpu => read_memory(nes, start, len)?,
+        MemorySpace::Ppu => addrs.map(|addr| nes.bus().ppu().peek(addr)).collect(),
+        MemorySpace::Oam => nes.bus().ppu().oam_data[start as usize..start as usize + len].to_vec(),
+        MemorySpace::Palette => addrs.map(|addr| nes.bus().ppu().peek(0x3F00 + addr)).collect(),
+    })
+}
+
+// CPU RAMはチートで固定中でも書き換える (固定の値は次のフレームで戻る)
+pub fn write_block(nes: &mut Nes, space: MemorySpace, start: u16, data: &[u8]) -> Result<(), EmuError> {
+    space.check(start, data.len())?;
+    let addrs = (start..).zip(data.iter().copied());
+    match space {
+        MemorySpace::Cpu => {
+            set_in_trace(true);
+            for (addr, value) in addrs {
+                if addr <= RAM_MIRRORS_END {
+                    nes.ram_mut()[(addr & RAM_MASK) as usize] = value;
+                } else {
+                    nes.bus_mut().mem_write(addr, value);
+                }
+            }
+            set_in_trace(false);
+        }
+        MemorySpace::Ppu => addrs.for_each(|(addr, value)| nes.bus_mut().ppu_mut().poke(addr, value)),
+        MemorySpace::Oam => {
+            let start = start as usize;
+            nes.bus_mut().ppu_mut().oam_data[start..start + data.len()].copy_from_slice(data);
+        }
+        MemorySpace::Palette => addrs.for_each(|(addr, value)| nes.bus_mut().ppu_mut().poke(0x3F00 + addr, value)),
+    }
+    nes.bus_mut().events_mut().emit(EmuEvent::MemoryEdited {
+        space,
+        start,
+        len: data.len(),
+    });
+    Ok(())
+}
+
+// CPU RAMの1バイトを固定する (固定チートとして追加し、すぐに書き込む)。戻り値はチートの番号
+pub fn freeze(nes: &mut Nes, addr: u16, value: u8) -> Result<usize, EmuError> {
+    let id = nes
+        .cheats_mut()
+        .add_cheat(addr, value, true)
+        .map_err(|_| EmuError::InvalidAddress { start: addr, len: 1 })?;
+    write_block(nes, MemorySpace::Cpu, addr, &[value])?;
+    Ok(id)
+}
+
+// addr を固定しているチートをすべて外す。外したら true
+pub fn unfreeze(nes: &mut Nes, addr: u16) -> bool {
+    let addr = addr & RAM_MASK;
+    let cheats = nes.cheats_mut();
+    let mut removed = false;
+    while let Some(id) = cheats.cheats().iter().position(|c| c.freeze && c.address == addr) {
+        cheats.remove(id);
+        removed = true;
+    }
+    removed
+}
+
+pub fn is_frozen(nes: &Nes, addr: u16) -> bool {
+    addr <= RAM_MIRRORS_END && nes.cheats().frozen_value(addr & RAM_MASK).is_some()
+}
+
+// 表示中の範囲 (前回から変わったバイトを強調表示するのに使う)
+pub struct MemoryView {
+    pub space: MemorySpace,
+    pub start: u16,
+    data: Vec<u8>,
+}
+
+impl MemoryView {
+    pub fn new(nes: &mut Nes, space: MemorySpace, start: u16, len: usize) -> Result<Self, EmuError> {
+        Ok(MemoryView {
+            space,
+            start,
+            data: read_block(nes, space, start, len)?,
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // 読み直して、前回から値が変わったアドレスを返す
+    pub fn refresh(&mut self, nes: &mut Nes) -> Result<Vec<u16>, EmuError> {
+        let data = read_block(nes, self.space, self.start, self.data.len())?;
+        let changed = (self.start..)
+            .zip(self.data.iter().zip(data.iter()))
+            .filter(|(_, (old, new))| old != new)
+            .map(|(addr, _)| addr)
+            .collect();
+        self.data = data;
+        Ok(changed)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::apu::{AudioConfig, APU};
+    use crate::rom::Rom;
+    use std::sync::{Arc, Mutex};
+
+    // CHR RAMのNROM (ずっとループしているだけ)
+    fn test_nes() -> Nes {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]); // JMP $8000
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        Nes::new(Rom::from_bytes(&raw).unwrap(), APU::headless(&AudioConfig::new())).unwrap()
+    }
+
+    #[test]
+    fn test_memory_view() {
+        let mut nes = test_nes();
+        let edits = Arc::new(Mutex::new(vec![]));
+        let log = edits.clone();
+        nes.add_event_hook(move |event| {
+            if let EmuEvent::MemoryEdited { space, start, len } = event {
+                log.lock().unwrap().push((*space, *start, *len));
+            }
+        });
+
+        // RAMのミラー, CHR RAM, ネームテーブル, パレットのミラー, OAM
+        write_block(&mut nes, MemorySpace::Cpu, 0x0810, &[1, 2]).unwrap();
+        assert_eq!(nes.ram()[0x10..0x12], [1, 2]);
+        write_block(&mut nes, MemorySpace::Ppu, 0x0000, &[0xAA]).unwrap();
+        write_block(&mut nes, MemorySpace::Ppu, 0x2001, &[0x55]).unwrap();
+        assert_eq!(read_block(&mut nes, MemorySpace::Ppu, 0x0000, 2).unwrap(), vec![0xAA, 0]);
+        assert_eq!(read_block(&mut nes, MemorySpace::Ppu, 0x3001, 1).unwrap(), vec![0x55]);
+        write_block(&mut nes, MemorySpace::Palette, 0x10, &[0x21]).unwrap();
+        assert_eq!(read_block(&mut nes, MemorySpace::Palette, 0x00, 1).unwrap(), vec![0x21]);
+        assert_eq!(read_block(&mut nes, MemorySpace::Ppu, 0x3F00, 1).unwrap(), vec![0x21]);
+        write_block(&mut nes, MemorySpace::Oam, 0xFC, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(read_block(&mut nes, MemorySpace::Oam, 0xFE, 2).unwrap(), vec![3, 4]);
+        assert_eq!(edits.lock().unwrap().len(), 5);
+        assert_eq!(edits.lock().unwrap()[0], (MemorySpace::Cpu, 0x0810, 2));
+
+        assert!(write_block(&mut nes, MemorySpace::Oam, 0xFE, &[0; 3]).is_err());
+        assert!(read_block(&mut nes, MemorySpace::Ppu, 0x3FFF, 2).is_err());
+        assert!(read_block(&mut nes, MemorySpace::Palette, 0x01, usize::MAX).is_err());
+
+        // 固定したバイトはゲームが書き換えても戻る
+        let mut view = MemoryView::new(&mut nes, MemorySpace::Cpu, 0x0010, 4).unwrap();
+        freeze(&mut nes, 0x0012, 0x99).unwrap();
+        assert!(is_frozen(&nes, 0x0812));
+        assert!(freeze(&mut nes, 0x6000, 0).is_err());
+        nes.bus_mut().mem_write(0x0012, 0);
+        assert_eq!(nes.ram()[0x12], 0x99);
+        assert_eq!(view.refresh(&mut nes).unwrap(), vec![0x0012]);
+        assert_eq!(view.refresh(&mut nes).unwrap(), vec![]);
+        assert!(unfreeze(&mut nes, 0x0012));
+        assert!(!is_frozen(&nes, 0x0012));
+        assert!(!unfreeze(&mut nes, 0x0012));
+    }
+}
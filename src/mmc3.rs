@@ -0,0 +1,342 @@
+// Mapper 4 (MMC3)
+// https://www.nesdev.org/wiki/MMC3
+// PRG 8KBバンク x4 (うち2つ固定), CHR 2KB x2 + 1KB x4, ミラーリング制御, スキャンラインIRQ
+//
+// Mapper 206 (DxROM / Namco 108)
+// https://www.nesdev.org/wiki/INES_Mapper_206
+// MMC3の前身。バンク切り替えは同じで、ミラーリング固定, IRQ/PRG RAM/モード切り替えなし
+use crate::common::*;
+use crate::mapper::{prg_ram_read, prg_ram_write, Mapper};
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+// チップごとの機能の有無
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mmc3Features {
+    pub mirroring_control: bool, // $A000
+    pub prg_ram: bool,           // $6000-$7FFF, $A001
+    pub irq: bool,               // $C000-$FFFF
+    pub bank_modes: bool,        // $8000 の bit6 (PRGモード), bit7 (CHR A12反転)
+}
+
+impl Mmc3Features {
+    pub const MMC3: Mmc3Features = Mmc3Features {
+        mirroring_control: true,
+        prg_ram: true,
+        irq: true,
+        bank_modes: true,
+    };
+
+    pub const NAMCO_108: Mmc3Features = Mmc3Features {
+        mirroring_control: false,
+        prg_ram: false,
+        irq: false,
+        bank_modes: false,
+    };
+}
+
+pub struct Mmc3 {
+    features: Mmc3Features,
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    prg_ram: Vec<u8>,
+    is_chr_ram: bool,
+    four_screen: bool,
+
+    bank_select: u8,    // $8000 (偶数)
+    banks: [u8; 8],     // $8001 (奇数) R0-R7
+    mirroring: Mirroring,
+    prg_ram_enable: bool,
+    prg_ram_protect: bool,
+
+    irq_latch: u8,      // $C000 (偶数)
+    irq_counter: u8,
+    irq_reload: bool,   // $C001 (奇数)
+    irq_enabled: bool,  // $E000 / $E001
+    irq_pending: bool,
+}
+
+impl_state!(Mmc3 {
+    chr, prg_ram, bank_select, banks, mirroring, prg_ram_enable, prg_ram_protect,
+    irq_latch, irq_counter, irq_reload, irq_enabled, irq_pending
+});
+
+impl Mmc3 {
+    pub fn new(rom: &Rom) -> Self {
+        Mmc3::with_features(rom, Mmc3Features::MMC3)
+    }
+
+    pub fn with_features(rom: &Rom, features: Mmc3Features) -> Self {
+        Mmc3 {
+            features,
+            prg_rom: rom.prg_rom.clone(),
+            chr: rom.chr_rom.clone(),
+            prg_ram: vec![0; rom.header.prg_ram_size],
+            is_chr_ram: rom.is_chr_ram,
+            four_screen: rom.mirroring == Mirroring::FOUR_SCREEN,
+
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.mirroring.clone(),
+            prg_ram_enable: true,
+            prg_ram_protect: false,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn bank_select(&self) -> u8 {
+        if self.features.bank_modes {
+            self.bank_select
+        } else {
+            self.bank_select & 0x07
+        }
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let count = self.prg_bank_count();
+        let second_last = count - 2;
+        let r6 = self.banks[6] as usize;
+        let r7 = self.banks[7] as usize;
+        // 7  bit  0
+        // CPMx xRRR
+        //  +-------- PRG ROM bank mode (0: $8000=R6, $C000=固定; 1: $8000=固定, $C000=R6)
+        let prg_mode = self.bank_select() & _BIT_6 != 0;
+
+        let bank = match (addr, prg_mode) {
+            (0x8000..=0x9FFF, false) => r6,
+            (0x8000..=0x9FFF, true) => second_last,
+            (0xA000..=0xBFFF, _) => r7,
+            (0xC000..=0xDFFF, false) => second_last,
+            (0xC000..=0xDFFF, true) => r6,
+            _ => count - 1,
+        };
+        (bank % count) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        // C-------- CHR A12の反転 (1: 2KBバンクを$1000-$1FFFに置く)
+        let addr = if self.bank_select() & _BIT_7 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        } & 0x1FFF;
+
+        let bank = match addr {
+            0x0000..=0x07FF => (self.banks[0] & 0xFE) as usize + (addr as usize >> 10 & 1),
+            0x0800..=0x0FFF => (self.banks[1] & 0xFE) as usize + (addr as usize >> 10 & 1),
+            0x1000..=0x13FF => self.banks[2] as usize,
+            0x1400..=0x17FF => self.banks[3] as usize,
+            0x1800..=0x1BFF => self.banks[4] as usize,
+            _ => self.banks[5] as usize,
+        };
+        let count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % count) * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.features.prg_ram && self.prg_ram_enable => prg_ram_read(&self.prg_ram, addr),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        let features = self.features;
+        match addr {
+            // 持っていないレジスタへの書き込みは無視
+            0x6000..=0x7FFF if !features.prg_ram => {}
+            0xA000..=0xBFFF if even && !features.mirroring_control => {}
+            0xA000..=0xBFFF if !even && !features.prg_ram => {}
+            0xC000..=0xFFFF if !features.irq => {}
+            0x6000..=0x7FFF if self.prg_ram_enable && !self.prg_ram_protect => {
+                prg_ram_write(&mut self.prg_ram, addr, data)
+            }
+            0x8000..=0x9FFF if even => self.bank_select = data,
+            0x8000..=0x9FFF => self.banks[(self.bank_select & 0x07) as usize] = data,
+            // 4画面のカートリッジはミラーリングを切り替えない
+            0xA000..=0xBFFF if even && self.four_screen => {}
+            0xA000..=0xBFFF if even => {
+                self.mirroring = if data & _BIT_0 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                };
+            }
+            0xA000..=0xBFFF => {
+                // RW-- ----  R: PRG RAM有効, W: 書き込み禁止
+                self.prg_ram_enable = data & _BIT_7 != 0;
+                self.prg_ram_protect = data & _BIT_6 != 0;
+            }
+            0xC000..=0xDFFF if even => self.irq_latch = data,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                // 無効化と同時に保留中のIRQも取り消す
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.is_chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = data;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    // PPU A12 の立ち上がりでカウンタを進める (通常は1スキャンラインに1回)
+    fn ppu_a12_rising(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_addr(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::banked_rom;
+
+    // PRG 8KB x16 (128KB), CHR 1KB x128 (128KB)
+    fn tkrom() -> Rom {
+        banked_rom(4, 16, 128)
+    }
+
+    #[test]
+    fn test_mmc3_prg_banking() {
+        let mut mapper = Mmc3::new(&tkrom());
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xA000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 14);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        // PRGモード1: $8000と$C000が入れ替わる
+        mapper.cpu_write(0x8000, 0x40);
+        assert_eq!(mapper.cpu_read(0x8000), 14);
+        assert_eq!(mapper.cpu_read(0xC000), 3);
+    }
+
+    #[test]
+    fn test_mmc3_chr_banking_and_mirroring() {
+        let mut mapper = Mmc3::new(&tkrom());
+        mapper.cpu_write(0x8000, 0);
+        mapper.cpu_write(0x8001, 9); // 2KBバンクは下位ビット無視
+        mapper.cpu_write(0x8000, 2);
+        mapper.cpu_write(0x8001, 20);
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+        assert_eq!(mapper.ppu_read(0x0400), 9);
+        assert_eq!(mapper.ppu_read(0x1000), 20);
+
+        // CHR A12反転
+        mapper.cpu_write(0x8000, 0x80);
+        assert_eq!(mapper.ppu_read(0x0000), 20);
+        assert_eq!(mapper.ppu_read(0x1400), 9);
+
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::HORIZONTAL);
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::VERTICAL);
+    }
+
+    #[test]
+    fn test_mmc3_scanline_irq() {
+        let mut mapper = Mmc3::new(&tkrom());
+        mapper.cpu_write(0xC000, 3); // latch
+        mapper.cpu_write(0xC001, 0); // reload
+        mapper.cpu_write(0xE001, 0); // enable
+
+        // reload, 2, 1, 0 => 4スキャンライン目でIRQ
+        for _ in 0..3 {
+            mapper.ppu_a12_rising();
+            assert!(!mapper.irq_pending());
+        }
+        mapper.ppu_a12_rising();
+        assert!(mapper.irq_pending());
+
+        // $E000で取り消し
+        mapper.cpu_write(0xE000, 0);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn test_namco108() {
+        let mut mapper = Mmc3::with_features(&tkrom(), Mmc3Features::NAMCO_108);
+        // モードビットは無視される
+        mapper.cpu_write(0x8000, 0xC6);
+        mapper.cpu_write(0x8001, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 14);
+        mapper.cpu_write(0x8000, 0x82);
+        mapper.cpu_write(0x8001, 20);
+        assert_eq!(mapper.ppu_read(0x1000), 20);
+
+        // ミラーリング固定, IRQなし
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::HORIZONTAL);
+        mapper.cpu_write(0xC000, 0);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+        mapper.ppu_a12_rising();
+        assert!(!mapper.irq_pending());
+    }
+}
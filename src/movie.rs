@@ -0,0 +1,207 @@
+// ムービー (フレームごとのコントローラー入力の記録と再生)
+// 開始時点 (電源投入 or 埋め込んだステート) と毎フレームの入力があれば同じプレイを再現できる
+// 電源投入の状態が決定的 (PowerOnState の seed が決まっている) なら、電源投入からは入力だけで再現できる
+//
+// ファイル形式 (リトルエンディアン):
+//   "RSMV" バージョン(u8) CRC32(u32) SHA-1(20) 開始種別(u8) [ステート長(u32) ステート]
+//   フレーム数(u32) 入力(1P 2P 3P 4P の4バイト) x フレーム数
+use crate::gamepad::Button;
+use crate::rom::Rom;
+use crate::state::{State, StateError, StateReader, StateWriter};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"RSMV";
+const VERSION: u8 = 1;
+pub const PLAYER_COUNT: usize = 4;
+
+pub type FrameInput = [Button; PLAYER_COUNT];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    // ステートセーブのデータをそのまま埋め込む
+    SaveState(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_crc32: u32,
+    pub rom_sha1: [u8; 20],
+    pub start: MovieStart,
+    pub frames: Vec<FrameInput>,
+}
+
+impl Movie {
+    pub fn new(rom: &Rom, start: MovieStart) -> Self {
+        Movie {
+            rom_crc32: rom.crc32,
+            rom_sha1: rom.sha1,
+            start,
+            frames: Vec::new(),
+        }
+    }
+
+    // 記録したときと同じROMか
+    pub fn matches(&self, rom: &Rom) -> bool {
+        self.rom_crc32 == rom.crc32 && self.rom_sha1 == rom.sha1
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_bytes(MAGIC);
+        VERSION.save(&mut w);
+        self.rom_crc32.save(&mut w);
+        self.rom_sha1.save(&mut w);
+        match &self.start {
+            MovieStart::PowerOn => 0u8.save(&mut w),
+            MovieStart::SaveState(state) => {
+                1u8.save(&mut w);
+                (state.len() as u32).save(&mut w);
+                w.write_bytes(state);
+            }
+        }
+        (self.frames.len() as u32).save(&mut w);
+        for input in self.frames.iter() {
+            for buttons in input.iter() {
+                buttons.bits().save(&mut w);
+            }
+        }
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut r = StateReader::new(data);
+        if r.read_bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::Mismatch("movie magic"));
+        }
+        let mut version = 0u8;
+        version.load(&mut r)?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let mut movie = Movie {
+            rom_crc32: 0,
+            rom_sha1: [0; 20],
+            start: MovieStart::PowerOn,
+            frames: Vec::new(),
+        };
+        movie.rom_crc32.load(&mut r)?;
+        movie.rom_sha1.load(&mut r)?;
+        let (mut start, mut len) = (0u8, 0u32);
+        start.load(&mut r)?;
+        movie.start = match start {
+            0 => MovieStart::PowerOn,
+            1 => {
+                len.load(&mut r)?;
+                MovieStart::SaveState(r.read_bytes(len as usize)?.to_vec())
+            }
+            _ => return Err(StateError::Mismatch("movie start")),
+        };
+        len.load(&mut r)?;
+        for _ in 0..len {
+            let bytes = r.read_bytes(PLAYER_COUNT)?;
+            let mut input = [Button::empty(); PLAYER_COUNT];
+            for (buttons, bits) in input.iter_mut().zip(bytes) {
+                *buttons = Button::from_bits_truncate(*bits);
+            }
+            movie.frames.push(input);
+        }
+        if !r.is_empty() {
+            return Err(StateError::Mismatch("trailing data"));
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_bytes()).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        Movie::from_bytes(&data).map_err(|e| e.to_string())
+    }
+}
+
+pub struct MovieRecorder {
+    pub movie: Movie,
+}
+
+impl MovieRecorder {
+    pub fn new(rom: &Rom, start: MovieStart) -> Self {
+        MovieRecorder {
+            movie: Movie::new(rom, start),
+        }
+    }
+
+    // 1フレーム分の入力を追加
+    pub fn record(&mut self, input: FrameInput) {
+        self.movie.frames.push(input);
+    }
+}
+
+pub struct MoviePlayer {
+    pub movie: Movie,
+    frame: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer { movie, frame: 0 }
+    }
+
+    // 最後まで再生したらNone
+    pub fn next_frame(&mut self) -> Option<FrameInput> {
+        let input = self.movie.frames.get(self.frame).copied();
+        if input.is_some() {
+            self.frame += 1;
+        }
+        input
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rom() -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        raw.resize(16 + 0x4000 + 0x2000, 0);
+        Rom::from_bytes(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_movie_record_and_play() {
+        let rom = test_rom();
+        let mut recorder = MovieRecorder::new(&rom, MovieStart::SaveState(vec![1, 2, 3]));
+        recorder.record([Button::BUTTON_A, Button::empty(), Button::empty(), Button::START]);
+        recorder.record([Button::empty(), Button::LEFT | Button::UP, Button::empty(), Button::empty()]);
+
+        let movie = Movie::from_bytes(&recorder.movie.to_bytes()).unwrap();
+        assert_eq!(movie, recorder.movie);
+        assert!(movie.matches(&rom));
+
+        let mut player = MoviePlayer::new(movie);
+        assert_eq!(player.next_frame().unwrap()[3], Button::START);
+        assert_eq!(player.next_frame().unwrap()[1], Button::LEFT | Button::UP);
+        assert!(player.is_finished());
+        assert_eq!(player.next_frame(), None);
+
+        assert_eq!(Movie::from_bytes(b"XXXX"), Err(StateError::Mismatch("movie magic")));
+        let bytes = recorder.movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&bytes[..bytes.len() - 1]), Err(StateError::UnexpectedEof));
+        let mut other = bytes.clone();
+        other.push(0);
+        assert_eq!(Movie::from_bytes(&other), Err(StateError::Mismatch("trailing data")));
+        other = bytes.clone();
+        other[4] = VERSION + 1;
+        assert_eq!(Movie::from_bytes(&other), Err(StateError::UnsupportedVersion(VERSION + 1)));
+    }
+}
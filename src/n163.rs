@@ -0,0 +1,318 @@
+// Namco 163 (Mapper 19)
+// https://www.nesdev.org/wiki/Namco_163
+// https://www.nesdev.org/wiki/Namco_163_audio
+// 内部RAM 128バイト (波形テーブル兼サウンドレジスタ), CPUクロックの15ビットIRQカウンタ
+use crate::common::*;
+use crate::cpu::in_trace;
+use crate::mapper::{prg_ram_read, prg_ram_write, Mapper};
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+const INTERNAL_RAM_SIZE: usize = 128;
+
+// 1チャンネルの更新に掛かるCPUサイクル
+const N163_CHANNEL_CYCLES: u8 = 15;
+// 出力 (-8..7) x 音量 (0..15) をAPUのチャンネルと同程度に揃える
+const N163_VOLUME: f32 = 0.25 / 120.0;
+
+pub struct N163 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    prg_ram: Vec<u8>,
+    is_chr_ram: bool,
+    mirroring: Mirroring,
+
+    prg_banks: [u8; 3],      // $E000, $E800, $F000
+    chr_banks: [u8; 8],      // $8000-$BFFF
+    nametable_banks: [u8; 4], // $C000-$DFFF
+
+    ram: [u8; INTERNAL_RAM_SIZE],
+    ram_addr: u8,            // $F800
+    ram_auto_increment: bool,
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    sound_disable: bool,
+    sound_cycles: u8,
+    sound_channel: usize,
+    sound_outputs: [f32; 8],
+}
+
+impl_state!(N163 {
+    chr, prg_ram, mirroring, prg_banks, chr_banks, nametable_banks, ram, ram_addr,
+    ram_auto_increment, irq_counter, irq_enabled, irq_pending, sound_disable,
+    sound_cycles, sound_channel, sound_outputs
+});
+
+impl N163 {
+    pub fn new(rom: &Rom) -> Self {
+        N163 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: rom.chr_rom.clone(),
+            prg_ram: vec![0; rom.header.prg_ram_size],
+            is_chr_ram: rom.is_chr_ram,
+            mirroring: rom.mirroring.clone(),
+
+            prg_banks: [0, 1, 2],
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0, 0xE0, 0xE1, 0xE1],
+
+            ram: [0; INTERNAL_RAM_SIZE],
+            ram_addr: 0,
+            ram_auto_increment: false,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+
+            sound_disable: false,
+            sound_cycles: 0,
+            sound_channel: 0,
+            sound_outputs: [0.0; 8],
+        }
+    }
+
+    fn prg_addr(&self, bank: usize, addr: u16) -> usize {
+        let count = self.prg_rom.len() / PRG_BANK_SIZE;
+        (bank % count) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[(addr as usize >> 10) & 0x07] as usize;
+        let count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % count) * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn read_ram(&mut self) -> u8 {
+        let value = self.ram[self.ram_addr as usize];
+        // デバッガから読んだときはアドレスを進めない
+        if self.ram_auto_increment && !in_trace() {
+            self.ram_addr = (self.ram_addr + 1) & 0x7F;
+        }
+        value
+    }
+
+    fn write_ram(&mut self, data: u8) {
+        self.ram[self.ram_addr as usize] = data;
+        if self.ram_auto_increment {
+            self.ram_addr = (self.ram_addr + 1) & 0x7F;
+        }
+    }
+
+    // ネームテーブル選択 ($E0以上で本体のVRAM) をミラーリングに置き換える
+    // (CHR ROMをネームテーブルに使うのは未対応)
+    fn update_mirroring(&mut self) {
+        let pages: Vec<u8> = self.nametable_banks.iter().map(|b| b & 0x01).collect();
+        self.mirroring = match pages[..] {
+            [0, 1, 0, 1] => Mirroring::VERTICAL,
+            [0, 0, 1, 1] => Mirroring::HORIZONTAL,
+            [0, 0, 0, 0] => Mirroring::ONE_SCREEN_LOWER,
+            [1, 1, 1, 1] => Mirroring::ONE_SCREEN_UPPER,
+            _ => self.mirroring.clone(),
+        };
+    }
+
+    // 有効なチャンネル数 ($7F の bit4-6 + 1)
+    fn sound_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    // 1チャンネル分の位相を進めて出力を更新する
+    // チャンネル7のレジスタが$78-$7F、チャンネル6が$70-$77 ... と後ろから並ぶ
+    fn update_sound_channel(&mut self, ch: usize) {
+        let base = 0x78 - ch * 8;
+        let r = &self.ram[base..base + 8];
+        let freq = r[0] as u32 | (r[2] as u32) << 8 | ((r[4] & 0x03) as u32) << 16;
+        let mut phase = r[1] as u32 | (r[3] as u32) << 8 | (r[5] as u32) << 16;
+        let length = (256 - (r[4] & 0xFC) as u32) << 16;
+        let wave_addr = r[6] as u32;
+        let volume = (r[7] & 0x0F) as f32;
+
+        phase = (phase + freq) % length;
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        // 波形は4ビット単位 (下位ニブルが先)
+        let nibble_addr = ((wave_addr + (phase >> 16)) & 0xFF) as usize;
+        let byte = self.ram[nibble_addr >> 1];
+        let sample = if nibble_addr & 1 == 0 { byte & 0x0F } else { byte >> 4 };
+        self.sound_outputs[ch] = (sample as f32 - 8.0) * volume;
+    }
+}
+
+impl Mapper for N163 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => self.read_ram(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | if self.irq_enabled { 0x80 } else { 0 },
+            0x6000..=0x7FFF => prg_ram_read(&self.prg_ram, addr),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_rom_offset(addr).unwrap()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.write_ram(data),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((data & 0x7F) as u16) << 8;
+                self.irq_enabled = data & _BIT_7 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF => prg_ram_write(&mut self.prg_ram, addr, data),
+            0x8000..=0xBFFF => self.chr_banks[((addr - 0x8000) >> 11) as usize] = data,
+            0xC000..=0xDFFF => {
+                self.nametable_banks[((addr - 0xC000) >> 11) as usize] = data;
+                self.update_mirroring();
+            }
+            0xE000..=0xE7FF => {
+                // -SPP PPPP  S: サウンド無効
+                self.prg_banks[0] = data & 0x3F;
+                self.sound_disable = data & _BIT_6 != 0;
+            }
+            0xE800..=0xEFFF => self.prg_banks[1] = data & 0x3F,
+            0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+            0xF800..=0xFFFF => {
+                // IAAA AAAA  I: 自動インクリメント
+                self.ram_addr = data & 0x7F;
+                self.ram_auto_increment = data & _BIT_7 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.is_chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = data;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        // 0x7FFFに達したら止まる
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        if self.sound_disable {
+            return;
+        }
+        self.sound_cycles += 1;
+        if self.sound_cycles >= N163_CHANNEL_CYCLES {
+            self.sound_cycles = 0;
+            // チャンネル7から順に、有効な数だけ巡回する
+            let channels = self.sound_channels();
+            self.sound_channel = (self.sound_channel + 1) % channels;
+            self.update_sound_channel(7 - self.sound_channel);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.sound_disable {
+            return 0.0;
+        }
+        // 実機は時分割で出力するので、平均をとる
+        let channels = self.sound_channels();
+        let sum: f32 = self.sound_outputs[8 - channels..].iter().sum();
+        sum / channels as f32 * N163_VOLUME
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xDFFF => {
+                let slot = (addr - 0x8000) as usize / PRG_BANK_SIZE;
+                Some(self.prg_addr(self.prg_banks[slot] as usize, addr))
+            }
+            0xE000..=0xFFFF => {
+                let last = self.prg_rom.len() / PRG_BANK_SIZE - 1;
+                Some(self.prg_addr(last, addr))
+            }
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::banked_rom;
+
+    // PRG 8KB x16, CHR 1KB x16
+    fn n163() -> Rom {
+        banked_rom(19, 16, 16)
+    }
+
+    #[test]
+    fn test_n163_banking() {
+        let mut mapper = N163::new(&n163());
+        mapper.cpu_write(0xE000, 4);
+        mapper.cpu_write(0xE800, 5);
+        mapper.cpu_write(0xF000, 6);
+        mapper.cpu_write(0x9800, 11);
+        assert_eq!(mapper.cpu_read(0x8000), 4);
+        assert_eq!(mapper.cpu_read(0xA000), 5);
+        assert_eq!(mapper.cpu_read(0xC000), 6);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+        assert_eq!(mapper.ppu_read(0x0C00), 11);
+
+        for (i, addr) in [0xC000, 0xC800, 0xD000, 0xD800].iter().enumerate() {
+            mapper.cpu_write(*addr, 0xE0 | (i as u8 >> 1));
+        }
+        assert_eq!(mapper.mirroring(), Mirroring::HORIZONTAL);
+    }
+
+    #[test]
+    fn test_n163_internal_ram_and_irq() {
+        let mut mapper = N163::new(&n163());
+        mapper.cpu_write(0xF800, 0x80 | 0x10);
+        mapper.cpu_write(0x4800, 0xAA);
+        mapper.cpu_write(0x4800, 0xBB);
+        mapper.cpu_write(0xF800, 0x80 | 0x10);
+        assert_eq!(mapper.cpu_read(0x4800), 0xAA);
+        assert_eq!(mapper.cpu_read(0x4800), 0xBB);
+
+        mapper.cpu_write(0x5000, 0xFD);
+        mapper.cpu_write(0x5800, 0xFF);
+        mapper.cpu_tick();
+        assert!(!mapper.irq_pending());
+        mapper.cpu_tick();
+        assert!(mapper.irq_pending());
+        // 止まっている
+        mapper.cpu_tick();
+        assert_eq!(mapper.cpu_read(0x5000), 0xFF);
+    }
+}
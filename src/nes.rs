@@ -0,0 +1,754 @@
+// ファミコン本体 (CPU, バス, PPU, APU, カートリッジ, コントローラーをまとめたもの)
+// https://www.nesdev.org/wiki/CPU_ALL
+// フロントエンドは run_frame で1フレームずつ進めて、返ってきた画面を表示する
+// 状態はすべて本体ごとに持つので、1つのプロセスで何台でも同時に動かせる
+use crate::apu::{AudioConfig, APU};
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::call_stack::CallStack;
+use crate::cdl::CodeDataLogger;
+use crate::cheat::CheatManager;
+use crate::controller::ControllerPorts;
+use crate::cpu::{trace_if_enabled, CPU};
+use crate::error::EmuError;
+use crate::event_viewer::EventLog;
+use crate::events::{EmuEvent, HookId};
+use crate::frame::Frame;
+use crate::gamepad::Button;
+use crate::power_on::PowerOnState;
+use crate::region::Region;
+use crate::render;
+use crate::rom::Rom;
+use crate::snapshot::DebugSnapshot;
+use crate::state::{State, StateError, StateReader, StateWriter};
+use crate::symbols::SymbolTable;
+use crate::sync::MutexGuard;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// ステートセーブの形式 ("RSST" バージョン CRC32 本体)
+// 中身の並びを変えたらバージョンを上げる
+const STATE_MAGIC: &[u8; 4] = b"RSST";
+const STATE_VERSION: u8 = 1;
+
+pub struct Nes {
+    cpu: CPU,
+    frame: Frame,
+    paused: bool,
+}
+
+impl Nes {
+    // 画面の表示や入力はフロントエンドが run_frame の合間に行う
+    // ホスト側のコールバックは持たないので、本体ごと別のスレッドに渡せる
+    pub fn new(rom: Rom, apu: APU) -> Result<Self, EmuError> {
+        let mut cpu = CPU::new(Bus::new(rom, apu)?);
+        cpu.reset();
+        Ok(Nes {
+            cpu,
+            frame: Frame::new(),
+            paused: false,
+        })
+    }
+
+    // メモリ上のiNESイメージから、音を出さない本体を作る (テストやツール用)
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, EmuError> {
+        Nes::new(Rom::from_bytes(raw)?, APU::headless(&AudioConfig::new()))
+    }
+
+    // 次のVBlankまで実行して、そのフレームの画面を返す
+    // 一時停止中は何もせず、前の画面を返す
+    pub fn run_frame(&mut self) -> &Frame {
+        if self.paused {
+            return &self.frame;
+        }
+        self.advance_frame()
+    }
+
+    // InputPoll で決めたタイミングで poll_input を呼んでコントローラーの状態を更新しながら1フレーム進める
+    // 画面は作り直さないので、必要なら render_frame を呼ぶ
+    pub fn run_frame_with_input<F>(&mut self, mut poll_input: F)
+    where
+        F: FnMut(&mut ControllerPorts),
+    {
+        let frame = self.frame_count();
+        while self.frame_count() == frame {
+            self.cpu.step_with_callback(trace_if_enabled);
+            if self.cpu.bus.take_input_request() {
+                poll_input(self.cpu.bus.controller_ports());
+            }
+        }
+    }
+
+    // 一時停止中でもちょうど1フレームだけ進める (コマ送り)
+    pub fn advance_frame(&mut self) -> &Frame {
+        self.cpu.run_frame();
+        self.render_frame()
+    }
+
+    // cpu_mut() から直接進めたあとに画面を作り直す
+    pub fn render_frame(&mut self) -> &Frame {
+        render::render(self.cpu.bus.ppu(), &mut self.frame);
+        &self.frame
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // 少なくとも cycles だけCPUを進める (命令の途中では止まらない)
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<(), EmuError> {
+        let start = self.cpu.bus.cycles();
+        while self.cpu.bus.cycles().wrapping_sub(start) < cycles {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // 次のVBlankの始まりまで進める (画面は作り直さない)
+    pub fn run_until_vblank(&mut self) -> Result<(), EmuError> {
+        let frame = self.frame_count();
+        while self.frame_count() == frame {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // PCが addr の命令に来るまで進める (すでに addr にいても少なくとも1命令は実行する)
+    // 割り込みは次の命令と一緒に実行するので、ハンドラの先頭の番地では止まらない
+    // たどり着かなければ返ってこないので、JAM以外では止まらない点に注意
+    pub fn run_until_pc(&mut self, addr: u16) -> Result<(), EmuError> {
+        loop {
+            self.step()?;
+            if self.cpu.program_counter == addr {
+                return Ok(());
+            }
+        }
+    }
+
+    // 少なくとも lines だけスキャンラインを進める (命令の途中では止まらない)
+    pub fn run_scanlines(&mut self, lines: usize) -> Result<(), EmuError> {
+        let start = self.scanline_count();
+        while self.scanline_count().saturating_sub(start) < lines {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    // 電源を入れてからのライン数 (フレーム番号はVBlankの始まりで増えるので、そこを起点に数える)
+    fn scanline_count(&self) -> usize {
+        let ppu = self.cpu.bus.ppu();
+        let lines = ppu.scanlines();
+        let line = (ppu.scanline() + lines - ppu.vblank_scanline()) % lines;
+        self.frame_count() * lines + line
+    }
+
+    // 1命令だけ実行する (JAMで止まったらエラー。run_frame は実機と同じく止まったまま進む)
+    pub fn step(&mut self) -> Result<(), EmuError> {
+        self.cpu.step();
+        if self.cpu.is_jammed() {
+            return Err(EmuError::CpuJam(self.cpu.program_counter));
+        }
+        Ok(())
+    }
+
+    // 最後に run_frame で描いた画面
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    // 描いた画面に重ねて描く場合 (次の run_frame で描き直される)
+    pub fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.cpu.bus.frame_count()
+    }
+
+    // 標準ではNES 2.0ヘッダのリージョン (なければNTSC)。ファイル名などから判断した場合はここで上書きする
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.bus.set_region(region);
+    }
+
+    pub fn region(&self) -> Region {
+        self.cpu.bus.region()
+    }
+
+    // RAMチート (add_cheat, toggle, remove)
+    pub fn cheats(&self) -> &CheatManager {
+        self.cpu.bus.cheats()
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut CheatManager {
+        self.cpu.bus.cheats_mut()
+    }
+
+    // Code/Data Logger (data に以前の .cdl を渡すとその続きから記録する)
+    pub fn start_cdl(&mut self, data: Option<&[u8]>) -> Result<(), String> {
+        let (prg_len, chr_len) = {
+            let cartridge = self.cartridge();
+            (cartridge.prg_rom_len(), cartridge.chr_rom_len())
+        };
+        let cdl = match data {
+            Some(data) => CodeDataLogger::from_bytes(data, prg_len, chr_len)?,
+            None => CodeDataLogger::new(prg_len, chr_len),
+        };
+        self.cpu.bus.set_cdl(Some(cdl));
+        Ok(())
+    }
+
+    // 記録をやめてログを返す
+    pub fn stop_cdl(&mut self) -> Option<CodeDataLogger> {
+        self.cpu.bus.set_cdl(None)
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cpu.bus.cdl()
+    }
+
+    // ラベル (逆アセンブル, トレース, デバッガで使う)
+    pub fn symbols(&self) -> &SymbolTable {
+        self.cpu.bus.symbols()
+    }
+
+    pub fn symbols_mut(&mut self) -> &mut SymbolTable {
+        self.cpu.bus.symbols_mut()
+    }
+
+    // イベントビューア (レジスタの書き込み, NMI/IRQ をスキャンライン/ドットと一緒に記録する)
+    pub fn start_event_log(&mut self) {
+        self.cpu.bus.set_event_log(Some(EventLog::new()));
+    }
+
+    pub fn stop_event_log(&mut self) -> Option<EventLog> {
+        self.cpu.bus.set_event_log(None)
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.cpu.bus.event_log()
+    }
+
+    // コールスタック (JSR/割り込みで積んだ戻り先を記録する。始める前に積まれたものは分からない)
+    pub fn start_call_stack(&mut self) {
+        self.cpu.bus.set_call_stack(Some(CallStack::new()));
+    }
+
+    pub fn stop_call_stack(&mut self) -> Option<CallStack> {
+        self.cpu.bus.set_call_stack(None)
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.cpu.bus.call_stack()
+    }
+
+    pub fn set_controller_state(&mut self, player: usize, buttons: Button) {
+        self.cpu.bus.set_controller_state(player, buttons);
+    }
+
+    pub fn controller_ports(&mut self) -> &mut ControllerPorts {
+        self.cpu.bus.controller_ports()
+    }
+
+    pub fn ram(&self) -> &[u8; 2048] {
+        self.cpu.bus.ram()
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8; 2048] {
+        self.cpu.bus.ram_mut()
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        self.cpu.bus.apu_mut()
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.cpu.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.cpu.bus
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    // CPU, PPU, APU, マッパーの状態のコピー (デバッガの表示用)
+    pub fn snapshot(&self) -> DebugSnapshot {
+        DebugSnapshot {
+            cpu: self.cpu.snapshot(),
+            ppu: self.cpu.bus.ppu().snapshot(),
+            apu: self.cpu.bus.apu().snapshot(),
+            mapper: self.cartridge().snapshot(),
+        }
+    }
+
+    // SRAMの読み書きなど (本体が手放されるときにSRAMは書き出される)
+    pub fn cartridge(&self) -> MutexGuard<'_, Cartridge> {
+        self.cpu.bus.cartridge()
+    }
+
+    // フレームの終わり, NMI, IRQ, ステートの作成, SRAMの書き換えで hook が呼ばれる
+    pub fn add_event_hook<F>(&mut self, hook: F) -> HookId
+    where
+        F: FnMut(&EmuEvent) + Send + 'static,
+    {
+        self.cpu.bus.events_mut().add(hook)
+    }
+
+    pub fn remove_event_hook(&mut self, id: HookId) -> bool {
+        self.cpu.bus.events_mut().remove(id)
+    }
+
+    // CPU, PPU, APU, RAM, コントローラー, マッパーの状態をまとめて保存する
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let state = self.write_state();
+        self.cpu.bus.events_mut().emit(EmuEvent::SaveStateCreated(state.len()));
+        state
+    }
+
+    fn write_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_bytes(STATE_MAGIC);
+        STATE_VERSION.save(&mut w);
+        self.cartridge().rom_crc32().save(&mut w);
+        self.cpu.save(&mut w);
+        self.cartridge().save_state(&mut w);
+        w.into_bytes()
+    }
+
+    // 別のROMや古いバージョンのステートならエラーを返し、何も変えない
+    // 途中で壊れていた場合も読み込む前の状態に戻す
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        if r.read_bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError::Mismatch("state magic"));
+        }
+        let (mut version, mut crc32) = (0u8, 0u32);
+        version.load(&mut r)?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        crc32.load(&mut r)?;
+        if crc32 != self.cartridge().rom_crc32() {
+            return Err(StateError::Mismatch("rom"));
+        }
+
+        let backup = self.write_state();
+        let result = self.load_body(&mut r);
+        if result.is_err() {
+            let mut r = StateReader::new(&backup[STATE_MAGIC.len() + 5..]);
+            self.load_body(&mut r).expect("[ERR] restore state");
+        }
+        result
+    }
+
+    fn load_body(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        State::load(&mut self.cpu, r)?;
+        self.cartridge().load_state(r)?;
+        if !r.is_empty() {
+            return Err(StateError::Mismatch("trailing data"));
+        }
+        Ok(())
+    }
+
+    pub fn soft_reset(&mut self) {
+        self.cpu.soft_reset();
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.cpu.power_cycle();
+    }
+
+    // 電源投入時の状態の決め方 (次の power_cycle / load_new_rom から)
+    pub fn set_power_on_state(&mut self, state: PowerOnState) {
+        self.cpu.bus.set_power_on_state(state);
+    }
+
+    pub fn power_on_state(&self) -> &PowerOnState {
+        self.cpu.bus.power_on_state()
+    }
+
+    pub fn load_new_rom(&mut self, rom: Rom) -> Result<(), EmuError> {
+        Ok(self.cpu.load_new_rom(rom)?)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    // NMIを有効にして無限ループするだけのNROM
+    fn test_rom() -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        let program = [
+            0xA9, 0x80, // LDA #$80
+            0x8D, 0x00, 0x20, // STA $2000
+            0xE6, 0x10, // loop: INC $10
+            0x4C, 0x05, 0x80, // JMP loop
+            0x40, // nmi: RTI
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFA..0x3FFC].copy_from_slice(&[0x0A, 0x80]); // NMI
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]); // RESET
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        Rom::from_bytes(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_nes_state() {
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        nes.run_frame();
+        let state = nes.save_state();
+        let (pc, counter) = (nes.cpu().program_counter, nes.ram()[0x10]);
+
+        nes.run_frame();
+        assert_ne!(nes.ram()[0x10], counter);
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu().program_counter, pc);
+        assert_eq!(nes.ram()[0x10], counter);
+        assert_eq!(nes.frame_count(), 1);
+
+        // 壊れたステートは読み込まず、元の状態のまま
+        nes.run_frame();
+        let counter = nes.ram()[0x10];
+        assert_eq!(nes.load_state(&state[..state.len() - 10]), Err(StateError::UnexpectedEof));
+        assert_eq!(nes.ram()[0x10], counter);
+
+        let mut other = state.clone();
+        other[4] = STATE_VERSION + 1;
+        assert_eq!(nes.load_state(&other), Err(StateError::UnsupportedVersion(STATE_VERSION + 1)));
+        let mut other = state.clone();
+        other[5] ^= 0xFF;
+        assert_eq!(nes.load_state(&other), Err(StateError::Mismatch("rom")));
+    }
+
+    #[test]
+    fn test_nes_multiple_instances() {
+        // CHRだけ違うROMを2台で同時に動かす
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        raw.resize(16, 0);
+        let rom = test_rom();
+        raw.extend(&rom.prg_rom);
+        raw.extend(vec![0x55; 0x2000]);
+        let mut a = Nes::new(rom, APU::headless(&AudioConfig::new())).unwrap();
+        let mut b = Nes::from_bytes(&raw).unwrap();
+        a.run_frame();
+        b.run_frame();
+        b.run_frame();
+        assert_eq!((a.frame_count(), b.frame_count()), (1, 2));
+        assert_eq!(a.bus().ppu().pattern_tables()[0], 0x00);
+        assert_eq!(b.bus().ppu().pattern_tables()[0], 0x55);
+        assert_eq!(b.load_state(&a.save_state()), Err(StateError::Mismatch("rom")));
+    }
+
+    #[test]
+    fn test_nes_cdl() {
+        use crate::cdl::{CDL_CODE, CDL_DATA};
+
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        assert!(nes.cdl().is_none());
+        nes.start_cdl(None).unwrap();
+        nes.run_frame();
+        nes.run_frame();
+        let cdl = nes.stop_cdl().unwrap();
+        // 命令のバイトはコード ($8000 -> AA=0)、オペランドはデータにならない
+        assert!(cdl.prg()[..11].iter().all(|&b| b == CDL_CODE));
+        // NMIのベクタはデータ ($E000 -> AA=3)
+        assert_eq!(cdl.prg()[0x3FFA], CDL_DATA | 0x0C);
+        assert_eq!(cdl.prg()[0x3FFC], 0);
+        assert_eq!(cdl.summary().code, 11);
+
+        nes.start_cdl(Some(&cdl.to_bytes())).unwrap();
+        assert_eq!(nes.cdl().unwrap().prg(), cdl.prg());
+        assert!(nes.start_cdl(Some(&[0; 16])).is_err());
+    }
+
+    #[test]
+    fn test_nes_event_log() {
+        use crate::event_viewer::{EventKind, ScanlineKind};
+
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        nes.start_event_log();
+        nes.run_frame();
+        nes.run_frame();
+        // run_frame はVBlankで止まるので、1フレーム目 (ライン0からプリレンダまで) が終わって、次のNMIまで進んでいる
+        let log = nes.event_log().unwrap();
+        let events = log.frame_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, EventKind::PpuWrite { addr: 0x2000, value: 0x80 });
+        assert_eq!(events[0].scanline, 0);
+        assert_eq!(events[1].kind, EventKind::Nmi);
+        assert_eq!(events[1].scanline as usize, nes.bus().ppu().vblank_scanline());
+        assert!(events[0].cpu_cycle < events[1].cpu_cycle);
+        assert_eq!(log.pending_events().len(), 1);
+
+        // スキャンラインごとの内訳 (描画は無効なので表示ラインは Idle。NMIのハンドラは RTI だけ)
+        let lines = log.frame_scanlines();
+        let vblank = nes.bus().ppu().vblank_scanline();
+        assert_eq!(lines.len(), nes.bus().ppu().scanlines());
+        assert_eq!(lines[0].kind, ScanlineKind::Idle);
+        assert_eq!(lines[vblank].kind, ScanlineKind::VBlank);
+        assert_eq!(lines[lines.len() - 1].kind, ScanlineKind::PreRender);
+        let total: usize = lines.iter().map(|l| l.cpu_cycles).sum();
+        assert!(total.abs_diff(29781) < 16, "{}", total);
+        assert_eq!(lines.iter().map(|l| l.interrupt_cycles).sum::<usize>(), 2 + 6);
+        assert!(lines.iter().all(|l| l.dma_cycles == 0));
+        assert!(nes.stop_event_log().is_some());
+        assert!(nes.event_log().is_none());
+    }
+
+    #[test]
+    fn test_nes_oam_dma() {
+        let source = "
+            LDA #$02
+            STA $4014
+        loop:
+            JMP loop
+        ";
+        let mut nes = crate::test_rom::TestRom::new().asm(0x8000, source).nes().unwrap();
+        nes.step().unwrap();
+        nes.start_event_log();
+        let dot = |nes: &Nes| nes.bus().ppu().scanline() * 341 + nes.bus().ppu().dot();
+        let (cycles, dots) = (nes.bus().cycles(), dot(&nes));
+        nes.step().unwrap();
+        // STA の4サイクル + DMAの513 (奇数サイクルなら514) サイクル。PPUは1サイクルに3ドット進む
+        let elapsed = nes.bus().cycles() - cycles;
+        assert!(elapsed == 4 + 513 || elapsed == 4 + 514, "{}", elapsed);
+        assert_eq!(dot(&nes) - dots, elapsed * 3);
+        nes.run_frame();
+        nes.run_frame();
+        let lines = nes.event_log().unwrap().frame_scanlines();
+        assert_eq!(lines.iter().map(|l| l.dma_cycles).sum::<usize>(), elapsed - 4);
+    }
+
+    #[test]
+    fn test_nes_send() {
+        // 別のスレッドでエミュレーションを動かして、画面だけ受け取る
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            for _ in 0..3 {
+                sender.send(nes.run_frame().data.clone()).unwrap();
+            }
+            nes
+        });
+        assert_eq!(receiver.iter().count(), 3);
+        assert_eq!(handle.join().unwrap().frame_count(), 3);
+    }
+
+    #[test]
+    fn test_nes_event_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        let log = events.clone();
+        let id = nes.add_event_hook(move |event| log.lock().unwrap().push(*event));
+        nes.run_frame();
+        let len = nes.save_state().len();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![EmuEvent::FrameComplete(1), EmuEvent::Nmi, EmuEvent::SaveStateCreated(len)]
+        );
+        assert!(nes.remove_event_hook(id));
+        nes.run_frame();
+        assert_eq!(events.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_nes_input_poll() {
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let mut polls = 0;
+        nes.run_frame_with_input(|_| polls += 1);
+        nes.run_frame_with_input(|_| polls += 1);
+        assert_eq!(polls, 2);
+    }
+
+    #[test]
+    fn test_nes_jam() {
+        // リセット直後に JAM ($02)
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0x02; 0x4000];
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        let mut nes = Nes::new(Rom::from_bytes(&raw).unwrap(), APU::headless(&AudioConfig::new())).unwrap();
+        assert!(matches!(nes.step(), Err(EmuError::CpuJam(0x8000))));
+        assert!(matches!(nes.run_cycles(100), Err(EmuError::CpuJam(0x8000))));
+        nes.soft_reset();
+        assert!(!nes.cpu().is_jammed());
+    }
+
+    #[test]
+    fn test_nes_run_frame() {
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        nes.run_frame();
+        assert_eq!(nes.frame_count(), 1);
+        nes.run_frame();
+        assert_eq!(nes.frame_count(), 2);
+        assert_ne!(nes.ram()[0x10], 0);
+
+        let pc = nes.cpu().program_counter;
+        nes.run_cycles(100).unwrap();
+        assert!(nes.cpu().program_counter >= 0x8005 && pc >= 0x8005);
+
+        // 一時停止中は進まず、コマ送りでだけ進む
+        nes.pause();
+        let frames = nes.frame_count();
+        nes.run_frame();
+        assert_eq!(nes.frame_count(), frames);
+        nes.advance_frame();
+        assert_eq!(nes.frame_count(), frames + 1);
+        nes.resume();
+        nes.run_frame();
+        assert_eq!(nes.frame_count(), frames + 2);
+
+        let mut samples = vec![];
+        nes.apu_mut().drain_samples(&mut samples);
+        assert!(!samples.is_empty());
+    }
+
+    #[test]
+    fn test_nes_run_until() {
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        nes.run_until_vblank().unwrap();
+        assert_eq!(nes.frame_count(), 1);
+        assert_eq!(nes.bus().ppu().scanline(), nes.bus().ppu().vblank_scanline());
+
+        nes.run_scanlines(10).unwrap();
+        assert_eq!(nes.bus().ppu().scanline(), nes.bus().ppu().vblank_scanline() + 10);
+
+        nes.run_until_pc(0x8007).unwrap();
+        assert_eq!(nes.cpu().program_counter, 0x8007);
+        let count = nes.ram()[0x10];
+        nes.run_until_pc(0x8007).unwrap();
+        assert_eq!(nes.ram()[0x10], count.wrapping_add(1));
+    }
+
+    #[test]
+    fn test_nes_script() {
+        use crate::script::ScriptHost;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let frames = Rc::new(Cell::new(0));
+        let seen = frames.clone();
+        let mut host = ScriptHost::new();
+        host.set_ram_map(crate::ram_map::RamMap::parse("marker $0300 u8").unwrap());
+        host.register_after(move |ctx| {
+            seen.set(ctx.frame_count());
+            ctx.write_byte(0x0300, 0x5A);
+            assert_eq!(ctx.read_byte(0x0B00), 0x5A);
+            assert_eq!(ctx.game_value("marker"), Some(0x5A));
+            assert!(ctx.register("pc").unwrap() >= 0x8005);
+            ctx.gui.pixel(0, 0, (1, 2, 3));
+        });
+        let frame = host.run_frame(&mut nes);
+        assert_eq!(frame.get_pixel(0, 0), Some((1, 2, 3)));
+        host.run_frame(&mut nes);
+        assert_eq!(frames.get(), 2);
+    }
+
+    #[test]
+    fn test_nes_cheats() {
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        // $10はメインループで毎回インクリメントされるが、固定すると変わらない
+        let id = nes.cheats_mut().add_cheat(0x0010, 0x42, true).unwrap();
+        nes.run_frame();
+        nes.run_cycles(1000).unwrap();
+        assert_eq!(nes.ram()[0x10], 0x42);
+
+        nes.cheats_mut().toggle(id);
+        nes.run_cycles(1000).unwrap();
+        assert_ne!(nes.ram()[0x10], 0x42);
+    }
+
+    #[test]
+    fn test_nes_region() {
+        // 1フレームのCPUサイクル数 NTSC: 29780.5, PAL: 33247.5, Dendy: 35464
+        for (region, cycles) in [(Region::Ntsc, 29780), (Region::Pal, 33247), (Region::Dendy, 35464)] {
+            let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+            nes.set_region(region);
+            nes.run_frame();
+            let start = nes.bus_mut().cycles();
+            nes.run_frame();
+            let frame = nes.bus_mut().cycles() - start;
+            assert!(frame.abs_diff(cycles) < 8, "{:?}: {}", region, frame);
+        }
+    }
+
+    #[test]
+    fn test_nes_debugger() {
+        use crate::debugger::{self, BreakCondition, Debugger, StopReason};
+
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let mut debugger = Debugger::new();
+        let lines = debugger.disassemble(&mut nes, 0x8000, 3);
+        assert_eq!(lines[0].text, "A9 80    LDA #$80");
+        assert_eq!(lines[2].address, 0x8005);
+
+        // ラベルがあればオペランドは名前になる
+        nes.symbols_mut().add_label(0x0010, "counter", "");
+        nes.symbols_mut().add_label(0x8005, "loop", "main loop");
+        let lines = debugger.disassemble(&mut nes, 0x8005, 2);
+        assert_eq!(lines[0].text, "E6 10    INC counter");
+        assert_eq!(lines[0].label.as_ref().unwrap().comment, "main loop");
+        assert_eq!(lines[1].text, "4C 05 80 JMP loop");
+        assert_eq!(lines[1].label, None);
+
+        // ループの先頭で止まり、もう一度呼ぶと1周して同じ場所で止まる
+        assert!(debugger.toggle_breakpoint(0x8005));
+        assert_eq!(debugger.run_frame(&mut nes), StopReason::Breakpoint(0x8005));
+        let counter = debugger::read_memory(&mut nes, 0x0010, 1).unwrap()[0];
+        assert_eq!(debugger.run_frame(&mut nes), StopReason::Breakpoint(0x8005));
+        assert_eq!(debugger::read_memory(&mut nes, 0x0010, 1).unwrap()[0], counter.wrapping_add(1));
+        assert_eq!(debugger::registers(&nes).pc, 0x8005);
+        assert!(matches!(
+            debugger::read_memory(&mut nes, 0xFFFF, 2),
+            Err(EmuError::InvalidAddress { start: 0xFFFF, len: 2 })
+        ));
+
+        assert!(!debugger.toggle_breakpoint(0x8005));
+        assert_eq!(debugger.run_frame(&mut nes), StopReason::FrameEnd);
+        assert!(!debugger::channel_status(&mut nes)[0].enabled);
+
+        // CPU以外のイベントで止める
+        let mut nes = Nes::new(test_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let write = BreakCondition::PpuWrite(0x3FF8); // $2000 のミラー
+        debugger.add_condition(write);
+        assert_eq!(debugger.run_frame(&mut nes), StopReason::Event(write));
+        assert_eq!(debugger::registers(&nes).pc, 0x8005);
+        assert!(debugger.remove_condition(write));
+        let position = BreakCondition::Position { scanline: 100, dot: 0 };
+        debugger.add_condition(position);
+        debugger.add_condition(BreakCondition::Nmi);
+        assert_eq!(debugger.run_frame(&mut nes), StopReason::Event(position));
+        assert_eq!(nes.bus().ppu().scanline(), 100);
+        assert_eq!(debugger.run_frame(&mut nes), StopReason::Event(BreakCondition::Nmi));
+        assert!(nes.bus().ppu().scanline() >= nes.bus().ppu().vblank_scanline());
+        assert_eq!(debugger.conditions().len(), 2);
+        assert_eq!(BreakCondition::parse("write=$2001"), Ok(BreakCondition::PpuWrite(0x2001)));
+        assert_eq!(BreakCondition::parse("scanline=100"), Ok(position));
+        assert_eq!(BreakCondition::parse("Sprite0"), Ok(BreakCondition::Sprite0Hit));
+        assert!(BreakCondition::parse("write=4000").is_err());
+        assert!(BreakCondition::parse("brk").is_err());
+    }
+}
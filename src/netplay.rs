@@ -0,0 +1,375 @@
+// ロールバック方式のネットプレイ (GGPO風)
+// https://www.ggpo.net/
+// 相手の入力が届く前は直前の入力が続くと予測して進め、外れていたら
+// 保存しておいたステートまで戻って、届いた入力で再実行する
+// 確定したフレームのステートのCRC32を交換して、ずれ(デシンク)を見つける
+//
+// 通信そのもの (UDPなど) はフロントエンドが行い、ここでは NetMessage のバイト列を受け渡すだけ
+// 毎フレーム save_state するので、ステートの保存/読み込みの速さがそのまま性能になる
+// (load_state は失敗に備えてバックアップを取るので、巻き戻しは保存の約2回分かかる)
+use crate::checksum;
+use crate::gamepad::Button;
+use crate::nes::Nes;
+use crate::state::{State, StateError, StateReader, StateWriter};
+use std::collections::{BTreeMap, VecDeque};
+
+// これ以上相手より先に進むと巻き戻せなくなるので待つ
+pub const MAX_ROLLBACK_FRAMES: usize = 8;
+// 何フレームごとにステートのハッシュを交換するか
+pub const HASH_INTERVAL: usize = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetMessage {
+    Input { frame: u32, buttons: Button },
+    StateHash { frame: u32, hash: u32 },
+}
+
+impl NetMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        match self {
+            NetMessage::Input { frame, buttons } => {
+                0u8.save(&mut w);
+                frame.save(&mut w);
+                buttons.bits().save(&mut w);
+            }
+            NetMessage::StateHash { frame, hash } => {
+                1u8.save(&mut w);
+                frame.save(&mut w);
+                hash.save(&mut w);
+            }
+        }
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut r = StateReader::new(data);
+        let (mut kind, mut frame) = (0u8, 0u32);
+        kind.load(&mut r)?;
+        frame.load(&mut r)?;
+        let message = match kind {
+            0 => {
+                let mut bits = 0u8;
+                bits.load(&mut r)?;
+                NetMessage::Input {
+                    frame,
+                    buttons: Button::from_bits_truncate(bits),
+                }
+            }
+            1 => {
+                let mut hash = 0u32;
+                hash.load(&mut r)?;
+                NetMessage::StateHash { frame, hash }
+            }
+            _ => return Err(StateError::Mismatch("net message")),
+        };
+        if !r.is_empty() {
+            return Err(StateError::Mismatch("trailing data"));
+        }
+        Ok(message)
+    }
+}
+
+pub struct RollbackSession {
+    local_player: usize, // 0 or 1 (相手はもう一方)
+    frame: usize,        // 次に実行するフレーム
+    local_inputs: Vec<Button>,
+    remote_inputs: Vec<Option<Button>>, // 届いた相手の入力
+    predicted: Vec<Button>,             // 実行に使った相手の入力
+    confirmed: usize,                   // このフレームより前は相手の入力が全部届いている
+    rollback_to: Option<usize>,
+    states: VecDeque<(usize, Vec<u8>)>, // フレームの開始時点のステート
+    hashes: BTreeMap<usize, u32>,       // 確定したフレームのハッシュ
+    remote_hashes: BTreeMap<usize, u32>,
+    sent_hash: Option<usize>, // 最後に送ったハッシュのフレーム
+    rollbacks: usize,
+}
+
+impl RollbackSession {
+    pub fn new(local_player: usize) -> Self {
+        RollbackSession {
+            local_player: local_player & 1,
+            frame: 0,
+            local_inputs: Vec::new(),
+            remote_inputs: Vec::new(),
+            predicted: Vec::new(),
+            confirmed: 0,
+            rollback_to: None,
+            states: VecDeque::new(),
+            hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            sent_hash: None,
+            rollbacks: 0,
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn confirmed_frame(&self) -> usize {
+        self.confirmed
+    }
+
+    // 巻き戻した回数 (統計用)
+    pub fn rollbacks(&self) -> usize {
+        self.rollbacks
+    }
+
+    // 相手より先に進みすぎていて、入力を待つ必要があるか
+    pub fn is_stalled(&self) -> bool {
+        // 相手のほうが先に進んでいれば confirmed > frame になる
+        self.frame.saturating_sub(self.confirmed) >= MAX_ROLLBACK_FRAMES
+    }
+
+    // 次のフレームの自分の入力。戻り値を相手に送る
+    pub fn add_local_input(&mut self, buttons: Button) -> NetMessage {
+        self.local_inputs.truncate(self.frame);
+        self.local_inputs.push(buttons);
+        NetMessage::Input {
+            frame: self.frame as u32,
+            buttons,
+        }
+    }
+
+    // 相手から届いたメッセージ
+    pub fn receive(&mut self, message: &NetMessage) -> Result<(), String> {
+        match *message {
+            NetMessage::Input { frame, buttons } => self.add_remote_input(frame as usize, buttons),
+            NetMessage::StateHash { frame, hash } => {
+                self.remote_hashes.insert(frame as usize, hash);
+                Ok(())
+            }
+        }
+    }
+
+    // 両方のハッシュがそろっていて食い違った最初のフレーム
+    // デシンクしたらどちらかのステートを送り直してやり直すしかない
+    pub fn desync_frame(&self) -> Option<usize> {
+        self.remote_hashes
+            .iter()
+            .find(|(frame, hash)| self.hashes.get(frame).is_some_and(|local| local != *hash))
+            .map(|(frame, _)| *frame)
+    }
+
+    // 相手も自分の入力を待つので、巻き戻せる範囲より先のフレームは届かないはず
+    // (壊れたパケットのフレーム番号で大きな確保をしないように弾く)
+    pub fn add_remote_input(&mut self, frame: usize, buttons: Button) -> Result<(), String> {
+        if frame > self.frame + MAX_ROLLBACK_FRAMES {
+            return Err(format!("remote input for frame {} is too far ahead of frame {}", frame, self.frame));
+        }
+        if self.remote_inputs.len() <= frame {
+            self.remote_inputs.resize(frame + 1, None);
+        }
+        self.remote_inputs[frame] = Some(buttons);
+        while self.confirmed < self.remote_inputs.len() && self.remote_inputs[self.confirmed].is_some() {
+            self.confirmed += 1;
+        }
+        // 予測して実行済みのフレームで外れていたら、次の advance_frame で巻き戻す
+        if frame < self.frame && self.predicted[frame] != buttons {
+            self.rollback_to = Some(self.rollback_to.map_or(frame, |f| f.min(frame)));
+        }
+        Ok(())
+    }
+
+    // 1フレーム進める。待つ必要があれば何もせず false
+    // 自分の入力は先に add_local_input で渡しておく
+    pub fn advance_frame(&mut self, nes: &mut Nes) -> Result<bool, String> {
+        if let Some(frame) = self.rollback_to.take() {
+            self.rollback(nes, frame)?;
+        }
+        self.update_hashes();
+        if self.is_stalled() {
+            return Ok(false);
+        }
+        if self.local_inputs.len() <= self.frame {
+            return Err(format!("no local input for frame {}", self.frame));
+        }
+        self.simulate(nes);
+        Ok(true)
+    }
+
+    // まだ送っていない確定フレームのハッシュ。相手に送って比べる
+    pub fn take_state_hashes(&mut self) -> Vec<NetMessage> {
+        let start = self.sent_hash.map_or(0, |frame| frame + 1);
+        let messages: Vec<NetMessage> = self
+            .hashes
+            .range(start..)
+            .map(|(&frame, &hash)| NetMessage::StateHash {
+                frame: frame as u32,
+                hash,
+            })
+            .collect();
+        if let Some(NetMessage::StateHash { frame, .. }) = messages.last() {
+            self.sent_hash = Some(*frame as usize);
+        }
+        messages
+    }
+
+    fn simulate(&mut self, nes: &mut Nes) {
+        let frame = self.frame;
+        self.states.push_back((frame, nes.save_state()));
+        while self.states.len() > MAX_ROLLBACK_FRAMES + 1 {
+            self.states.pop_front();
+        }
+
+        // 届いていなければ最後に届いた入力が続くと予測する
+        let remote = self.remote_inputs.get(frame).copied().flatten().unwrap_or_else(|| {
+            self.remote_inputs[..frame.min(self.remote_inputs.len())]
+                .iter()
+                .rev()
+                .find_map(|b| *b)
+                .unwrap_or(Button::empty())
+        });
+        self.predicted.truncate(frame);
+        self.predicted.push(remote);
+
+        nes.set_controller_state(self.local_player, self.local_inputs[frame]);
+        nes.set_controller_state(self.local_player ^ 1, remote);
+        nes.run_frame();
+        self.frame += 1;
+    }
+
+    fn rollback(&mut self, nes: &mut Nes, frame: usize) -> Result<(), String> {
+        let index = self
+            .states
+            .iter()
+            .position(|(f, _)| *f == frame)
+            .ok_or(format!("cannot roll back to frame {}", frame))?;
+        nes.load_state(&self.states[index].1).map_err(|e| e.to_string())?;
+        self.states.truncate(index);
+        let end = self.frame;
+        self.frame = frame;
+        while self.frame < end {
+            self.simulate(nes);
+        }
+        self.rollbacks += 1;
+        Ok(())
+    }
+
+    fn update_hashes(&mut self) {
+        for (frame, state) in self.states.iter() {
+            if *frame <= self.confirmed && *frame % HASH_INTERVAL == 0 && !self.hashes.contains_key(frame) {
+                self.hashes.insert(*frame, checksum::crc32(state));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::{AudioConfig, APU};
+    use crate::rom::Rom;
+
+    // 1PのAボタンを押していたら$10を増やし続けるNROM
+    fn controller_rom() -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        let program = [
+            0xA9, 0x80, // LDA #$80
+            0x8D, 0x00, 0x20, // STA $2000
+            0xA9, 0x01, // loop: LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x16, 0x40, // STA $4016
+            0xAD, 0x16, 0x40, // LDA $4016
+            0x29, 0x01, // AND #$01
+            0x18, // CLC
+            0x65, 0x10, // ADC $10
+            0x85, 0x10, // STA $10
+            0x4C, 0x05, 0x80, // JMP loop
+            0x40, // nmi: RTI
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFA..0x3FFC].copy_from_slice(&[0x1C, 0x80]); // NMI
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]); // RESET
+        raw.extend(prg);
+        raw.extend(vec![0; 0x2000]);
+        Rom::from_bytes(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_rollback_session() {
+        let mut nes = Nes::new(controller_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let start = nes.save_state();
+        let remote: Vec<Button> = (0..13)
+            .map(|f| if (3..6).contains(&f) { Button::BUTTON_A } else { Button::empty() })
+            .collect();
+
+        // 相手(1P)の入力は4フレーム遅れて届く
+        let mut session = RollbackSession::new(1);
+        for frame in 0..12 {
+            session.add_local_input(Button::empty());
+            assert_eq!(session.advance_frame(&mut nes), Ok(true));
+            if frame >= 4 {
+                let message = NetMessage::Input {
+                    frame: (frame - 4) as u32,
+                    buttons: remote[frame - 4],
+                };
+                assert_eq!(session.receive(&message), Ok(()));
+            }
+        }
+        for (frame, &buttons) in remote.iter().enumerate().take(12).skip(8) {
+            assert_eq!(session.add_remote_input(frame, buttons), Ok(()));
+        }
+        session.add_local_input(Button::empty());
+        assert_eq!(session.advance_frame(&mut nes), Ok(true));
+        assert_eq!(session.rollbacks(), 2);
+        assert_eq!(session.take_state_hashes().len(), 1); // フレーム0
+        let result = nes.save_state();
+
+        // 最初から正しい入力で動かしたのと同じになる
+        nes.load_state(&start).unwrap();
+        for buttons in remote.iter() {
+            nes.set_controller_state(0, *buttons);
+            nes.run_frame();
+        }
+        assert_ne!(nes.ram()[0x10], 0);
+        assert!(nes.save_state() == result);
+    }
+
+    #[test]
+    fn test_remote_ahead() {
+        let mut nes = Nes::new(controller_rom(), APU::headless(&AudioConfig::new())).unwrap();
+        let mut session = RollbackSession::new(0);
+        // 相手のほうが先に進んでいても待たない
+        for frame in 0..MAX_ROLLBACK_FRAMES {
+            assert_eq!(session.add_remote_input(frame, Button::BUTTON_A), Ok(()));
+        }
+        assert_eq!(session.confirmed_frame(), MAX_ROLLBACK_FRAMES);
+        assert!(!session.is_stalled());
+        session.add_local_input(Button::empty());
+        assert_eq!(session.advance_frame(&mut nes), Ok(true));
+        assert_eq!(session.rollbacks(), 0);
+
+        // 巻き戻せる範囲より先のフレームは確保せずに弾く
+        let message = NetMessage::Input {
+            frame: u32::MAX,
+            buttons: Button::BUTTON_A,
+        };
+        assert!(session.receive(&message).is_err());
+        assert!(session.add_remote_input(1 + MAX_ROLLBACK_FRAMES + 1, Button::empty()).is_err());
+        assert_eq!(session.add_remote_input(1 + MAX_ROLLBACK_FRAMES, Button::empty()), Ok(()));
+    }
+
+    #[test]
+    fn test_net_message() {
+        let messages = [
+            NetMessage::Input {
+                frame: 1234,
+                buttons: Button::BUTTON_A | Button::START,
+            },
+            NetMessage::StateHash {
+                frame: 60,
+                hash: 0xDEADBEEF,
+            },
+        ];
+        for message in messages.iter() {
+            assert_eq!(NetMessage::from_bytes(&message.to_bytes()).as_ref(), Ok(message));
+        }
+        assert!(NetMessage::from_bytes(&[2, 0, 0, 0, 0]).is_err());
+        assert!(NetMessage::from_bytes(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,139 @@
+// Mapper 0 (NROM)
+// https://www.nesdev.org/wiki/NROM
+// PRG ROM 16KB/32KB (16KBの場合は$C000-$FFFFにミラー), CHR ROM 8KB (またはCHR RAM)
+use crate::mapper::{prg_ram_read, prg_ram_write, Mapper};
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    prg_ram: Vec<u8>, // Family BASIC用 ($6000-$7FFF)
+    is_chr_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl_state!(Nrom { chr, prg_ram, mirroring });
+
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        Nrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: rom.chr_rom.clone(),
+            prg_ram: vec![0; rom.header.prg_ram_size],
+            is_chr_ram: rom.is_chr_ram,
+            mirroring: rom.mirroring.clone(),
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => prg_ram_read(&self.prg_ram, addr),
+            0x8000..=0xFFFF => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if (0x6000..=0x7FFF).contains(&addr) {
+            prg_ram_write(&mut self.prg_ram, addr, data);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.is_chr_ram {
+            let len = self.chr.len();
+            self.chr[(addr & 0x1FFF) as usize % len] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some((addr & 0x1FFF) as usize % self.chr.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    // リセットベクタが$8000を指すだけの小さなNROMイメージ
+    //   $8000: LDA #$42 / STA $6000 / JMP $8005
+    fn homebrew_rom(prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, 0x01, 0x00];
+        raw.resize(16, 0);
+
+        let mut prg = vec![0xEA; prg_banks as usize * 0x4000];
+        prg[..8].copy_from_slice(&[0xA9, 0x42, 0x8D, 0x00, 0x60, 0x4C, 0x05, 0x80]);
+        let len = prg.len();
+        prg[len - 4..len - 2].copy_from_slice(&[0x00, 0x80]); // RESET
+        // 32KBの場合は後半に目印を置く
+        if prg_banks == 2 {
+            prg[0x4000] = 0xAB;
+        }
+        raw.extend(prg);
+
+        let mut chr: Vec<u8> = (0..chr_banks as usize * 0x2000).map(|i| i as u8).collect();
+        raw.append(&mut chr);
+        raw
+    }
+
+    #[test]
+    fn test_nrom_128() {
+        let rom = Rom::new(&homebrew_rom(1, 1)).unwrap();
+        let mut cart = Cartridge::new(&rom).unwrap();
+        assert_eq!(cart.mirroring(), Mirroring::VERTICAL);
+
+        // 16KBは$C000-$FFFFにミラーされる
+        assert_eq!(cart.cpu_read(0x8000), 0xA9);
+        assert_eq!(cart.cpu_read(0xC000), 0xA9);
+        assert_eq!(cart.cpu_read(0xFFFC), 0x00);
+        assert_eq!(cart.cpu_read(0xFFFD), 0x80);
+
+        // CHR ROMには書き込めない
+        assert_eq!(cart.ppu_read(0x0123), 0x23);
+        cart.ppu_write(0x0123, 0xFF);
+        assert_eq!(cart.ppu_read(0x0123), 0x23);
+
+        cart.cpu_write(0x6000, 0x42);
+        assert_eq!(cart.cpu_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_nrom_256_chr_ram() {
+        let rom = Rom::new(&homebrew_rom(2, 0)).unwrap();
+        let mut cart = Cartridge::new(&rom).unwrap();
+
+        assert_eq!(cart.cpu_read(0x8000), 0xA9);
+        assert_eq!(cart.cpu_read(0xC000), 0xAB);
+
+        // PRG ROMへの書き込みは無視
+        cart.cpu_write(0x8000, 0x00);
+        assert_eq!(cart.cpu_read(0x8000), 0xA9);
+
+        cart.ppu_write(0x1FFF, 0x5A);
+        assert_eq!(cart.ppu_read(0x1FFF), 0x5A);
+    }
+}
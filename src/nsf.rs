@@ -0,0 +1,379 @@
+// NSF / NSFe (NES Sound Format)
+// https://www.nesdev.org/wiki/NSF
+// https://www.nesdev.org/wiki/NSFe
+// 曲データとINIT/PLAYのエントリポイント、トラック情報を読み込み、
+// $5FF8-$5FFF の4KBバンク切り替えを含むPRGの配置をマッパーとして提供する
+// (INIT/PLAYを呼ぶ再生ドライバは別)
+use crate::mapper::Mapper;
+use crate::impl_state;
+use crate::rom::{Mirroring, RomError};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const NSF_TAG: [u8; 5] = [0x4E, 0x45, 0x53, 0x4D, 0x1A]; // NESM^Z
+const NSFE_TAG: [u8; 4] = [0x4E, 0x53, 0x46, 0x45]; // NSFE
+const NSF_HEADER_SIZE: usize = 0x80;
+const NSF_BANK_SIZE: usize = 4 * 1024;
+const NSF_DEFAULT_SPEED_NTSC: u16 = 16639; // 約60.1Hz (μs)
+const NSF_DEFAULT_SPEED_PAL: u16 = 19997; // 約50.0Hz (μs)
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nsf {
+    pub total_songs: u8,
+    pub starting_song: u8, // 0始まり
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub play_speed_ntsc: u16, // PLAYを呼ぶ間隔 (μs)
+    pub play_speed_pal: u16,
+    pub bankswitch: Option<[u8; 8]>, // 全て0ならバンク切り替えなし
+    pub is_pal: bool,
+    pub expansion: u8, // 拡張音源のビットフラグ (VRC6, VRC7, FDS, MMC5, N163, 5B)
+    pub track_labels: Vec<String>,   // NSFeのみ
+    pub track_times: Vec<Option<u32>>, // NSFeのみ (ミリ秒)
+    pub data: Vec<u8>,
+}
+
+pub fn is_nsf(raw: &[u8]) -> bool {
+    raw.starts_with(&NSF_TAG) || raw.starts_with(&NSFE_TAG)
+}
+
+fn invalid(reason: &'static str) -> RomError {
+    RomError::InvalidNsf(reason)
+}
+
+fn read_u16(raw: &[u8], pos: usize) -> u16 {
+    raw[pos] as u16 | (raw[pos + 1] as u16) << 8
+}
+
+// 0終端 (またはフィールド長いっぱい) の文字列
+fn read_string(raw: &[u8]) -> String {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+fn parse_bankswitch(raw: &[u8]) -> Option<[u8; 8]> {
+    let mut banks = [0u8; 8];
+    banks[..raw.len().min(8)].copy_from_slice(&raw[..raw.len().min(8)]);
+    if banks.iter().any(|&b| b != 0) {
+        Some(banks)
+    } else {
+        None
+    }
+}
+
+impl Nsf {
+    pub fn parse(raw: &[u8]) -> Result<Nsf, RomError> {
+        if raw.starts_with(&NSF_TAG) {
+            Nsf::parse_nsf(raw)
+        } else if raw.starts_with(&NSFE_TAG) {
+            Nsf::parse_nsfe(raw)
+        } else {
+            Err(RomError::InvalidMagic)
+        }
+    }
+
+    fn parse_nsf(raw: &[u8]) -> Result<Nsf, RomError> {
+        if raw.len() < NSF_HEADER_SIZE {
+            return Err(RomError::TooShort {
+                expected: NSF_HEADER_SIZE,
+                actual: raw.len(),
+            });
+        }
+        if raw[0x06] == 0 {
+            return Err(invalid("no songs"));
+        }
+        // NSF2ではヘッダに曲データ長がある (0ならファイル末尾まで)
+        let length = raw[0x7D] as usize | (raw[0x7E] as usize) << 8 | (raw[0x7F] as usize) << 16;
+        let end = if raw[0x05] >= 2 && length != 0 {
+            (NSF_HEADER_SIZE + length).min(raw.len())
+        } else {
+            raw.len()
+        };
+
+        Ok(Nsf {
+            total_songs: raw[0x06],
+            starting_song: raw[0x07].saturating_sub(1),
+            load_addr: read_u16(raw, 0x08),
+            init_addr: read_u16(raw, 0x0A),
+            play_addr: read_u16(raw, 0x0C),
+            title: read_string(&raw[0x0E..0x2E]),
+            artist: read_string(&raw[0x2E..0x4E]),
+            copyright: read_string(&raw[0x4E..0x6E]),
+            play_speed_ntsc: read_u16(raw, 0x6E),
+            play_speed_pal: read_u16(raw, 0x78),
+            bankswitch: parse_bankswitch(&raw[0x70..0x78]),
+            is_pal: raw[0x7A] & 0x01 != 0,
+            expansion: raw[0x7B],
+            track_labels: vec![],
+            track_times: vec![],
+            data: raw[NSF_HEADER_SIZE..end].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(raw: &[u8]) -> Result<Nsf, RomError> {
+        let mut nsf = Nsf {
+            total_songs: 1,
+            starting_song: 0,
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            play_speed_ntsc: NSF_DEFAULT_SPEED_NTSC,
+            play_speed_pal: NSF_DEFAULT_SPEED_PAL,
+            bankswitch: None,
+            is_pal: false,
+            expansion: 0,
+            track_labels: vec![],
+            track_times: vec![],
+            data: vec![],
+        };
+        let mut has_info = false;
+        let mut has_data = false;
+
+        // チャンク: 長さ(4) + ID(4) + データ
+        let mut pos = NSFE_TAG.len();
+        while pos + 8 <= raw.len() {
+            let len = u32::from_le_bytes([raw[pos], raw[pos + 1], raw[pos + 2], raw[pos + 3]]) as usize;
+            let id = &raw[pos + 4..pos + 8];
+            let start = pos + 8;
+            if start + len > raw.len() {
+                return Err(invalid("truncated chunk"));
+            }
+            let chunk = &raw[start..start + len];
+            pos = start + len;
+
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(invalid("INFO chunk too short"));
+                    }
+                    nsf.load_addr = read_u16(chunk, 0);
+                    nsf.init_addr = read_u16(chunk, 2);
+                    nsf.play_addr = read_u16(chunk, 4);
+                    nsf.is_pal = chunk[6] & 0x01 != 0;
+                    nsf.expansion = chunk[7];
+                    if chunk.len() > 8 {
+                        nsf.total_songs = chunk[8];
+                    }
+                    if chunk.len() > 9 {
+                        nsf.starting_song = chunk[9];
+                    }
+                    has_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = chunk.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => nsf.bankswitch = parse_bankswitch(chunk),
+                b"RATE" => {
+                    if chunk.len() >= 2 {
+                        nsf.play_speed_ntsc = read_u16(chunk, 0);
+                    }
+                    if chunk.len() >= 4 {
+                        nsf.play_speed_pal = read_u16(chunk, 2);
+                    }
+                }
+                b"auth" => {
+                    let mut fields = chunk.split(|&b| b == 0).map(read_string);
+                    nsf.title = fields.next().unwrap_or_default();
+                    nsf.artist = fields.next().unwrap_or_default();
+                    nsf.copyright = fields.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_labels = chunk.split(|&b| b == 0).map(read_string).collect();
+                    nsf.track_labels.truncate(nsf.total_songs as usize);
+                }
+                b"time" => {
+                    nsf.track_times = chunk
+                        .chunks_exact(4)
+                        .map(|t| i32::from_le_bytes([t[0], t[1], t[2], t[3]]))
+                        .map(|t| if t < 0 { None } else { Some(t as u32) })
+                        .collect();
+                }
+                b"NEND" => break,
+                // 大文字で始まるチャンクは必須 (知らないものは読めない)
+                _ if id[0].is_ascii_uppercase() => return Err(invalid("unknown required chunk")),
+                _ => {}
+            }
+        }
+
+        if !has_info || !has_data {
+            return Err(invalid("missing INFO or DATA chunk"));
+        }
+        if nsf.total_songs == 0 {
+            return Err(invalid("no songs"));
+        }
+        Ok(nsf)
+    }
+
+    pub fn track_label(&self, song: u8) -> Option<&str> {
+        self.track_labels.get(song as usize).map(|s| s.as_str())
+    }
+
+    pub fn track_time_ms(&self, song: u8) -> Option<u32> {
+        self.track_times.get(song as usize).copied().flatten()
+    }
+}
+
+// NSF用のカートリッジ: $6000-$7FFF はRAM、$8000-$FFFF は4KB x8 のバンク
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    banks: [u8; 8], // $5FF8-$5FFF
+}
+
+impl_state!(NsfMapper { prg_ram, chr_ram, banks });
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let (prg, banks) = match nsf.bankswitch {
+            // バンク切り替えあり: 読み込みアドレスの下位12ビット分だけ前を詰める
+            Some(banks) => {
+                let padding = (nsf.load_addr & 0x0FFF) as usize;
+                let mut prg = vec![0; padding];
+                prg.extend_from_slice(&nsf.data);
+                let size = prg.len().div_ceil(NSF_BANK_SIZE).max(1) * NSF_BANK_SIZE;
+                prg.resize(size, 0);
+                (prg, banks)
+            }
+            // バンク切り替えなし: 32KBの空間にそのまま置く
+            None => {
+                let mut prg = vec![0; 8 * NSF_BANK_SIZE];
+                let start = (nsf.load_addr.max(0x8000) - 0x8000) as usize;
+                let len = nsf.data.len().min(prg.len() - start);
+                prg[start..start + len].copy_from_slice(&nsf.data[..len]);
+                (prg, [0, 1, 2, 3, 4, 5, 6, 7])
+            }
+        };
+        NsfMapper {
+            prg,
+            prg_ram: vec![0; 8 * 1024],
+            chr_ram: vec![0; 8 * 1024],
+            banks,
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xFFFF => {
+                let count = self.prg.len() / NSF_BANK_SIZE;
+                let bank = self.banks[((addr - 0x8000) >> 12) as usize] as usize % count;
+                self.prg[bank * NSF_BANK_SIZE + (addr as usize & (NSF_BANK_SIZE - 1))]
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5FF8..=0x5FFF => self.banks[(addr - 0x5FF8) as usize] = data,
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr_ram[(addr & 0x1FFF) as usize]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr_ram[(addr & 0x1FFF) as usize] = data;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::VERTICAL
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nsf(load: u16, banks: [u8; 8], data: &[u8]) -> Vec<u8> {
+        let mut raw = NSF_TAG.to_vec();
+        raw.resize(NSF_HEADER_SIZE, 0);
+        raw[0x05] = 1;
+        raw[0x06] = 3;
+        raw[0x07] = 2;
+        raw[0x08..0x0A].copy_from_slice(&load.to_le_bytes());
+        raw[0x0A..0x0C].copy_from_slice(&0x8003u16.to_le_bytes());
+        raw[0x0C..0x0E].copy_from_slice(&0x8006u16.to_le_bytes());
+        raw[0x0E..0x13].copy_from_slice(b"Title");
+        raw[0x6E..0x70].copy_from_slice(&NSF_DEFAULT_SPEED_NTSC.to_le_bytes());
+        raw[0x70..0x78].copy_from_slice(&banks);
+        raw.extend_from_slice(data);
+        raw
+    }
+
+    #[test]
+    fn test_nsf_parse_and_mapping() {
+        let raw = nsf(0x8000, [0; 8], &[0xA9, 0x01, 0x60]);
+        assert!(is_nsf(&raw));
+        let info = Nsf::parse(&raw).unwrap();
+        assert_eq!(info.total_songs, 3);
+        assert_eq!(info.starting_song, 1);
+        assert_eq!(info.init_addr, 0x8003);
+        assert_eq!(info.play_addr, 0x8006);
+        assert_eq!(info.title, "Title");
+        assert_eq!(info.bankswitch, None);
+        let mut mapper = NsfMapper::new(&info);
+        assert_eq!(mapper.cpu_read(0x8000), 0xA9);
+        assert_eq!(mapper.cpu_read(0x8002), 0x60);
+
+        // バンク切り替えあり ($x800から読み込み => 先頭に0x800バイト詰める)
+        let mut data = vec![0u8; 3 * NSF_BANK_SIZE];
+        data[0] = 0x11;
+        data[NSF_BANK_SIZE] = 0x22;
+        let info = Nsf::parse(&nsf(0x8800, [0, 1, 2, 0, 0, 0, 0, 0], &data)).unwrap();
+        let mut mapper = NsfMapper::new(&info);
+        assert_eq!(mapper.cpu_read(0x8800), 0x11);
+        assert_eq!(mapper.cpu_read(0x9800), 0x22);
+        mapper.cpu_write(0x5FF8, 1);
+        assert_eq!(mapper.cpu_read(0x8800), 0x22);
+    }
+
+    #[test]
+    fn test_nsfe_parse() {
+        fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+            let mut c = (data.len() as u32).to_le_bytes().to_vec();
+            c.extend_from_slice(id);
+            c.extend_from_slice(data);
+            c
+        }
+        let mut raw = NSFE_TAG.to_vec();
+        raw.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x00, 0x00, 2, 0]));
+        raw.extend(chunk(b"DATA", &[0xEA; 16]));
+        raw.extend(chunk(b"auth", b"Game\0Composer\0(C)\0Ripper\0"));
+        raw.extend(chunk(b"tlbl", b"Opening\0Ending\0"));
+        raw.extend(chunk(b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+        raw.extend(chunk(b"zzzz", &[1, 2, 3])); // 任意チャンクは無視
+        raw.extend(chunk(b"NEND", &[]));
+
+        let info = Nsf::parse(&raw).unwrap();
+        assert_eq!(info.total_songs, 2);
+        assert_eq!(info.init_addr, 0x8003);
+        assert_eq!(info.artist, "Composer");
+        assert_eq!(info.track_label(1), Some("Ending"));
+        assert_eq!(info.track_time_ms(0), Some(10000));
+        assert_eq!(info.track_time_ms(1), None);
+        assert_eq!(info.data.len(), 16);
+
+        let mut raw = NSFE_TAG.to_vec();
+        raw.extend(chunk(b"DATA", &[0xEA; 16]));
+        assert!(matches!(Nsf::parse(&raw), Err(RomError::InvalidNsf(_))));
+    }
+}
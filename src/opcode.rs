@@ -1,4 +1,5 @@
 use crate::cpu::{AddressingMode, CycleCalcMode, OpCode, CPU};
+use alloc::vec::Vec;
 
 lazy_static! {
   pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
@@ -0,0 +1,163 @@
+// ヘッダ上書きファイル (ヘッダが間違っているダンプの補正用)
+// ヘッダの解析後に、チェックサムが一致するROMのヘッダ値を書き換える
+//
+// 1行1エントリ、空白区切り:
+//   <CRC32(8桁) または SHA-1(40桁)> key=value ...
+// key:
+//   mapper=<0-255> submapper=<0-15> mirroring=<H|V|4> battery=<0|1>
+//   prg_ram=<バイト数 (8K のようにK指定も可)>
+// '#'以降はコメント
+use crate::rom::Mirroring;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HeaderOverride {
+    pub mapper: Option<u8>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub has_battery: Option<bool>,
+    pub prg_ram_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Checksum {
+    Crc32(u32),
+    Sha1([u8; 20]),
+}
+
+pub struct HeaderOverrides {
+    entries: HashMap<Checksum, HeaderOverride>,
+}
+
+impl HeaderOverrides {
+    pub fn new() -> Self {
+        HeaderOverrides {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        HeaderOverrides::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut overrides = HeaderOverrides::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, entry) = parse_line(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            overrides.entries.insert(key, entry);
+        }
+        Ok(overrides)
+    }
+
+    // CRC32よりSHA-1の指定を優先する
+    pub fn lookup(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&HeaderOverride> {
+        self.entries
+            .get(&Checksum::Sha1(*sha1))
+            .or_else(|| self.entries.get(&Checksum::Crc32(crc32)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for HeaderOverrides {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_checksum(hex: &str) -> Result<Checksum, String> {
+    match hex.len() {
+        8 => u32::from_str_radix(hex, 16)
+            .map(Checksum::Crc32)
+            .map_err(|e| format!("crc32: {}", e)),
+        40 => {
+            let mut sha1 = [0u8; 20];
+            for (i, byte) in sha1.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|e| format!("sha1: {}", e))?;
+            }
+            Ok(Checksum::Sha1(sha1))
+        }
+        _ => Err(format!("checksum must be CRC32 or SHA-1: {}", hex)),
+    }
+}
+
+fn parse_size(value: &str) -> Result<usize, String> {
+    let (digits, unit) = match value.strip_suffix(|c| c == 'K' || c == 'k') {
+        Some(digits) => (digits, 1024),
+        None => (value, 1),
+    };
+    digits.parse::<usize>().map(|n| n * unit).map_err(|e| format!("prg_ram: {}", e))
+}
+
+fn parse_line(line: &str) -> Result<(Checksum, HeaderOverride), String> {
+    let mut fields = line.split_whitespace();
+    let checksum = parse_checksum(fields.next().unwrap_or(""))?;
+    let mut entry = HeaderOverride::default();
+    for field in fields {
+        let (key, value) = field.split_once('=').ok_or(format!("expected key=value: {}", field))?;
+        match key {
+            "mapper" => entry.mapper = Some(value.parse().map_err(|e| format!("mapper: {}", e))?),
+            "submapper" => entry.submapper = Some(value.parse().map_err(|e| format!("submapper: {}", e))?),
+            "mirroring" => {
+                entry.mirroring = Some(match value {
+                    "H" => Mirroring::HORIZONTAL,
+                    "V" => Mirroring::VERTICAL,
+                    "4" => Mirroring::FOUR_SCREEN,
+                    _ => return Err(format!("mirroring: {}", value)),
+                })
+            }
+            "battery" => {
+                entry.has_battery = Some(match value {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(format!("battery: {}", value)),
+                })
+            }
+            "prg_ram" => entry.prg_ram_size = Some(parse_size(value)?),
+            _ => return Err(format!("unknown key: {}", key)),
+        }
+    }
+    Ok((checksum, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_parse() {
+        let overrides = HeaderOverrides::parse(
+            "# comment\n\
+             6EBED2EE mapper=4 mirroring=V prg_ram=2K # trailing\n\
+             da39a3ee5e6b4b0d3255bfef95601890afd80709 battery=1\n",
+        )
+        .unwrap();
+        assert_eq!(overrides.len(), 2);
+
+        let entry = overrides.lookup(0x6EBED2EE, &[0; 20]).unwrap();
+        assert_eq!(entry.mapper, Some(4));
+        assert_eq!(entry.mirroring, Some(Mirroring::VERTICAL));
+        assert_eq!(entry.prg_ram_size, Some(2048));
+        assert_eq!(entry.has_battery, None);
+
+        let sha1 = crate::checksum::sha1(b"");
+        assert_eq!(overrides.lookup(0, &sha1).unwrap().has_battery, Some(true));
+        assert!(overrides.lookup(0, &[0; 20]).is_none());
+
+        assert!(HeaderOverrides::parse("6EBED2EE mapper=x").is_err());
+        assert!(HeaderOverrides::parse("6EBED2EE color=red").is_err());
+        assert!(HeaderOverrides::parse("123 mapper=1").is_err());
+    }
+}
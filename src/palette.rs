@@ -1,3 +1,5 @@
+use crate::sync::OnceCell;
+
 #[rustfmt::skip]
 
 pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
@@ -15,3 +17,28 @@ pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
 ];
+
+// 読み込んだパレット (起動時に一度だけ設定する)
+static CUSTOM_PALETTE: OnceCell<[(u8, u8, u8); 64]> = OnceCell::new();
+
+pub fn system_palette() -> &'static [(u8, u8, u8); 64] {
+    CUSTOM_PALETTE.get().unwrap_or(&SYSTEM_PALLETE)
+}
+
+pub fn set_palette(palette: [(u8, u8, u8); 64]) -> bool {
+    CUSTOM_PALETTE.set(palette).is_ok()
+}
+
+// .palファイル (RGB 64色。エンファシス込みの512色でも先頭の64色だけ使う)
+#[cfg(feature = "std")]
+pub fn load_pal(path: &std::path::Path) -> Result<[(u8, u8, u8); 64], String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    if data.len() < 64 * 3 {
+        return Err(format!("palette must have 64 colors ({} bytes)", data.len()));
+    }
+    let mut palette = [(0, 0, 0); 64];
+    for (color, rgb) in palette.iter_mut().zip(data.chunks_exact(3)) {
+        *color = (rgb[0], rgb[1], rgb[2]);
+    }
+    Ok(palette)
+}
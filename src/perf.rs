@@ -0,0 +1,167 @@
+// フレームごとの処理時間 (動作が重いときの報告や画面上の表示に使う)
+// 時間はフロントエンドが測って record に渡す (wasm では Instant が使えないので本体では測らない)
+use crate::frame::Frame;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// 平均を取るフレーム数 (約1秒)
+const WINDOW: usize = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameTiming {
+    pub emulation: Duration, // run_frame にかかった時間
+    pub render: Duration,    // 画面を作って表示するまでの時間
+    pub interval: Duration,  // 前のフレームからの経過時間 (FPSの計算用)
+    pub audio_fill_ms: f32,  // フレームの終わりにリングバッファに溜まっていたサンプル量
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerfSummary {
+    pub fps: f64,
+    pub emulation_ms: f64,
+    pub render_ms: f64,
+    pub audio_fill_ms: f32,
+}
+
+impl std::fmt::Display for PerfSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "FPS {:.1} EMU {:.2}MS REN {:.2}MS AUD {:.1}MS",
+            self.fps, self.emulation_ms, self.render_ms, self.audio_fill_ms
+        )
+    }
+}
+
+pub struct PerfStats {
+    history: VecDeque<FrameTiming>,
+}
+
+impl PerfStats {
+    pub fn new() -> Self {
+        PerfStats {
+            history: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    pub fn record(&mut self, timing: FrameTiming) {
+        if self.history.len() == WINDOW {
+            self.history.pop_front();
+        }
+        self.history.push_back(timing);
+    }
+
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    // 直近 WINDOW フレームの平均 (音声は最新の値)。まだ1フレームも無ければ None
+    pub fn summary(&self) -> Option<PerfSummary> {
+        let last = self.history.back()?;
+        let n = self.history.len() as f64;
+        let ms = |f: fn(&FrameTiming) -> Duration| {
+            self.history.iter().map(|t| f(t).as_secs_f64()).sum::<f64>() * 1000.0 / n
+        };
+        let interval_ms = ms(|t| t.interval);
+        Some(PerfSummary {
+            fps: if interval_ms > 0.0 { 1000.0 / interval_ms } else { 0.0 },
+            emulation_ms: ms(|t| t.emulation),
+            render_ms: ms(|t| t.render),
+            audio_fill_ms: last.audio_fill_ms,
+        })
+    }
+}
+
+impl Default for PerfStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 3x5ドットの文字 (PerfSummary の表示に使う分だけ)。各行の下位3ビットが左から右
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        _ => [0; 5],
+    }
+}
+
+// 画面の左上に1項目ずつ行を分けて重ねて描く (読みやすいように黒い縁を付ける)
+pub fn draw_overlay(frame: &mut Frame, summary: &PerfSummary) {
+    let text = summary.to_string();
+    let mut words = text.split(' ');
+    let mut y = 2;
+    while let (Some(label), Some(value)) = (words.next(), words.next()) {
+        draw_text(frame, 2, y, &format!("{} {}", label, value));
+        y += 7;
+    }
+}
+
+fn draw_text(frame: &mut Frame, x: usize, y: usize, text: &str) {
+    let width = text.chars().count() * 4 + 1;
+    for dy in 0..7 {
+        for dx in 0..width {
+            frame.set_pixel(x + dx - 1, y + dy - 1, (0, 0, 0));
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    frame.set_pixel(x + i * 4 + col, y + row, (255, 255, 255));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perf_stats() {
+        let mut stats = PerfStats::new();
+        assert_eq!(stats.summary(), None);
+        for i in 0..(WINDOW + 10) {
+            stats.record(FrameTiming {
+                emulation: Duration::from_millis(if i < 10 { 100 } else { 4 }),
+                render: Duration::from_millis(1),
+                interval: Duration::from_millis(20),
+                audio_fill_ms: i as f32,
+            });
+        }
+        // 古いフレームは平均に入らない
+        let summary = stats.summary().unwrap();
+        assert!((summary.fps - 50.0).abs() < 1e-6);
+        assert!((summary.emulation_ms - 4.0).abs() < 1e-6);
+        assert!((summary.render_ms - 1.0).abs() < 1e-6);
+        assert_eq!(summary.audio_fill_ms, (WINDOW + 9) as f32);
+        assert_eq!(summary.to_string(), "FPS 50.0 EMU 4.00MS REN 1.00MS AUD 69.0MS");
+
+        let mut frame = Frame::new();
+        draw_overlay(&mut frame, &summary);
+        assert_eq!(frame.get_pixel(2, 2), Some((255, 255, 255))); // 'F' の左上
+        assert_eq!(frame.get_pixel(1, 1), Some((0, 0, 0)));
+    }
+}
@@ -0,0 +1,150 @@
+// 電源投入時の状態 (RAMの中身, CPUとPPU/APUのクロックの位相) の決め方
+// https://www.nesdev.org/wiki/CPU_power_up_state
+// https://www.nesdev.org/wiki/PPU_power_up_state
+// 実機では電源を入れるたびにRAMの中身も位相も変わる。seed を決めておけば (決定的モード)
+// 同じ種と同じ入力からは必ず同じ実行になる (ムービー, ネットプレイ, 巻き戻し, バグ報告の再現用)
+// 種からの値は自前の xorshift で作る (randクレートの版が変わっても同じ種なら同じ値になるように)
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamInit {
+    Pattern, // 4バイトごとに $00/$FF を繰り返す
+    Fill(u8),
+    Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PowerOnState {
+    pub ram: RamInit,
+    pub random_phase: bool, // PPUのドットとAPUのフレームシーケンサの位相をずらす
+    pub seed: Option<u64>,  // Noneなら電源を入れるたびに変わる (決定的でない)
+}
+
+impl PowerOnState {
+    // これまでどおりの固定の状態 (RAMはパターン, 位相は0)
+    pub fn new() -> Self {
+        PowerOnState {
+            ram: RamInit::Pattern,
+            random_phase: false,
+            seed: Some(0),
+        }
+    }
+
+    // RAMも位相も種から決める
+    pub fn deterministic(seed: u64) -> Self {
+        PowerOnState {
+            ram: RamInit::Random,
+            random_phase: true,
+            seed: Some(seed),
+        }
+    }
+
+    // 実機のように毎回変える (RAMの初期値に頼っているゲームを見つける用)
+    pub fn random() -> Self {
+        PowerOnState {
+            ram: RamInit::Random,
+            random_phase: true,
+            seed: None,
+        }
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.seed.is_some() || (self.ram != RamInit::Random && !self.random_phase)
+    }
+
+    // 電源投入1回分の値を作る
+    pub fn roll(&self) -> PowerOnValues {
+        let mut rng = Xorshift::new(self.seed.unwrap_or_else(entropy));
+        let mut ram = [0; 2048];
+        for (addr, v) in ram.iter_mut().enumerate() {
+            *v = match self.ram {
+                RamInit::Pattern if addr & 0x04 == 0 => 0x00,
+                RamInit::Pattern => 0xFF,
+                RamInit::Fill(value) => value,
+                RamInit::Random => rng.next_u64() as u8,
+            };
+        }
+        let (ppu_dot, apu_cycle) = if self.random_phase {
+            ((rng.next_u64() % 3) as usize, (rng.next_u64() % 2) as usize)
+        } else {
+            (0, 0)
+        };
+        PowerOnValues {
+            ram,
+            ppu_dot,
+            apu_cycle,
+        }
+    }
+}
+
+impl Default for PowerOnState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct PowerOnValues {
+    pub ram: [u8; 2048],
+    pub ppu_dot: usize,   // CPUの最初のサイクルでPPUが何ドット進んでいるか (0-2)
+    pub apu_cycle: usize, // APUのフレームシーケンサのサイクルの偶奇 (0-1)
+}
+
+// xorshift64*
+pub struct Xorshift(u64);
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Self {
+        // 0だと0しか出ないので混ぜる
+        Xorshift(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+// 種を決めていないとき用 (wasmでも使える RandomState から取る)
+#[cfg(feature = "std")]
+fn entropy() -> u64 {
+    use core::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new().build_hasher().finish()
+}
+
+// no_std では乱数の元がないので、電源を入れるたびに変わるだけ (起動し直すと同じ並びになる)
+#[cfg(not(feature = "std"))]
+fn entropy() -> u64 {
+    use core::sync::atomic::{AtomicU32, Ordering};
+    static COUNT: AtomicU32 = AtomicU32::new(0);
+    COUNT.fetch_add(1, Ordering::Relaxed) as u64
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_power_on_state() {
+        let values = PowerOnState::new().roll();
+        assert_eq!((values.ram[3], values.ram[4], values.ppu_dot, values.apu_cycle), (0x00, 0xFF, 0, 0));
+        assert_eq!(PowerOnState::deterministic(7).roll().ram, PowerOnState::deterministic(7).roll().ram);
+        assert_ne!(PowerOnState::deterministic(7).roll().ram, PowerOnState::deterministic(8).roll().ram);
+        assert!(!PowerOnState::random().is_deterministic());
+
+        // 同じ種なら電源を入れ直しても同じ実行になる
+        let run = |seed: u64| {
+            let mut nes = TestRom::new().asm(0x8000, "loop: INC $0700\nLDA $2002\nJMP loop").nes().unwrap();
+            nes.set_power_on_state(PowerOnState::deterministic(seed));
+            nes.power_cycle();
+            let ram = nes.ram().to_vec();
+            nes.run_frame();
+            (ram, nes.ram()[0x700], nes.bus().cycles())
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1).0, run(2).0);
+    }
+}
@@ -1,12 +1,16 @@
 use bitflags::bitflags;
-use log::{debug, info, trace};
-use crate::MAPPER;
-use crate::{cpu::IN_TRACE, rom::Mirroring};
+use log::{debug, trace};
+use crate::cartridge::SharedCartridge;
+use crate::region::Region;
+use crate::snapshot::PpuSnapshot;
+use crate::state::{load_resizable, save_resizable, State, StateError, StateReader, StateWriter};
+use crate::{impl_state, impl_state_bits};
+use crate::{cpu::in_trace, rom::Mirroring};
+use alloc::vec::Vec;
 
 pub struct PPU {
-    pub chr_rom: Vec<u8>,
     pub mirroring: Mirroring,
-    pub is_chr_ram: bool,
+    cartridge: SharedCartridge, // CHR (パターンテーブル) とマッパーのIRQ用
 
     pub palette_table: [u8; 32],
     pub vram: [u8; 2048],
@@ -29,8 +33,12 @@ pub struct PPU {
 
     cycles: usize,
     scanline: usize,
+    scanlines: usize,       // 1フレームのライン数 (リージョンで変わる)
+    vblank_scanline: usize, // VBlankが始まるライン
     pub nmi_interrupt: Option<i32>,
     pub clear_nmi_interrupt: bool,
+    a12_clocks: usize, // A12の立ち上がりの回数 (イベントビューア用、ステートには含めない)
+    sprite_zero_hits: usize, // スプライト0ヒットが立った回数 (同上)
 
     // 描画中にパレットテーブルを書き換えることが可能なので、その対応。
     // 書き込まれた時点でのscanlineとその時のパレットのスナップショットを持っておき、
@@ -40,11 +48,10 @@ pub struct PPU {
 }
 
 impl PPU {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring, is_chr_ram: bool) -> Self {
+    pub fn new(mirroring: Mirroring, cartridge: SharedCartridge) -> Self {
         PPU {
-            chr_rom: chr_rom,
             mirroring: mirroring,
-            is_chr_ram: is_chr_ram,
+            cartridge,
             vram: [0; 2048],
             oam_data: [0; 64 * 4],
             oam_addr: 0,
@@ -57,37 +64,69 @@ impl PPU {
             internal_data_buf: 0,
             cycles: 0,
             scanline: 0,
+            scanlines: Region::Ntsc.scanlines(),
+            vblank_scanline: Region::Ntsc.vblank_scanline(),
             nmi_interrupt: None,
             clear_nmi_interrupt: false,
+            a12_clocks: 0,
+            sprite_zero_hits: 0,
             scanline_palette_indexes: vec![],
             scanline_palette_tables: vec![],
         }
     }
 
+    // リセットボタン: $2000/$2001/$2005/$2006のラッチとバッファがクリアされる
+    // (VRAM, OAM, パレットはそのまま)
+    pub fn reset(&mut self) {
+        self.ctrl = ControlRegister::new();
+        self.mask = MaskRegister::new();
+        self.scroll.reset();
+        self.addr.reset_latch();
+        self.internal_data_buf = 0;
+        self.cycles = 0;
+        self.scanline = 0;
+        self.nmi_interrupt = None;
+        self.clear_nmi_interrupt = false;
+    }
+
+    // 電源の入れ直し
+    pub fn power_on(&mut self) {
+        let (scanlines, vblank_scanline) = (self.scanlines, self.vblank_scanline);
+        *self = PPU::new(self.mirroring.clone(), self.cartridge.clone());
+        self.scanlines = scanlines;
+        self.vblank_scanline = vblank_scanline;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.scanlines = region.scanlines();
+        self.vblank_scanline = region.vblank_scanline();
+    }
+
+    // 現在のバンク設定で見えるパターンテーブル ($0000-$1FFF)
+    pub fn pattern_tables(&self) -> Vec<u8> {
+        let mut cartridge = self.cartridge.lock();
+        (0..0x2000).map(|addr| cartridge.ppu_read(addr)).collect()
+    }
 
     pub fn read_data(&mut self) -> u8 {
         let addr = self.addr.get();
-        if !unsafe { IN_TRACE } {
+        if !in_trace() {
             self.increment_vram_addr();
         }
-        debug!("READ PPU: {:04X}", addr);
+        debug!(target: "ppu", "READ PPU: {:04X}", addr);
 
         match addr {
             0..=0x1FFF => {
-                if unsafe { IN_TRACE } {
+                if in_trace() {
                     self.internal_data_buf
                 } else {
                     let result = self.internal_data_buf;
-                    let mapper = MAPPER.lock().unwrap().mapper;
-                    match mapper {
-                        3 | 4 => self.internal_data_buf = MAPPER.lock().unwrap().read_chr_rom(addr),
-                        _ => self.internal_data_buf = self.chr_rom[addr as usize],
-                    }
+                    self.internal_data_buf = self.cartridge.lock().ppu_read(addr);
                     result
                 }
             }
             0x2000..=0x2FFF => {
-                if unsafe { IN_TRACE } {
+                if in_trace() {
                     self.internal_data_buf
                 } else {
                     let result = self.internal_data_buf;
@@ -96,7 +135,7 @@ impl PPU {
                 }
             }
             0x3000..=0x3EFF => {
-                if unsafe { IN_TRACE } {
+                if in_trace() {
                     self.internal_data_buf
                 } else {
                     let result = self.internal_data_buf;
@@ -105,7 +144,7 @@ impl PPU {
                 }
             }
             0x3F00..=0x3FFF => {
-                if unsafe { IN_TRACE } {
+                if in_trace() {
                     self.internal_data_buf
                 } else {
                     self.internal_data_buf =
@@ -123,24 +162,19 @@ impl PPU {
 
     pub fn write_to_data(&mut self, value: u8) {
         let addr = self.addr.get();
-        if !unsafe { IN_TRACE } {
+        if !in_trace() {
             self.increment_vram_addr();
         }
-        debug!("WRITE PPU: {:04X} => {:02X}", addr, value);
+        debug!(target: "ppu", "WRITE PPU: {:04X} => {:02X}", addr, value);
 
         match addr {
             0x0000..=0x1FFF => {
-                let mapper = MAPPER.lock().unwrap().mapper;
-                match mapper {
-                    3 | 4 => { MAPPER.lock().unwrap().write(addr, value); },
-                    _ => { if self.is_chr_ram {
-                            self.chr_rom[addr as usize] = value;
-                        }
-                    },
-                }
+                self.cartridge.lock().ppu_write(addr, value);
             }
             0x2000..=0x2FFF => {
+                self.mirroring = self.cartridge.lock().mirroring();
                 trace!(
+                    target: "ppu",
                     "WRITE PPU_VRAM {:04X} {:02X} => ({:02X})",
                     addr,
                     self.mirror_vram_addr(addr) as usize,
@@ -150,6 +184,7 @@ impl PPU {
             }
             0x3000..=0x3EFF => {
                 trace!(
+                    target: "ppu",
                     "WRITE PPU_VRAM MIRROR {:04X} {:02X} => ({:02X})",
                     addr,
                     self.mirror_vram_addr(addr) as usize,
@@ -159,6 +194,7 @@ impl PPU {
             }
             0x3F00..=0x3F1F => {
                 debug!(
+                    target: "ppu",
                     "WRITE PALATTE {:04X} {:02X} => ({:02X}) SL={}",
                     addr,
                     self.mirror_palette_addr(addr) as usize,
@@ -169,6 +205,7 @@ impl PPU {
             }
             0x3F20..=0x3FFF => {
                 debug!(
+                    target: "ppu",
                     "WRITE PALATTE MIRROR {:04X} {:02X} => ({:02X}) SL={}",
                     addr,
                     self.mirror_palette_addr(addr) as usize,
@@ -181,6 +218,24 @@ impl PPU {
         }
     }
 
+    // デバッガ用に $0000-$3FFF を直接読む (アドレスもバッファも進めない)
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => self.cartridge.lock().ppu_read(addr),
+            addr @ 0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(addr) as usize],
+            addr => self.palette_table[self.mirror_palette_addr(addr) as usize],
+        }
+    }
+
+    // デバッガ用に $0000-$3FFF へ直接書く (CHR ROMへの書き込みはマッパー次第で無視される)
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => self.cartridge.lock().ppu_write(addr, value),
+            addr @ 0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(addr) as usize] = value,
+            addr => self.write_palette_table(addr, value),
+        }
+    }
+
     fn write_palette_table(&mut self, addr: u16, value: u8) {
         let addr = self.mirror_palette_addr(addr) as usize;
 
@@ -251,7 +306,7 @@ impl PPU {
 
     pub fn read_status(&mut self) -> u8 {
         // スクロール ($2005)  PPUSTATUSを読み取ってアドレス ラッチをリセットした後
-        if unsafe { IN_TRACE } {
+        if in_trace() {
             self.status.bits()
         } else {
             self.scroll.reset();
@@ -275,7 +330,7 @@ impl PPU {
     }
 
     pub fn write_to_oam_data(&mut self, value: u8) {
-        debug!("OAM: {:04X} => {:02X}", self.oam_addr, value);
+        debug!(target: "ppu", "OAM: {:04X} => {:02X}", self.oam_addr, value);
         self.oam_data[self.oam_addr as usize] = value;
         self.oam_addr = self.oam_addr.wrapping_add(1)
     }
@@ -285,8 +340,8 @@ impl PPU {
     }
 
     pub fn write_to_oam_dma(&mut self, values: [u8; 256]) {
-        debug!("OAM DMA: ADDR:{:02X}", self.oam_addr);
-        debug!("{:?}", values);
+        debug!(target: "ppu", "OAM DMA: ADDR:{:02X}", self.oam_addr);
+        debug!(target: "ppu", "{:?}", values);
         self.oam_data = values;
     }
 
@@ -308,21 +363,91 @@ impl PPU {
             (Mirroring::HORIZONTAL, 2) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 1) => vram_index - 0x400,
             (Mirroring::HORIZONTAL, 3) => vram_index - 0x800,
+            (Mirroring::ONE_SCREEN_LOWER, _) => vram_index & 0x3FF,
+            (Mirroring::ONE_SCREEN_UPPER, _) => 0x400 | (vram_index & 0x3FF),
             _ => vram_index,
         }
     }
 
+    pub fn scanline(&self) -> usize {
+        self.scanline
+    }
+
+    pub fn scanlines(&self) -> usize {
+        self.scanlines
+    }
+
+    // スキャンラインの中の位置 (0-340)
+    pub fn dot(&self) -> usize {
+        self.cycles
+    }
+
+    // 電源投入時のCPUとの位相 (power_on の後に呼ぶ)
+    pub fn set_power_on_dot(&mut self, dot: usize) {
+        self.cycles = dot;
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask.show_background() || self.mask.show_sprites()
+    }
+
+    pub fn a12_clocks(&self) -> usize {
+        self.a12_clocks
+    }
+
+    pub fn sprite_zero_hits(&self) -> usize {
+        self.sprite_zero_hits
+    }
+
+    pub fn snapshot(&self) -> PpuSnapshot {
+        PpuSnapshot {
+            ctrl: self.ctrl.bits(),
+            mask: self.mask.bits(),
+            status: self.status.bits(),
+            oam_addr: self.oam_addr,
+            vram_addr: self.addr.get(),
+            write_latch: !self.addr.hi_ptr,
+            scroll_x: self.scroll.scroll_x,
+            scroll_y: self.scroll.scroll_y,
+            read_buffer: self.internal_data_buf,
+            scanline: self.scanline,
+            dot: self.cycles,
+            nmi_pending: self.nmi_interrupt.is_some(),
+            sprite_zero_hits: self.sprite_zero_hits,
+            a12_clocks: self.a12_clocks,
+            mirroring: self.mirroring.clone(),
+        }
+    }
+
+    pub fn vblank_scanline(&self) -> usize {
+        self.vblank_scanline
+    }
+
     pub fn tick(&mut self, cycles: u8) -> bool {
         self.cycles += cycles as usize;
         if self.cycles >= 341 {
             if self.is_sprite_zero_hit(self.cycles) {
+                if !self.status.contains(StatusRegister::SPRITE_ZERO_HIT) {
+                    self.sprite_zero_hits = self.sprite_zero_hits.wrapping_add(1);
+                }
                 self.status.set_sprite_zero_hit(true);
             }
 
+            // 描画中のスキャンライン(プリレンダ含む)ではスプライトのフェッチでA12が立ち上がる
+            // (BGが$0000, スプライトが$1000の一般的な構成を想定して1ラインに1回)
+            let rendering = self.mask.show_background() || self.mask.show_sprites();
+            let mut cartridge = self.cartridge.lock();
+            if rendering && (self.scanline < 240 || self.scanline == self.scanlines - 1) {
+                cartridge.ppu_a12_rising();
+                self.a12_clocks = self.a12_clocks.wrapping_add(1);
+            }
+            self.mirroring = cartridge.mirroring();
+            drop(cartridge);
+
             self.cycles = self.cycles - 341;
             self.scanline += 1;
 
-            if self.scanline == 241 {
+            if self.scanline == self.vblank_scanline {
                 self.status.set_vblank_status(true);
                 self.status.set_sprite_zero_hit(false);
                 if self.ctrl.generate_vblank_nmi() {
@@ -331,7 +456,7 @@ impl PPU {
                 }
             }
 
-            if self.scanline >= 262 {
+            if self.scanline >= self.scanlines {
                 self.scanline = 0;
                 self.status.set_sprite_zero_hit(false);
                 self.status.reset_vblank_status();
@@ -355,6 +480,49 @@ impl PPU {
     }
 }
 
+// パレットの履歴はフレームの途中で保存したときのために長さごと保存する
+impl State for PPU {
+    fn save(&self, w: &mut StateWriter) {
+        self.mirroring.save(w);
+        self.palette_table.save(w);
+        self.vram.save(w);
+        self.oam_addr.save(w);
+        self.oam_data.save(w);
+        self.addr.save(w);
+        self.ctrl.save(w);
+        self.internal_data_buf.save(w);
+        self.mask.save(w);
+        self.status.save(w);
+        self.scroll.save(w);
+        self.cycles.save(w);
+        self.scanline.save(w);
+        self.nmi_interrupt.save(w);
+        self.clear_nmi_interrupt.save(w);
+        save_resizable(&self.scanline_palette_indexes, w);
+        save_resizable(&self.scanline_palette_tables, w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mirroring.load(r)?;
+        self.palette_table.load(r)?;
+        self.vram.load(r)?;
+        self.oam_addr.load(r)?;
+        self.oam_data.load(r)?;
+        self.addr.load(r)?;
+        self.ctrl.load(r)?;
+        self.internal_data_buf.load(r)?;
+        self.mask.load(r)?;
+        self.status.load(r)?;
+        self.scroll.load(r)?;
+        self.cycles.load(r)?;
+        self.scanline.load(r)?;
+        self.nmi_interrupt.load(r)?;
+        self.clear_nmi_interrupt.load(r)?;
+        load_resizable(&mut self.scanline_palette_indexes, r)?;
+        load_resizable(&mut self.scanline_palette_tables, r)
+    }
+}
+
 pub struct AddrRegister {
     value: (u8, u8),
     hi_ptr: bool,
@@ -406,6 +574,14 @@ impl AddrRegister {
     }
 }
 
+impl Default for AddrRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_state!(AddrRegister { value, hi_ptr });
+
 bitflags! {
     pub struct ControlRegister: u8 {
         const NAMETABLE1               = 0b0000_0001;
@@ -475,6 +651,12 @@ impl ControlRegister {
     }
 }
 
+impl Default for ControlRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 bitflags! {
     pub struct StatusRegister: u8 {
         const PPU_OPEN_BUS1       = 0b0000_0001;
@@ -514,6 +696,12 @@ impl StatusRegister {
     }
 }
 
+impl Default for StatusRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 bitflags! {
     pub struct MaskRegister: u8 {
         const GREYSCALE               = 0b0000_0001;
@@ -532,6 +720,10 @@ impl MaskRegister {
         MaskRegister::from_bits_truncate(0b0000_0000)
     }
 
+    pub fn show_background(&self) -> bool {
+        self.contains(MaskRegister::SHOW_BACKGROUND)
+    }
+
     pub fn show_sprites(&self) -> bool {
         self.contains(MaskRegister::SHOW_SPRITES)
     }
@@ -541,12 +733,21 @@ impl MaskRegister {
     }
 }
 
+impl Default for MaskRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct ScrollRegister {
     pub scroll_x: u8,
     pub scroll_y: u8,
     write_x: bool,
 }
 
+impl_state!(ScrollRegister { scroll_x, scroll_y, write_x });
+impl_state_bits!(ControlRegister, StatusRegister, MaskRegister);
+
 impl ScrollRegister {
     pub fn new() -> Self {
         ScrollRegister {
@@ -569,3 +770,9 @@ impl ScrollRegister {
         self.write_x = true;
     }
 }
+
+impl Default for ScrollRegister {
+    fn default() -> Self {
+        Self::new()
+    }
+}
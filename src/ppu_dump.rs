@@ -0,0 +1,189 @@
+// PPUのメモリ (VRAM, ネームテーブル, OAM, パレット) をファイルに書き出す (表示の乱れを後で調べる用)
+// https://www.nesdev.org/wiki/PPU_memory_map
+// https://www.nesdev.org/wiki/PPU_OAM
+// そのままのバイト列 (.bin) と、絵にしたもの (.png) の両方を書く
+//   Vram: PPU空間 $0000-$3FFF (16KB) / パターンテーブル (パレット0)
+//   Nametables: $2000-$2FFF (ミラーリング込み 4KB) / 4画面分
+//   Oam: 256バイト / スプライト64個を8x8に並べる (反転込み, 8x16モードなら縦長)
+//   Palettes: 32バイト / 16色x2段 (背景, スプライト)
+use crate::capture::encode_png;
+use crate::debugger::{name_tables, pattern_tables, Rgb, NAME_TABLE_HEIGHT, NAME_TABLE_WIDTH, PATTERN_TABLE_HEIGHT, PATTERN_TABLE_WIDTH};
+use crate::error::EmuError;
+use crate::memory_view::{read_block, MemorySpace};
+use crate::nes::Nes;
+use crate::palette;
+use std::path::{Path, PathBuf};
+
+const SWATCH_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PpuRegion {
+    Vram,
+    Nametables,
+    Oam,
+    Palettes,
+}
+
+impl PpuRegion {
+    pub const ALL: [PpuRegion; 4] = [PpuRegion::Vram, PpuRegion::Nametables, PpuRegion::Oam, PpuRegion::Palettes];
+
+    // ファイル名に使う
+    pub fn name(&self) -> &'static str {
+        match self {
+            PpuRegion::Vram => "vram",
+            PpuRegion::Nametables => "nametables",
+            PpuRegion::Oam => "oam",
+            PpuRegion::Palettes => "palettes",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        PpuRegion::ALL.iter().copied().find(|region| region.name() == name.to_ascii_lowercase())
+    }
+}
+
+pub struct PpuImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl PpuImage {
+    pub fn to_png(&self) -> Vec<u8> {
+        encode_png(self.width, self.height, &self.pixels)
+    }
+}
+
+// 副作用なしで読む
+pub fn dump_raw(nes: &mut Nes, region: PpuRegion) -> Result<Vec<u8>, EmuError> {
+    match region {
+        PpuRegion::Vram => read_block(nes, MemorySpace::Ppu, 0x0000, 0x4000),
+        PpuRegion::Nametables => read_block(nes, MemorySpace::Ppu, 0x2000, 0x1000),
+        PpuRegion::Oam => read_block(nes, MemorySpace::Oam, 0, 256),
+        PpuRegion::Palettes => read_block(nes, MemorySpace::Palette, 0, 32),
+    }
+}
+
+pub fn dump_image(nes: &Nes, region: PpuRegion) -> PpuImage {
+    match region {
+        PpuRegion::Vram => PpuImage {
+            width: PATTERN_TABLE_WIDTH,
+            height: PATTERN_TABLE_HEIGHT,
+            pixels: pattern_tables(nes, 0),
+        },
+        PpuRegion::Nametables => PpuImage {
+            width: NAME_TABLE_WIDTH,
+            height: NAME_TABLE_HEIGHT,
+            pixels: name_tables(nes),
+        },
+        PpuRegion::Oam => sprites(nes),
+        PpuRegion::Palettes => palettes(nes),
+    }
+}
+
+// dir/<prefix>_<名前>.bin と .png をすべて書き、書いたファイルを返す
+pub fn save_all(nes: &mut Nes, dir: &Path, prefix: &str) -> Result<Vec<PathBuf>, EmuError> {
+    std::fs::create_dir_all(dir)?;
+    let mut paths = vec![];
+    for region in PpuRegion::ALL {
+        let path = dir.join(format!("{}_{}.bin", prefix, region.name()));
+        std::fs::write(&path, dump_raw(nes, region)?)?;
+        paths.push(path);
+        let path = dir.join(format!("{}_{}.png", prefix, region.name()));
+        std::fs::write(&path, dump_image(nes, region).to_png())?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+fn sprites(nes: &Nes) -> PpuImage {
+    let ppu = nes.bus().ppu();
+    let chr = ppu.pattern_tables();
+    let tall = ppu.ctrl.is_sprite_8x16_mode();
+    let height = if tall { 16 } else { 8 };
+    let mut image = vec![palette::system_palette()[ppu.palette_table[0] as usize & 0x3F]; 64 * height * 8];
+    for (n, sprite) in ppu.oam_data.chunks(4).enumerate() {
+        let (tile, attr) = (sprite[1] as usize, sprite[2]);
+        let (flip_v, flip_h) = (attr & 0x80 != 0, attr & 0x40 != 0);
+        let (ox, oy) = (n % 8 * 8, n / 8 * height);
+        for y in 0..height {
+            let row = if flip_v { height - 1 - y } else { y };
+            let addr = if tall {
+                (tile & 1) * 0x1000 + ((tile & 0xFE) + row / 8) * 16 + row % 8
+            } else {
+                ppu.ctrl.sprite_pattern_addr() as usize + tile * 16 + row
+            };
+            let lo = chr.get(addr).copied().unwrap_or(0);
+            let hi = chr.get(addr + 8).copied().unwrap_or(0);
+            for x in 0..8 {
+                let bit = if flip_h { x } else { 7 - x };
+                let value = (((hi >> bit) & 1) << 1 | ((lo >> bit) & 1)) as usize;
+                if value != 0 {
+                    let index = ppu.palette_table[0x10 + (attr as usize & 0x03) * 4 + value];
+                    image[(oy + y) * 64 + ox + x] = palette::system_palette()[index as usize & 0x3F];
+                }
+            }
+        }
+    }
+    PpuImage {
+        width: 64,
+        height: height * 8,
+        pixels: image,
+    }
+}
+
+fn palettes(nes: &Nes) -> PpuImage {
+    let ppu = nes.bus().ppu();
+    let (width, height) = (16 * SWATCH_SIZE, 2 * SWATCH_SIZE);
+    let pixels = (0..width * height)
+        .map(|i| {
+            let (x, y) = (i % width / SWATCH_SIZE, i / width / SWATCH_SIZE);
+            palette::system_palette()[ppu.palette_table[y * 16 + x] as usize & 0x3F]
+        })
+        .collect();
+    PpuImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_ppu_dump() {
+        // スプライト0に タイル1 (左上の1ドットだけ色3) を左右反転で置く
+        let mut chr = vec![0; 0x2000];
+        chr[0x10] = 0x80;
+        chr[0x18] = 0x80;
+        let mut nes = TestRom::new().chr(&chr).nes().unwrap();
+        {
+            let ppu = nes.bus_mut().ppu_mut();
+            ppu.oam_data[..4].copy_from_slice(&[0x10, 0x01, 0x41, 0x20]);
+            ppu.palette_table[0x17] = 0x16;
+            ppu.vram[0] = 0x01;
+        }
+        assert_eq!(dump_raw(&mut nes, PpuRegion::Oam).unwrap()[..4], [0x10, 0x01, 0x41, 0x20]);
+        assert_eq!(dump_raw(&mut nes, PpuRegion::Palettes).unwrap()[0x17], 0x16);
+        assert_eq!(dump_raw(&mut nes, PpuRegion::Vram).unwrap().len(), 0x4000);
+        assert_eq!(dump_raw(&mut nes, PpuRegion::Nametables).unwrap()[0], 0x01);
+
+        let oam = dump_image(&nes, PpuRegion::Oam);
+        assert_eq!((oam.width, oam.height), (64, 64));
+        assert_eq!(oam.pixels[7], palette::system_palette()[0x16]);
+        assert_ne!(oam.pixels[0], oam.pixels[7]);
+        let palettes = dump_image(&nes, PpuRegion::Palettes);
+        assert_eq!(palettes.pixels[SWATCH_SIZE * 16 * SWATCH_SIZE + 7 * SWATCH_SIZE], palette::system_palette()[0x16]);
+        assert!(dump_image(&nes, PpuRegion::Nametables).to_png().starts_with(b"\x89PNG"));
+        assert_eq!(PpuRegion::parse("OAM"), Some(PpuRegion::Oam));
+
+        let dir = std::env::temp_dir().join(format!("rscom_ppu_dump_{}", std::process::id()));
+        let paths = save_all(&mut nes, &dir, "test").unwrap();
+        assert_eq!(paths.len(), 8);
+        assert_eq!(std::fs::read(dir.join("test_palettes.bin")).unwrap().len(), 32);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+// サブルーチンごとの実行時間 (CPUサイクル) を集計する
+// https://www.nesdev.org/wiki/Cycle_counting
+// JSR と割り込み (NMI/IRQ/BRK) で呼び出しを積み、RTS/RTI でスタックポインタが戻ったら抜けたとみなす
+// (PLA PLA で戻り先を捨てるような書き方でも、SPが呼び出し前より上に戻れば外側の分もまとめて抜ける)
+//   inclusive: 呼び出してから戻るまで (中から呼んだサブルーチンを含む)
+//   exclusive: inclusive から中で呼んだサブルーチンの分を引いたもの
+// 戻る前に集計を止めた呼び出しは数えない (メインループのように戻ってこないもの)
+use crate::bus::Mem;
+use crate::cpu::{set_in_trace, CPU};
+use crate::nes::Nes;
+use std::collections::HashMap;
+
+const JSR: u8 = 0x20;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FunctionProfile {
+    pub addr: u16,
+    pub calls: usize,
+    pub inclusive: usize,
+    pub exclusive: usize,
+}
+
+impl std::fmt::Display for FunctionProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "${:04X}  calls {:>7}  incl {:>10}  excl {:>10}",
+            self.addr, self.calls, self.inclusive, self.exclusive
+        )
+    }
+}
+
+// 呼び出し中のサブルーチン
+struct Call {
+    addr: u16,
+    sp: u8, // 呼び出す前のSP (ここまで戻ったら抜けた)
+    start: usize,
+    child: usize,
+}
+
+pub struct Profiler {
+    stack: Vec<Call>,
+    functions: HashMap<u16, FunctionProfile>,
+    // 前の命令が JSR なら飛び先
+    pending_call: Option<(u16, u8)>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            stack: vec![],
+            functions: HashMap::new(),
+            pending_call: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+        self.functions.clear();
+        self.pending_call = None;
+    }
+
+    // 1フレーム実行して集計する
+    pub fn run_frame(&mut self, nes: &mut Nes) {
+        nes.cpu_mut().run_frame_with_callback(|cpu| self.record(cpu));
+    }
+
+    // 命令を実行する直前 (run_frame_with_callback のコールバック) に呼ぶ
+    pub fn record(&mut self, cpu: &mut CPU) {
+        let now = cpu.bus.cycles();
+        let pc = cpu.program_counter.wrapping_sub(1);
+        let sp = cpu.stack_pointer;
+
+        // RTS/RTI (や PLA) でSPが呼び出し前まで戻ったものは抜けた
+        while self.stack.last().is_some_and(|call| call.sp <= sp) {
+            self.leave(now);
+        }
+
+        set_in_trace(true);
+        let op = cpu.mem_read(pc);
+        let target = cpu.mem_read_u16(pc.wrapping_add(1));
+        let vectors = [cpu.mem_read_u16(NMI_VECTOR), cpu.mem_read_u16(IRQ_VECTOR)];
+        set_in_trace(false);
+
+        // 前の命令が JSR だった
+        if let Some((addr, sp)) = self.pending_call.take() {
+            self.enter(addr, sp, now);
+        }
+        // 割り込みの入口 (PC, Pが積まれている)。JSR で飛んできたときは除く
+        if vectors.contains(&pc) && self.stack.last().is_none_or(|call| call.addr != pc || call.start != now) {
+            self.enter(pc, sp.wrapping_add(3), now);
+        }
+        if op == JSR {
+            self.pending_call = Some((target, sp));
+        }
+    }
+
+    fn enter(&mut self, addr: u16, sp: u8, now: usize) {
+        self.stack.push(Call {
+            addr,
+            sp,
+            start: now,
+            child: 0,
+        });
+    }
+
+    fn leave(&mut self, now: usize) {
+        let call = self.stack.pop().unwrap();
+        let inclusive = now - call.start;
+        if let Some(parent) = self.stack.last_mut() {
+            parent.child += inclusive;
+        }
+        let entry = self.functions.entry(call.addr).or_insert(FunctionProfile {
+            addr: call.addr,
+            calls: 0,
+            inclusive: 0,
+            exclusive: 0,
+        });
+        entry.calls += 1;
+        entry.inclusive += inclusive;
+        entry.exclusive += inclusive - call.child;
+    }
+
+    // exclusive の多い順
+    pub fn report(&self) -> Vec<FunctionProfile> {
+        let mut report: Vec<FunctionProfile> = self.functions.values().copied().collect();
+        report.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.addr.cmp(&b.addr)));
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::{AudioConfig, APU};
+    use crate::rom::Rom;
+
+    #[test]
+    fn test_profiler() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        let program = [
+            0x20, 0x10, 0x80, // loop: JSR outer
+            0x4C, 0x00, 0x80, // JMP loop
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        let outer = [
+            0x20, 0x20, 0x80, // outer: JSR inner
+            0x20, 0x20, 0x80, // JSR inner
+            0x60, // RTS
+        ];
+        prg[0x10..0x10 + outer.len()].copy_from_slice(&outer);
+        prg[0x20] = 0x60; // inner: RTS
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        let mut nes = Nes::new(Rom::from_bytes(&raw).unwrap(), APU::headless(&AudioConfig::new())).unwrap();
+
+        let mut profiler = Profiler::new();
+        for _ in 0..100 {
+            nes.cpu_mut().step_with_callback(|cpu| profiler.record(cpu));
+        }
+        let report = profiler.report();
+        let outer = report.iter().find(|f| f.addr == 0x8010).unwrap();
+        let inner = report.iter().find(|f| f.addr == 0x8020).unwrap();
+        assert_eq!(report.len(), 2);
+        assert_eq!(inner.calls, outer.calls * 2);
+        // 呼び出した JSR は呼んだ側に数える。inner: RTS, outer: JSR x2 + RTS
+        assert_eq!(inner.inclusive, inner.calls * 6);
+        assert_eq!(outer.exclusive, outer.calls * (6 + 6 + 6));
+        assert_eq!(outer.inclusive, outer.exclusive + 2 * outer.calls * 6);
+    }
+}
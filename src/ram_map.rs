@@ -0,0 +1,249 @@
+// ゲームごとのRAMの意味 (スコア, 自機の座標など) を書いたファイル
+// 生のRAMの値を、ボットやオーバーレイ, RTAのツールで使える値にする
+// 1行に1つ: <名前> <アドレス> <型> [<符号化>]  (# から後はコメント, アドレスは16進)
+//   型: u8 u16 u24 ... u64 (符号なし), s8 s16 s32 (符号付き), bool (0以外ならtrue)
+//   符号化: le (リトルエンディアン, 既定), be, bcd (1バイトに2桁, 上の桁から), digits (1バイトに1桁, 上の桁から)
+//   例: score $07DD u48 digits / player_x $0086 u8 / lives $075A u8
+// ROMと同じ場所の <ROMの名前>.rammap を読む (load_beside)
+use crate::error::EmuError;
+use crate::memory_view::{read_block, MemorySpace};
+use crate::nes::Nes;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldType {
+    Unsigned(usize), // バイト数
+    Signed(usize),
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Little,
+    Big,
+    Bcd,
+    Digits,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RamField {
+    pub name: String,
+    pub addr: u16,
+    pub kind: FieldType,
+    pub encoding: Encoding,
+}
+
+impl RamField {
+    pub fn size(&self) -> usize {
+        match self.kind {
+            FieldType::Unsigned(size) | FieldType::Signed(size) => size,
+            FieldType::Bool => 1,
+        }
+    }
+
+    // BCDや桁に0-9以外が入っていたらNone
+    pub fn decode(&self, bytes: &[u8]) -> Option<i64> {
+        let value = match self.encoding {
+            Encoding::Little => bytes.iter().rev().fold(0u64, |v, &b| v << 8 | b as u64),
+            Encoding::Big => bytes.iter().fold(0u64, |v, &b| v << 8 | b as u64),
+            Encoding::Bcd => bytes.iter().try_fold(0u64, |v, &b| {
+                if b >> 4 > 9 || b & 0x0F > 9 {
+                    return None;
+                }
+                Some(v * 100 + (b >> 4) as u64 * 10 + (b & 0x0F) as u64)
+            })?,
+            Encoding::Digits => bytes.iter().try_fold(0u64, |v, &b| if b > 9 { None } else { Some(v * 10 + b as u64) })?,
+        };
+        Some(match self.kind {
+            FieldType::Unsigned(_) => value as i64,
+            FieldType::Signed(size) => {
+                let shift = 64 - size * 8;
+                (value << shift) as i64 >> shift
+            }
+            FieldType::Bool => (value != 0) as i64,
+        })
+    }
+
+    pub fn format_value(&self, value: Option<i64>) -> String {
+        match (self.kind, value) {
+            (_, None) => "??".to_string(),
+            (FieldType::Bool, Some(value)) => (value != 0).to_string(),
+            (_, Some(value)) => value.to_string(),
+        }
+    }
+
+    // 副作用なしで読む
+    pub fn read(&self, nes: &mut Nes) -> Result<Option<i64>, EmuError> {
+        let bytes = read_block(nes, MemorySpace::Cpu, self.addr, self.size())?;
+        Ok(self.decode(&bytes))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RamValue {
+    pub name: String,
+    pub addr: u16,
+    pub value: Option<i64>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RamMap {
+    fields: Vec<RamField>,
+}
+
+impl RamMap {
+    pub fn new() -> Self {
+        RamMap { fields: vec![] }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut map = RamMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let field = parse_field(&fields).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            if map.field(&field.name).is_some() {
+                return Err(format!("line {}: duplicate name: {}", i + 1, field.name));
+            }
+            map.fields.push(field);
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        RamMap::parse(&text)
+    }
+
+    // game.nes なら game.rammap (なければNone)
+    pub fn load_beside(rom_path: &Path) -> Option<Result<Self, String>> {
+        let path = rom_path.with_extension("rammap");
+        if path.exists() {
+            Some(RamMap::load(&path))
+        } else {
+            None
+        }
+    }
+
+    pub fn fields(&self) -> &[RamField] {
+        &self.fields
+    }
+
+    pub fn field(&self, name: &str) -> Option<&RamField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    // 名前の値 (知らない名前ならNone)
+    pub fn value(&self, nes: &mut Nes, name: &str) -> Option<i64> {
+        self.field(name)?.read(nes).ok()?
+    }
+
+    // すべての値 (フレームごとに呼ぶ)
+    pub fn read_all(&self, nes: &mut Nes) -> Result<Vec<RamValue>, EmuError> {
+        let mut values = vec![];
+        for field in &self.fields {
+            let value = field.read(nes)?;
+            values.push(RamValue {
+                name: field.name.clone(),
+                addr: field.addr,
+                value,
+                text: field.format_value(value),
+            });
+        }
+        Ok(values)
+    }
+}
+
+impl Default for RamMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_field(fields: &[&str]) -> Result<RamField, String> {
+    let (name, addr, kind, encoding) = match fields {
+        [name, addr, kind] => (name, addr, kind, "le"),
+        [name, addr, kind, encoding] => (name, addr, kind, *encoding),
+        _ => return Err("expected <name> <address> <type> [encoding]".to_string()),
+    };
+    let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).map_err(|_| format!("bad address: {}", addr))?;
+    let bits = |text: &str| match text.parse::<usize>() {
+        Ok(bits) if bits % 8 == 0 && (8..=64).contains(&bits) => Ok(bits / 8),
+        _ => Err(format!("bad type: {}", kind)),
+    };
+    let kind = match *kind {
+        "bool" => FieldType::Bool,
+        _ if kind.starts_with('u') => FieldType::Unsigned(bits(&kind[1..])?),
+        _ if kind.starts_with('s') => FieldType::Signed(bits(&kind[1..])?),
+        _ => return Err(format!("bad type: {}", kind)),
+    };
+    let encoding = match encoding {
+        "le" => Encoding::Little,
+        "be" => Encoding::Big,
+        "bcd" => Encoding::Bcd,
+        "digits" => Encoding::Digits,
+        _ => return Err(format!("bad encoding: {}", encoding)),
+    };
+    let field = RamField {
+        name: name.to_string(),
+        addr,
+        kind,
+        encoding,
+    };
+    if field.addr as usize + field.size() > 0x10000 {
+        return Err(format!("address out of range: ${:04X}", field.addr));
+    }
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_ram_map() {
+        let text = "
+            # スコアは1バイトに1桁
+            score    $07DD u48 digits
+            coins    $07ED u8  bcd   # 2桁
+            player_x $0086 u8
+            speed    $0057 s8
+            timer    $0010 u16 be
+            alive    $0011 bool
+        ";
+        let map = RamMap::parse(text).unwrap();
+        assert_eq!(map.fields().len(), 6);
+        assert_eq!(map.field("score").unwrap().size(), 6);
+        assert!(RamMap::parse("a $10 u12").is_err());
+        assert!(RamMap::parse("a $10 u8\na $11 u8").is_err());
+        assert!(RamMap::parse("a $FFFF u16").is_err());
+        assert_eq!(RamMap::parse("a 10 u8 gray").unwrap_err(), "line 1: bad encoding: gray");
+
+        let mut nes = TestRom::new().asm(0x8000, "loop: JMP loop").nes().unwrap();
+        nes.ram_mut()[0x7DD..0x7E3].copy_from_slice(&[0, 1, 2, 3, 5, 0]);
+        nes.ram_mut()[0x7ED] = 0x42;
+        nes.ram_mut()[0x86] = 0xC8;
+        nes.ram_mut()[0x57] = 0xFD;
+        nes.ram_mut()[0x10..0x12].copy_from_slice(&[0x01, 0x02]);
+        assert_eq!(map.value(&mut nes, "score"), Some(12350));
+        assert_eq!(map.value(&mut nes, "coins"), Some(42));
+        assert_eq!(map.value(&mut nes, "speed"), Some(-3));
+        assert_eq!(map.value(&mut nes, "timer"), Some(0x0102));
+        assert_eq!(map.value(&mut nes, "lives"), None);
+
+        let values = map.read_all(&mut nes).unwrap();
+        assert_eq!(values[2].text, "200");
+        assert_eq!(values[5].text, "true");
+        // BCDに A-F が入っていたら読めない
+        nes.ram_mut()[0x7ED] = 0x4A;
+        assert_eq!(map.read_all(&mut nes).unwrap()[1].text, "??");
+    }
+}
@@ -0,0 +1,119 @@
+// RAMサーチ (チートを探す): CPU RAMのスナップショットを取っては条件で候補のアドレスを絞り込む
+// https://fceux.com/web/help/CheatSearch.html
+// 例: 残機が3のときに Equal(3) -> 1機減らして Decreased -> ChangedBy(-1) ...
+// 比べるのは前回絞り込んだときの値 (スナップショット)。絞り込むたびにスナップショットを取り直す
+
+const RAM_SIZE: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchFilter {
+    // 今の値と比べる
+    Equal(u8),
+    NotEqual(u8),
+    Greater(u8),
+    Less(u8),
+    // 前回の値と比べる
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    ChangedBy(i16), // 今 - 前回 (8ビットで回り込んだものは数えない)
+}
+
+impl SearchFilter {
+    pub fn matches(&self, previous: u8, current: u8) -> bool {
+        match *self {
+            SearchFilter::Equal(v) => current == v,
+            SearchFilter::NotEqual(v) => current != v,
+            SearchFilter::Greater(v) => current > v,
+            SearchFilter::Less(v) => current < v,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Unchanged => current == previous,
+            SearchFilter::Increased => current > previous,
+            SearchFilter::Decreased => current < previous,
+            SearchFilter::ChangedBy(n) => current as i16 - previous as i16 == n,
+        }
+    }
+}
+
+pub struct RamSearch {
+    candidates: Vec<u16>,
+    previous: [u8; RAM_SIZE],
+}
+
+impl RamSearch {
+    // 全アドレスを候補にして始める
+    pub fn new(ram: &[u8; RAM_SIZE]) -> Self {
+        RamSearch {
+            candidates: (0..RAM_SIZE as u16).collect(),
+            previous: *ram,
+        }
+    }
+
+    pub fn reset(&mut self, ram: &[u8; RAM_SIZE]) {
+        *self = RamSearch::new(ram);
+    }
+
+    // 絞り込まずにスナップショットだけ取り直す
+    pub fn snapshot(&mut self, ram: &[u8; RAM_SIZE]) {
+        self.previous = *ram;
+    }
+
+    // 残った候補の数を返す
+    pub fn filter(&mut self, ram: &[u8; RAM_SIZE], filter: SearchFilter) -> usize {
+        let previous = &self.previous;
+        self.candidates
+            .retain(|&addr| filter.matches(previous[addr as usize], ram[addr as usize]));
+        self.previous = *ram;
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // 候補から外す (UIで明らかに違うものを消す)
+    pub fn exclude(&mut self, addr: u16) -> bool {
+        let len = self.candidates.len();
+        self.candidates.retain(|&a| a != addr);
+        self.candidates.len() != len
+    }
+
+    // 前回の値
+    pub fn previous(&self, addr: u16) -> u8 {
+        self.previous[addr as usize % RAM_SIZE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ram_search() {
+        let mut ram = [0; RAM_SIZE];
+        ram[0x30] = 3; // 残機
+        ram[0x40] = 3;
+        ram[0x50] = 3;
+        let mut search = RamSearch::new(&ram);
+        assert_eq!(search.filter(&ram, SearchFilter::Equal(3)), 3);
+
+        ram[0x30] = 2;
+        ram[0x40] = 5;
+        ram[0x50] = 2;
+        assert_eq!(search.filter(&ram, SearchFilter::Decreased), 2);
+        ram[0x30] = 1;
+        assert_eq!(search.filter(&ram, SearchFilter::ChangedBy(-1)), 1);
+        assert_eq!(search.candidates(), &[0x30]);
+        assert_eq!(search.previous(0x30), 1);
+        assert!(search.exclude(0x30) && !search.exclude(0x30));
+
+        search.reset(&ram);
+        ram[0x10] = 1;
+        search.snapshot(&ram);
+        assert_eq!(search.filter(&ram, SearchFilter::Unchanged), RAM_SIZE);
+        ram[0x10] = 0xFF;
+        assert_eq!(search.filter(&ram, SearchFilter::Changed), 1);
+        assert!(!SearchFilter::ChangedBy(1).matches(0xFF, 0x00));
+    }
+}
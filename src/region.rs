@@ -0,0 +1,140 @@
+// 本体の地域 (NTSC/PAL/Dendy) ごとのタイミング
+// https://www.nesdev.org/wiki/Cycle_reference_chart
+// https://www.nesdev.org/wiki/NES_2.0#CPU/PPU_Timing
+use crate::rom::RomHeader;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc,  // 北米・日本 (RP2A03/RP2C02)
+    Pal,   // 欧州 (RP2A07/RP2C07)
+    Dendy, // ロシアなどの互換機 (PALのクロックでNTSCに近い動作)
+}
+
+impl Region {
+    // NES 2.0 の byte12 (iNESにはないのでNone。マルチリージョンもNone)
+    // NSFはPALフラグが立っていればPAL
+    pub fn from_header(header: &RomHeader) -> Option<Region> {
+        if !header.is_nes2 && header.timing == 0 {
+            return None;
+        }
+        match header.timing {
+            0 => Some(Region::Ntsc),
+            1 => Some(Region::Pal),
+            3 => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    // ファイル名のタグから推測する (GoodNES/No-Intro の命名)
+    pub fn from_filename(path: &str) -> Option<Region> {
+        let name = path.to_ascii_lowercase();
+        let has = |tags: &[&str]| tags.iter().any(|tag| name.contains(tag));
+        if has(&["(dendy)"]) {
+            Some(Region::Dendy)
+        } else if has(&["(e)", "(europe)", "(pal)", "(a)", "(australia)", "(g)", "(germany)", "(f)", "(france)"]) {
+            Some(Region::Pal)
+        } else if has(&["(u)", "(usa)", "(j)", "(japan)", "(ntsc)", "(ju)", "(jue)"]) {
+            Some(Region::Ntsc)
+        } else {
+            None
+        }
+    }
+
+    // ヘッダ -> ファイル名 の順に調べ、分からなければNTSC
+    pub fn detect(header: &RomHeader, path: &str) -> Region {
+        Region::from_header(header)
+            .or_else(|| Region::from_filename(path))
+            .unwrap_or(Region::Ntsc)
+    }
+
+    // CPUクロック (Hz)
+    pub fn cpu_clock(&self) -> f32 {
+        match self {
+            Region::Ntsc => 1_789_772.7,  // 21.477272 MHz / 12
+            Region::Pal => 1_662_607.0,   // 26.601712 MHz / 16
+            Region::Dendy => 1_773_447.5, // 26.601712 MHz / 15
+        }
+    }
+
+    // CPU 1サイクルあたりのPPUドット (分子, 分母)
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (u32, u32) {
+        match self {
+            Region::Pal => (16, 5), // 3.2
+            _ => (3, 1),
+        }
+    }
+
+    // 1フレームのスキャンライン数 (プリレンダを含む)
+    pub fn scanlines(&self) -> usize {
+        match self {
+            Region::Ntsc => 262,
+            _ => 312,
+        }
+    }
+
+    // VBlankフラグが立つスキャンライン
+    // Dendyはポストレンダが長く、VBlankの長さはNTSCと同じ20ライン
+    pub fn vblank_scanline(&self) -> usize {
+        match self {
+            Region::Dendy => 291,
+            _ => 241,
+        }
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        let dots_per_frame = (self.scanlines() * 341) as f64;
+        let (num, den) = self.ppu_dots_per_cpu_cycle();
+        self.cpu_clock() as f64 * num as f64 / den as f64 / dots_per_frame
+    }
+
+    // 動画に書き出すときの正確なフレームレート (分子, 分母)
+    // マスタークロック / (ドットの分周 x 1フレームのドット数)
+    pub fn frame_rate_fraction(&self) -> (u64, u64) {
+        let dots_per_frame = (self.scanlines() * 341) as u64;
+        match self {
+            Region::Ntsc => (236_250_000, 11 * 4 * dots_per_frame), // 21.477272 MHz = 236.25MHz / 11
+            _ => (26_601_712, 5 * dots_per_frame),
+        }
+    }
+
+    // APUのノイズ周期とフレームシーケンサはPALだけが違う (DendyはNTSCと同じ)
+    pub fn is_pal_apu(&self) -> bool {
+        *self == Region::Pal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_detect() {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0x08];
+        raw.resize(16, 0);
+        raw[12] = 1;
+        let header = RomHeader::parse(&raw).unwrap();
+        assert_eq!(Region::from_header(&header), Some(Region::Pal));
+        // ヘッダが優先
+        assert_eq!(Region::detect(&header, "rom/Game (U).nes"), Region::Pal);
+
+        // iNESではファイル名で判断する
+        raw[7] = 0;
+        raw[12] = 0;
+        let header = RomHeader::parse(&raw).unwrap();
+        assert_eq!(Region::from_header(&header), None);
+        assert_eq!(Region::detect(&header, "rom/Game (Europe).nes"), Region::Pal);
+        assert_eq!(Region::detect(&header, "rom/Game (Dendy).nes"), Region::Dendy);
+        assert_eq!(Region::detect(&header, "rom/Game.nes"), Region::Ntsc);
+    }
+
+    #[test]
+    fn test_region_frame_rate() {
+        assert!((Region::Ntsc.frame_rate() - 60.10).abs() < 0.01);
+        assert!((Region::Pal.frame_rate() - 50.01).abs() < 0.01);
+        assert!((Region::Dendy.frame_rate() - 50.01).abs() < 0.01);
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let (num, den) = region.frame_rate_fraction();
+            assert!((num as f64 / den as f64 - region.frame_rate()).abs() < 0.001);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// 外部のツール (エディタの拡張, 自動テストプレイ) から操作するためのTCPサーバー
+// 1行に1コマンド (空白区切り, アドレスと値は16進)。応答は1行のJSON ({"ok":true,...} か {"ok":false,"error":"..."})
+//   load <PATH>                     ROMを読み込む
+//   reset / power                   リセット / 電源の入れ直し
+//   frame [N]                       Nフレーム実行 (ブレークポイントやイベントで止まる)
+//   step [N]                        N命令実行
+//   regs                            CPUのレジスタ
+//   read <ADDR> <LEN> [SPACE]       メモリを読む (SPACE: cpu, ppu, oam, palette)
+//   write <ADDR> <HEX> [SPACE]      メモリに書く
+//   break <ADDR> / delete <ADDR> / breakpoints
+//   input <PLAYER> <BUTTONS>        コントローラーの状態 (gamepad::Button のビット)
+//   framebuffer                     画面 (256x240 RGB) を16進で
+//   quit                            切断
+// WebSocketは話さないので、ブラウザからは websockify などで中継する
+use crate::cartridge::load_rom;
+use crate::checksum::{from_hex, to_hex};
+use crate::debugger::{registers, Debugger, StopReason};
+use crate::frame::Frame;
+use crate::gamepad::Button;
+use crate::memory_view::{read_block, write_block, MemorySpace};
+use crate::nes::Nes;
+use log::{info, warn};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteAction {
+    Reply(String),
+    Disconnect,
+}
+
+pub struct RemoteServer {
+    debugger: Debugger,
+}
+
+impl RemoteServer {
+    pub fn new() -> Self {
+        RemoteServer {
+            debugger: Debugger::new(),
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    // 1行を処理して応答を返す
+    pub fn handle_line(&mut self, nes: &mut Nes, line: &str) -> RemoteAction {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let reply = match args.split_first() {
+            None => return RemoteAction::Reply(error("empty command")),
+            Some((&"quit", _)) => return RemoteAction::Disconnect,
+            Some((&command, args)) => self.command(nes, command, args),
+        };
+        RemoteAction::Reply(reply.unwrap_or_else(|e| error(&e)))
+    }
+
+    fn command(&mut self, nes: &mut Nes, command: &str, args: &[&str]) -> Result<String, String> {
+        match command {
+            "load" => {
+                let path = args.first().ok_or("load needs a path")?;
+                let rom = load_rom(path).map_err(|e| e.to_string())?;
+                nes.load_new_rom(rom).map_err(|e| e.to_string())?;
+                Ok(ok(&format!("\"crc32\":\"{:08X}\"", nes.cartridge().rom_crc32())))
+            }
+            "reset" => {
+                nes.soft_reset();
+                Ok(ok(""))
+            }
+            "power" => {
+                nes.power_cycle();
+                Ok(ok(""))
+            }
+            "frame" => {
+                let count = parse_count(args.first())?;
+                let mut stop = StopReason::FrameEnd;
+                for _ in 0..count {
+                    stop = self.debugger.run_frame(nes);
+                    if stop != StopReason::FrameEnd {
+                        break;
+                    }
+                }
+                Ok(ok(&format!(
+                    "\"stop\":\"{}\",\"frame\":{},\"pc\":\"{:04X}\"",
+                    stop_name(&stop),
+                    nes.frame_count(),
+                    nes.cpu().program_counter
+                )))
+            }
+            "step" => {
+                for _ in 0..parse_count(args.first())? {
+                    nes.step().map_err(|e| e.to_string())?;
+                }
+                Ok(ok(&format!("\"pc\":\"{:04X}\"", nes.cpu().program_counter)))
+            }
+            "regs" => {
+                let r = registers(nes);
+                Ok(ok(&format!(
+                    "\"a\":\"{:02X}\",\"x\":\"{:02X}\",\"y\":\"{:02X}\",\"p\":\"{:02X}\",\"sp\":\"{:02X}\",\"pc\":\"{:04X}\",\"cycles\":{}",
+                    r.a,
+                    r.x,
+                    r.y,
+                    r.p,
+                    r.sp,
+                    r.pc,
+                    nes.bus().cycles()
+                )))
+            }
+            "read" => {
+                let (addr, len) = (parse_hex(args.first())?, parse_len(args.get(1))?);
+                let data = read_block(nes, parse_space(args.get(2))?, addr, len).map_err(|e| e.to_string())?;
+                Ok(ok(&format!("\"data\":\"{}\"", to_hex(&data))))
+            }
+            "write" => {
+                let addr = parse_hex(args.first())?;
+                let data = from_hex(args.get(1).ok_or("write needs data")?).ok_or("bad hex data")?;
+                write_block(nes, parse_space(args.get(2))?, addr, &data).map_err(|e| e.to_string())?;
+                Ok(ok(""))
+            }
+            "break" => {
+                self.debugger.add_breakpoint(parse_hex(args.first())?);
+                Ok(ok(""))
+            }
+            "delete" => {
+                self.debugger.remove_breakpoint(parse_hex(args.first())?);
+                Ok(ok(""))
+            }
+            "breakpoints" => {
+                let list: Vec<String> = self.debugger.breakpoints().map(|addr| format!("\"{:04X}\"", addr)).collect();
+                Ok(ok(&format!("\"breakpoints\":[{}]", list.join(","))))
+            }
+            "input" => {
+                let player = args.first().and_then(|p| p.parse::<usize>().ok()).ok_or("input needs a player")?;
+                let buttons = parse_hex(args.get(1))?;
+                if player >= 4 || buttons > 0xFF {
+                    return Err(format!("bad input: {} {:X}", player, buttons));
+                }
+                nes.set_controller_state(player, Button::from_bits_truncate(buttons as u8));
+                Ok(ok(""))
+            }
+            "framebuffer" => Ok(ok(&format!(
+                "\"width\":{},\"height\":{},\"data\":\"{}\"",
+                Frame::WIDTH,
+                Frame::HEIGHT,
+                to_hex(&nes.frame().data)
+            ))),
+            _ => Err(format!("unknown command: {}", command)),
+        }
+    }
+
+    // 1つの接続が切れるまで応答する
+    pub fn serve(&mut self, nes: &mut Nes, stream: TcpStream) -> std::io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            match self.handle_line(nes, line?.trim()) {
+                RemoteAction::Reply(reply) => writeln!(writer, "{}", reply)?,
+                RemoteAction::Disconnect => {
+                    writeln!(writer, "{}", ok(""))?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RemoteServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// port で接続を待ち、切れたら次の接続を待つ
+pub fn listen(nes: &mut Nes, port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let mut server = RemoteServer::new();
+    info!("REMOTE: listening on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let stream = stream?;
+        info!("REMOTE: connected from {}", stream.peer_addr()?);
+        if let Err(e) = server.serve(nes, stream) {
+            warn!("[ERR] REMOTE: {}", e);
+        }
+        info!("REMOTE: disconnected");
+    }
+    Ok(())
+}
+
+fn ok(fields: &str) -> String {
+    if fields.is_empty() {
+        "{\"ok\":true}".to_string()
+    } else {
+        format!("{{\"ok\":true,{}}}", fields)
+    }
+}
+
+fn error(message: &str) -> String {
+    format!("{{\"ok\":false,\"error\":{}}}", json_string(message))
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn stop_name(stop: &StopReason) -> &'static str {
+    match stop {
+        StopReason::Breakpoint(_) => "breakpoint",
+        StopReason::FrameEnd => "frame",
+        StopReason::Jam(_) => "jam",
+        StopReason::Event(_) => "event",
+    }
+}
+
+fn parse_count(arg: Option<&&str>) -> Result<usize, String> {
+    match arg {
+        None => Ok(1),
+        Some(n) => n.parse().map_err(|_| format!("bad count: {}", n)),
+    }
+}
+
+fn parse_hex(arg: Option<&&str>) -> Result<u16, String> {
+    let text = arg.ok_or("missing argument")?;
+    u16::from_str_radix(text.trim_start_matches('$'), 16).map_err(|_| format!("bad hex: {}", text))
+}
+
+// 長さは 64KB 全体 ($10000) も指定できるように usize で読む (範囲は read_block が調べる)
+fn parse_len(arg: Option<&&str>) -> Result<usize, String> {
+    let text = arg.ok_or("missing argument")?;
+    usize::from_str_radix(text.trim_start_matches('$'), 16).map_err(|_| format!("bad hex: {}", text))
+}
+
+fn parse_space(arg: Option<&&str>) -> Result<MemorySpace, String> {
+    match arg.copied().unwrap_or("cpu") {
+        "cpu" => Ok(MemorySpace::Cpu),
+        "ppu" => Ok(MemorySpace::Ppu),
+        "oam" => Ok(MemorySpace::Oam),
+        "palette" => Ok(MemorySpace::Palette),
+        other => Err(format!("unknown memory space: {}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_remote_commands() {
+        let source = "
+            loop: INC $10
+                  LDA $4016
+                  STA $11
+                  JMP loop
+        ";
+        let mut nes = TestRom::new().asm(0x8000, source).nes().unwrap();
+        let mut server = RemoteServer::new();
+        let mut send = |line: &str| match server.handle_line(&mut nes, line) {
+            RemoteAction::Reply(reply) => reply,
+            RemoteAction::Disconnect => "bye".to_string(),
+        };
+        assert_eq!(send("write 10 4142"), "{\"ok\":true}");
+        assert_eq!(send("read 10 2"), "{\"ok\":true,\"data\":\"4142\"}");
+        assert_eq!(send("step 1"), "{\"ok\":true,\"pc\":\"8002\"}");
+        assert_eq!(send("read 10 1"), "{\"ok\":true,\"data\":\"42\"}");
+        assert!(send("regs").contains("\"pc\":\"8002\""));
+        assert_eq!(send("break 8007"), "{\"ok\":true}");
+        assert_eq!(send("breakpoints"), "{\"ok\":true,\"breakpoints\":[\"8007\"]}");
+        assert_eq!(send("frame"), "{\"ok\":true,\"stop\":\"breakpoint\",\"frame\":0,\"pc\":\"8007\"}");
+        assert_eq!(send("delete 8007"), "{\"ok\":true}");
+        assert!(send("frame 2").starts_with("{\"ok\":true,\"stop\":\"frame\",\"frame\":2,"));
+        assert!(send("framebuffer").len() > Frame::WIDTH * Frame::HEIGHT * 6);
+        assert_eq!(send("input 0 81"), "{\"ok\":true}");
+        // 64KB 全体は読めるが、はみ出すとエラー
+        assert_eq!(send("read 0 10000").len(), "{\"ok\":true,\"data\":\"\"}".len() + 0x20000);
+        assert!(send("read 1 10000").starts_with("{\"ok\":false"));
+        assert!(send("read 0 ffffffffffffffff").starts_with("{\"ok\":false"));
+        assert_eq!(send("read 0 4000 oam"), "{\"ok\":false,\"error\":\"Address range $0000+16384 is out of the address space\"}");
+        assert_eq!(send("fly"), "{\"ok\":false,\"error\":\"unknown command: fly\"}");
+        assert!(send("load /nonexistent.nes").starts_with("{\"ok\":false"));
+        assert_eq!(send("quit"), "bye");
+        assert_eq!(json_string("a\"b\n"), "\"a\\\"b\\u000a\"");
+    }
+}
@@ -24,6 +24,8 @@ impl Rect {
 }
 
 pub fn render(ppu: &PPU, frame: &mut Frame) {
+    let chr = ppu.pattern_tables();
+
     // draw background
     let scroll_x = (ppu.scroll.scroll_x) as usize;
     let scroll_y = (ppu.scroll.scroll_y) as usize;
@@ -41,9 +43,15 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
         (Mirroring::HORIZONTAL, 0x2800) | (Mirroring::HORIZONTAL, 0x2C00) => {
             (&ppu.vram[0x400..0x800], &ppu.vram[0x000..0x400])
         }
-        (_, _) => {
-            panic!("Not supported mirroring type {:?}", ppu.mirroring);
+        (Mirroring::ONE_SCREEN_LOWER, _) => {
+            (&ppu.vram[0x000..0x400], &ppu.vram[0x000..0x400])
+        }
+        (Mirroring::ONE_SCREEN_UPPER, _) => {
+            (&ppu.vram[0x400..0x800], &ppu.vram[0x400..0x800])
         }
+        // 4画面 (カートリッジ側のVRAM) は持っていないので、垂直ミラーとして描く
+        (_, 0x2400) | (_, 0x2C00) => (&ppu.vram[0x400..0x800], &ppu.vram[0x000..0x400]),
+        (_, _) => (&ppu.vram[0x000..0x400], &ppu.vram[0x400..0x800]),
     };
 
     let screen_w = 256;
@@ -52,6 +60,7 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
     // 左上
     render_name_table(
         ppu,
+        &chr,
         frame,
         main_name_table,
         Rect::new(scroll_x, scroll_y, screen_w, screen_h),
@@ -62,6 +71,7 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
     // 右下
     render_name_table(
         ppu,
+        &chr,
         frame,
         second_name_table,
         Rect::new(0, 0, scroll_x, scroll_y),
@@ -72,6 +82,7 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
     // 左下
     render_name_table(
         ppu,
+        &chr,
         frame,
         main_name_table,
         Rect::new(scroll_x, 0, screen_w, scroll_y),
@@ -82,6 +93,7 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
     // 右上
     render_name_table(
         ppu,
+        &chr,
         frame,
         second_name_table,
         Rect::new(0, scroll_y, scroll_x, screen_h),
@@ -105,7 +117,7 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
         let bank: u16 = ppu.ctrl.sprite_pattern_addr();
 
         let tile =
-            &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
+            &chr[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
 
         for y in 0..=7 {
             let mut upper = tile[y];
@@ -116,9 +128,9 @@ pub fn render(ppu: &PPU, frame: &mut Frame) {
                 lower = lower >> 1;
                 let rgb = match value {
                     0 => continue 'ololo, // skip coloring the pixel
-                    1 => palette::SYSTEM_PALLETE[sprite_palette[1] as usize],
-                    2 => palette::SYSTEM_PALLETE[sprite_palette[2] as usize],
-                    3 => palette::SYSTEM_PALLETE[sprite_palette[3] as usize],
+                    1 => palette::system_palette()[sprite_palette[1] as usize],
+                    2 => palette::system_palette()[sprite_palette[2] as usize],
+                    3 => palette::system_palette()[sprite_palette[3] as usize],
                     _ => panic!("can't be"),
                 };
 
@@ -168,6 +180,7 @@ fn sprite_palette(ppu: &PPU, tile_y: usize, palette_idx: u8) -> [u8; 4] {
 
 fn render_name_table(
     ppu: &PPU,
+    chr: &[u8],
     frame: &mut Frame,
     name_table: &[u8],
     view_port: Rect,
@@ -182,7 +195,7 @@ fn render_name_table(
         let tile_row = i / 32;
         let tile_idx = name_table[i] as u16;
         let tile =
-            &ppu.chr_rom[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
+            &chr[(bank + tile_idx * 16) as usize..=(bank + tile_idx * 16 + 15) as usize];
         let palette = bg_pallette(ppu, attribute_table, tile_column, tile_row);
 
         for y in 0..=7 {
@@ -194,10 +207,10 @@ fn render_name_table(
                 upper = upper >> 1;
                 lower = lower >> 1;
                 let rgb = match value {
-                    0 => palette::SYSTEM_PALLETE[palette[0] as usize],
-                    1 => palette::SYSTEM_PALLETE[palette[1] as usize],
-                    2 => palette::SYSTEM_PALLETE[palette[2] as usize],
-                    3 => palette::SYSTEM_PALLETE[palette[3] as usize],
+                    0 => palette::system_palette()[palette[0] as usize],
+                    1 => palette::system_palette()[palette[1] as usize],
+                    2 => palette::system_palette()[palette[2] as usize],
+                    3 => palette::system_palette()[palette[3] as usize],
                     _ => panic!("can't be"),
                 };
 
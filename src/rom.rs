@@ -1,11 +1,21 @@
 use crate::{common};
 use common::*;
+use crate::checksum;
+use crate::nsf::{self, Nsf};
+#[cfg(feature = "std")]
+use crate::overrides::HeaderOverrides;
+#[cfg(feature = "std")]
+use crate::romdb::RomDatabase;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // NES^Z
 const PRG_ROM_PAGE_SIZE: usize = 16 * 1024; // 16KiB
 const CHR_ROM_PAGE_SIZE: usize = 8 * 1024; // 8KiB
+const INES_HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 #[allow(non_camel_case_types)]
 pub enum Mirroring {
     VERTICAL,
@@ -37,31 +47,83 @@ pub enum RomType {
     UNKNOWN // (Fail　Safe)
 }
 
-pub struct Rom {
-    pub prg_rom: Vec<u8>,
-    pub chr_rom: Vec<u8>,
-    pub mapper: u8,
-    pub mirroring: Mirroring,
-    pub is_batt: bool,
-    pub is_chr_ram: bool,
-    pub is_prg_ram: bool,
-    pub rom_type: RomType,
+#[derive(Debug)]
+pub enum RomError {
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    TooShort { expected: usize, actual: usize },
+    InvalidMagic,
+    EmptyPrgRom,
+    UnsupportedMapper(u8),
+    InvalidNsf(&'static str),
 }
 
-impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        Self:: mem_blank();
-
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+impl core::fmt::Display for RomError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            RomError::Io(e) => write!(f, "ROM read error: {}", e),
+            RomError::TooShort { expected, actual } => write!(
+                f,
+                "ROM file is truncated (expected {} bytes, got {})",
+                expected, actual
+            ),
+            RomError::InvalidMagic => write!(f, "File is not in iNES file format"),
+            RomError::EmptyPrgRom => write!(f, "PRG ROM size is 0"),
+            RomError::UnsupportedMapper(m) => write!(f, "Not supported mapper {}", m),
+            RomError::InvalidNsf(reason) => write!(f, "Invalid NSF file: {}", reason),
         }
+    }
+}
 
-        let mapper = (raw[7] & 0xF0) | (raw[6] >> 4);
+impl core::error::Error for RomError {}
 
-        // let ines_ver = (raw[7] >> 2) & 0b11;
-        // if ines_ver != 0 {
-        //     return Err("NES2.0 format is not supported".to_string());
-        // }
+#[cfg(feature = "std")]
+impl From<std::io::Error> for RomError {
+    fn from(e: std::io::Error) -> Self {
+        RomError::Io(e)
+    }
+}
+
+// NES 2.0: byte10 の下位ニブルが揮発性、上位ニブルが不揮発性 (それぞれ 64 << n バイト)
+// iNES: byte8 が8KB単位 (0でも8KBとみなす)
+fn prg_ram_size(raw: &[u8], is_nes2: bool, dirty: bool) -> usize {
+    let shift = |n: u8| if n == 0 { 0 } else { 64 << n };
+    if is_nes2 {
+        shift(raw[10] & 0x0F) + shift(raw[10] >> 4)
+    } else if dirty {
+        _MEM_SIZE_8K as usize
+    } else {
+        raw[8].max(1) as usize * _MEM_SIZE_8K as usize
+    }
+}
+
+// iNESヘッダ (先頭16バイト) の内容
+#[derive(Debug, PartialEq, Clone)]
+pub struct RomHeader {
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize, // 0ならCHR RAM
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub has_battery: bool,
+    pub has_trainer: bool,
+    pub is_nes2: bool,
+    pub submapper: u8, // NES 2.0のみ (iNESでは0)
+    pub prg_ram_size: usize, // $6000-$7FFF (バッテリーバックアップ分も含む)
+    pub timing: u8, // NES 2.0のみ 0:NTSC 1:PAL 2:マルチ 3:Dendy (iNESでは0)
+}
+
+impl RomHeader {
+    pub fn parse(raw: &[u8]) -> Result<RomHeader, RomError> {
+        if raw.len() < INES_HEADER_SIZE {
+            return Err(RomError::TooShort {
+                expected: INES_HEADER_SIZE,
+                actual: raw.len(),
+            });
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::InvalidMagic);
+        }
 
         // MMMMftcm
         // ||||||||
@@ -71,22 +133,108 @@ impl Rom {
         // |||||+--- 1: $7000-$71FFにある512バイトのトレーナー（PRGデータの前に格納される）
         // ||||+---- 1：ミラーリング制御または上記ミラーリングビットを無視し、代わりに4画面VRAMを提供する
         // ++++----- マッパー番号の下位ニブル
-        let four_screen = raw[6] & _BIT_3 != 0;
-        let is_batt: bool = raw[6] & _BIT_1 != 0;
-        let mirroring_type = raw[6] & _BIT_0 != 0;
-        let mirroring = match (four_screen, mirroring_type) {
+        let flags6 = raw[6];
+        // MMMMvvpu
+        // ||||||||
+        // |||||||+- VS Unisystem
+        // ||||||+-- PlayChoice-10
+        // ||||++--- 2ならNES 2.0形式
+        // ++++----- マッパー番号の上位ニブル
+        let flags7 = raw[7];
+        let is_nes2 = (flags7 >> 2) & 0b11 == 0b10;
+
+        // 古いツールがヘッダ末尾に書いた "DiskDude!" などのゴミがある場合、flags7は信用しない
+        let dirty = !is_nes2 && raw[12..16].iter().any(|&b| b != 0);
+        let mapper_hi = if dirty { 0 } else { flags7 & 0xF0 };
+        let mapper = mapper_hi | (flags6 >> 4);
+
+        let four_screen = flags6 & _BIT_3 != 0;
+        let mirroring = match (four_screen, flags6 & _BIT_0 != 0) {
             (true, _) => Mirroring::FOUR_SCREEN,
             (false, true) => Mirroring::VERTICAL,
             (false, false) => Mirroring::HORIZONTAL,
         };
 
         let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        if prg_rom_size == 0 {
+            return Err(RomError::EmptyPrgRom);
+        }
+
+        Ok(RomHeader {
+            prg_rom_size,
+            chr_rom_size: raw[5] as usize * CHR_ROM_PAGE_SIZE,
+            mapper: mapper,
+            mirroring: mirroring,
+            has_battery: flags6 & _BIT_1 != 0,
+            has_trainer: flags6 & _BIT_2 != 0,
+            is_nes2,
+            submapper: if is_nes2 { raw[8] >> 4 } else { 0 },
+            prg_ram_size: prg_ram_size(raw, is_nes2, dirty),
+            timing: if is_nes2 { raw[12] & 0b11 } else { 0 },
+        })
+    }
+
+    // ヘッダが示すファイルサイズ (これより短ければ壊れている)
+    pub fn file_size(&self) -> usize {
+        self.prg_rom_offset() + self.prg_rom_size + self.chr_rom_size
+    }
+
+    fn prg_rom_offset(&self) -> usize {
+        INES_HEADER_SIZE + if self.has_trainer { TRAINER_SIZE } else { 0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub is_batt: bool,
+    pub is_chr_ram: bool,
+    pub is_prg_ram: bool,
+    pub rom_type: RomType,
+    pub header: RomHeader,
+    pub trainer: Option<Vec<u8>>, // $7000-$71FF に置かれる
+    pub crc32: u32,               // PRG+CHR
+    pub sha1: [u8; 20],           // PRG+CHR
+    pub board: Option<String>,    // データベースから
+    pub title: Option<String>,    // データベースから
+    pub nsf: Option<Nsf>,         // NSF/NSFeの場合
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        Rom::from_bytes(raw)
+    }
+
+    // ファイルを介さずにメモリ上のiNESイメージから読み込む
+    pub fn from_bytes(raw: &[u8]) -> Result<Rom, RomError> {
+        if nsf::is_nsf(raw) {
+            return Rom::from_nsf(Nsf::parse(raw)?);
+        }
+        let header = RomHeader::parse(raw)?;
+        if raw.len() < header.file_size() {
+            return Err(RomError::TooShort {
+                expected: header.file_size(),
+                actual: raw.len(),
+            });
+        }
+
+        let mapper = header.mapper;
+        let mirroring = header.mirroring.clone();
+        let is_batt = header.has_battery;
+        let prg_rom_size = header.prg_rom_size;
+        let chr_rom_size = header.chr_rom_size;
         let is_prg_ram = (chr_rom_size == 0) && (is_batt != false);
 
-        let skip_trainer = raw[6] & 0b100 != 0;
+        let trainer = if header.has_trainer {
+            Some(raw[INES_HEADER_SIZE..(INES_HEADER_SIZE + TRAINER_SIZE)].to_vec())
+        } else {
+            None
+        };
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        let prg_rom_start = header.prg_rom_offset();
         let chr_rom_start = prg_rom_start + prg_rom_size;
 
         let mut is_chr_ram = false;
@@ -109,14 +257,12 @@ impl Rom {
                     rom_type = RomType::SNROM;
                 }
             },
-            _MAPPER_2 => { if (prg_rom_size <= (_MEM_SIZE_128K as usize))
-                && (is_chr_ram != false) && (is_prg_ram != true) {
+            _MAPPER_2 if (prg_rom_size <= (_MEM_SIZE_128K as usize))
+                && (is_chr_ram != false) && (is_prg_ram != true) => {
                     rom_type = RomType::UXROM;
-                }
             },
-            _MAPPER_3 => { if (chr_rom_size != 0) && (is_prg_ram != true) {
+            _MAPPER_3 if (chr_rom_size != 0) && (is_prg_ram != true) => {
                     rom_type = RomType::CNROM;
-                }
             },
             _MAPPER_4 => { if (prg_rom_size >= (_MEM_SIZE_256K as usize))
                 && (chr_rom_size >= (_MEM_SIZE_128K as usize)) && (is_prg_ram != false) {
@@ -128,11 +274,18 @@ impl Rom {
                     rom_type = RomType::TSROM;
                 }
             },
-            _ => panic!("[ERR] Not Support ROM (Mapper: {}, ROM Type: {:?})",mapper, rom_type),
+            // 対応しているかどうかはカートリッジ側で判断する
+            _ => {}
+        }
+
+        let prg_rom = raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec();
+        let mut image = prg_rom.clone();
+        if !is_chr_ram {
+            image.extend_from_slice(&chr_rom);
         }
 
         Ok(Rom {
-            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
+            prg_rom,
             chr_rom: chr_rom,
             mapper: mapper,
             mirroring: mirroring,
@@ -140,9 +293,99 @@ impl Rom {
             is_chr_ram: is_chr_ram,
             is_prg_ram: is_prg_ram,
             rom_type: rom_type,
+            header,
+            trainer,
+            crc32: checksum::crc32(&image),
+            sha1: checksum::sha1(&image),
+            board: None,
+            title: None,
+            nsf: None,
+        })
+    }
+
+    // ユーザー指定の上書きがあればヘッダの値を書き換える
+    #[cfg(feature = "std")]
+    pub fn apply_overrides(&mut self, overrides: &HeaderOverrides) -> bool {
+        let entry = match overrides.lookup(self.crc32, &self.sha1) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if let Some(mapper) = entry.mapper {
+            self.mapper = mapper;
+            self.header.mapper = mapper;
+        }
+        if let Some(submapper) = entry.submapper {
+            self.header.submapper = submapper;
+        }
+        if let Some(mirroring) = &entry.mirroring {
+            self.mirroring = mirroring.clone();
+            self.header.mirroring = mirroring.clone();
+        }
+        if let Some(has_battery) = entry.has_battery {
+            self.is_batt = has_battery;
+            self.header.has_battery = has_battery;
+        }
+        if let Some(prg_ram_size) = entry.prg_ram_size {
+            self.header.prg_ram_size = prg_ram_size;
+        }
+        true
+    }
+
+    // NSFはiNESのヘッダを持たないので、CHR RAMのみのカートリッジとして扱う
+    // (マッパーはカートリッジ側でNSF用のものが選ばれる)
+    fn from_nsf(nsf: Nsf) -> Result<Rom, RomError> {
+        let header = RomHeader {
+            prg_rom_size: nsf.data.len(),
+            chr_rom_size: 0,
+            mapper: 0,
+            mirroring: Mirroring::VERTICAL,
+            has_battery: false,
+            has_trainer: false,
+            is_nes2: false,
+            submapper: 0,
+            prg_ram_size: _MEM_SIZE_8K as usize,
+            timing: if nsf.is_pal { 1 } else { 0 },
+        };
+        Ok(Rom {
+            prg_rom: nsf.data.clone(),
+            chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+            mapper: 0,
+            mirroring: Mirroring::VERTICAL,
+            is_batt: false,
+            is_chr_ram: true,
+            is_prg_ram: true,
+            rom_type: RomType::UNKNOWN,
+            header,
+            trainer: None,
+            crc32: checksum::crc32(&nsf.data),
+            sha1: checksum::sha1(&nsf.data),
+            board: None,
+            title: Some(nsf.title.clone()),
+            nsf: Some(nsf),
         })
     }
 
+    // データベースにあれば、ヘッダの誤りを補正してボード名とタイトルを付ける
+    #[cfg(feature = "std")]
+    pub fn apply_database(&mut self, db: &RomDatabase) -> bool {
+        let entry = match db.lookup(self.crc32) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        self.mapper = entry.mapper;
+        self.header.mapper = entry.mapper;
+        self.header.submapper = entry.submapper;
+        if let Some(mirroring) = &entry.mirroring {
+            self.mirroring = mirroring.clone();
+            self.header.mirroring = mirroring.clone();
+        }
+        self.is_batt = entry.has_battery;
+        self.header.has_battery = entry.has_battery;
+        self.board = Some(entry.board.clone());
+        self.title = Some(entry.title.clone());
+        true
+    }
+
     pub fn mem_blank() -> Self {
         return Rom {
             prg_rom: vec![],
@@ -153,6 +396,147 @@ impl Rom {
             is_chr_ram: false,
             is_prg_ram: false,
             rom_type: RomType::NROM,
+            header: RomHeader {
+                prg_rom_size: 0,
+                chr_rom_size: 0,
+                mapper: 0,
+                mirroring: Mirroring::VERTICAL,
+                has_battery: false,
+                has_trainer: false,
+                is_nes2: false,
+                submapper: 0,
+                prg_ram_size: _MEM_SIZE_8K as usize,
+                timing: 0,
+            },
+            trainer: None,
+            crc32: 0,
+            sha1: [0; 20],
+            board: None,
+            title: None,
+            nsf: None,
         };
     }
 }
+
+// マッパーのテスト用の iNES イメージ: PRG 8KB x prg_banks, CHR 1KB x chr_banks
+// 各バンクの先頭にバンク番号を書いておくので、読んだ値でどのバンクが見えているか分かる
+#[cfg(test)]
+pub(crate) fn banked_rom(mapper: u8, prg_banks: usize, chr_banks: usize) -> Rom {
+    const PRG_BANK: usize = 8 * 1024;
+    const CHR_BANK: usize = 1024;
+    let mut raw = vec![
+        0x4E,
+        0x45,
+        0x53,
+        0x1A,
+        (prg_banks * PRG_BANK / PRG_ROM_PAGE_SIZE) as u8,
+        (chr_banks * CHR_BANK / CHR_ROM_PAGE_SIZE) as u8,
+        (mapper & 0x0F) << 4,
+        mapper & 0xF0,
+    ];
+    raw.resize(INES_HEADER_SIZE, 0);
+    for (size, banks) in [(PRG_BANK, prg_banks), (CHR_BANK, chr_banks)] {
+        for bank in 0..banks {
+            let mut data = vec![0; size];
+            data[0] = bank as u8;
+            raw.extend(data);
+        }
+    }
+    Rom::new(&raw).unwrap()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn ines(prg_banks: u8, chr_banks: u8, flags6: u8, flags7: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, flags6, flags7];
+        raw.resize(INES_HEADER_SIZE, 0);
+        raw.resize(
+            INES_HEADER_SIZE
+                + prg_banks as usize * PRG_ROM_PAGE_SIZE
+                + chr_banks as usize * CHR_ROM_PAGE_SIZE,
+            0,
+        );
+        raw
+    }
+
+    #[test]
+    fn test_header() {
+        let header = RomHeader::parse(&ines(2, 1, 0x13, 0x00)).unwrap();
+        assert_eq!(header.prg_rom_size, 0x8000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+        assert_eq!(header.mapper, 1);
+        assert_eq!(header.mirroring, Mirroring::VERTICAL);
+        assert!(header.has_battery);
+        assert!(!header.has_trainer);
+        assert!(!header.is_nes2);
+
+        // DiskDude! 付きのヘッダはflags7を無視する
+        let mut raw = ines(1, 1, 0x40, 0x40);
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(RomHeader::parse(&raw).unwrap().mapper, 4);
+    }
+
+    #[test]
+    fn test_rom_errors() {
+        assert!(matches!(
+            Rom::new(&[0x4E, 0x45]),
+            Err(RomError::TooShort { expected: 16, actual: 2 })
+        ));
+
+        let mut raw = ines(1, 1, 0, 0);
+        raw[3] = 0x00;
+        assert!(matches!(Rom::new(&raw), Err(RomError::InvalidMagic)));
+
+        assert!(matches!(Rom::new(&ines(0, 1, 0, 0)), Err(RomError::EmptyPrgRom)));
+
+        let mut raw = ines(2, 1, 0, 0);
+        raw.truncate(raw.len() - 1);
+        assert!(matches!(Rom::new(&raw), Err(RomError::TooShort { .. })));
+
+        // トレーナー分が足りない
+        assert!(matches!(Rom::new(&ines(1, 1, 0x04, 0)), Err(RomError::TooShort { .. })));
+
+        let rom = Rom::new(&ines(1, 1, 0x50, 0)).unwrap();
+        assert_eq!(rom.rom_type, RomType::UNKNOWN);
+        assert!(matches!(
+            crate::cartridge::Cartridge::new(&rom),
+            Err(RomError::UnsupportedMapper(5))
+        ));
+    }
+
+    #[test]
+    fn test_rom_database() {
+        // PRG 16KB + CHR 8KB がすべて0
+        let mut rom = Rom::new(&ines(1, 1, 0x00, 0)).unwrap();
+        assert_eq!(rom.crc32, 0x6EBED2EE);
+        assert!(!rom.apply_database(&RomDatabase::new()));
+
+        let db = RomDatabase::parse("6EBED2EE,3,0,V,1,NES-CNROM,Blank").unwrap();
+        assert!(rom.apply_database(&db));
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.header.mapper, 3);
+        assert_eq!(rom.mirroring, Mirroring::VERTICAL);
+        assert!(rom.is_batt);
+        assert_eq!(rom.board.as_deref(), Some("NES-CNROM"));
+        assert_eq!(rom.title.as_deref(), Some("Blank"));
+    }
+
+    #[test]
+    fn test_rom_overrides() {
+        let mut rom = Rom::new(&ines(1, 1, 0x00, 0)).unwrap();
+        assert_eq!(rom.header.prg_ram_size, 0x2000);
+        let overrides = HeaderOverrides::parse("6EBED2EE mapper=2 prg_ram=0 battery=1").unwrap();
+        assert!(rom.apply_overrides(&overrides));
+        assert_eq!(rom.mapper, 2);
+        assert_eq!(rom.header.prg_ram_size, 0);
+        assert!(rom.header.has_battery);
+        assert_eq!(rom.mirroring, Mirroring::HORIZONTAL);
+
+        // NES 2.0: 揮発性 64<<7 = 8KB + 不揮発性 64<<6 = 4KB
+        let mut raw = ines(1, 1, 0x00, 0x08);
+        raw[10] = 0x67;
+        assert_eq!(RomHeader::parse(&raw).unwrap().prg_ram_size, 0x3000);
+    }
+}
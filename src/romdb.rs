@@ -0,0 +1,129 @@
+// ROMデータベース
+// PRG+CHRのCRC32から、ヘッダの誤り (マッパー番号やミラーリング) を補正し、
+// ボード名とタイトルを引く
+// 形式は romdb.txt を参照
+use crate::rom::Mirroring;
+use std::collections::HashMap;
+
+const BUNDLED_DATABASE: &str = include_str!("romdb.txt");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomDbEntry {
+    pub crc32: u32,
+    pub mapper: u8,
+    pub submapper: u8,
+    pub mirroring: Option<Mirroring>,
+    pub has_battery: bool,
+    pub board: String,
+    pub title: String,
+}
+
+pub struct RomDatabase {
+    entries: HashMap<u32, RomDbEntry>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        RomDatabase {
+            entries: HashMap::new(),
+        }
+    }
+
+    // 同梱のデータベース
+    pub fn bundled() -> Self {
+        RomDatabase::parse(BUNDLED_DATABASE).unwrap_or_else(|e| panic!("[ERR] romdb.txt: {}", e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut db = RomDatabase::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_entry(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            db.insert(entry);
+        }
+        Ok(db)
+    }
+
+    pub fn insert(&mut self, entry: RomDbEntry) {
+        self.entries.insert(entry.crc32, entry);
+    }
+
+    pub fn lookup(&self, crc32: u32) -> Option<&RomDbEntry> {
+        self.entries.get(&crc32)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for RomDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_entry(line: &str) -> Result<RomDbEntry, String> {
+    // タイトルにはカンマが含まれることがあるので、最後の列は残りすべて
+    let fields: Vec<&str> = line.splitn(7, ',').map(|f| f.trim()).collect();
+    if fields.len() != 7 {
+        return Err(format!("expected 7 fields, got {}", fields.len()));
+    }
+    let crc32 = u32::from_str_radix(fields[0], 16).map_err(|e| format!("crc32: {}", e))?;
+    let mapper = fields[1].parse().map_err(|e| format!("mapper: {}", e))?;
+    let submapper = fields[2].parse().map_err(|e| format!("submapper: {}", e))?;
+    let mirroring = match fields[3] {
+        "H" => Some(Mirroring::HORIZONTAL),
+        "V" => Some(Mirroring::VERTICAL),
+        "4" => Some(Mirroring::FOUR_SCREEN),
+        "-" => None,
+        m => return Err(format!("mirroring: {}", m)),
+    };
+    let has_battery = match fields[4] {
+        "0" => false,
+        "1" => true,
+        b => return Err(format!("battery: {}", b)),
+    };
+    Ok(RomDbEntry {
+        crc32,
+        mapper,
+        submapper,
+        mirroring,
+        has_battery,
+        board: fields[5].to_string(),
+        title: fields[6].to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_romdb_parse() {
+        // 同梱のデータベースが読めること
+        RomDatabase::bundled();
+
+        let db = RomDatabase::parse(
+            "# comment\n\n0123ABCD,4,0,H,1,NES-TKROM,Title, With Comma\nFFFFFFFF,0,0,-,0,NES-NROM-256,X\n",
+        )
+        .unwrap();
+        assert_eq!(db.len(), 2);
+        let entry = db.lookup(0x0123ABCD).unwrap();
+        assert_eq!(entry.mapper, 4);
+        assert_eq!(entry.mirroring, Some(Mirroring::HORIZONTAL));
+        assert!(entry.has_battery);
+        assert_eq!(entry.title, "Title, With Comma");
+        assert_eq!(db.lookup(0xFFFFFFFF).unwrap().mirroring, None);
+
+        assert!(RomDatabase::parse("0123ABCD,4,0,X,1,B,T").is_err());
+        assert!(RomDatabase::parse("0123ABCD,4").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+// ステートセーブのスロット (ROMのCRC32ごとにディレクトリを分けて保存する)
+//   <directory>/<CRC32>/slot<N>.state
+//   <directory>/<CRC32>/autosave.state (終了時に自動で保存する。スロットとは別)
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub const SLOT_COUNT: u8 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotInfo {
+    pub slot: u8,
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+pub struct SaveSlots {
+    directory: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new(directory: &Path, rom_crc32: u32) -> Self {
+        SaveSlots {
+            directory: directory.join(format!("{:08X}", rom_crc32)),
+        }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.directory.join(format!("slot{}.state", slot))
+    }
+
+    pub fn save(&self, slot: u8, state: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        fs::write(self.path(slot), state)
+    }
+
+    pub fn load(&self, slot: u8) -> io::Result<Vec<u8>> {
+        fs::read(self.path(slot))
+    }
+
+    pub fn autosave_path(&self) -> PathBuf {
+        self.directory.join("autosave.state")
+    }
+
+    pub fn autosave(&self, state: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        fs::write(self.autosave_path(), state)
+    }
+
+    // 保存済みのスロット (スロット番号順)
+    pub fn list(&self) -> Vec<SlotInfo> {
+        (0..SLOT_COUNT)
+            .filter_map(|slot| {
+                let path = self.path(slot);
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                Some(SlotInfo {
+                    slot,
+                    path,
+                    modified,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_slots() {
+        let dir = std::env::temp_dir().join(format!("rscom_slots_{}", std::process::id()));
+        let slots = SaveSlots::new(&dir, 0x1234ABCD);
+        assert!(slots.list().is_empty());
+        assert!(slots.load(1).is_err());
+
+        slots.save(3, &[1, 2, 3]).unwrap();
+        slots.save(1, &[4]).unwrap();
+        assert_eq!(slots.load(3).unwrap(), vec![1, 2, 3]);
+        assert_eq!(slots.path(3), dir.join("1234ABCD").join("slot3.state"));
+        let list: Vec<u8> = slots.list().iter().map(|info| info.slot).collect();
+        assert_eq!(list, vec![1, 3]);
+
+        // 自動保存はスロットの一覧に出ない
+        slots.autosave(&[5]).unwrap();
+        assert_eq!(fs::read(slots.autosave_path()).unwrap(), vec![5]);
+        assert_eq!(slots.list().len(), 2);
+
+        // 別のROMのスロットは見えない
+        assert!(SaveSlots::new(&dir, 0).list().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+// スクリプトから使う操作 (FCEUXのLua APIに合わせた名前)
+// https://fceux.com/web/help/LuaFunctionsList.html
+//   memory.readbyte/writebyte, memory.getregister/setregister,
+//   emu.framecount, emu.registerafter, joypad.set, gui.pixel/line/box
+//   ラベルの登録 (add_label, label_address) と RAMマップの値 (game_value) はFCEUXには無い
+// Lua (mlua) からは lua.rs がこの上にテーブルを登録する (--features lua)。Rustのクロージャからも使える
+use crate::bus::Mem;
+use crate::cpu::set_in_trace;
+use crate::frame::Frame;
+use crate::gamepad::Button;
+use crate::nes::Nes;
+use crate::ram_map::{RamMap, RamValue};
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Debug, Clone, PartialEq)]
+enum DrawCommand {
+    Pixel(i32, i32, Rgb),
+    Line(i32, i32, i32, i32, Rgb),
+    Box(i32, i32, i32, i32, Option<Rgb>, Rgb), // 塗りつぶし, 枠
+}
+
+// フレームの上に重ねて描く (次のフレームを描いたら消える)
+pub struct Overlay {
+    commands: Vec<DrawCommand>,
+}
+
+impl Overlay {
+    pub fn new() -> Self {
+        Overlay { commands: vec![] }
+    }
+
+    pub fn pixel(&mut self, x: i32, y: i32, color: Rgb) {
+        self.commands.push(DrawCommand::Pixel(x, y, color));
+    }
+
+    pub fn line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, color: Rgb) {
+        self.commands.push(DrawCommand::Line(x1, y1, x2, y2, color));
+    }
+
+    pub fn draw_box(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, fill: Option<Rgb>, outline: Rgb) {
+        self.commands.push(DrawCommand::Box(x1, y1, x2, y2, fill, outline));
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        for command in &self.commands {
+            match *command {
+                DrawCommand::Pixel(x, y, color) => put_pixel(frame, x, y, color),
+                DrawCommand::Line(x1, y1, x2, y2, color) => draw_line(frame, x1, y1, x2, y2, color),
+                DrawCommand::Box(x1, y1, x2, y2, fill, outline) => {
+                    let (left, right) = (x1.min(x2), x1.max(x2));
+                    let (top, bottom) = (y1.min(y2), y1.max(y2));
+                    if let Some(fill) = fill {
+                        for y in top..=bottom {
+                            for x in left..=right {
+                                put_pixel(frame, x, y, fill);
+                            }
+                        }
+                    }
+                    draw_line(frame, left, top, right, top, outline);
+                    draw_line(frame, left, bottom, right, bottom, outline);
+                    draw_line(frame, left, top, left, bottom, outline);
+                    draw_line(frame, right, top, right, bottom, outline);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 画面の外は描かない (set_pixelは次の行にはみ出すので)
+fn put_pixel(frame: &mut Frame, x: i32, y: i32, color: Rgb) {
+    if frame.get_pixel(x, y).is_some() {
+        frame.set_pixel(x as usize, y as usize, color);
+    }
+}
+
+// ブレゼンハム
+fn draw_line(frame: &mut Frame, x1: i32, y1: i32, x2: i32, y2: i32, color: Rgb) {
+    let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+    let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+    let (mut x, mut y, mut err) = (x1, y1, dx + dy);
+    loop {
+        put_pixel(frame, x, y, color);
+        if x == x2 && y == y2 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+// joypad.set のボタン名 (FCEUXと同じ)
+pub fn button_from_name(name: &str) -> Option<Button> {
+    match name {
+        "A" => Some(Button::BUTTON_A),
+        "B" => Some(Button::BUTTON_B),
+        "select" => Some(Button::SELECT),
+        "start" => Some(Button::START),
+        "up" => Some(Button::UP),
+        "down" => Some(Button::DOWN),
+        "left" => Some(Button::LEFT),
+        "right" => Some(Button::RIGHT),
+        _ => None,
+    }
+}
+
+// コールバックに渡す操作対象
+pub struct ScriptContext<'a> {
+    pub nes: &'a mut Nes,
+    pub gui: &'a mut Overlay,
+    pub ram_map: &'a RamMap,
+}
+
+impl ScriptContext<'_> {
+    // memory.readbyte (PPUレジスタなどを読んでも副作用はない)
+    pub fn read_byte(&mut self, addr: u16) -> u8 {
+        set_in_trace(true);
+        let value = self.nes.bus_mut().mem_read(addr);
+        set_in_trace(false);
+        value
+    }
+
+    // memory.writebyte
+    pub fn write_byte(&mut self, addr: u16, value: u8) {
+        self.nes.bus_mut().mem_write(addr, value);
+    }
+
+    // memory.getregister ("a", "x", "y", "s", "p", "pc")
+    pub fn register(&self, name: &str) -> Option<u16> {
+        let cpu = self.nes.cpu();
+        match name {
+            "a" => Some(cpu.register_a as u16),
+            "x" => Some(cpu.register_x as u16),
+            "y" => Some(cpu.register_y as u16),
+            "s" => Some(cpu.stack_pointer as u16),
+            "p" => Some(cpu.status as u16),
+            "pc" => Some(cpu.program_counter),
+            _ => None,
+        }
+    }
+
+    // memory.setregister (知らない名前ならfalse)
+    pub fn set_register(&mut self, name: &str, value: u16) -> bool {
+        let cpu = self.nes.cpu_mut();
+        match name {
+            "a" => cpu.register_a = value as u8,
+            "x" => cpu.register_x = value as u8,
+            "y" => cpu.register_y = value as u8,
+            "s" => cpu.stack_pointer = value as u8,
+            "p" => cpu.status = value as u8,
+            "pc" => cpu.program_counter = value,
+            _ => return false,
+        }
+        true
+    }
+
+    // emu.framecount
+    pub fn frame_count(&self) -> usize {
+        self.nes.frame_count()
+    }
+
+    // ラベルを付ける (逆アセンブルやトレースに出る)
+    pub fn add_label(&mut self, addr: u16, name: &str, comment: &str) {
+        self.nes.symbols_mut().add_label(addr, name, comment);
+    }
+
+    // ラベルの名前からアドレスを探す
+    pub fn label_address(&self, name: &str) -> Option<u16> {
+        self.nes.symbols().address_of(name)
+    }
+
+    // RAMマップの名前の値 (知らない名前や読めない値ならNone)
+    pub fn game_value(&mut self, name: &str) -> Option<i64> {
+        self.ram_map.value(self.nes, name)
+    }
+
+    // RAMマップのすべての値
+    pub fn game_values(&mut self) -> Vec<RamValue> {
+        self.ram_map.read_all(self.nes).unwrap_or_default()
+    }
+
+    // joypad.set (player: 1-4)
+    pub fn set_joypad(&mut self, player: usize, buttons: Button) {
+        if player >= 1 {
+            self.nes.set_controller_state(player - 1, buttons);
+        }
+    }
+}
+
+type FrameCallback<'call> = Box<dyn FnMut(&mut ScriptContext<'_>) + 'call>;
+
+pub struct ScriptHost<'call> {
+    after_frame: Vec<FrameCallback<'call>>,
+    overlay: Overlay,
+    ram_map: RamMap,
+}
+
+impl<'call> ScriptHost<'call> {
+    pub fn new() -> Self {
+        ScriptHost {
+            after_frame: vec![],
+            overlay: Overlay::new(),
+            ram_map: RamMap::new(),
+        }
+    }
+
+    // コールバックの game_value で使う
+    pub fn set_ram_map(&mut self, map: RamMap) {
+        self.ram_map = map;
+    }
+
+    pub fn ram_map(&self) -> &RamMap {
+        &self.ram_map
+    }
+
+    // emu.registerafter
+    pub fn register_after<F>(&mut self, callback: F)
+    where
+        F: FnMut(&mut ScriptContext<'_>) + 'call,
+    {
+        self.after_frame.push(Box::new(callback));
+    }
+
+    // 1フレーム進めてコールバックを呼び、オーバーレイを重ねた画面を返す
+    pub fn run_frame<'a>(&mut self, nes: &'a mut Nes) -> &'a Frame {
+        nes.run_frame();
+        self.overlay.clear();
+        let mut context = ScriptContext {
+            nes,
+            gui: &mut self.overlay,
+            ram_map: &self.ram_map,
+        };
+        for callback in self.after_frame.iter_mut() {
+            callback(&mut context);
+        }
+        self.overlay.draw(nes.frame_mut());
+        nes.frame()
+    }
+}
+
+impl Default for ScriptHost<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overlay() {
+        let mut frame = Frame::new();
+        let mut overlay = Overlay::new();
+        overlay.draw_box(-2, 10, 3, 12, Some((1, 1, 1)), (255, 0, 0));
+        overlay.line(250, 0, 260, 0, (0, 255, 0));
+        overlay.pixel(-1, 0, (0, 0, 255));
+        overlay.draw(&mut frame);
+
+        assert_eq!(frame.get_pixel(0, 10), Some((255, 0, 0)));
+        assert_eq!(frame.get_pixel(2, 11), Some((1, 1, 1)));
+        assert_eq!(frame.get_pixel(3, 11), Some((255, 0, 0)));
+        assert_eq!(frame.get_pixel(255, 0), Some((0, 255, 0)));
+        // 右端からはみ出した線は次の行に回り込まない
+        assert_eq!(frame.get_pixel(0, 1), Some((0, 0, 0)));
+        assert_eq!(button_from_name("start"), Some(Button::START));
+        assert_eq!(button_from_name("Start"), None);
+    }
+}
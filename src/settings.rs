@@ -0,0 +1,125 @@
+// 実行中にホットキーで変えた設定 (終了時に保存して、次の起動で引き継ぐ)
+// 1行1項目、<名前>=<値>。知らない名前は無視する ('#'以降はコメント)
+//   scale_mode=integer|free
+//   aspect=square|ntsc
+//   vsync=0|1
+//   perf_hud=0|1
+//   slot=<0-9>
+// コマンドラインで指定したものはこちらより優先する
+use nes_core::common::*;
+use nes_core::save_slots::SLOT_COUNT;
+use nes_core::video::{AspectRatio, ScaleMode};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub scale_mode: ScaleMode,
+    pub aspect: AspectRatio,
+    pub vsync: bool,
+    pub perf_hud: bool,
+    pub slot: u8,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            scale_mode: _SCALE_MODE,
+            aspect: _ASPECT_RATIO,
+            vsync: _VSYNC,
+            perf_hud: _SHOW_PERF_HUD,
+            slot: 0,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Settings::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut settings = Settings::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: &str| format!("line {}: {}", i + 1, e);
+            let (name, value) = line.split_once('=').ok_or_else(|| error("expected <name>=<value>"))?;
+            let (name, value) = (name.trim(), value.trim());
+            let flag = || match value {
+                "0" => Ok(false),
+                "1" => Ok(true),
+                _ => Err(error(&format!("{}: {}", name, value))),
+            };
+            match name {
+                "scale_mode" => {
+                    settings.scale_mode = match value {
+                        "integer" => ScaleMode::Integer,
+                        "free" => ScaleMode::Free,
+                        _ => return Err(error(&format!("scale_mode: {}", value))),
+                    }
+                }
+                "aspect" => {
+                    settings.aspect = match value {
+                        "square" => AspectRatio::Square,
+                        "ntsc" => AspectRatio::Ntsc,
+                        _ => return Err(error(&format!("aspect: {}", value))),
+                    }
+                }
+                "vsync" => settings.vsync = flag()?,
+                "perf_hud" => settings.perf_hud = flag()?,
+                "slot" => {
+                    settings.slot = match value.parse() {
+                        Ok(slot) if slot < SLOT_COUNT => slot,
+                        _ => return Err(error(&format!("slot: {}", value))),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn to_text(self) -> String {
+        let scale_mode = match self.scale_mode {
+            ScaleMode::Integer => "integer",
+            ScaleMode::Free => "free",
+        };
+        let aspect = match self.aspect {
+            AspectRatio::Square => "square",
+            AspectRatio::Ntsc => "ntsc",
+        };
+        format!(
+            "scale_mode={}\naspect={}\nvsync={}\nperf_hud={}\nslot={}\n",
+            scale_mode, aspect, self.vsync as u8, self.perf_hud as u8, self.slot
+        )
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_parse() {
+        assert_eq!(Settings::parse("").unwrap(), Settings::new());
+
+        let settings = Settings::parse("# saved\nscale_mode=free\naspect = ntsc\nvsync=0\nperf_hud=1\nslot=7\nunknown=1\n").unwrap();
+        assert_eq!(settings.scale_mode, ScaleMode::Free);
+        assert_eq!(settings.aspect, AspectRatio::Ntsc);
+        assert!(!settings.vsync && settings.perf_hud);
+        assert_eq!(settings.slot, 7);
+        assert_eq!(Settings::parse(&settings.to_text()).unwrap(), settings);
+
+        assert!(Settings::parse("slot=10").is_err());
+        assert!(Settings::parse("vsync=yes").is_err());
+        assert!(Settings::parse("scale_mode").is_err());
+    }
+}
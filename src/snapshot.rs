@@ -0,0 +1,136 @@
+// デバッガ表示用のスナップショット (CPU, PPU, APU, マッパー)
+// https://www.nesdev.org/wiki/PPU_registers
+// https://www.nesdev.org/wiki/APU
+// 外から見て意味のある状態 (レジスタ, カウンタ, バンクの割り当て) をコピーして返すので、
+// 非公開のフィールドに触らずに全部表示できる。取っても本体の状態は変わらない
+use crate::apu::ChannelStatus;
+use crate::rom::Mirroring;
+use alloc::vec::Vec;
+
+const PRG_WINDOW: usize = 0x2000; // $8000-$FFFF を8KBごと
+const CHR_WINDOW: usize = 0x0400; // $0000-$1FFF を1KBごと
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuSnapshot {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub cycles: usize, // 電源投入からのCPUサイクル
+    pub jammed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PpuSnapshot {
+    pub ctrl: u8,   // $2000
+    pub mask: u8,   // $2001
+    pub status: u8, // $2002 (読んだときのようにフラグを落とさない)
+    pub oam_addr: u8,
+    pub vram_addr: u16,    // $2006/$2007 のアドレス
+    pub write_latch: bool, // $2005/$2006 の次の書き込みが2回目
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    pub read_buffer: u8, // $2007 の読み出しバッファ
+    pub scanline: usize,
+    pub dot: usize,
+    pub nmi_pending: bool,
+    pub sprite_zero_hits: usize,
+    pub a12_clocks: usize,
+    pub mirroring: Mirroring,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApuSnapshot {
+    pub channels: [ChannelStatus; 4], // 矩形波1, 矩形波2, 三角波, ノイズ
+    pub status: u8,                   // $4015
+    pub frame_counter: u8,            // $4017
+    pub sequencer_mode: u8,           // 4 or 5 (ステップ)
+    pub sequencer_cycles: usize,
+    pub frame_irq: bool,
+    pub cycles: u64,
+    pub expansion: f32, // カートリッジの拡張音源の出力
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapperSnapshot {
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub irq_pending: bool,
+    pub prg_banks: Vec<Option<usize>>, // $8000 から8KBごとに、割り当てられているPRG ROMの位置
+    pub chr_banks: Vec<Option<usize>>, // $0000 から1KBごとに、割り当てられているCHRの位置
+    pub prg_rom_len: usize,
+    pub chr_rom_len: usize, // CHR RAMなら0
+}
+
+impl MapperSnapshot {
+    // prg_offset/chr_offset: マッパーのアドレスからROMの位置 (Mapper::prg_rom_offset/chr_offset)
+    pub fn banks<P, C>(prg_offset: P, chr_offset: C) -> (Vec<Option<usize>>, Vec<Option<usize>>)
+    where
+        P: Fn(u16) -> Option<usize>,
+        C: Fn(u16) -> Option<usize>,
+    {
+        let prg = (0x8000..0x10000).step_by(PRG_WINDOW).map(|addr| prg_offset(addr as u16)).collect();
+        let chr = (0..0x2000).step_by(CHR_WINDOW).map(|addr| chr_offset(addr as u16)).collect();
+        (prg, chr)
+    }
+
+    // 8KBバンクの番号で (表示用)
+    pub fn prg_bank_numbers(&self) -> Vec<Option<usize>> {
+        self.prg_banks.iter().map(|offset| offset.map(|o| o / PRG_WINDOW)).collect()
+    }
+
+    // 1KBバンクの番号で
+    pub fn chr_bank_numbers(&self) -> Vec<Option<usize>> {
+        self.chr_banks.iter().map(|offset| offset.map(|o| o / CHR_WINDOW)).collect()
+    }
+}
+
+// 全部まとめたもの (Nes::snapshot)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugSnapshot {
+    pub cpu: CpuSnapshot,
+    pub ppu: PpuSnapshot,
+    pub apu: ApuSnapshot,
+    pub mapper: MapperSnapshot,
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_snapshot() {
+        // UxROM: $8000 にバンク2を割り当て、PPUのレジスタを書いて止まる
+        let source = "
+            reset: LDA #2
+                   STA $8000
+                   LDA #$80
+                   STA $2000
+                   LDA #$1E
+                   STA $2001
+                   LDA #$21
+                   STA $2006
+                   LDA #$08
+                   STA $2006
+            done:  JMP done
+        ";
+        let mut nes = TestRom::new().mapper(2).prg_banks(4).asm(0xC000, source).reset(0xC000).nes().unwrap();
+        nes.run_until_pc(0xC019).unwrap(); // done
+        let snapshot: DebugSnapshot = nes.snapshot();
+        assert_eq!((snapshot.cpu.a, snapshot.cpu.pc, snapshot.cpu.jammed), (0x08, 0xC019, false));
+        assert_eq!(snapshot.cpu.cycles, nes.bus().cycles());
+        assert_eq!((snapshot.ppu.ctrl, snapshot.ppu.mask, snapshot.ppu.vram_addr), (0x80, 0x1E, 0x2108));
+        assert!(!snapshot.ppu.write_latch);
+        assert!(snapshot.apu.channels.iter().all(|channel| !channel.enabled));
+        assert_eq!(snapshot.mapper.mapper, 2);
+        assert_eq!(snapshot.mapper.prg_bank_numbers(), vec![Some(4), Some(5), Some(6), Some(7)]);
+        assert_eq!(snapshot.mapper.chr_bank_numbers(), (0..8).map(Some).collect::<Vec<_>>());
+        assert_eq!(snapshot.mapper.chr_rom_len, 0);
+
+        // 取っても状態は変わらない
+        assert_eq!(nes.snapshot(), snapshot);
+    }
+}
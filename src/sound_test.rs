@@ -20,9 +20,8 @@ impl AudioCallback for TriangleWave {
     fn callback(&mut self, out: &mut [f32]) {
         for x in out.iter_mut() {
             let res = self.receiver.recv_timeout(Duration::from_millis(0));
-            match res {
-                Ok(note) => self.note = note,
-                Err(_) => {}
+            if let Ok(note) = res {
+                self.note = note;
             }
             *x = (if self.phase <= 0.5 {
                 self.phase
@@ -39,7 +38,7 @@ fn main() {
     let sdl_context = sdl2::init().unwrap();
     let audio_subsystem = sdl_context.audio().unwrap();
 
-    let (sender, receiver) = channel::<TriangleNote>();
+    let (_sender, receiver) = channel::<TriangleNote>();
 
     let desired_spec = AudioSpecDesired {
         freq: Some(44100),
@@ -51,7 +50,7 @@ fn main() {
         .open_playback(None, &desired_spec, |spec| TriangleWave {
             freq: spec.freq as f32,
             phase: 0.0,
-            receiver: receiver,
+            receiver,
             note: TriangleNote { hz: 440.0 },
         })
         .unwrap();
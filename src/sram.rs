@@ -0,0 +1,107 @@
+// バッテリーバックアップされたPRG RAM (.sav) の読み込みと書き出し
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct SramConfig {
+    // Noneの場合はROMと同じディレクトリ
+    pub directory: Option<PathBuf>,
+    // 変更があればこの間隔で書き出す
+    pub flush_interval: Duration,
+}
+
+impl SramConfig {
+    pub fn new() -> Self {
+        SramConfig {
+            directory: None,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+        }
+    }
+
+    // foo/bar.nes => <directory>/bar.sav
+    pub fn save_path(&self, rom_path: &Path) -> PathBuf {
+        let file_name = rom_path.with_extension("sav");
+        match &self.directory {
+            Some(dir) => dir.join(file_name.file_name().unwrap_or_default()),
+            None => file_name,
+        }
+    }
+}
+
+impl Default for SramConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct SramFile {
+    path: PathBuf,
+    flush_interval: Duration,
+    last_flush: Instant,
+    saved: Vec<u8>, // 最後に書き出した内容 (変更がなければ書かない)
+}
+
+impl SramFile {
+    // .savがあればramに読み込む
+    pub fn open(path: PathBuf, config: &SramConfig, ram: &mut [u8]) -> io::Result<Self> {
+        match fs::read(&path) {
+            Ok(data) => {
+                let len = data.len().min(ram.len());
+                ram[..len].copy_from_slice(&data[..len]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(SramFile {
+            path,
+            flush_interval: config.flush_interval,
+            last_flush: Instant::now(),
+            saved: ram.to_vec(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn save(&mut self, ram: &[u8]) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        if self.saved == ram {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        fs::write(&self.path, ram)?;
+        self.saved = ram.to_vec();
+        Ok(())
+    }
+
+    // 最後に書き出した内容に戻す (電源を入れ直してもバッテリーで保持される)
+    pub fn restore(&self, ram: &mut [u8]) {
+        let len = self.saved.len().min(ram.len());
+        ram[..len].copy_from_slice(&self.saved[..len]);
+    }
+
+    pub fn is_flush_due(&self) -> bool {
+        self.last_flush.elapsed() >= self.flush_interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_path() {
+        let mut config = SramConfig::new();
+        assert_eq!(config.save_path(Path::new("rom/nes/zelda.nes")), PathBuf::from("rom/nes/zelda.sav"));
+        config.directory = Some(PathBuf::from("saves"));
+        assert_eq!(config.save_path(Path::new("rom/nes/zelda.nes")), PathBuf::from("saves/zelda.sav"));
+    }
+}
@@ -0,0 +1,357 @@
+// ステートセーブ用のバイナリ形式 (リトルエンディアン、フィールドを宣言順に並べるだけ)
+// 構造体は impl_state! にフィールドを列挙すれば State を実装できる
+use crate::rom::Mirroring;
+use alloc::vec::Vec;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    UnexpectedEof,
+    // 保存時と構造が違う (別のROMやバージョンのステートなど)
+    Mismatch(&'static str),
+    // 対応していないバージョンのステート
+    UnsupportedVersion(u8),
+}
+
+impl core::fmt::Display for StateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StateError::UnexpectedEof => write!(f, "State data is truncated"),
+            StateError::Mismatch(what) => write!(f, "State does not match: {}", what),
+            StateError::UnsupportedVersion(v) => write!(f, "Unsupported state version: {}", v),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: vec![] }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.pos + len > self.data.len() {
+            return Err(StateError::UnexpectedEof);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+pub trait State {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+// 構造体のフィールドを順番に保存/復元する State の実装を作る
+//   impl_state!(Mmc3 { bank_select, banks, irq_counter });
+#[macro_export]
+macro_rules! impl_state {
+    ($name:ty { $($field:ident),* $(,)? }) => {
+        #[allow(unused_variables)]
+        impl $crate::state::State for $name {
+            fn save(&self, w: &mut $crate::state::StateWriter) {
+                $($crate::state::State::save(&self.$field, w);)*
+            }
+            fn load(
+                &mut self,
+                r: &mut $crate::state::StateReader,
+            ) -> Result<(), $crate::state::StateError> {
+                $($crate::state::State::load(&mut self.$field, r)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+// bitflags のレジスタはビット列をそのまま保存する
+//   impl_state_bits!(ControlRegister, MaskRegister);
+#[macro_export]
+macro_rules! impl_state_bits {
+    ($($name:ty),*) => {
+        $(
+            impl $crate::state::State for $name {
+                fn save(&self, w: &mut $crate::state::StateWriter) {
+                    $crate::state::State::save(&self.bits(), w);
+                }
+                fn load(
+                    &mut self,
+                    r: &mut $crate::state::StateReader,
+                ) -> Result<(), $crate::state::StateError> {
+                    let mut bits = self.bits();
+                    $crate::state::State::load(&mut bits, r)?;
+                    *self = <$name>::from_bits_retain(bits);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_state_number {
+    ($($t:ty),*) => {
+        $(
+            impl State for $t {
+                fn save(&self, w: &mut StateWriter) {
+                    w.write_bytes(&self.to_le_bytes());
+                }
+                fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+                    let bytes = r.read_bytes(core::mem::size_of::<$t>())?;
+                    *self = <$t>::from_le_bytes(bytes.try_into().unwrap());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_state_number!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+// 環境によってサイズが変わらないように64ビットで保存する
+impl State for usize {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u64).save(w);
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut v = 0u64;
+        v.load(r)?;
+        *self = v as usize;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, w: &mut StateWriter) {
+        (*self as u8).save(w);
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut v = 0u8;
+        v.load(r)?;
+        *self = v != 0;
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, w: &mut StateWriter) {
+        for v in self.iter() {
+            v.save(w);
+        }
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for v in self.iter_mut() {
+            v.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+// 長さも保存し、復元時には長さが一致することを確認する (RAMのサイズはROMで決まる)
+impl<T: State> State for Vec<T> {
+    fn save(&self, w: &mut StateWriter) {
+        (self.len() as u32).save(w);
+        for v in self.iter() {
+            v.save(w);
+        }
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut len = 0u32;
+        len.load(r)?;
+        if len as usize != self.len() {
+            return Err(StateError::Mismatch("buffer size"));
+        }
+        for v in self.iter_mut() {
+            v.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, w: &mut StateWriter) {
+        self.is_some().save(w);
+        if let Some(v) = self {
+            v.save(w);
+        }
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut is_some = false;
+        is_some.load(r)?;
+        *self = if is_some {
+            let mut v = T::default();
+            v.load(r)?;
+            Some(v)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+// 長さが変わるバッファ (描画途中の履歴など) 用。復元時は長さも合わせる
+pub fn save_resizable<T: State>(v: &[T], w: &mut StateWriter) {
+    (v.len() as u32).save(w);
+    for item in v.iter() {
+        item.save(w);
+    }
+}
+
+pub fn load_resizable<T: State + Default>(v: &mut Vec<T>, r: &mut StateReader) -> Result<(), StateError> {
+    let mut len = 0u32;
+    len.load(r)?;
+    v.clear();
+    for _ in 0..len {
+        let mut item = T::default();
+        item.load(r)?;
+        v.push(item);
+    }
+    Ok(())
+}
+
+impl<A: State, B: State> State for (A, B) {
+    fn save(&self, w: &mut StateWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.0.load(r)?;
+        self.1.load(r)
+    }
+}
+
+impl<A: State, B: State, C: State> State for (A, B, C) {
+    fn save(&self, w: &mut StateWriter) {
+        self.0.save(w);
+        self.1.save(w);
+        self.2.save(w);
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.0.load(r)?;
+        self.1.load(r)?;
+        self.2.load(r)
+    }
+}
+
+impl State for Mirroring {
+    fn save(&self, w: &mut StateWriter) {
+        let v: u8 = match self {
+            Mirroring::VERTICAL => 0,
+            Mirroring::HORIZONTAL => 1,
+            Mirroring::FOUR_SCREEN => 2,
+            Mirroring::ONE_SCREEN_LOWER => 3,
+            Mirroring::ONE_SCREEN_UPPER => 4,
+        };
+        v.save(w);
+    }
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut v = 0u8;
+        v.load(r)?;
+        *self = match v {
+            0 => Mirroring::VERTICAL,
+            1 => Mirroring::HORIZONTAL,
+            2 => Mirroring::FOUR_SCREEN,
+            3 => Mirroring::ONE_SCREEN_LOWER,
+            4 => Mirroring::ONE_SCREEN_UPPER,
+            _ => return Err(StateError::Mismatch("mirroring")),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sample {
+        a: u8,
+        b: [u16; 2],
+        c: bool,
+        d: Vec<u8>,
+        e: (usize, i16),
+        f: Mirroring,
+        g: f32,
+    }
+
+    impl_state!(Sample { a, b, c, d, e, f, g });
+
+    fn sample() -> Sample {
+        Sample {
+            a: 0,
+            b: [0; 2],
+            c: false,
+            d: vec![0; 3],
+            e: (0, 0),
+            f: Mirroring::VERTICAL,
+            g: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let mut src = sample();
+        src.a = 0x12;
+        src.b = [0x3456, 0x789A];
+        src.c = true;
+        src.d = vec![1, 2, 3];
+        src.e = (0x1234_5678, -2);
+        src.f = Mirroring::ONE_SCREEN_UPPER;
+        src.g = 0.5;
+
+        let mut w = StateWriter::new();
+        src.save(&mut w);
+        let bytes = w.into_bytes();
+
+        let mut dst = sample();
+        let mut r = StateReader::new(&bytes);
+        dst.load(&mut r).unwrap();
+        assert!(r.is_empty());
+        assert_eq!(dst.a, 0x12);
+        assert_eq!(dst.b, [0x3456, 0x789A]);
+        assert!(dst.c);
+        assert_eq!(dst.d, vec![1, 2, 3]);
+        assert_eq!(dst.e, (0x1234_5678, -2));
+        assert_eq!(dst.f, Mirroring::ONE_SCREEN_UPPER);
+        assert_eq!(dst.g, 0.5);
+
+        // 途中で切れている
+        assert_eq!(sample().load(&mut StateReader::new(&bytes[..5])), Err(StateError::UnexpectedEof));
+        // RAMのサイズが違う
+        let mut other = sample();
+        other.d = vec![0; 4];
+        assert_eq!(other.load(&mut StateReader::new(&bytes)), Err(StateError::Mismatch("buffer size")));
+    }
+}
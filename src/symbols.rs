@@ -0,0 +1,271 @@
+// ラベル (アドレスの名前とコメント) のファイル
+// https://fceux.com/web/help/NLFilesFormat.html
+// https://www.mesen.ca/docs/debugging/debuggerintegration.html
+//   FCEUX (.nl): $C000#名前#コメント。配列は $0300/10#名前#
+//     <ROM>.ram.nl は $0000-$7FFF、<ROM>.<N>.nl はPRGの16KBバンクN
+//   Mesen (.mlb): 種類:アドレス[-終わり]:名前[:コメント]
+//     P (NesPrgRom) はPRG ROMの位置, R (NesInternalRam) はRAM, S/W (NesSaveRam/NesWorkRam) は$6000から, G (NesMemory) はCPUアドレス
+// PRG ROMのラベルは、そのバンクが割り当てられているときだけ見える (Bus::label)
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::path::Path;
+use alloc::string::{String, ToString};
+
+const PRG_BANK_SIZE: usize = 0x4000; // .nl のバンクの単位
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const RAM_MASK: u16 = 0x07FF;
+const PRG_RAM: u16 = 0x6000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub name: String,
+    pub comment: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolTable {
+    cpu: BTreeMap<u16, Label>,   // CPUアドレス (RAMは$0000-$07FF)
+    prg: BTreeMap<usize, Label>, // PRG ROMの位置
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            cpu: BTreeMap::new(),
+            prg: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cpu.len() + self.prg.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.cpu.clear();
+        self.prg.clear();
+    }
+
+    // スクリプトなどから登録する (同じアドレスなら上書き)
+    pub fn add_label(&mut self, addr: u16, name: &str, comment: &str) {
+        self.cpu.insert(mirror(addr), label(name, comment));
+    }
+
+    pub fn add_prg_label(&mut self, offset: usize, name: &str, comment: &str) {
+        self.prg.insert(offset, label(name, comment));
+    }
+
+    pub fn remove_label(&mut self, addr: u16) -> bool {
+        self.cpu.remove(&mirror(addr)).is_some()
+    }
+
+    // prg_offset はそのアドレスに今割り当てられているPRG ROMの位置 (Cartridge::prg_rom_offset)
+    pub fn lookup(&self, addr: u16, prg_offset: Option<usize>) -> Option<&Label> {
+        prg_offset
+            .and_then(|offset| self.prg.get(&offset))
+            .or_else(|| self.cpu.get(&mirror(addr)))
+    }
+
+    // 名前からCPUアドレスを探す (PRG ROMのラベルはバンクによって変わるので探さない)
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.cpu.iter().find(|(_, label)| label.name == name).map(|(&addr, _)| addr)
+    }
+
+    // bank: <ROM>.<N>.nl ならN、<ROM>.ram.nl ならNone
+    pub fn parse_nl(&mut self, text: &str, bank: Option<usize>) -> Result<usize, String> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if !line.starts_with('$') {
+                continue;
+            }
+            let error = |e: &str| format!("line {}: {}", i + 1, e);
+            let mut fields = line[1..].splitn(3, '#');
+            let addr = fields.next().unwrap_or("");
+            let name = fields.next().ok_or_else(|| error("expected $addr#name#comment"))?;
+            let comment = fields.next().unwrap_or("").trim_end_matches('#');
+            let (addr, size) = match addr.split_once('/') {
+                Some((addr, size)) => (addr, usize::from_str_radix(size, 16).map_err(|_| error(size))?),
+                None => (addr, 1),
+            };
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error(addr))?;
+            for n in 0..size.max(1) {
+                let addr = addr.wrapping_add(n as u16);
+                let name = indexed(name, n);
+                match bank {
+                    Some(bank) if addr >= 0x8000 => {
+                        let offset = bank * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+                        self.add_prg_label(offset, &name, comment)
+                    }
+                    _ => self.add_label(addr, &name, comment),
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn parse_mlb(&mut self, text: &str) -> Result<usize, String> {
+        let mut count = 0;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |e: &str| format!("line {}: {}", i + 1, e);
+            let mut fields = line.splitn(4, ':');
+            let kind = fields.next().unwrap_or("");
+            let range = fields.next().ok_or_else(|| error("expected type:addr:name"))?;
+            let name = fields.next().unwrap_or("");
+            let comment = fields.next().unwrap_or("").replace("\\n", "\n");
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let hex = |s: &str| usize::from_str_radix(s, 16).map_err(|_| error(s));
+            let (start, end) = (hex(start)?, hex(end)?);
+            if end < start {
+                return Err(error(range));
+            }
+            for n in 0..=(end - start) {
+                let name = indexed(name, n);
+                let offset = start + n;
+                match kind {
+                    "P" | "NesPrgRom" => self.add_prg_label(offset, &name, &comment),
+                    "R" | "NesInternalRam" | "G" | "NesMemory" => self.add_label(offset as u16, &name, &comment),
+                    "S" | "W" | "NesSaveRam" | "NesWorkRam" => {
+                        self.add_label(PRG_RAM.wrapping_add(offset as u16), &name, &comment)
+                    }
+                    // CHRなどCPUから見えないもの
+                    _ => continue,
+                }
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    // 拡張子で形式を決める (.nl はファイル名からバンクを決める)
+    #[cfg(feature = "std")]
+    pub fn load(&mut self, path: &Path) -> Result<usize, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("mlb") => self.parse_mlb(&text),
+            Some("nl") => {
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+                let bank = stem.rsplit('.').next().and_then(|b| b.parse().ok());
+                self.parse_nl(&text, bank)
+            }
+            _ => Err(format!("unknown label file: {}", path.display())),
+        }
+    }
+
+    // ROMと同じ場所にあるラベルファイルをすべて読む (game.nes.ram.nl, game.nes.0.nl, ..., game.mlb)
+    #[cfg(feature = "std")]
+    pub fn load_beside(&mut self, rom_path: &Path) -> Vec<(std::path::PathBuf, Result<usize, String>)> {
+        let mut paths = vec![rom_path.with_extension("mlb"), nl_path(rom_path, "ram")];
+        let mut bank = 0;
+        while nl_path(rom_path, &bank.to_string()).exists() {
+            paths.push(nl_path(rom_path, &bank.to_string()));
+            bank += 1;
+        }
+        paths
+            .into_iter()
+            .filter(|path| path.exists())
+            .map(|path| {
+                let result = self.load(&path);
+                (path, result)
+            })
+            .collect()
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 逆アセンブルやトレースの1行のオペランド ($XX / $XXXX) を名前に置き換える (即値の #$XX はそのまま)
+pub fn replace_operand<F>(text: &str, name: F) -> String
+where
+    F: Fn(u16) -> Option<String>,
+{
+    let bytes = text.as_bytes();
+    let start = match (0..bytes.len()).find(|&i| bytes[i] == b'$' && (i == 0 || bytes[i - 1] != b'#')) {
+        Some(start) => start,
+        None => return text.to_string(),
+    };
+    let digits = text[start + 1..].chars().take_while(|c| c.is_ascii_hexdigit()).count();
+    let end = start + 1 + digits;
+    match u16::from_str_radix(&text[start + 1..end], 16).ok().and_then(name) {
+        Some(name) if !name.is_empty() && (digits == 2 || digits == 4) => {
+            format!("{}{}{}", &text[..start], name, &text[end..])
+        }
+        _ => text.to_string(),
+    }
+}
+
+fn mirror(addr: u16) -> u16 {
+    if addr <= RAM_MIRRORS_END {
+        addr & RAM_MASK
+    } else {
+        addr
+    }
+}
+
+fn label(name: &str, comment: &str) -> Label {
+    Label {
+        name: name.to_string(),
+        comment: comment.to_string(),
+    }
+}
+
+// 配列の2バイト目以降は 名前+1, 名前+2, ...
+fn indexed(name: &str, n: usize) -> String {
+    if n == 0 || name.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}+{}", name, n)
+    }
+}
+
+#[cfg(feature = "std")]
+fn nl_path(rom_path: &Path, suffix: &str) -> std::path::PathBuf {
+    let mut name = rom_path.as_os_str().to_owned();
+    name.push(format!(".{}.nl", suffix));
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols_parse() {
+        let mut symbols = SymbolTable::new();
+        let nl = "$0010#counter#frame counter\n$0300/3#buffer#\n$C000#Reset#\n";
+        assert_eq!(symbols.parse_nl(nl, None).unwrap(), 5);
+        assert_eq!(symbols.lookup(0x0810, None).unwrap().comment, "frame counter");
+        assert_eq!(symbols.lookup(0x0302, None).unwrap().name, "buffer+2");
+        assert_eq!(symbols.address_of("Reset"), Some(0xC000));
+
+        // バンク1の $C123 -> PRG $4123。割り当てられていなければ見えない
+        assert_eq!(symbols.parse_nl("$C123#Nmi#", Some(1)).unwrap(), 1);
+        assert_eq!(symbols.lookup(0xC123, Some(0x4123)).unwrap().name, "Nmi");
+        assert_eq!(symbols.lookup(0xC123, Some(0x0123)), None);
+
+        let mlb = "P:0000:Start:entry\\npoint\nR:0020-0021:ptr\nS:0000:save\nP:0005::only a comment\nC:0000:tile\n";
+        let mut symbols = SymbolTable::new();
+        assert_eq!(symbols.parse_mlb(mlb).unwrap(), 5);
+        assert_eq!(symbols.lookup(0x8000, Some(0)).unwrap().comment, "entry\npoint");
+        assert_eq!(symbols.lookup(0x0021, None).unwrap().name, "ptr+1");
+        assert_eq!(symbols.lookup(0x6000, None).unwrap().name, "save");
+        assert!(symbols.parse_mlb("P:zz:bad").is_err());
+
+        let name = |addr: u16| (addr == 0x0020).then(|| "ptr".to_string());
+        assert_eq!(replace_operand("B1 20    LDA ($20),Y", name), "B1 20    LDA (ptr),Y");
+        assert_eq!(replace_operand("A9 20    LDA #$20", name), "A9 20    LDA #$20");
+        assert_eq!(replace_operand("AD 20 00 LDA $0020", name), "AD 20 00 LDA ptr");
+    }
+}
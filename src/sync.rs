@@ -0,0 +1,97 @@
+// std と no_std で同じように使うロック
+// std では std::sync、no_std では spin (割り込みハンドラからは触らない前提のスピンロック)
+// lock() はどちらもガードをそのまま返す (std のポイズンは無視する。パニックしたらエミュレーターごと止まる)
+pub use alloc::sync::Arc;
+
+#[cfg(feature = "std")]
+pub type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+#[cfg(not(feature = "std"))]
+pub type MutexGuard<'a, T> = spin::MutexGuard<'a, T>;
+
+pub struct Mutex<T> {
+    #[cfg(feature = "std")]
+    inner: std::sync::Mutex<T>,
+    #[cfg(not(feature = "std"))]
+    inner: spin::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Mutex {
+            #[cfg(feature = "std")]
+            inner: std::sync::Mutex::new(value),
+            #[cfg(not(feature = "std"))]
+            inner: spin::Mutex::new(value),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.inner.lock()
+    }
+
+    // ほかで使っていたら待たずに None (オーディオのコールバックなど)
+    #[cfg(feature = "std")]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        match self.inner.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(std::sync::TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(std::sync::TryLockError::WouldBlock) => None,
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+}
+
+// 一度だけ設定する値 (パレットの差し替えなど)
+pub struct OnceCell<T> {
+    inner: spin::Once<T>,
+}
+
+impl<T> OnceCell<T> {
+    pub const fn new() -> Self {
+        OnceCell { inner: spin::Once::new() }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.inner.get()
+    }
+
+    // すでに設定されていたら値を返す
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.inner.call_once(|| value.take().unwrap());
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+}
+
+impl<T> Default for OnceCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_once_cell() {
+        let cell = OnceCell::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(cell.set(1), Ok(()));
+        assert_eq!(cell.set(2), Err(2));
+        assert_eq!(cell.get(), Some(&1));
+    }
+}
@@ -0,0 +1,346 @@
+// テスト用のカートリッジをコードで作る (バイナリのROMファイルを置かずに済むように)
+// https://www.nesdev.org/wiki/INES
+//   TestRom: PRG/CHRの大きさ, マッパー, ベクタを決めて iNES のバイト列にする
+//   assemble: 公式命令だけの小さなアセンブラ (ラベル, .byte, .word)
+//     LDA #$01 / STA $2000 / loop: JMP loop のように1行に1命令 (';'以降はコメント)
+//     数値は $16進, %2進, 10進。ラベルはいつも2バイトのアドレス (ゼロページにはならない)
+use crate::apu::{AudioConfig, APU};
+use crate::cpu::AddressingMode;
+use crate::error::EmuError;
+use crate::nes::Nes;
+use crate::opcode::CPU_OPS_CODES;
+use crate::rom::{Rom, RomError};
+use std::collections::HashMap;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+pub struct TestRom {
+    mapper: u8,
+    prg: Vec<u8>,
+    chr: Vec<u8>, // 空ならCHR RAM
+    vertical_mirroring: bool,
+    battery: bool,
+}
+
+impl TestRom {
+    // NROM-128 (PRG 16KB, CHR RAM)。中身は NOP、ベクタはすべて $8000
+    pub fn new() -> Self {
+        let mut rom = TestRom {
+            mapper: 0,
+            prg: vec![0xEA; PRG_BANK_SIZE],
+            chr: vec![],
+            vertical_mirroring: false,
+            battery: false,
+        };
+        rom.reset(0x8000).nmi(0x8000).irq(0x8000);
+        rom
+    }
+
+    pub fn mapper(&mut self, mapper: u8) -> &mut Self {
+        self.mapper = mapper;
+        self
+    }
+
+    // PRGを banks x 16KB にする (中身は NOP に戻る。ベクタは最後のバンクに置き直す)
+    pub fn prg_banks(&mut self, banks: usize) -> &mut Self {
+        let vectors = self.prg[self.prg.len() - 6..].to_vec();
+        self.prg = vec![0xEA; banks.max(1) * PRG_BANK_SIZE];
+        let len = self.prg.len();
+        self.prg[len - 6..].copy_from_slice(&vectors);
+        self
+    }
+
+    // CHR ROM (8KB単位に切り上げる)。空ならCHR RAM
+    pub fn chr(&mut self, chr: &[u8]) -> &mut Self {
+        self.chr = chr.to_vec();
+        let len = self.chr.len().div_ceil(CHR_BANK_SIZE) * CHR_BANK_SIZE;
+        self.chr.resize(len, 0);
+        self
+    }
+
+    pub fn vertical_mirroring(&mut self) -> &mut Self {
+        self.vertical_mirroring = true;
+        self
+    }
+
+    pub fn battery(&mut self) -> &mut Self {
+        self.battery = true;
+        self
+    }
+
+    // PRG ROMの位置 offset に書く
+    pub fn prg_bytes(&mut self, offset: usize, bytes: &[u8]) -> &mut Self {
+        self.prg[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    // 最後のバンクを $C000-$FFFF (16KBなら $8000 にもミラー) としてCPUアドレス addr に書く
+    pub fn code(&mut self, addr: u16, bytes: &[u8]) -> &mut Self {
+        let offset = self.prg.len() - PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1));
+        self.prg_bytes(offset, bytes)
+    }
+
+    // addr にアセンブルして置く (アセンブルできなければpanic。テスト用なので)
+    pub fn asm(&mut self, addr: u16, source: &str) -> &mut Self {
+        let bytes = assemble(addr, source).unwrap_or_else(|e| panic!("[ERR] asm: {}", e));
+        self.code(addr, &bytes)
+    }
+
+    pub fn reset(&mut self, addr: u16) -> &mut Self {
+        self.code(RESET_VECTOR, &addr.to_le_bytes())
+    }
+
+    pub fn nmi(&mut self, addr: u16) -> &mut Self {
+        self.code(NMI_VECTOR, &addr.to_le_bytes())
+    }
+
+    pub fn irq(&mut self, addr: u16) -> &mut Self {
+        self.code(IRQ_VECTOR, &addr.to_le_bytes())
+    }
+
+    // iNES のバイト列
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let flags6 = (self.mapper & 0x0F) << 4 | (self.battery as u8) << 1 | self.vertical_mirroring as u8;
+        let mut raw = vec![
+            0x4E,
+            0x45,
+            0x53,
+            0x1A,
+            (self.prg.len() / PRG_BANK_SIZE) as u8,
+            (self.chr.len() / CHR_BANK_SIZE) as u8,
+            flags6,
+            self.mapper & 0xF0,
+        ];
+        raw.resize(16, 0);
+        raw.extend(&self.prg);
+        raw.extend(&self.chr);
+        raw
+    }
+
+    pub fn build(&self) -> Result<Rom, RomError> {
+        Rom::from_bytes(&self.to_bytes())
+    }
+
+    // 音を出さない本体に挿して電源を入れる
+    pub fn nes(&self) -> Result<Nes, EmuError> {
+        Nes::new(self.build()?, APU::headless(&AudioConfig::new()))
+    }
+}
+
+impl Default for TestRom {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 1行ずつ見て、命令の大きさを決めてから (1回目) バイト列にする (2回目)
+pub fn assemble(origin: u16, source: &str) -> Result<Vec<u8>, String> {
+    let mut labels = HashMap::new();
+    let mut lines = vec![];
+    let mut pc = origin;
+    for (i, line) in source.lines().enumerate() {
+        let mut line = line.split(';').next().unwrap_or("").trim();
+        if let Some((label, rest)) = line.split_once(':') {
+            if labels.insert(label.trim().to_string(), pc).is_some() {
+                return Err(format!("line {}: duplicate label {}", i + 1, label));
+            }
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, operand) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let (mnemonic, operand) = (mnemonic.to_ascii_uppercase(), operand.trim().to_string());
+        let size = match mnemonic.as_str() {
+            ".BYTE" => operand.split(',').count(),
+            ".WORD" => operand.split(',').count() * 2,
+            _ => {
+                let (mode, _) = addressing(&mnemonic, &operand, &|_| Some(0)).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                opcode(&mnemonic, &mode).ok_or_else(|| format!("line {}: {} {}", i + 1, mnemonic, operand))?.1 as usize
+            }
+        };
+        lines.push((i + 1, pc, mnemonic, operand));
+        pc = pc.wrapping_add(size as u16);
+    }
+
+    let mut out = vec![];
+    for (line, pc, mnemonic, operand) in lines {
+        let error = |e: String| format!("line {}: {}", line, e);
+        let resolve = |s: &str| labels.get(s).copied();
+        match mnemonic.as_str() {
+            ".BYTE" => {
+                for value in operand.split(',') {
+                    out.push(number(value.trim(), &resolve).map_err(error)? as u8);
+                }
+            }
+            ".WORD" => {
+                for value in operand.split(',') {
+                    out.extend(number(value.trim(), &resolve).map_err(error)?.to_le_bytes());
+                }
+            }
+            _ => {
+                let (mode, value) = addressing(&mnemonic, &operand, &resolve).map_err(error)?;
+                let (code, _) = opcode(&mnemonic, &mode).unwrap();
+                out.push(code);
+                match mode {
+                    AddressingMode::Implied | AddressingMode::Accumulator => {}
+                    AddressingMode::Relative => {
+                        let offset = value as i32 - (pc as i32 + 2);
+                        if !(-128..=127).contains(&offset) {
+                            return Err(error(format!("branch out of range: {}", operand)));
+                        }
+                        out.push(offset as i8 as u8);
+                    }
+                    AddressingMode::Immediate
+                    | AddressingMode::ZeroPage
+                    | AddressingMode::ZeroPage_X
+                    | AddressingMode::ZeroPage_Y
+                    | AddressingMode::Indirect_X
+                    | AddressingMode::Indirect_Y => out.push(value as u8),
+                    _ => out.extend(value.to_le_bytes()),
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+// 公式命令のオペコードと大きさ
+fn opcode(mnemonic: &str, mode: &AddressingMode) -> Option<(u8, u16)> {
+    CPU_OPS_CODES
+        .iter()
+        .find(|op| op.name == mnemonic && op.addressing_mode == *mode)
+        .map(|op| (op.code, op.bytes))
+}
+
+// オペランドの書き方からアドレッシングモードと値を決める
+fn addressing<F>(mnemonic: &str, operand: &str, resolve: &F) -> Result<(AddressingMode, u16), String>
+where
+    F: Fn(&str) -> Option<u16>,
+{
+    let upper = operand.to_ascii_uppercase();
+    if operand.is_empty() {
+        let mode = if opcode(mnemonic, &AddressingMode::Accumulator).is_some() {
+            AddressingMode::Accumulator
+        } else {
+            AddressingMode::Implied
+        };
+        return Ok((mode, 0));
+    }
+    if upper == "A" {
+        return Ok((AddressingMode::Accumulator, 0));
+    }
+    if let Some(value) = operand.strip_prefix('#') {
+        return Ok((AddressingMode::Immediate, number(value, resolve)?));
+    }
+    if opcode(mnemonic, &AddressingMode::Relative).is_some() {
+        return Ok((AddressingMode::Relative, number(operand, resolve)?));
+    }
+    if let Some(inner) = upper.strip_prefix('(') {
+        let inner_len = operand.len() - 1;
+        if let Some(base) = inner.strip_suffix(",X)") {
+            return Ok((AddressingMode::Indirect_X, number(&operand[1..1 + base.len()], resolve)?));
+        }
+        if let Some(base) = inner.strip_suffix("),Y") {
+            return Ok((AddressingMode::Indirect_Y, number(&operand[1..1 + base.len()], resolve)?));
+        }
+        if inner.ends_with(')') {
+            return Ok((AddressingMode::Indirect, number(&operand[1..inner_len], resolve)?));
+        }
+        return Err(format!("bad operand: {}", operand));
+    }
+    let (base, index) = match upper.rsplit_once(',') {
+        Some((base, index)) => (&operand[..base.len()], Some(index.trim().to_string())),
+        None => (operand, None),
+    };
+    let value = number(base.trim(), resolve)?;
+    // ゼロページは $xx と書いたときだけ (ラベルは2バイト)
+    let zero_page = value <= 0xFF && !base.trim().starts_with(|c: char| c.is_ascii_alphabetic() || c == '_');
+    let zero_page = zero_page && base.trim().trim_start_matches('$').len() <= 2;
+    let modes = match index.as_deref() {
+        None => (AddressingMode::ZeroPage, AddressingMode::Absolute),
+        Some("X") => (AddressingMode::ZeroPage_X, AddressingMode::Absolute_X),
+        Some("Y") => (AddressingMode::ZeroPage_Y, AddressingMode::Absolute_Y),
+        Some(index) => return Err(format!("bad index: {}", index)),
+    };
+    if zero_page && opcode(mnemonic, &modes.0).is_some() {
+        Ok((modes.0, value))
+    } else {
+        Ok((modes.1, value))
+    }
+}
+
+fn number<F>(text: &str, resolve: &F) -> Result<u16, String>
+where
+    F: Fn(&str) -> Option<u16>,
+{
+    let text = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix('%') {
+        u16::from_str_radix(bin, 2).ok()
+    } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+        text.parse().ok()
+    } else {
+        resolve(text)
+    };
+    parsed.ok_or_else(|| format!("bad number or unknown label: {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            reset: LDA #$80     ; NMIを有効にする
+                   STA $2000
+                   LDX #0
+            loop:  INC $10,X
+                   LDA ($20),Y
+                   ASL
+                   JMP (vector)
+                   BNE loop
+                   JSR sub
+            sub:   RTS
+            vector: .word loop
+                   .byte 1, %11, $FF
+        ";
+        let bytes = assemble(0x8000, source).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA2, 0x00, // reset
+                0xF6, 0x10, 0xB1, 0x20, 0x0A, 0x6C, 0x15, 0x80, 0xD0, 0xF6, 0x20, 0x14, 0x80, // loop
+                0x60, 0x07, 0x80, 0x01, 0x03, 0xFF,
+            ]
+        );
+        assert!(assemble(0x8000, "LDA #1\nBNE far\n.byte 0\nfar:").is_ok());
+        assert!(assemble(0x8000, "FOO #1").is_err());
+        assert!(assemble(0x8000, "JMP nowhere").is_err());
+        assert!(assemble(0x8000, "x: NOP\nx: NOP").is_err());
+    }
+
+    #[test]
+    fn test_test_rom() {
+        let mut nes = TestRom::new()
+            .asm(0x8000, "LDA #$42\nSTA $10\nloop: JMP loop")
+            .chr(&[0x55; 16])
+            .vertical_mirroring()
+            .nes()
+            .unwrap();
+        nes.run_frame();
+        assert_eq!(nes.ram()[0x10], 0x42);
+        assert_eq!(nes.bus().ppu().pattern_tables()[0], 0x55);
+
+        let rom = TestRom::new().mapper(2).prg_banks(4).battery().build().unwrap();
+        assert_eq!((rom.mapper, rom.prg_rom.len(), rom.header.has_battery), (2, 0x10000, true));
+        assert_eq!(rom.prg_rom[0xFFFC..0xFFFE], [0x00, 0x80]);
+        assert!(rom.is_chr_ram);
+    }
+}
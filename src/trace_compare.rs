@@ -0,0 +1,255 @@
+// 他のエミュレーターのトレースと1命令ずつ比べて、最初に食い違ったところで止める
+// https://www.qmtpro.com/~nes/misc/nestest.log
+//   nestest/このエミュレーター: C000  4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 P:24 SP:FD ... CYC:7
+//   FCEUX:  $C000:4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 S:FD P:nvUbdIzc
+//   Mesen:  C000 $4C $F5 $C5  JMP $C5F5  A:00 X:00 Y:00 S:FD P:nvUbdIzc ... Cycle:7
+// 行の先頭のPCと A/X/Y/P/SP (S) を比べる。P の B と bit5 はエミュレーターによって違うので見ない
+// サイクル (CYC:/Cycle:) が書いてあれば、最初の行からの差で比べる
+// 最初の行のPCがリセットベクタと違えば、そこから始める (nestest の自動テストは $C000 から)
+use crate::cpu::{trace, CPU};
+use crate::nes::Nes;
+use std::io::BufRead;
+
+const P_IGNORED: u8 = 0b0011_0000;
+const P_LETTERS: &str = "NVUBDIZC";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycle: Option<usize>,
+}
+
+impl TraceState {
+    // 命令を実行する直前 (run_frame_with_callback のコールバック) の状態
+    pub fn of(cpu: &CPU) -> Self {
+        TraceState {
+            pc: cpu.program_counter.wrapping_sub(1),
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            p: cpu.status,
+            sp: cpu.stack_pointer,
+            cycle: Some(cpu.bus.cycles()),
+        }
+    }
+
+    // 参照トレースの1行 (読めなければNone)
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut tokens = line.split_whitespace();
+        let first = tokens.next()?.trim_start_matches('$');
+        let pc = u16::from_str_radix(first.get(..4)?, 16).ok()?;
+        let mut state = TraceState {
+            pc,
+            a: 0,
+            x: 0,
+            y: 0,
+            p: 0,
+            sp: 0,
+            cycle: None,
+        };
+        let mut found = 0;
+        for token in line.split_whitespace() {
+            let (name, value) = match token.split_once(':') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let hex = || u8::from_str_radix(value, 16).ok();
+            match name {
+                "A" => state.a = hex()?,
+                "X" => state.x = hex()?,
+                "Y" => state.y = hex()?,
+                "SP" | "S" => state.sp = hex()?,
+                "P" if value.len() == 8 => state.p = parse_flags(value)?,
+                "P" => state.p = hex()?,
+                "CYC" | "Cycle" => state.cycle = value.parse().ok(),
+                _ => continue,
+            }
+            found += 1;
+        }
+        // A X Y P SP がそろっていない行 (見出しなど) は飛ばす
+        if found < 5 {
+            return None;
+        }
+        Some(state)
+    }
+
+    // cycle_base: 最初の行のサイクル (参照側, こちら側)
+    fn matches(&self, other: &TraceState, cycle_base: (Option<usize>, Option<usize>)) -> bool {
+        let cycles = match (self.cycle, other.cycle, cycle_base) {
+            (Some(a), Some(b), (Some(a0), Some(b0))) => a.wrapping_sub(a0) == b.wrapping_sub(b0),
+            _ => true,
+        };
+        self.pc == other.pc
+            && self.a == other.a
+            && self.x == other.x
+            && self.y == other.y
+            && self.p & !P_IGNORED == other.p & !P_IGNORED
+            && self.sp == other.sp
+            && cycles
+    }
+}
+
+impl std::fmt::Display for TraceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc, self.a, self.x, self.y, self.p, self.sp
+        )?;
+        if let Some(cycle) = self.cycle {
+            write!(f, " CYC:{}", cycle)?;
+        }
+        Ok(())
+    }
+}
+
+// nvUbdIzc (大文字が立っている)
+fn parse_flags(text: &str) -> Option<u8> {
+    let mut p = 0;
+    for (c, letter) in text.chars().zip(P_LETTERS.chars()) {
+        if c == letter {
+            p = p << 1 | 1;
+        } else if c == letter.to_ascii_lowercase() {
+            p <<= 1;
+        } else {
+            return None;
+        }
+    }
+    Some(p)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub line: usize, // 参照トレースの行番号 (1から)
+    pub instruction: usize,
+    pub expected: TraceState,
+    pub actual: TraceState,
+    pub expected_text: String,
+    pub actual_text: String, // cpu::trace の形式
+    pub previous_text: Option<String>, // 一致した最後の命令
+}
+
+impl std::fmt::Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "diverged at line {} (instruction {})", self.line, self.instruction)?;
+        if let Some(previous) = self.previous_text.as_deref() {
+            writeln!(f, "  last match: {}", previous)?;
+        }
+        writeln!(f, "  reference:  {}", self.expected_text.trim_end())?;
+        writeln!(f, "  this:       {}", self.actual_text)?;
+        writeln!(f, "  expected:   {}", self.expected)?;
+        write!(f, "  actual:     {}", self.actual)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompareResult {
+    Match(usize), // 比べた命令の数
+    Diverged(Box<Divergence>),
+}
+
+// 参照トレースの行がなくなるか max_instructions 命令まで比べる
+pub fn compare<R: BufRead>(nes: &mut Nes, reference: R, max_instructions: Option<usize>) -> Result<CompareResult, String> {
+    let mut lines = reference
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| match line {
+            Ok(line) => TraceState::parse(&line).map(|state| Ok((i + 1, line, state))),
+            Err(e) => Some(Err(e.to_string())),
+        })
+        .peekable();
+
+    // 参照の最初のPCから始める
+    if let Some(Ok((_, _, first))) = lines.peek() {
+        nes.cpu_mut().program_counter = first.pc;
+    }
+
+    let mut count = 0;
+    let mut cycle_base = (None, None);
+    let mut previous_text = None;
+    let mut divergence = None;
+    while max_instructions.is_none_or(|max| count < max) && divergence.is_none() {
+        let (line, expected_text, expected) = match lines.next() {
+            Some(entry) => entry?,
+            None => break,
+        };
+        nes.cpu_mut().step_with_callback(|cpu| {
+            let actual = TraceState::of(cpu);
+            if count == 0 {
+                cycle_base = (expected.cycle, actual.cycle);
+            }
+            let actual_text = trace(cpu);
+            if !expected.matches(&actual, cycle_base) {
+                divergence = Some(Box::new(Divergence {
+                    line,
+                    instruction: count + 1,
+                    expected,
+                    actual,
+                    expected_text: expected_text.clone(),
+                    actual_text,
+                    previous_text: previous_text.take(),
+                }));
+            } else {
+                previous_text = Some(actual_text);
+            }
+        });
+        if nes.cpu().is_jammed() && divergence.is_none() {
+            return Err(format!("CPU jammed at ${:04X}", nes.cpu().program_counter));
+        }
+        count += 1;
+    }
+    Ok(match divergence {
+        Some(divergence) => CompareResult::Diverged(divergence),
+        None => CompareResult::Match(count),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_trace_compare() {
+        let nestest = "C000  A2 05     LDX #$05    A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+        let state = TraceState::parse(nestest).unwrap();
+        assert_eq!((state.pc, state.p, state.sp, state.cycle), (0xC000, 0x24, 0xFD, Some(7)));
+        let fceux = "$C002:CA        DEX                A:00 X:05 Y:00 S:FD P:nvUbdIzc";
+        let state = TraceState::parse(fceux).unwrap();
+        assert_eq!((state.pc, state.x, state.p), (0xC002, 5, 0x24));
+        assert_eq!(TraceState::parse("FCEUX 2.6.4 - Trace Log File"), None);
+
+        let source = "LDX #5\nloop: DEX\nBNE loop\nend: JMP end";
+        let mut nes = TestRom::new().asm(0xC000, source).nes().unwrap();
+        let mut reference = vec![];
+        nes.cpu_mut().program_counter = 0xC000;
+        for _ in 0..8 {
+            nes.cpu_mut().step_with_callback(|cpu| reference.push(format!("{}", TraceState::of(cpu))));
+        }
+        let reference = reference.join("\n");
+
+        let run = |reference: &str, max: Option<usize>| {
+            let mut nes = TestRom::new().asm(0xC000, source).nes().unwrap();
+            compare(&mut nes, reference.as_bytes(), max).unwrap()
+        };
+        assert_eq!(run(&reference, None), CompareResult::Match(8));
+        assert_eq!(run(&reference, Some(3)), CompareResult::Match(3));
+
+        // 5行目で X を変えると、そこで止まる
+        let mut lines: Vec<String> = reference.lines().map(|l| l.to_string()).collect();
+        lines[4] = lines[4].replace("X:03", "X:04");
+        match run(&lines.join("\n"), None) {
+            CompareResult::Diverged(d) => {
+                assert_eq!((d.line, d.instruction, d.expected.x, d.actual.x), (5, 5, 4, 3));
+                assert!(d.previous_text.unwrap().starts_with("C002"));
+                assert!(d.actual_text.starts_with("C003"));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// 命令トレースをファイルに書き出す (絞り込み付き)
+// 1行の形式は cpu::trace と同じ (nestest.log と比べられる。ラベルがあればオペランドは名前になる)
+// 絞り込みはカンマ区切りで指定する。同じ種類はどれか1つ、違う種類はすべてに合えば書き出す
+//   8000-80FF  PCの範囲 (1アドレスなら 8000)
+//   branch     命令の種類 (branch, jump, load, store, stack, unofficial)
+//   A=10       レジスタの値 (A, X, Y, P, SP。16進)
+// 数百万命令でも書けるように、出力はまとめて書き込む
+use crate::bus::Mem;
+use crate::cpu::{set_in_trace, trace, AddressingMode, OpCode, CPU};
+use crate::opcode::CPU_OPS_CODES;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpClass {
+    Branch,     // 条件分岐
+    Jump,       // JMP, JSR, RTS, RTI, BRK
+    Load,       // LDA, LDX, LDY, LAX
+    Store,      // STA, STX, STY, SAX
+    Stack,      // PHA, PHP, PLA, PLP, TSX, TXS
+    Unofficial, // 非公式命令
+}
+
+impl OpClass {
+    fn parse(name: &str) -> Option<OpClass> {
+        Some(match name {
+            "branch" => OpClass::Branch,
+            "jump" => OpClass::Jump,
+            "load" => OpClass::Load,
+            "store" => OpClass::Store,
+            "stack" => OpClass::Stack,
+            "unofficial" => OpClass::Unofficial,
+            _ => return None,
+        })
+    }
+
+    fn contains(&self, op: &OpCode) -> bool {
+        let name = op.name.trim_start_matches('*');
+        match self {
+            OpClass::Branch => matches!(op.addressing_mode, AddressingMode::Relative),
+            OpClass::Jump => ["JMP", "JSR", "RTS", "RTI", "BRK"].contains(&name),
+            OpClass::Load => ["LDA", "LDX", "LDY", "LAX"].contains(&name),
+            OpClass::Store => ["STA", "STX", "STY", "SAX"].contains(&name),
+            OpClass::Stack => ["PHA", "PHP", "PLA", "PLP", "TSX", "TXS"].contains(&name),
+            OpClass::Unofficial => op.name.starts_with('*'),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    P,
+    SP,
+}
+
+impl Register {
+    fn get(&self, cpu: &CPU) -> u8 {
+        match self {
+            Register::A => cpu.register_a,
+            Register::X => cpu.register_x,
+            Register::Y => cpu.register_y,
+            Register::P => cpu.status,
+            Register::SP => cpu.stack_pointer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFilter {
+    ranges: Vec<(u16, u16)>,
+    classes: Vec<OpClass>,
+    opcodes: Option<Vec<bool>>, // classes に合うオペコード (256個)
+    conditions: Vec<(Register, u8)>,
+}
+
+impl TraceFilter {
+    // すべての命令を書き出す
+    pub fn new() -> Self {
+        TraceFilter {
+            ranges: vec![],
+            classes: vec![],
+            opcodes: None,
+            conditions: vec![],
+        }
+    }
+
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = TraceFilter::new();
+        for term in spec.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            let hex = |s: &str| u16::from_str_radix(s.trim().trim_start_matches('$'), 16);
+            if let Some((name, value)) = term.split_once('=') {
+                let register = match name.trim().to_ascii_uppercase().as_str() {
+                    "A" => Register::A,
+                    "X" => Register::X,
+                    "Y" => Register::Y,
+                    "P" => Register::P,
+                    "SP" => Register::SP,
+                    _ => return Err(format!("unknown register: {}", term)),
+                };
+                match hex(value) {
+                    Ok(value) if value <= 0xFF => filter.conditions.push((register, value as u8)),
+                    _ => return Err(format!("bad register value: {}", term)),
+                }
+            } else if let Some(class) = OpClass::parse(&term.to_ascii_lowercase()) {
+                filter.classes.push(class);
+            } else {
+                let (start, end) = term.split_once('-').unwrap_or((term, term));
+                match (hex(start), hex(end)) {
+                    (Ok(start), Ok(end)) if start <= end => filter.ranges.push((start, end)),
+                    _ => return Err(format!("bad trace filter: {}", term)),
+                }
+            }
+        }
+        if !filter.classes.is_empty() {
+            let mut opcodes = vec![false; 256];
+            for op in CPU_OPS_CODES.iter() {
+                opcodes[op.code as usize] = filter.classes.iter().any(|c| c.contains(op));
+            }
+            filter.opcodes = Some(opcodes);
+        }
+        Ok(filter)
+    }
+
+    // 命令を読む前 (run_frame_with_callback のコールバック) に呼ぶ
+    pub fn matches(&self, cpu: &mut CPU) -> bool {
+        let pc = cpu.program_counter.wrapping_sub(1);
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|&(start, end)| start <= pc && pc <= end) {
+            return false;
+        }
+        if !self.conditions.iter().all(|&(register, value)| register.get(cpu) == value) {
+            return false;
+        }
+        if let Some(opcodes) = self.opcodes.as_ref() {
+            set_in_trace(true);
+            let op = cpu.mem_read(pc);
+            set_in_trace(false);
+            return opcodes[op as usize];
+        }
+        true
+    }
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct TraceLogger<W: Write> {
+    filter: TraceFilter,
+    writer: W,
+    lines: usize,
+    error: Option<std::io::Error>, // 最初の書き込みエラー (以降は書かない)
+}
+
+impl TraceLogger<BufWriter<File>> {
+    pub fn create(path: &Path, filter: TraceFilter) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(TraceLogger::new(BufWriter::with_capacity(BUFFER_SIZE, file), filter))
+    }
+}
+
+impl<W: Write> TraceLogger<W> {
+    pub fn new(writer: W, filter: TraceFilter) -> Self {
+        TraceLogger {
+            filter,
+            writer,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    // run_frame_with_callback のコールバックから呼ぶ
+    pub fn log(&mut self, cpu: &mut CPU) {
+        if self.error.is_some() || !self.filter.matches(cpu) {
+            return;
+        }
+        let line = trace(cpu);
+        let line = cpu.bus.label_operand(&line);
+        match writeln!(self.writer, "{}", line) {
+            Ok(()) => self.lines += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    // 残りを書き出して、(書いた行数, 出力先) を返す
+    pub fn finish(mut self) -> std::io::Result<(usize, W)> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()?;
+        Ok((self.lines, self.writer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apu::{AudioConfig, APU};
+    use crate::nes::Nes;
+    use crate::rom::Rom;
+
+    fn test_nes() -> Nes {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x00];
+        raw.resize(16, 0);
+        let mut prg = vec![0xEA; 0x4000];
+        let program = [
+            0xA2, 0x00, // LDX #$00
+            0xE8, // loop: INX
+            0xD0, 0xFD, // BNE loop
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        raw.extend(prg);
+        Nes::new(Rom::from_bytes(&raw).unwrap(), APU::headless(&AudioConfig::new())).unwrap()
+    }
+
+    fn run(spec: &str, steps: usize) -> Vec<String> {
+        let mut nes = test_nes();
+        let mut logger = TraceLogger::new(vec![], TraceFilter::parse(spec).unwrap());
+        for _ in 0..steps {
+            nes.cpu_mut().step_with_callback(|cpu| logger.log(cpu));
+        }
+        let (lines, out) = logger.finish().unwrap();
+        let text: Vec<String> = String::from_utf8(out).unwrap().lines().map(|l| l.to_string()).collect();
+        assert_eq!(text.len(), lines);
+        text
+    }
+
+    #[test]
+    fn test_trace_filter() {
+        // 1周は LDX + (INX, BNE) x 256 + JMP
+        assert_eq!(run("", 514).len(), 514);
+        let lines = run("jump", 514);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("8005  4C 00 80  JMP $8000"));
+        assert_eq!(run("branch,load", 514).len(), 257);
+        assert_eq!(run("8002", 514).len(), 256);
+        // INX の直前に X=$FF なのは1回だけ
+        let lines = run("8002-8002,X=FF", 514);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("X:FF"));
+
+        assert!(TraceFilter::parse("8000-7FFF").is_err());
+        assert!(TraceFilter::parse("Q=1").is_err());
+        assert!(TraceFilter::parse("A=100").is_err());
+        assert!(TraceFilter::parse("calls").is_err());
+    }
+}
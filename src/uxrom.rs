@@ -0,0 +1,127 @@
+// Mapper 2 (UxROM)
+// https://www.nesdev.org/wiki/UxROM
+// $8000-$BFFF: 16KB切り替え, $C000-$FFFF: 最後のバンクに固定, CHR RAM 8KB
+use crate::mapper::Mapper;
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+    mirroring: Mirroring,
+    bank: usize,
+    // 書き込んだ値とROMの値がANDされる (NES 2.0 サブマッパー2)
+    bus_conflicts: bool,
+}
+
+impl_state!(Uxrom { chr, bank });
+
+impl Uxrom {
+    pub fn new(rom: &Rom) -> Self {
+        Uxrom {
+            prg_rom: rom.prg_rom.clone(),
+            chr: rom.chr_rom.clone(),
+            is_chr_ram: rom.is_chr_ram,
+            mirroring: rom.mirroring.clone(),
+            bank: 0,
+            bus_conflicts: rom.header.submapper == 2,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.prg_rom_offset(addr).map_or(0, |offset| self.prg_rom[offset])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        let data = if self.bus_conflicts {
+            data & self.cpu_read(addr)
+        } else {
+            data
+        };
+        self.bank = data as usize % self.bank_count();
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.is_chr_ram {
+            let len = self.chr.len();
+            self.chr[(addr & 0x1FFF) as usize % len] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xBFFF => Some(self.bank * PRG_BANK_SIZE + (addr - 0x8000) as usize),
+            0xC000..=0xFFFF => Some((self.bank_count() - 1) * PRG_BANK_SIZE + (addr - 0xC000) as usize),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some((addr & 0x1FFF) as usize % self.chr.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各バンクの先頭にバンク番号を書いた128KBのUNROMイメージ
+    fn unrom(submapper: u8) -> Rom {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 8, 0, 0x20, 0x08, submapper << 4];
+        raw.resize(16, 0);
+        for bank in 0..8u8 {
+            let mut prg = vec![0xFF; PRG_BANK_SIZE];
+            prg[0] = bank;
+            prg[1] = 0x00;
+            raw.extend(prg);
+        }
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_uxrom_banking() {
+        let mut mapper = Uxrom::new(&unrom(0));
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        mapper.cpu_write(0x8000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3);
+        assert_eq!(mapper.cpu_read(0xC000), 7);
+
+        // CHR RAM
+        mapper.ppu_write(0x0010, 0x99);
+        assert_eq!(mapper.ppu_read(0x0010), 0x99);
+    }
+
+    #[test]
+    fn test_uxrom_bus_conflicts() {
+        let mut mapper = Uxrom::new(&unrom(2));
+        // $8001 には 0x00 が入っているので、何を書いてもバンク0
+        mapper.cpu_write(0x8001, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        // $8002 は 0xFF なのでそのまま
+        mapper.cpu_write(0x8002, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+    }
+}
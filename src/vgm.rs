@@ -0,0 +1,119 @@
+// VGM (Video Game Music) 形式でAPUのレジスタ書き込みを記録する
+// https://vgmrips.net/wiki/VGM_Specification
+use alloc::vec::Vec;
+const VGM_IDENT: [u8; 4] = [0x56, 0x67, 0x6D, 0x20]; // "Vgm "
+const VGM_VERSION: u32 = 0x0000_0161;
+const VGM_HEADER_SIZE: usize = 0x100;
+const VGM_SAMPLE_RATE: u64 = 44100;
+
+const VGM_CMD_WAIT: u8 = 0x61;     // 0x61 nn nn : nnnnサンプル待つ
+const VGM_CMD_WAIT_60HZ: u8 = 0x62; // 735サンプル待つ
+const VGM_CMD_WAIT_50HZ: u8 = 0x63; // 882サンプル待つ
+const VGM_CMD_END: u8 = 0x66;
+const VGM_CMD_NES_APU: u8 = 0xB4;   // 0xB4 aa dd : $4000+aa に dd を書き込む
+const VGM_CMD_WAIT_SHORT: u8 = 0x70; // 0x7n : n+1サンプル待つ
+
+const NES_APU_CLOCK: u32 = 1_789_772;
+const CPU_CLOCK_HZ: u64 = 1_789_773;
+
+pub struct VgmLogger {
+    start_cycle: u64,
+    last_sample: u64,
+    commands: Vec<u8>,
+}
+
+impl VgmLogger {
+    pub fn new(start_cycle: u64) -> Self {
+        VgmLogger {
+            start_cycle,
+            last_sample: 0,
+            commands: vec![],
+        }
+    }
+
+    // cycle: 書き込み時点のCPUサイクル, addr: $4000-$401F
+    pub fn write_apu(&mut self, cycle: u64, addr: u16, data: u8) {
+        self.wait_until(cycle);
+        self.commands.push(VGM_CMD_NES_APU);
+        self.commands.push((addr - 0x4000) as u8);
+        self.commands.push(data);
+    }
+
+    fn wait_until(&mut self, cycle: u64) {
+        let sample = cycle.saturating_sub(self.start_cycle) * VGM_SAMPLE_RATE / CPU_CLOCK_HZ;
+        if sample <= self.last_sample {
+            return;
+        }
+        let mut wait = sample - self.last_sample;
+        self.last_sample = sample;
+
+        while wait > 0 {
+            match wait {
+                735 => {
+                    self.commands.push(VGM_CMD_WAIT_60HZ);
+                    wait = 0;
+                }
+                882 => {
+                    self.commands.push(VGM_CMD_WAIT_50HZ);
+                    wait = 0;
+                }
+                1..=16 => {
+                    self.commands.push(VGM_CMD_WAIT_SHORT | (wait - 1) as u8);
+                    wait = 0;
+                }
+                _ => {
+                    let n = wait.min(0xFFFF);
+                    self.commands.push(VGM_CMD_WAIT);
+                    self.commands.extend_from_slice(&(n as u16).to_le_bytes());
+                    wait -= n;
+                }
+            }
+        }
+    }
+
+    // 記録を終了してVGMファイルのバイト列を作る (end_cycle までの無音も含める)
+    pub fn finish(&mut self, end_cycle: u64) -> Vec<u8> {
+        self.wait_until(end_cycle);
+
+        let mut data = vec![0; VGM_HEADER_SIZE];
+        data.extend_from_slice(&self.commands);
+        data.push(VGM_CMD_END);
+
+        let eof_offset = (data.len() - 0x04) as u32;
+        let data_offset = (VGM_HEADER_SIZE - 0x34) as u32;
+        data[0x00..0x04].copy_from_slice(&VGM_IDENT);
+        data[0x04..0x08].copy_from_slice(&eof_offset.to_le_bytes());
+        data[0x08..0x0C].copy_from_slice(&VGM_VERSION.to_le_bytes());
+        data[0x18..0x1C].copy_from_slice(&(self.last_sample as u32).to_le_bytes());
+        data[0x34..0x38].copy_from_slice(&data_offset.to_le_bytes());
+        data[0x84..0x88].copy_from_slice(&NES_APU_CLOCK.to_le_bytes());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vgm_header_and_commands() {
+        let mut vgm = VgmLogger::new(1000);
+        vgm.write_apu(1000, 0x4000, 0xBF);
+        // 約1フレーム後 (29781 CPUサイクル = 733サンプル)
+        vgm.write_apu(1000 + 29781, 0x4015, 0x0F);
+        let data = vgm.finish(1000 + 29781);
+
+        assert_eq!(&data[0..4], b"Vgm ");
+        assert_eq!(u32::from_le_bytes(data[0x08..0x0C].try_into().unwrap()), 0x161);
+        assert_eq!(u32::from_le_bytes(data[0x18..0x1C].try_into().unwrap()), 733);
+        assert_eq!(u32::from_le_bytes(data[0x34..0x38].try_into().unwrap()) as usize + 0x34, 0x100);
+        assert_eq!(
+            u32::from_le_bytes(data[0x04..0x08].try_into().unwrap()) as usize + 4,
+            data.len()
+        );
+        assert_eq!(
+            &data[0x100..],
+            &[0xB4, 0x00, 0xBF, 0x61, 0xDD, 0x02, 0xB4, 0x15, 0x0F, 0x66]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+// 画面の拡大方法と縦横比 (ウィンドウのどこに256x240の画面を置くか)
+// https://www.nesdev.org/wiki/Overscan
+// NTSCの画素は横長 (PAR 8:7) なので、ブラウン管に近づけるなら横に伸ばす
+use crate::frame::Frame;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    Integer, // 整数倍だけ (ドットの大きさが揃う。余りは黒)
+    Free,    // ウィンドウいっぱいまで
+}
+
+impl ScaleMode {
+    pub fn next(&self) -> ScaleMode {
+        match self {
+            ScaleMode::Integer => ScaleMode::Free,
+            ScaleMode::Free => ScaleMode::Integer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AspectRatio {
+    Square, // 1ドット = 1画素 (256:240)
+    Ntsc,   // PAR 8:7 (約292:240)
+}
+
+impl AspectRatio {
+    pub fn next(&self) -> AspectRatio {
+        match self {
+            AspectRatio::Square => AspectRatio::Ntsc,
+            AspectRatio::Ntsc => AspectRatio::Square,
+        }
+    }
+
+    // 1ドットの横の長さ (縦を1として)
+    pub fn pixel_aspect(&self) -> f64 {
+        match self {
+            AspectRatio::Square => 1.0,
+            AspectRatio::Ntsc => 8.0 / 7.0,
+        }
+    }
+}
+
+// ウィンドウの中に画面を置く矩形 (x, y, w, h)。中央に寄せて、余りは黒で埋める
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+impl Viewport {
+    pub fn fit(window: (u32, u32), scale_mode: ScaleMode, aspect: AspectRatio) -> Viewport {
+        let width = Frame::WIDTH as f64 * aspect.pixel_aspect();
+        let height = Frame::HEIGHT as f64;
+        let mut scale = (window.0 as f64 / width).min(window.1 as f64 / height);
+        if scale_mode == ScaleMode::Integer {
+            // ウィンドウが1倍より小さければ縮める
+            scale = scale.floor().max(scale.min(1.0));
+        }
+        let (w, h) = ((width * scale).round() as u32, (height * scale).round() as u32);
+        Viewport {
+            x: (window.0 as i32 - w as i32) / 2,
+            y: (window.1 as i32 - h as i32) / 2,
+            w,
+            h,
+        }
+    }
+
+    // ウィンドウの座標を画面のドットに直す (画面の外ならNone。ザッパーの照準に使う)
+    pub fn to_frame(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        if self.w == 0 || self.h == 0 || x < self.x || y < self.y {
+            return None;
+        }
+        let fx = (x - self.x) as i64 * Frame::WIDTH as i64 / self.w as i64;
+        let fy = (y - self.y) as i64 * Frame::HEIGHT as i64 / self.h as i64;
+        if fx >= Frame::WIDTH as i64 || fy >= Frame::HEIGHT as i64 {
+            return None;
+        }
+        Some((fx as i32, fy as i32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_viewport_fit() {
+        // 整数倍ならぴったり、余りは上下左右に均等に
+        let v = Viewport::fit((800, 600), ScaleMode::Integer, AspectRatio::Square);
+        assert_eq!(v, Viewport { x: 144, y: 60, w: 512, h: 480 });
+        let v = Viewport::fit((800, 600), ScaleMode::Free, AspectRatio::Square);
+        assert_eq!(v, Viewport { x: 80, y: 0, w: 640, h: 600 });
+        let v = Viewport::fit((1920, 1080), ScaleMode::Integer, AspectRatio::Ntsc);
+        assert_eq!(v, Viewport { x: 375, y: 60, w: 1170, h: 960 });
+        let v = Viewport::fit((128, 120), ScaleMode::Integer, AspectRatio::Square);
+        assert_eq!((v.w, v.h), (128, 120));
+
+        let v = Viewport::fit((800, 600), ScaleMode::Integer, AspectRatio::Square);
+        assert_eq!(v.to_frame(144, 60), Some((0, 0)));
+        assert_eq!(v.to_frame(144 + 511, 60 + 479), Some((255, 239)));
+        assert_eq!(v.to_frame(100, 100), None);
+        assert_eq!(v.to_frame(144 + 512, 100), None);
+    }
+}
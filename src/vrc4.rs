@@ -0,0 +1,309 @@
+// Konami VRC2/VRC4 (Mapper 21/22/23/25)
+// https://www.nesdev.org/wiki/VRC2_and_VRC4
+// 基板ごとにレジスタ選択用のアドレス線(A0/A1)の配線が違うので、先に正規化してから処理する
+use crate::common::*;
+use crate::mapper::{prg_ram_read, prg_ram_write, Mapper};
+use crate::impl_state;
+use crate::rom::{Mirroring, Rom};
+use alloc::vec::Vec;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+// IRQ制御レジスタ ($F002)
+const IRQ_ENABLE_AFTER_ACK: u8 = _BIT_0;
+const IRQ_ENABLE: u8 = _BIT_1;
+const IRQ_CYCLE_MODE: u8 = _BIT_2;
+
+pub struct Vrc4 {
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    prg_ram: Vec<u8>,
+    is_chr_ram: bool,
+    mapper: u8,
+    is_vrc2: bool,
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_control: u8,
+    irq_counter: u8,
+    irq_prescaler: i16,
+    irq_pending: bool,
+}
+
+impl_state!(Vrc4 {
+    chr, prg_ram, prg_banks, prg_swap, chr_banks, mirroring, irq_latch, irq_control,
+    irq_counter, irq_prescaler, irq_pending
+});
+
+impl Vrc4 {
+    pub fn new(rom: &Rom) -> Self {
+        Vrc4 {
+            prg_rom: rom.prg_rom.clone(),
+            chr: rom.chr_rom.clone(),
+            prg_ram: vec![0; rom.header.prg_ram_size],
+            is_chr_ram: rom.is_chr_ram,
+            mapper: rom.mapper,
+            is_vrc2: rom.mapper == 22,
+
+            prg_banks: [0, 1],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: rom.mirroring.clone(),
+
+            irq_latch: 0,
+            irq_control: 0,
+            irq_counter: 0,
+            irq_prescaler: 341,
+            irq_pending: false,
+        }
+    }
+
+    // 基板の配線に合わせて $x000-$x003 の形に直す
+    //   21: VRC4a (A1,A2) / VRC4c (A6,A7)
+    //   22: VRC2a (A1,A0)
+    //   23: VRC2b,VRC4f (A0,A1) / VRC4e (A2,A3)
+    //   25: VRC2c,VRC4b (A1,A0) / VRC4d (A3,A2)
+    fn register(&self, addr: u16) -> u16 {
+        let (a0, a1) = match self.mapper {
+            21 => ((addr >> 1 | addr >> 6) & 1, (addr >> 2 | addr >> 7) & 1),
+            22 => ((addr >> 1) & 1, addr & 1),
+            25 => ((addr >> 1 | addr >> 3) & 1, (addr | addr >> 2) & 1),
+            _ => ((addr | addr >> 2) & 1, (addr >> 1 | addr >> 3) & 1),
+        };
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+
+    fn prg_addr(&self, addr: u16) -> usize {
+        let count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match (addr, self.prg_swap) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => count - 2,
+            _ => count - 1,
+        };
+        (bank % count) * PRG_BANK_SIZE + (addr as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let mut bank = self.chr_banks[(addr as usize >> 10) & 0x07] as usize;
+        if self.is_vrc2 {
+            // VRC2aはCHRバンクの最下位ビットが無視される
+            bank >>= 1;
+        }
+        let count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % count) * CHR_BANK_SIZE + (addr as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_chr_bank(&mut self, reg: u16, data: u8) {
+        // $B000-$E003: 1つのバンクを下位4ビット/上位5ビットの2回に分けて書く
+        let index = (((reg - 0xB000) >> 12) * 2 + ((reg & 0x02) >> 1)) as usize;
+        let bank = self.chr_banks[index];
+        self.chr_banks[index] = if reg & 0x01 == 0 {
+            (bank & 0x1F0) | (data & 0x0F) as u16
+        } else {
+            (bank & 0x00F) | ((data & 0x1F) as u16) << 4
+        };
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => prg_ram_read(&self.prg_ram, addr),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_addr(addr)],
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            prg_ram_write(&mut self.prg_ram, addr, data);
+            return;
+        }
+        if addr < 0x8000 {
+            return;
+        }
+
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x1F,
+            0x9000..=0x9001 if self.is_vrc2 => {
+                self.mirroring = if data & 0x01 == 0 {
+                    Mirroring::VERTICAL
+                } else {
+                    Mirroring::HORIZONTAL
+                };
+            }
+            0x9000 => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::VERTICAL,
+                    1 => Mirroring::HORIZONTAL,
+                    2 => Mirroring::ONE_SCREEN_LOWER,
+                    _ => Mirroring::ONE_SCREEN_UPPER,
+                };
+            }
+            0x9002 if !self.is_vrc2 => self.prg_swap = data & _BIT_1 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = data & 0x1F,
+            0xB000..=0xE003 => self.write_chr_bank(reg, data),
+            0xF000 => self.irq_latch = (self.irq_latch & 0xF0) | (data & 0x0F),
+            0xF001 => self.irq_latch = (self.irq_latch & 0x0F) | (data << 4),
+            0xF002 => {
+                self.irq_control = data & 0x07;
+                self.irq_pending = false;
+                if data & IRQ_ENABLE != 0 {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = 341;
+                }
+            }
+            0xF003 => {
+                self.irq_pending = false;
+                // A を E にコピー
+                if self.irq_control & IRQ_ENABLE_AFTER_ACK != 0 {
+                    self.irq_control |= IRQ_ENABLE;
+                } else {
+                    self.irq_control &= !IRQ_ENABLE;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[self.chr_addr(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.is_chr_ram {
+            let addr = self.chr_addr(addr);
+            self.chr[addr] = data;
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    // VRC4のIRQカウンタはCPUクロックで動く
+    // スキャンラインモードでは 341/3 CPUサイクル(=1スキャンライン)ごとにカウントする
+    fn cpu_tick(&mut self) {
+        if self.is_vrc2 || self.irq_control & IRQ_ENABLE == 0 {
+            return;
+        }
+        if self.irq_control & IRQ_CYCLE_MODE != 0 {
+            self.clock_irq_counter();
+        } else {
+            self.irq_prescaler -= 3;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += 341;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring.clone()
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_addr(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        Some(self.chr_addr(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::banked_rom;
+
+    // PRG 8KB x16, CHR 1KB x256
+    fn vrc(mapper: u8) -> Rom {
+        banked_rom(mapper, 16, 256)
+    }
+
+    #[test]
+    fn test_vrc4_address_lines() {
+        // VRC4a: $B002 (A1) => $B001,  VRC4c: $B040 (A6) => $B001
+        let mapper = Vrc4::new(&vrc(21));
+        assert_eq!(mapper.register(0xB002), 0xB001);
+        assert_eq!(mapper.register(0xB040), 0xB001);
+        assert_eq!(mapper.register(0xB004), 0xB002);
+        assert_eq!(mapper.register(0xB080), 0xB002);
+
+        // VRC4b: $B002 (A1) => $B001,  VRC4d: $B008 (A3) => $B001
+        let mapper = Vrc4::new(&vrc(25));
+        assert_eq!(mapper.register(0xB002), 0xB001);
+        assert_eq!(mapper.register(0xB008), 0xB001);
+        assert_eq!(mapper.register(0xB001), 0xB002);
+    }
+
+    #[test]
+    fn test_vrc4_banking() {
+        let mut mapper = Vrc4::new(&vrc(23));
+        mapper.cpu_write(0x8000, 5);
+        mapper.cpu_write(0xA000, 6);
+        assert_eq!(mapper.cpu_read(0x8000), 5);
+        assert_eq!(mapper.cpu_read(0xA000), 6);
+        assert_eq!(mapper.cpu_read(0xC000), 14);
+        assert_eq!(mapper.cpu_read(0xE000), 15);
+
+        // PRGスワップモード
+        mapper.cpu_write(0x9002, 0x02);
+        assert_eq!(mapper.cpu_read(0x8000), 14);
+        assert_eq!(mapper.cpu_read(0xC000), 5);
+
+        // CHRバンク3 ($C002/$C003) = 0x1A3
+        mapper.cpu_write(0xC002, 0x03);
+        mapper.cpu_write(0xC003, 0x1A);
+        assert_eq!(mapper.ppu_read(0x0C00), 0xA3);
+
+        mapper.cpu_write(0x9000, 3);
+        assert_eq!(mapper.mirroring(), Mirroring::ONE_SCREEN_UPPER);
+    }
+
+    #[test]
+    fn test_vrc4_cycle_irq() {
+        let mut mapper = Vrc4::new(&vrc(23));
+        mapper.cpu_write(0xF000, 0x0D);
+        mapper.cpu_write(0xF001, 0x0F); // latch = 0xFD
+        mapper.cpu_write(0xF002, IRQ_ENABLE | IRQ_CYCLE_MODE);
+
+        // FD -> FE -> FF -> (reload) IRQ
+        mapper.cpu_tick();
+        mapper.cpu_tick();
+        assert!(!mapper.irq_pending());
+        mapper.cpu_tick();
+        assert!(mapper.irq_pending());
+
+        mapper.cpu_write(0xF003, 0);
+        assert!(!mapper.irq_pending());
+        // A=0 なので止まる
+        for _ in 0..10 {
+            mapper.cpu_tick();
+        }
+        assert!(!mapper.irq_pending());
+    }
+}
@@ -0,0 +1,76 @@
+// ブラウザ向けのエクスポート (wasm32-unknown-unknown, SDLなし)
+// https://rustwasm.github.io/docs/wasm-bindgen/
+//   cargo build --release --lib --target wasm32-unknown-unknown --no-default-features --features wasm
+//   wasm-bindgen --target web --out-dir web/pkg target/wasm32-unknown-unknown/release/nes_core.wasm
+// 使い方は web/rscom.js を参照
+//
+// JS からは Emulator クラスとして見える (new Emulator(rom, sampleRate) してから run_frame を呼ぶ)
+use crate::apu::{AudioConfig, APU};
+use crate::gamepad::Button;
+use crate::nes::Nes;
+use crate::region::Region;
+use crate::rom::Rom;
+use wasm_bindgen::prelude::*;
+
+const WIDTH: usize = 256;
+const HEIGHT: usize = 240;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    nes: Nes,
+    framebuffer: Vec<u8>, // RGBA (ImageDataにそのまま渡せる)
+    samples: Vec<f32>,    // 直前のフレームで生成したサンプル (モノラル)
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    // sample_rate は AudioContext.sampleRate を渡す
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8], sample_rate: i32) -> Result<Emulator, JsError> {
+        let rom = Rom::from_bytes(rom)?;
+        let region = Region::from_header(&rom.header).unwrap_or(Region::Ntsc);
+        let config = AudioConfig {
+            sample_rate,
+            ..AudioConfig::new()
+        };
+        let mut nes = Nes::new(rom, APU::headless(&config))?;
+        nes.set_region(region);
+        Ok(Emulator {
+            nes,
+            framebuffer: vec![0xFF; WIDTH * HEIGHT * 4],
+            samples: vec![],
+        })
+    }
+
+    // requestAnimationFrame はモニタのリフレッシュレートなので、JS側で frame_rate に合わせて呼ぶ
+    pub fn frame_rate(&self) -> f64 {
+        self.nes.region().frame_rate()
+    }
+
+    pub fn run_frame(&mut self) {
+        let frame = self.nes.run_frame();
+        for (rgba, rgb) in self.framebuffer.chunks_exact_mut(4).zip(frame.data.chunks_exact(3)) {
+            rgba[..3].copy_from_slice(rgb);
+        }
+        self.samples.clear();
+        self.nes.apu_mut().drain_samples(&mut self.samples);
+    }
+
+    // JS側には Uint8Array としてコピーされる
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.framebuffer.clone()
+    }
+
+    pub fn audio_samples(&self) -> Vec<f32> {
+        self.samples.clone()
+    }
+
+    // buttons: ビット0から A, B, SELECT, START, UP, DOWN, LEFT, RIGHT (コントローラーの読み出し順)
+    pub fn set_input(&mut self, player: usize, buttons: u8) {
+        self.nes.set_controller_state(player, Button::from_bits_truncate(buttons));
+    }
+
+    pub fn reset(&mut self) {
+        self.nes.soft_reset();
+    }
+}
@@ -0,0 +1,238 @@
+// ウォッチリスト (名前を付けたアドレスの値をフレームごとに表示する) と値の固定
+// https://fceux.com/web/help/MemoryWatch.html
+// 固定はチート (CheatManager) で行うので、CPU RAM ($0000-$1FFF) だけ
+//   EveryFrame: フレームごとに書き戻す (途中でゲームが書き換えた値も一瞬は見える)
+//   Writes: ゲームからの書き込みを無視する
+// 2バイトのものはリトルエンディアン (addr が下位)
+// RAMマップ (ram_map.rs) の値は add_ram_map で並べて表示できる
+use crate::error::EmuError;
+use crate::memory_view::{read_block, write_block, MemorySpace};
+use crate::nes::Nes;
+use crate::ram_map::{Encoding, FieldType, RamMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchFormat {
+    Hex,
+    Unsigned,
+    Signed,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FreezeMode {
+    EveryFrame,
+    Writes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    pub name: String,
+    pub addr: u16,
+    pub size: u8, // 1 or 2
+    pub format: WatchFormat,
+    pub frozen: Option<FreezeMode>,
+    previous: Option<u16>,
+}
+
+impl Watch {
+    pub fn format_value(&self, value: u16) -> String {
+        match (self.format, self.size) {
+            (WatchFormat::Hex, 1) => format!("${:02X}", value),
+            (WatchFormat::Hex, _) => format!("${:04X}", value),
+            (WatchFormat::Unsigned, _) => format!("{}", value),
+            (WatchFormat::Signed, 1) => format!("{}", value as u8 as i8),
+            (WatchFormat::Signed, _) => format!("{}", value as i16),
+            (WatchFormat::Binary, 1) => format!("%{:08b}", value),
+            (WatchFormat::Binary, _) => format!("%{:016b}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchValue {
+    pub name: String,
+    pub addr: u16,
+    pub value: u16,
+    pub text: String,
+    pub changed: bool, // 前回の update から変わった
+}
+
+pub struct WatchList {
+    watches: Vec<Watch>,
+}
+
+impl WatchList {
+    pub fn new() -> Self {
+        WatchList { watches: vec![] }
+    }
+
+    // 追加したウォッチの番号を返す
+    pub fn add(&mut self, name: &str, addr: u16, size: u8, format: WatchFormat) -> Result<usize, String> {
+        if size != 1 && size != 2 {
+            return Err(format!("size must be 1 or 2: {}", size));
+        }
+        if addr as usize + size as usize > 0x10000 {
+            return Err(format!("address out of range: ${:04X}", addr));
+        }
+        self.watches.push(Watch {
+            name: name.to_string(),
+            addr,
+            size,
+            format,
+            frozen: None,
+            previous: None,
+        });
+        Ok(self.watches.len() - 1)
+    }
+
+    // RAMマップのうち、ウォッチで表せるもの (1/2バイトのリトルエンディアン) を追加して、追加した数を返す
+    // BCDなどは RamMap::read_all で読む
+    pub fn add_ram_map(&mut self, map: &RamMap) -> usize {
+        let mut count = 0;
+        for field in map.fields() {
+            let format = match field.kind {
+                FieldType::Signed(_) => WatchFormat::Signed,
+                FieldType::Unsigned(_) | FieldType::Bool => WatchFormat::Unsigned,
+            };
+            let little = field.encoding == Encoding::Little || field.size() == 1 && field.encoding == Encoding::Big;
+            if little && self.add(&field.name, field.addr, field.size() as u8, format).is_ok() {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // 固定していたら外してから消す。以降の番号は1つずつ詰まる
+    pub fn remove(&mut self, nes: &mut Nes, id: usize) -> Option<Watch> {
+        if id >= self.watches.len() {
+            return None;
+        }
+        self.unfreeze(nes, id);
+        Some(self.watches.remove(id))
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    // フレームごとに呼ぶ (副作用なしで読む)
+    pub fn update(&mut self, nes: &mut Nes) -> Result<Vec<WatchValue>, EmuError> {
+        let mut values = vec![];
+        for watch in self.watches.iter_mut() {
+            let bytes = read_block(nes, MemorySpace::Cpu, watch.addr, watch.size as usize)?;
+            let value = bytes.iter().rev().fold(0u16, |v, &b| v << 8 | b as u16);
+            values.push(WatchValue {
+                name: watch.name.clone(),
+                addr: watch.addr,
+                value,
+                text: watch.format_value(value),
+                changed: watch.previous.is_some_and(|previous| previous != value),
+            });
+            watch.previous = Some(value);
+        }
+        Ok(values)
+    }
+
+    // value に固定する (すぐに書き込む)
+    pub fn freeze(&mut self, nes: &mut Nes, id: usize, value: u16, mode: FreezeMode) -> Result<(), String> {
+        let watch = self.watches.get(id).ok_or(format!("no watch: {}", id))?.clone();
+        if watch.addr as usize + watch.size as usize > 0x2000 {
+            return Err(format!("address is not CPU RAM: ${:04X}", watch.addr));
+        }
+        self.unfreeze(nes, id);
+        let bytes = value.to_le_bytes();
+        for (n, &byte) in bytes[..watch.size as usize].iter().enumerate() {
+            nes.cheats_mut().add_cheat(watch.addr + n as u16, byte, mode == FreezeMode::Writes)?;
+        }
+        write_block(nes, MemorySpace::Cpu, watch.addr, &bytes[..watch.size as usize]).map_err(|e| e.to_string())?;
+        self.watches[id].frozen = Some(mode);
+        Ok(())
+    }
+
+    // そのアドレスのチートをすべて外す
+    pub fn unfreeze(&mut self, nes: &mut Nes, id: usize) -> bool {
+        let watch = match self.watches.get_mut(id) {
+            Some(watch) if watch.frozen.is_some() => watch,
+            _ => return false,
+        };
+        watch.frozen = None;
+        let addrs: Vec<u16> = (watch.addr..).take(watch.size as usize).map(|addr| addr & 0x07FF).collect();
+        let cheats = nes.cheats_mut();
+        while let Some(id) = cheats.cheats().iter().position(|c| addrs.contains(&c.address)) {
+            cheats.remove(id);
+        }
+        true
+    }
+}
+
+impl Default for WatchList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rom::TestRom;
+
+    #[test]
+    fn test_watch_list() {
+        // フレームごとに $10 を増やし、$20 に $12 を書き続ける
+        let source = "
+            loop: LDA $2002
+                  BPL loop
+                  INC $10
+                  LDA #$12
+                  STA $20
+                  JMP loop
+        ";
+        let mut nes = TestRom::new().asm(0x8000, source).nes().unwrap();
+        let mut watches = WatchList::new();
+        let counter = watches.add("counter", 0x0010, 1, WatchFormat::Unsigned).unwrap();
+        let pair = watches.add("pair", 0x0020, 2, WatchFormat::Hex).unwrap();
+        assert!(watches.add("bad", 0x0000, 3, WatchFormat::Hex).is_err());
+        let rom = watches.add("rom", 0x8000, 1, WatchFormat::Hex).unwrap();
+        assert!(watches.freeze(&mut nes, rom, 0, FreezeMode::Writes).is_err());
+        watches.remove(&mut nes, rom).unwrap();
+        nes.ram_mut()[0x21] = 0x34;
+
+        nes.run_frame();
+        nes.run_frame();
+        let first = watches.update(&mut nes).unwrap();
+        assert_eq!(first[pair].text, "$3412");
+        nes.run_frame();
+        let second = watches.update(&mut nes).unwrap();
+        assert!(second[counter].changed && !second[pair].changed);
+        assert_eq!(second[counter].value, first[counter].value + 1);
+
+        // 書き込みを無視する固定と、フレームごとに書き戻す固定
+        watches.freeze(&mut nes, counter, 0x80, FreezeMode::Writes).unwrap();
+        watches.freeze(&mut nes, pair, 0xBEEF, FreezeMode::EveryFrame).unwrap();
+        nes.run_frame();
+        let frozen = watches.update(&mut nes).unwrap();
+        assert_eq!(frozen[counter].value, 0x80);
+        assert_eq!(frozen[pair].value, 0xBEEF);
+        assert_eq!(nes.cheats().cheats().len(), 3);
+        // フレームの途中ではゲームが書き換えた値が見える
+        nes.run_scanlines(30).unwrap();
+        assert_eq!((nes.ram()[0x10], nes.ram()[0x20]), (0x80, 0x12));
+
+        watches.remove(&mut nes, counter).unwrap();
+        assert_eq!(nes.cheats().cheats().len(), 2);
+        assert!(watches.unfreeze(&mut nes, 0));
+        assert!(nes.cheats().is_empty());
+        assert_eq!(watches.watches()[0].format_value(0xFFFE), "$FFFE");
+        let signed = Watch {
+            format: WatchFormat::Signed,
+            size: 1,
+            ..watches.watches()[0].clone()
+        };
+        assert_eq!(signed.format_value(0xFF), "-1");
+
+        let map = RamMap::parse("score $0300 u24 bcd\nx $0010 u8\nspeed $0011 s16").unwrap();
+        let mut from_map = WatchList::new();
+        assert_eq!(from_map.add_ram_map(&map), 2);
+        assert_eq!(from_map.watches()[1].format, WatchFormat::Signed);
+    }
+}
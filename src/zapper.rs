@@ -0,0 +1,104 @@
+// ザッパー (光線銃)
+// https://www.nesdev.org/wiki/Zapper
+// D3: 光を検出していれば0、D4: トリガーを引いていれば1
+// 光の検出は狙っている位置のまわりが明るいかどうかで判定する (走査線のタイミングまでは見ない)
+use core::any::Any;
+
+use crate::controller::Peripheral;
+use crate::frame::Frame;
+use crate::impl_state;
+
+// この明るさ以上なら光っているとみなす (RGBの平均)
+const LIGHT_THRESHOLD: u16 = 0xC0;
+// 狙った位置から上下左右何ドットまで見るか (実機のセンサーの視野は広い)
+const SENSE_RADIUS: i32 = 2;
+
+pub struct Zapper {
+    aim: Option<(i32, i32)>,
+    trigger: bool,
+    light: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Zapper {
+            aim: None,
+            trigger: false,
+            light: false,
+        }
+    }
+
+    // 画面上 (256x240) の位置。画面の外ならNone
+    pub fn set_aim(&mut self, aim: Option<(i32, i32)>) {
+        self.aim = aim;
+    }
+
+    pub fn aim(&self) -> Option<(i32, i32)> {
+        self.aim
+    }
+
+    pub fn set_trigger(&mut self, trigger: bool) {
+        self.trigger = trigger;
+    }
+
+    // 狙っている位置の明るさを見る (画面の外を狙っていたら光は見えない)
+    pub fn sense_light(&mut self, frame: &Frame) {
+        self.light = match self.aim {
+            Some((x, y)) => (-SENSE_RADIUS..=SENSE_RADIUS)
+                .flat_map(|dy| (-SENSE_RADIUS..=SENSE_RADIUS).map(move |dx| (x + dx, y + dy)))
+                .filter_map(|(x, y)| frame.get_pixel(x, y))
+                .any(|(r, g, b)| (r as u16 + g as u16 + b as u16) / 3 >= LIGHT_THRESHOLD),
+            None => false,
+        };
+    }
+}
+
+impl Default for Zapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_state!(Zapper { trigger, light });
+
+impl Peripheral for Zapper {
+    fn name(&self) -> &'static str {
+        "zapper"
+    }
+
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self) -> u8 {
+        let light = if self.light { 0 } else { 1 };
+        light << 3 | (self.trigger as u8) << 4
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zapper() {
+        let mut zapper = Zapper::new();
+        let mut frame = Frame::new();
+        frame.set_pixel(100, 50, (0xFF, 0xFF, 0xFF));
+
+        zapper.set_aim(Some((101, 51)));
+        zapper.sense_light(&frame);
+        assert_eq!(zapper.read(), 0x00);
+
+        zapper.set_aim(Some((10, 10)));
+        zapper.set_trigger(true);
+        zapper.sense_light(&frame);
+        assert_eq!(zapper.read(), 0x18);
+
+        zapper.set_aim(None);
+        zapper.sense_light(&frame);
+        assert_eq!(zapper.read(), 0x18);
+    }
+}
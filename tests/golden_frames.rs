@@ -0,0 +1,107 @@
+// 決まったフレームの画面を tests/golden の画像と比べる (PPUの回帰テスト)
+// 画像を作り直すときは NES_GOLDEN_UPDATE=1 cargo test --test golden_frames
+use nes_core::blargg;
+use nes_core::golden::{self, GoldenCase, GoldenOutcome};
+use nes_core::nes::Nes;
+use nes_core::test_rom::TestRom;
+use std::path::PathBuf;
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn check(nes: &mut Nes, case: &GoldenCase) -> Result<(), String> {
+    let frame = golden::run_to_frame(nes, case.frame);
+    let outcome = golden::compare(frame, &golden_dir().join(case.image_name()), case.crc32, golden::update_requested())
+        .map_err(|e| e.to_string())?;
+    match outcome {
+        GoldenOutcome::Match | GoldenOutcome::Updated => Ok(()),
+        GoldenOutcome::Missing { actual } => Err(format!(
+            "{} frame {}: no reference (crc32 {:08X}), run with {}=1",
+            case.rom,
+            case.frame,
+            actual,
+            golden::UPDATE_ENV
+        )),
+        other => Err(format!("{} frame {}: {:?}", case.rom, case.frame, other)),
+    }
+}
+
+// 組み込みのROM: パレットとネームテーブルを書いて背景を表示する
+#[test]
+fn test_rom_frames() {
+    let source = "
+        reset: LDX #$FF
+               TXS
+        wait1: LDA $2002
+               BPL wait1
+        wait2: LDA $2002
+               BPL wait2
+               LDA #$3F
+               STA $2006
+               LDA #$00
+               STA $2006
+               LDA #$0F
+               STA $2007
+               LDA #$16
+               STA $2007
+               LDA #$27
+               STA $2007
+               LDA #$18
+               STA $2007
+               LDA #$20
+               STA $2006
+               LDA #$00
+               STA $2006
+               LDY #4
+        outer: LDX #0
+        inner: TXA
+               AND #$03
+               STA $2007
+               INX
+               BNE inner
+               DEY
+               BNE outer
+               LDA #$00
+               STA $2005
+               STA $2005
+               LDA #$0A
+               STA $2001
+        done:  JMP done
+    ";
+    // タイル1: 色1で塗りつぶし, 2: 色2の市松, 3: 色3の横じま
+    let mut chr = vec![0; 0x2000];
+    for y in 0..8 {
+        chr[0x10 + y] = 0xFF;
+        chr[0x28 + y] = if y % 2 == 0 { 0xAA } else { 0x55 };
+        chr[0x30 + y] = if y % 2 == 0 { 0xFF } else { 0x00 };
+        chr[0x38 + y] = if y % 2 == 0 { 0xFF } else { 0x00 };
+    }
+    let mut nes = TestRom::new().asm(0x8000, source).chr(&chr).nes().unwrap();
+    let case = GoldenCase {
+        rom: "test_rom.nes".to_string(),
+        frame: 10,
+        crc32: None,
+    };
+    check(&mut nes, &case).unwrap();
+}
+
+// tests/golden/golden.txt のROM
+#[test]
+fn rom_frames() {
+    let manifest = std::fs::read_to_string(golden_dir().join("golden.txt")).unwrap();
+    let root = blargg::test_roms_root(env!("CARGO_MANIFEST_DIR"));
+    let mut failures = vec![];
+    for case in golden::parse_manifest(&manifest).unwrap() {
+        let path = root.join(&case.rom);
+        if !path.exists() {
+            eprintln!("[ERR] {} not found, skipped", path.display());
+            continue;
+        }
+        let mut nes = Nes::from_bytes(&std::fs::read(&path).unwrap()).unwrap();
+        if let Err(e) = check(&mut nes, &case) {
+            failures.push(e);
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,28 @@
+// blargg の instr_test-v5 (CPUの全命令のテスト) を画面なしで実行する
+// https://github.com/christopherpow/nes-test-roms/tree/master/instr_test-v5
+// ROMは test_roms/instr_test-v5/rom_singles/*.nes に置く。置いていなければ何もしない
+use nes_core::blargg;
+
+const MAX_FRAMES: usize = 60 * 60;
+
+#[test]
+fn instr_test_v5() {
+    let roms = match blargg::test_roms(env!("CARGO_MANIFEST_DIR"), "instr_test-v5/rom_singles") {
+        Some(roms) => roms,
+        None => {
+            eprintln!("[ERR] instr_test-v5 not found, skipped");
+            return;
+        }
+    };
+
+    let mut failures = vec![];
+    for rom in &roms {
+        let result = blargg::run_file(rom.to_str().unwrap(), MAX_FRAMES).unwrap();
+        let name = rom.file_name().unwrap().to_string_lossy();
+        println!("{}: {:?} ({} frames)\n{}", name, result.code, result.frames, result.text);
+        if !result.passed() {
+            failures.push(format!("{}: {:?}\n{}", name, result.code, result.text.trim_end()));
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} failed\n{}", failures.len(), roms.len(), failures.join("\n"));
+}
@@ -0,0 +1,43 @@
+// blargg の古いPPUタイミングのテスト (スプライト0ヒット, スプライトオーバーフロー, VBlank/NMI) を画面なしで実行する
+// https://github.com/christopherpow/nes-test-roms (sprite_hit_tests_2005.10.05, sprite_overflow_tests, vbl_nmi_timing)
+// ROMは test_roms/<スイート名>/*.nes に置く。置いていなければ何もしない
+// 結果は $F8 に残るので、どのROMも終わるまで十分なフレーム数を回してから読む
+use nes_core::blargg;
+
+const FRAMES: usize = 60 * 15;
+
+fn run_suite(suite: &str) {
+    let roms = match blargg::test_roms(env!("CARGO_MANIFEST_DIR"), suite) {
+        Some(roms) => roms,
+        None => {
+            eprintln!("[ERR] {} not found, skipped", suite);
+            return;
+        }
+    };
+
+    let mut failures = vec![];
+    for rom in &roms {
+        let result = blargg::run_legacy_file(rom.to_str().unwrap(), FRAMES).unwrap();
+        let name = rom.file_name().unwrap().to_string_lossy();
+        println!("{}: {:?}", name, result.code);
+        if !result.passed() {
+            failures.push(format!("{}: {:?}", name, result.code));
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} failed\n{}", failures.len(), roms.len(), failures.join("\n"));
+}
+
+#[test]
+fn sprite_hit_tests() {
+    run_suite("sprite_hit_tests_2005.10.05");
+}
+
+#[test]
+fn sprite_overflow_tests() {
+    run_suite("sprite_overflow_tests");
+}
+
+#[test]
+fn vbl_nmi_timing() {
+    run_suite("vbl_nmi_timing");
+}
@@ -0,0 +1,71 @@
+// rscom (wasm) のブラウザ用グルー
+// pkg/ は wasm-bindgen --target web --out-dir web/pkg で作る (src/wasm.rs を参照)
+//   const nes = await Rscom.load(new Uint8Array(await file.arrayBuffer()));
+//   nes.start(canvas);
+import init, { Emulator } from "./pkg/nes_core.js";
+
+const WIDTH = 256;
+const HEIGHT = 240;
+
+// キーボードの割り当て (ビット0から A, B, SELECT, START, UP, DOWN, LEFT, RIGHT)
+const KEYS = {
+  KeyX: 0x01, KeyZ: 0x02, ShiftRight: 0x04, Enter: 0x08,
+  ArrowUp: 0x10, ArrowDown: 0x20, ArrowLeft: 0x40, ArrowRight: 0x80,
+};
+
+export class Rscom {
+  // ROMが読めなければ Emulator のコンストラクタが例外を投げる
+  static async load(rom) {
+    await init();
+    const audio = new AudioContext();
+    return new Rscom(new Emulator(rom, audio.sampleRate), audio);
+  }
+
+  constructor(emulator, audio) {
+    this.emulator = emulator;
+    this.buttons = 0;
+    this.audio = audio;
+    this.audioTime = 0;
+  }
+
+  start(canvas) {
+    const ctx = canvas.getContext("2d");
+    const image = ctx.createImageData(WIDTH, HEIGHT);
+    const frameTime = 1000 / this.emulator.frame_rate();
+    let next = performance.now();
+
+    window.addEventListener("keydown", (e) => { this.buttons |= KEYS[e.code] || 0; });
+    window.addEventListener("keyup", (e) => { this.buttons &= ~(KEYS[e.code] || 0); });
+
+    const tick = (now) => {
+      // requestAnimationFrame はモニタに合わせて呼ばれるので、NESのフレームレートに合わせて進める
+      while (next <= now) {
+        this.emulator.set_input(0, this.buttons);
+        this.emulator.run_frame();
+        this.playAudio();
+        next += frameTime;
+      }
+      image.data.set(this.emulator.framebuffer());
+      ctx.putImageData(image, 0, 0);
+      requestAnimationFrame(tick);
+    };
+    requestAnimationFrame(tick);
+  }
+
+  playAudio() {
+    const samples = this.emulator.audio_samples();
+    if (samples.length === 0 || this.audio.state !== "running") {
+      this.audio.resume();
+      return;
+    }
+    const buffer = this.audio.createBuffer(1, samples.length, this.audio.sampleRate);
+    buffer.copyToChannel(samples, 0);
+    const source = this.audio.createBufferSource();
+    source.buffer = buffer;
+    source.connect(this.audio.destination);
+    // 遅れていたら少し先から鳴らし直す
+    this.audioTime = Math.max(this.audioTime, this.audio.currentTime + 0.05);
+    source.start(this.audioTime);
+    this.audioTime += buffer.duration;
+  }
+}